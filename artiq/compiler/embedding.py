@@ -48,8 +48,8 @@ class SpecializedFunction:
 
 
 class EmbeddingMap:
-    def __init__(self):
-        self.object_current_key = 0
+    def __init__(self, first_object_key=0):
+        self.object_current_key = first_object_key
         self.object_forward_map = {}
         self.object_reverse_map = {}
         self.module_map = {}
@@ -197,6 +197,14 @@ class EmbeddingMap:
         return any(filter(lambda x: inspect.isfunction(x) or inspect.ismethod(x),
                           self.object_forward_map.values()))
 
+    def merge_objects(self, other):
+        # objects of a map whose keys were allocated after ours, e.g. the RPC
+        # targets of a subkernel, which the host serves through this map
+        for obj_key, obj_ref in other.object_forward_map.items():
+            assert obj_key > self.object_current_key
+            self.object_forward_map[obj_key] = obj_ref
+        self.object_current_key = max(self.object_current_key, other.object_current_key)
+
 
 class ASTSynthesizer:
     def __init__(self, embedding_map, value_map, quote_function=None, expanded_from=None):
@@ -787,7 +795,8 @@ class TypedtreeHasher(algorithm.Visitor):
         return hash(tuple(freeze(getattr(node, field_name)) for field_name in fields))
 
 class Stitcher:
-    def __init__(self, core, dmgr, engine=None, print_as_rpc=True, destination=0, subkernel_arg_types=[]):
+    def __init__(self, core, dmgr, engine=None, print_as_rpc=True, destination=0, subkernel_arg_types=[],
+                 first_object_key=0):
         self.core = core
         self.dmgr = dmgr
         if engine is None:
@@ -809,7 +818,7 @@ class Stitcher:
 
         self.functions = {}
 
-        self.embedding_map = EmbeddingMap()
+        self.embedding_map = EmbeddingMap(first_object_key)
         self.value_map = defaultdict(lambda: [])
         self.definitely_changed = False
 
@@ -93,6 +93,7 @@ class Target:
     tool_strip = "llvm-strip"
     tool_symbolizer = "llvm-symbolizer"
     tool_cxxfilt = "llvm-cxxfilt"
+    tool_nm = "llvm-nm"
 
     def __init__(self, subkernel_id=None):
         self.llcontext = ll.Context()
@@ -257,6 +258,20 @@ class Target:
                                       last_inlined))
             return backtrace
 
+    def symbol_table(self, library):
+        # Function symbols sorted by address, allowing the core device to
+        # annotate backtraces of kernels it runs on its own (subkernels).
+        with RunTool([self.tool_nm, "--defined-only", "--numeric-sort", "--demangle",
+                      "{library}"],
+                     library=library) \
+                as results:
+            symbols = []
+            for line in results["__stdout__"].read().rstrip().split("\n"):
+                fields = line.split(" ", 2)
+                if len(fields) == 3 and fields[1] in ("T", "t"):
+                    symbols.append((int(fields[0], 16), fields[2]))
+            return symbols
+
     def demangle(self, names):
         if not any(names):
             return names
@@ -281,6 +296,7 @@ class RV32IMATarget(Target):
     tool_strip = "llvm-strip"
     tool_symbolizer = "llvm-symbolizer"
     tool_cxxfilt = "llvm-cxxfilt"
+    tool_nm = "llvm-nm"
 
 class RV32GTarget(Target):
     triple = "riscv32-unknown-linux"
@@ -294,6 +310,7 @@ class RV32GTarget(Target):
     tool_strip = "llvm-strip"
     tool_symbolizer = "llvm-symbolizer"
     tool_cxxfilt = "llvm-cxxfilt"
+    tool_nm = "llvm-nm"
 
 class CortexA9Target(Target):
     triple = "armv7-unknown-linux-gnueabihf"
@@ -307,3 +324,4 @@ class CortexA9Target(Target):
     tool_strip = "llvm-strip"
     tool_symbolizer = "llvm-symbolizer"
     tool_cxxfilt = "llvm-cxxfilt"
+    tool_nm = "llvm-nm"
@@ -400,6 +400,8 @@ class LLVMIRGenerator:
 
         elif name == "subkernel_send_message":
             llty = ll.FunctionType(llvoid, [lli32, lli8, llsliceptr, llptrptr])
+        elif name == "subkernel_send_message_async":
+            llty = ll.FunctionType(llvoid, [lli32, lli8, llsliceptr, llptrptr])
         elif name == "subkernel_load_run":
             llty = ll.FunctionType(llvoid, [lli32, lli1])
         elif name == "subkernel_await_finish":
@@ -1726,7 +1728,9 @@ class LLVMIRGenerator:
 
         llsid = ll.Constant(lli32, 0)  # return goes back to master, sid is ignored
         lltagcount = ll.Constant(lli8, 1)  # only one thing is returned
-        self.llbuilder.call(self.llbuiltin("subkernel_send_message"),
+        # the kernel does not wait for the master to take it, the satellite
+        # reports the subkernel finished only once it has been sent
+        self.llbuilder.call(self.llbuiltin("subkernel_send_message_async"),
                             [llsid, lltagcount, lltagptr, llrets])
 
     def process_Call(self, insn):
@@ -6,6 +6,10 @@ from artiq.language.types import *
 def cache_get(key: TStr) -> TList(TInt32):
     raise NotImplementedError("syscall not simulated")
 
+@syscall
+def cache_get_many(keys: TList(TStr), values: TList(TList(TInt32))) -> TNone:
+    raise NotImplementedError("syscall not simulated")
+
 @syscall
 def cache_put(key: TStr, value: TList(TInt32)) -> TNone:
     raise NotImplementedError("syscall not simulated")
@@ -33,6 +37,18 @@ class CoreCache:
         """
         return cache_get(key)
 
+    @kernel
+    def get_many(self, keys, values):
+        """Extract several values from the core device cache at once.
+        This is equivalent to calling :meth:`get` for each key, but the lookups
+        share fewer round-trips between the kernel and comms CPUs.
+
+        :param list keys: cache keys
+        :param list values: list of lists of 32-bit integers, at least as long as ``keys``;
+            ``values[i]`` is replaced with the value associated with ``keys[i]``
+        """
+        cache_get_many(keys, values)
+
     @kernel
     def put(self, key, value):
         """Put a value into the core device cache. The value will persist until reboot.
@@ -24,6 +24,20 @@ class Request(Enum):
     RPCException = 8
 
     SubkernelUpload = 9
+    SubkernelUploadMany = 10
+
+    SetWatchdog = 11
+
+    QueueExperiment = 12
+    ClearExperiments = 13
+
+    Reattach = 14
+
+    Authenticate = 15
+
+    Heartbeat = 16
+
+    StageSubkernels = 17
 
 
 class Reply(Enum):
@@ -38,8 +52,19 @@ class Reply(Enum):
 
     RPCRequest = 10
 
+    WatchdogExpired = 14
     ClockFailure = 15
 
+    ExperimentQueue = 16
+    ExperimentQueueFull = 17
+
+    SessionAttached = 18
+
+    Authenticated = 19
+    Unauthorized = 20
+
+    SessionExpired = 21
+
 
 class UnsupportedDevice(Exception):
     pass
@@ -49,6 +74,18 @@ class LoadError(Exception):
     pass
 
 
+class ExperimentQueueFull(Exception):
+    pass
+
+
+class AuthenticationError(Exception):
+    pass
+
+
+class SessionExpired(Exception):
+    pass
+
+
 class RPCReturnValueError(ValueError):
     pass
 
@@ -171,6 +208,24 @@ class CommKernelDummy:
     def check_system_info(self):
         pass
 
+    def set_watchdog(self, timeout):
+        pass
+
+    def set_heartbeat(self, timeout):
+        pass
+
+    def heartbeat(self):
+        pass
+
+    def queue_experiment(self, kernel_library, subkernels):
+        return 0
+
+    def clear_experiments(self):
+        pass
+
+    def attach_session(self):
+        return False
+
 
 def incompatible_versions(v1, v2):
     if v1.endswith(".beta") or v2.endswith(".beta"):
@@ -185,13 +240,17 @@ def incompatible_versions(v1, v2):
 class CommKernel:
     warned_of_mismatch = False
 
-    def __init__(self, host, port=1381):
+    def __init__(self, host, port=1381, auth_token=None):
         self._read_type = None
         self.host = host
         self.port = port
+        self.auth_token = auth_token
         self.read_buffer = bytearray()
         self.write_buffer = bytearray()
-
+        # subkernel session of the core device, 0 until attach_session
+        self.session = 0
+        # see set_heartbeat
+        self.heartbeat_ms = 0
 
     def open(self):
         if hasattr(self, "socket"):
@@ -215,11 +274,31 @@ class CommKernel:
         self.pack_int64 = struct.Struct(self.endian + "q").pack
         self.pack_float64 = struct.Struct(self.endian + "d").pack
 
+        if self.auth_token is not None:
+            self._authenticate()
+
+    def _authenticate(self):
+        token = self.auth_token
+        if isinstance(token, str):
+            token = token.encode("utf-8")
+        self._write_header(Request.Authenticate)
+        self._write_bytes(token)
+        self._flush()
+
+        self._read_header()
+        if self._read_type == Reply.Unauthorized:
+            self.close()
+            raise AuthenticationError("Core device refused the authentication token")
+        self._read_expect(Reply.Authenticated)
+
     def close(self):
         if not hasattr(self, "socket"):
             return
         self.socket.close()
         del self.socket
+        # left over from a connection that was lost
+        self.read_buffer.clear()
+        self.write_buffer.clear()
         logger.debug("disconnected")
 
     #
@@ -258,6 +337,12 @@ class CommKernel:
         # Read message header.
         raw_type = self._read(1)[0]
         self._read_type = Reply(raw_type)
+        if self._read_type == Reply.Unauthorized and self.auth_token is None:
+            raise AuthenticationError("Core device requires an authentication token")
+        if self._read_type == Reply.SessionExpired:
+            self.close()
+            raise SessionExpired("Core device ended the session, "
+                                 "no heartbeat was sent in time")
 
         logger.debug("receiving message: type=%r",
                      self._read_type)
@@ -385,11 +470,22 @@ class CommKernel:
         else:
             self._read_expect(Reply.LoadCompleted)
 
-    def upload_subkernel(self, kernel_library, id, destination):
+    def _pack_symbol_table(self, symbols):
+        # compact symbol table: address and name of each function
+        symbol_table = b""
+        for address, name in symbols:
+            name = name.encode("utf-8")
+            symbol_table += self.pack_int32(address) + self.pack_int32(len(name)) + name
+        return symbol_table
+
+    def upload_subkernel(self, kernel_library, id, destination, symbols=[],
+                         persistent=False):
         self._write_header(Request.SubkernelUpload)
         self._write_int32(id)
         self._write_int8(destination)
         self._write_bytes(kernel_library)
+        self._write_bytes(self._pack_symbol_table(symbols))
+        self._write_bool(persistent)
         self._flush()
 
         self._read_header()
@@ -398,6 +494,106 @@ class CommKernel:
         else:
             self._read_expect(Reply.LoadCompleted)
 
+    def upload_subkernels(self, subkernels, staged=False):
+        """Upload several subkernels at once, given as (kernel_library, id,
+        destination, symbols, persistent) tuples. The core device sends them to
+        their satellites concurrently.
+
+        Persistent subkernels stay uploaded once the session ends, a later
+        session uploading the same library under the same ID does not send it
+        to the satellite again.
+
+        If ``staged`` is true, the core device replies as soon as it holds the
+        subkernels and sends them to their satellites in the background, while
+        the kernel currently running, if any, goes on. Subkernels the running
+        kernel uses cannot be replaced that way."""
+        if staged:
+            self._write_header(Request.StageSubkernels)
+        else:
+            self._write_header(Request.SubkernelUploadMany)
+        self._write_subkernels(subkernels)
+        self._flush()
+
+        self._read_header()
+        if self._read_type == Reply.LoadFailed:
+            raise LoadError(self._read_string())
+        else:
+            self._read_expect(Reply.LoadCompleted)
+
+    def _write_subkernels(self, subkernels):
+        self._write_int32(len(subkernels))
+        for kernel_library, id, destination, symbols, persistent in subkernels:
+            self._write_int32(id)
+            self._write_int8(destination)
+            self._write_bytes(kernel_library)
+            self._write_bytes(self._pack_symbol_table(symbols))
+            self._write_bool(persistent)
+
+    def queue_experiment(self, kernel_library, subkernels):
+        """Leave a kernel, with its subkernels given as for
+        :meth:`upload_subkernels`, to the core device. Queued kernels run one
+        after the other once no host is connected, under the watchdog set when
+        they were queued. Return the number of kernels queued."""
+        self._write_header(Request.QueueExperiment)
+        self._write_bytes(kernel_library)
+        self._write_subkernels(subkernels)
+        self._flush()
+
+        self._read_header()
+        if self._read_type == Reply.LoadFailed:
+            raise LoadError(self._read_string())
+        elif self._read_type == Reply.ExperimentQueueFull:
+            raise ExperimentQueueFull("Experiment queue of the core device is full")
+        else:
+            self._read_expect(Reply.ExperimentQueue)
+            return self._read_int32()
+
+    def clear_experiments(self):
+        """Drop the kernels queued that have not started running yet."""
+        self._write_empty(Request.ClearExperiments)
+        self._flush()
+
+        self._read_header()
+        self._read_expect(Reply.ExperimentQueue)
+        self._read_int32()
+
+    def attach_session(self):
+        """Tie the connection to a subkernel session of the core device. After
+        a reconnection, this is the session of the previous connection if the
+        core device still keeps it, with its subkernels as they are. Return
+        whether it was."""
+        self._write_header(Request.Reattach)
+        self._write_int32(self.session)
+        self._flush()
+
+        self._read_header()
+        self._read_expect(Reply.SessionAttached)
+        self.session = self._read_int32()
+        return self._read_bool()
+
+    def set_watchdog(self, timeout):
+        """Bound the time each kernel run afterwards may take, in seconds,
+        awaiting subkernels included. ``None`` lifts the bound."""
+        self._write_header(Request.SetWatchdog)
+        self._write_int32(0 if timeout is None else max(1, round(timeout*1000)))
+        self._flush()
+
+    def set_heartbeat(self, timeout):
+        """Have the core device end the session, stopping its subkernels,
+        when the host says nothing for longer than ``timeout`` seconds while
+        the core device waits on it. ``None`` lifts the bound."""
+        self.heartbeat_ms = 0 if timeout is None else max(1, round(timeout*1000))
+        self._write_header(Request.Heartbeat)
+        self._write_int32(self.heartbeat_ms)
+        self._flush()
+
+    def heartbeat(self):
+        """Tell the core device that the host is still there, when it does
+        not send anything else for a while."""
+        self._write_header(Request.Heartbeat)
+        self._write_int32(self.heartbeat_ms)
+        self._flush()
+
     def run(self):
         self._write_empty(Request.RunKernel)
         self._flush()
@@ -692,10 +888,13 @@ class CommKernel:
             stack_pointers.append(self._read_int32())
 
         self._process_async_error()
+        kernel_log = self._read_string()
+        severity = self._read_int8()
 
         traceback = list(symbolizer(backtrace))
         core_exn = exceptions.CoreException(nested_exceptions, exception_info,
-                                            traceback, stack_pointers)
+                                            traceback, stack_pointers,
+                                            kernel_log, severity)
 
         if core_exn.id == 0:
             python_exn_type = getattr(exceptions, core_exn.name.split('.')[-1])
@@ -732,6 +931,8 @@ class CommKernel:
                 self._serve_exception(embedding_map, symbolizer, demangler)
             elif self._read_type == Reply.ClockFailure:
                 raise exceptions.ClockFailure
+            elif self._read_type == Reply.WatchdogExpired:
+                raise exceptions.WatchdogExpired
             else:
                 self._read_expect(Reply.KernelFinished)
                 self._process_async_error()
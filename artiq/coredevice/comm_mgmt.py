@@ -2,6 +2,8 @@ from enum import Enum
 import logging
 import struct
 
+import numpy
+
 from sipyco.keepalive import create_connection
 
 logger = logging.getLogger(__name__)
@@ -11,8 +13,33 @@ class Request(Enum):
     GetLog = 1
     ClearLog = 2
     PullLog = 7
+    GetSatelliteLog = 17
     SetLogFilter = 3
     SetUartLogFilter = 6
+    SetSatelliteLogFilter = 16
+    SetSatelliteLogRateLimit = 18
+    SetSatelliteLogOutputs = 19
+    SatelliteLogControl = 20
+
+    SuppressSatelliteException = 21
+    GetSatelliteSuppressedCount = 22
+    SetSatelliteExceptionReplay = 23
+    GetSatelliteMailboxStats = 24
+    SetSatelliteCommTimeouts = 25
+    InjectSubkernelMessage = 26
+    GetSatelliteMessageStats = 27
+    CancelSubkernelMessages = 28
+    RunSatelliteBenchmark = 29
+    GetSatelliteLoopStats = 30
+    SetSatelliteWorkBudget = 31
+    GetLinkErrors = 32
+    MeasureAuxLatency = 33
+    ReloadRoutingTable = 34
+    SetAnalyzerCapture = 35
+    GetSubkernelStats = 36
+    Authenticate = 37
+    GetSatelliteMemoryStats = 38
+    UnsuppressSatelliteExceptions = 39
 
     ConfigRead = 12
     ConfigWrite = 13
@@ -33,6 +60,17 @@ class Reply(Enum):
 
     ConfigData = 7
 
+    SuppressedCount = 8
+    MailboxStats = 9
+    MessageStats = 10
+    BenchmarkResults = 11
+    LoopStats = 12
+    LinkErrors = 13
+    AuxLatency = 14
+    SubkernelStats = 15
+    Unauthorized = 16
+    MemoryStats = 17
+
     RebootImminent = 3
 
 
@@ -45,10 +83,28 @@ class LogLevel(Enum):
     TRACE = 5
 
 
+class SatelliteLogControl(Enum):
+    PAUSE = 0
+    RESUME = 1
+    CLEAR = 2
+
+
+class SatelliteLogTarget(Enum):
+    ALL = 0
+    KERNEL = 1
+    DRTIO = 2
+    MANAGER = 3
+
+
+class AuthenticationError(Exception):
+    pass
+
+
 class CommMgmt:
-    def __init__(self, host, port=1380):
+    def __init__(self, host, port=1380, auth_token=None):
         self.host = host
         self.port = port
+        self.auth_token = auth_token
 
     def open(self):
         if hasattr(self, "socket"):
@@ -63,6 +119,20 @@ class CommMgmt:
         else:
             raise IOError("Incorrect reply from device: expected e/E.")
 
+        if self.auth_token is not None:
+            token = self.auth_token
+            if isinstance(token, str):
+                token = token.encode("utf-8")
+            self._write_header(Request.Authenticate)
+            self._write_bytes(token)
+            reply = self._read_header()
+            if reply == Reply.Unauthorized:
+                self.close()
+                raise AuthenticationError("Core device refused the authentication token")
+            elif reply != Reply.Success:
+                raise IOError("Incorrect reply from device: {} (expected {})".
+                              format(reply, Reply.Success))
+
     def close(self):
         if not hasattr(self, "socket"):
             return
@@ -84,6 +154,9 @@ class CommMgmt:
     def _write_int8(self, value):
         self._write(struct.pack("B", value))
 
+    def _write_bool(self, value):
+        self._write(struct.pack("B", 1 if value else 0))
+
     def _write_int32(self, value):
         self._write(struct.pack(self.endian + "l", value))
 
@@ -94,6 +167,65 @@ class CommMgmt:
     def _write_string(self, value):
         self._write_bytes(value.encode("utf-8"))
 
+    def _pack_value(self, value):
+        # tag, then value, as rpc_proto::send_value on the device
+        if value is None:
+            return b"n"
+        elif isinstance(value, (bool, numpy.bool_)):
+            return b"b" + struct.pack("B", 1 if value else 0)
+        elif isinstance(value, (int, numpy.integer)):
+            if isinstance(value, numpy.int64) or not -2**31 <= value < 2**31:
+                return b"I" + struct.pack(self.endian + "q", value)
+            return b"i" + struct.pack(self.endian + "l", value)
+        elif isinstance(value, (float, numpy.floating)):
+            return b"f" + struct.pack(self.endian + "d", value)
+        elif isinstance(value, str):
+            data = value.encode("utf-8")
+            return b"s" + struct.pack(self.endian + "l", len(data)) + data
+        elif isinstance(value, (bytes, bytearray)):
+            tag = b"B" if isinstance(value, bytes) else b"A"
+            return tag + struct.pack(self.endian + "l", len(value)) + value
+        elif isinstance(value, tuple):
+            return (b"t" + struct.pack("B", len(value)) +
+                    b"".join(self._pack_value(elt) for elt in value))
+        elif isinstance(value, list):
+            return (b"l" + struct.pack(self.endian + "l", len(value)) +
+                    self._pack_elements(value))
+        elif isinstance(value, numpy.ndarray):
+            header = (b"a" + struct.pack("B", value.ndim) +
+                      b"".join(struct.pack(self.endian + "l", dim) for dim in value.shape))
+            elt_tag = self._ARRAY_ELEMENT_TAGS.get(value.dtype.kind + str(value.dtype.itemsize))
+            if elt_tag is None:
+                return header + self._pack_elements(list(value.flat))
+            # numeric elements go as one contiguous block, in row-major order
+            dtype = value.dtype.newbyteorder(self.endian)
+            return header + elt_tag + numpy.ascontiguousarray(value, dtype=dtype).tobytes()
+        else:
+            raise TypeError("Cannot send {!r} to a subkernel".format(value))
+
+    _ARRAY_ELEMENT_TAGS = {
+        "b1": b"b",
+        "i4": b"i",
+        "i8": b"I",
+        "f8": b"f",
+    }
+
+    def _pack_elements(self, values):
+        # element tag once, then the elements, as rpc_proto::send_elements
+        if not values:
+            return b"n"
+        packed = [self._pack_value(value) for value in values]
+        tags = {p[:1] for p in packed}
+        if tags == {b"i", b"I"}:
+            packed = [b"I" + struct.pack(self.endian + "q", value) for value in values]
+            tags = {b"I"}
+        if len(tags) != 1:
+            raise TypeError("Elements sent to a subkernel must all have the same type")
+        tag = tags.pop()
+        if tag in (b"b", b"i", b"I", b"f"):
+            return tag + b"".join(p[1:] for p in packed)
+        return tag + b"".join(packed)
+
     def _read(self, length):
         r = bytes()
         while len(r) < length:
@@ -106,6 +238,8 @@ class CommMgmt:
     def _read_header(self):
         ty = Reply(*struct.unpack("B", self._read(1)))
         logger.debug("receiving message: type=%r", ty)
+        if ty == Reply.Unauthorized and self.auth_token is None:
+            raise AuthenticationError("Core device requires an authentication token")
 
         return ty
 
@@ -136,6 +270,22 @@ class CommMgmt:
         self._write_header(Request.ClearLog)
         self._read_expect(Reply.Success)
 
+    def get_satellite_log(self, destination, kernel_id=None):
+        self._write_header(Request.GetSatelliteLog)
+        self._write_int8(destination)
+        self._write_bool(kernel_id is not None)
+        if kernel_id is not None:
+            self._write_int32(kernel_id)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("Destination {} is not a reachable satellite".format(destination))
+        elif ty == Reply.Error:
+            raise IOError("Failed to retrieve satellite log. More information may be available in the log.")
+        elif ty != Reply.LogContent:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.LogContent))
+        return self._read_string()
+
     def pull_log(self):
         self._write_header(Request.PullLog)
         self._read_expect(Reply.LogContent)
@@ -157,6 +307,425 @@ class CommMgmt:
         self._write_int8(getattr(LogLevel, level).value)
         self._read_expect(Reply.Success)
 
+    def set_satellite_log_level(self, destination, target, level):
+        if target not in SatelliteLogTarget.__members__:
+            raise ValueError("invalid satellite log target {}".format(target))
+        if level not in LogLevel.__members__:
+            raise ValueError("invalid log level {}".format(level))
+
+        self._write_header(Request.SetSatelliteLogFilter)
+        self._write_int8(destination)
+        self._write_int8(getattr(SatelliteLogTarget, target).value)
+        self._write_int8(getattr(LogLevel, level).value)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("Destination {} is not a reachable satellite".format(destination))
+        elif ty == Reply.Error:
+            raise IOError("Satellite failed to change log level. More information may be available in the log.")
+        elif ty != Reply.Success:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.Success))
+
+    def set_satellite_log_rate_limit(self, destination, rate):
+        self._write_header(Request.SetSatelliteLogRateLimit)
+        self._write_int8(destination)
+        self._write_int32(rate)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("Destination {} is not a reachable satellite".format(destination))
+        elif ty == Reply.Error:
+            raise IOError("Satellite failed to change log rate limit. More information may be available in the log.")
+        elif ty != Reply.Success:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.Success))
+
+    def set_satellite_log_outputs(self, destination, uart, forward):
+        self._write_header(Request.SetSatelliteLogOutputs)
+        self._write_int8(destination)
+        self._write_bool(uart)
+        self._write_bool(forward)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("Destination {} is not a reachable satellite".format(destination))
+        elif ty == Reply.Error:
+            raise IOError("Satellite failed to change log outputs. More information may be available in the log.")
+        elif ty != Reply.Success:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.Success))
+
+    def _satellite_log_control(self, destination, action):
+        self._write_header(Request.SatelliteLogControl)
+        self._write_int8(destination)
+        self._write_int8(action.value)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("Destination {} is not a reachable satellite".format(destination))
+        elif ty == Reply.Error:
+            raise IOError("Satellite failed to {} kernel logging. More information may be available in the log.".
+                          format(action.name.lower()))
+        elif ty != Reply.Success:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.Success))
+
+    def pause_satellite_log(self, destination):
+        self._satellite_log_control(destination, SatelliteLogControl.PAUSE)
+
+    def resume_satellite_log(self, destination):
+        self._satellite_log_control(destination, SatelliteLogControl.RESUME)
+
+    def clear_satellite_log(self, destination):
+        self._satellite_log_control(destination, SatelliteLogControl.CLEAR)
+
+    def suppress_satellite_exception(self, destination, exception_id=None, prefix=""):
+        self._write_header(Request.SuppressSatelliteException)
+        self._write_int8(destination)
+        self._write_bool(exception_id is not None)
+        if exception_id is not None:
+            self._write_int32(exception_id)
+        self._write_string(prefix)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("Destination {} is not a reachable satellite".format(destination))
+        elif ty == Reply.Error:
+            raise IOError("Satellite failed to install suppression rule. More information may be available in the log.")
+        elif ty != Reply.Success:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.Success))
+
+    def get_satellite_suppressed_count(self, destination, clear=False):
+        self._write_header(Request.GetSatelliteSuppressedCount)
+        self._write_int8(destination)
+        self._write_bool(clear)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("Destination {} is not a reachable satellite".format(destination))
+        elif ty == Reply.Error:
+            raise IOError("Failed to retrieve suppressed exception count. More information may be available in the log.")
+        elif ty != Reply.SuppressedCount:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.SuppressedCount))
+        return self._read_int32()
+
+    def unsuppress_satellite_exceptions(self, destination):
+        self._write_header(Request.UnsuppressSatelliteExceptions)
+        self._write_int8(destination)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("Destination {} is not a reachable satellite".format(destination))
+        elif ty == Reply.Error:
+            raise IOError("Satellite failed to remove suppression rules. More information may be available in the log.")
+        elif ty != Reply.Success:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.Success))
+
+    # in the order sent by the satellite, see drtioaux_proto::MAILBOX_STATS_COUNT
+    MAILBOX_STATS_FIELDS = [
+        "received_log", "received_cache", "received_rpc", "received_dma",
+        "received_subkernel", "received_hwreq", "received_other",
+        "acknowledged", "sent", "send_wait_total_ms", "send_wait_max_us",
+        "acknowledged_early", "early_ack_saved_us"
+    ]
+
+    def get_satellite_mailbox_stats(self, destination, clear=False):
+        self._write_header(Request.GetSatelliteMailboxStats)
+        self._write_int8(destination)
+        self._write_bool(clear)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("Destination {} is not a reachable satellite".format(destination))
+        elif ty == Reply.Error:
+            raise IOError("Failed to retrieve mailbox statistics. More information may be available in the log.")
+        elif ty != Reply.MailboxStats:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.MailboxStats))
+        counters = [self._read_int32() for _ in range(self._read_int32())]
+        return dict(zip(self.MAILBOX_STATS_FIELDS, counters))
+
+    # in the order sent by the satellite, see drtioaux_proto::MESSAGE_STATS_COUNT
+    MESSAGE_STATS_FIELDS = [
+        "messages_sent", "messages_received", "rpcs_sent", "rpc_replies_received",
+        "bytes_serialized", "bytes_received", "slices_sent", "slices_received",
+        "service_time_total_ms", "service_time_max_us",
+        "link_time_total_ms", "link_time_max_us",
+        "pool_buffers_reused", "pool_exhausted", "pool_oversized"
+    ]
+
+    def get_satellite_message_stats(self, destination, clear=False):
+        self._write_header(Request.GetSatelliteMessageStats)
+        self._write_int8(destination)
+        self._write_bool(clear)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("Destination {} is not a reachable satellite".format(destination))
+        elif ty == Reply.Error:
+            raise IOError("Failed to retrieve message statistics. More information may be available in the log.")
+        elif ty != Reply.MessageStats:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.MessageStats))
+        counters = [self._read_int32() for _ in range(self._read_int32())]
+        return dict(zip(self.MESSAGE_STATS_FIELDS, counters))
+
+    def set_satellite_exception_replay(self, destination, enable):
+        self._write_header(Request.SetSatelliteExceptionReplay)
+        self._write_int8(destination)
+        self._write_bool(enable)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("Destination {} is not a reachable satellite".format(destination))
+        elif ty == Reply.Error:
+            raise IOError("Failed to set exception replay. More information may be available in the log.")
+        elif ty != Reply.Success:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.Success))
+
+    def set_satellite_comm_timeouts(self, destination, slot_ms=100, await_poll_ms=0):
+        self._write_header(Request.SetSatelliteCommTimeouts)
+        self._write_int8(destination)
+        self._write_int32(slot_ms)
+        self._write_int32(await_poll_ms)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("Destination {} is not a reachable satellite".format(destination))
+        elif ty == Reply.Error:
+            raise IOError("Satellite failed to change communication timeouts. More information may be available in the log.")
+        elif ty != Reply.Success:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.Success))
+
+    def inject_subkernel_message(self, destination, subkernel_id, *values):
+        """Passes a message to a subkernel running on a satellite, which receives
+        it with ``subkernel_recv`` like one from the main kernel, e.g. to adjust
+        the parameters of a long-running servo without stopping it.
+
+        The values must have the types the subkernel expects: integers are sent
+        as 32-bit unless they do not fit, use ``numpy.int64`` for 64-bit ones."""
+        self.open()
+        data = struct.pack("B", len(values)) + b"".join(self._pack_value(value) for value in values)
+        # return tag the device expects at the end of a message, see send_args
+        data += b"\x00" + struct.pack(self.endian + "l", 0)
+        self._write_header(Request.InjectSubkernelMessage)
+        self._write_int8(destination)
+        self._write_int32(subkernel_id)
+        self._write_bytes(data)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("Destination {} is not a reachable satellite".format(destination))
+        elif ty == Reply.Error:
+            raise IOError("Subkernel {} is not running on destination {}, or did not "
+                          "take the message".format(subkernel_id, destination))
+        elif ty != Reply.Success:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.Success))
+
+    def cancel_subkernel_messages(self, destination):
+        """Drop the subkernel messages still being received from and by the
+        satellite at ``destination``, e.g. after the sender went away. A kernel
+        waiting for one of them gets a ``SubkernelError`` instead."""
+        self._write_header(Request.CancelSubkernelMessages)
+        self._write_int8(destination)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("Destination {} is not a reachable satellite".format(destination))
+        elif ty == Reply.Error:
+            raise IOError("Failed to cancel subkernel messages. More information may be available in the log.")
+        elif ty != Reply.Success:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.Success))
+
+    BENCHMARK_RESULT_FIELDS = [
+        "mailbox_round_trip_ns", "cache_put_get_ns", "message_loopback_ns",
+        "message_throughput_kBps"
+    ]
+
+    def run_satellite_benchmark(self, destination, iterations=1000, message_size=1024):
+        """Run a benchmark kernel on the satellite at ``destination``, replacing
+        any subkernel loaded there, and return the averaged time of a mailbox
+        round trip, of a cache put and get and of a subkernel message looped
+        back through the firmware, along with the message throughput."""
+        self._write_header(Request.RunSatelliteBenchmark)
+        self._write_int8(destination)
+        self._write_int32(iterations)
+        self._write_int32(message_size)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("Destination {} is not a reachable satellite".format(destination))
+        elif ty == Reply.Error:
+            raise IOError("Failed to run the benchmark. More information may be available in the log.")
+        elif ty != Reply.BenchmarkResults:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.BenchmarkResults))
+        results = [self._read_int32() for _ in range(self._read_int32())]
+        return dict(zip(self.BENCHMARK_RESULT_FIELDS, results))
+
+    LOOP_STATS_FIELDS = [
+        "{}_{}".format(section, field)
+        for section in ["kernel", "aux", "iteration"]
+        for field in ["count", "median_us", "p99_us", "max_us"]
+    ]
+
+    def get_satellite_loop_stats(self, destination, clear=False):
+        """Return how long the satellite at ``destination`` spends in passes of
+        its main loop, and in processing kernel messages and aux packets
+        within them. Times are upper bounds of power-of-two buckets."""
+        self._write_header(Request.GetSatelliteLoopStats)
+        self._write_int8(destination)
+        self._write_bool(clear)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("Destination {} is not a reachable satellite".format(destination))
+        elif ty == Reply.Error:
+            raise IOError("Failed to retrieve main loop statistics. More information may be available in the log.")
+        elif ty != Reply.LoopStats:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.LoopStats))
+        counters = [self._read_int32() for _ in range(self._read_int32())]
+        return dict(zip(self.LOOP_STATS_FIELDS, counters))
+
+    def set_satellite_work_budget(self, destination, budget_us):
+        """Bound the time the satellite at ``destination`` spends on subkernel
+        work in each pass of its main loop, so that DRTIO aux requests are
+        answered within about that much. Messages are then passed to subkernels
+        a few values at a time and exceptions are stored uncompressed.
+        0 lifts the bound."""
+        self._write_header(Request.SetSatelliteWorkBudget)
+        self._write_int8(destination)
+        self._write_int32(budget_us)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("Destination {} is not a reachable satellite".format(destination))
+        elif ty == Reply.Error:
+            raise IOError("Failed to set the work budget. More information may be available in the log.")
+        elif ty != Reply.Success:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.Success))
+
+    LINK_ERROR_FIELDS = [
+        "received", "crc_errors", "gateware_errors", "timeouts", "retries",
+        "unsolicited"
+    ]
+
+    def get_link_errors(self, destination, clear=False):
+        """Return the DRTIO aux error counters of the link towards
+        ``destination``, as kept by the master and by the satellite at the
+        other end, to spot links that degrade before subkernels fail."""
+        self._write_header(Request.GetLinkErrors)
+        self._write_int8(destination)
+        self._write_bool(clear)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("Destination {} is not a reachable satellite".format(destination))
+        elif ty == Reply.Error:
+            raise IOError("Failed to retrieve link error counters. More information may be available in the log.")
+        elif ty != Reply.LinkErrors:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.LinkErrors))
+        counters = [self._read_int32() for _ in range(self._read_int32())]
+        count = len(self.LINK_ERROR_FIELDS)
+        return {
+            "master": dict(zip(self.LINK_ERROR_FIELDS, counters[:count])),
+            "satellite": dict(zip(self.LINK_ERROR_FIELDS, counters[count:]))
+        }
+
+    def measure_aux_latency(self, destination, count=100):
+        """Time ``count`` DRTIO aux round trips from the master to
+        ``destination`` and return the shortest, average and longest, in us.
+        This is a lower bound for the timeouts given to ``await_finish`` and
+        ``message_await`` on subkernels there. The master keeps the last
+        measurement, a ``count`` of 0 returns it without measuring again."""
+        self._write_header(Request.MeasureAuxLatency)
+        self._write_int8(destination)
+        self._write_int32(count)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("Destination {} is not a reachable satellite".format(destination))
+        elif ty == Reply.Error:
+            raise IOError("Failed to measure aux latency. More information may be available in the log.")
+        elif ty != Reply.AuxLatency:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.AuxLatency))
+        latency = [self._read_int32() for _ in range(self._read_int32())]
+        return dict(zip(["min_us", "avg_us", "max_us"], latency))
+
+    def reload_routing_table(self):
+        """Read the ``routing_table`` config key again on the master and send
+        it to the satellites. It takes effect once no kernel is running; until
+        then, subkernels on destinations whose route changes fail to upload,
+        load or receive messages, and those running are reported as having
+        lost communication."""
+        self._write_header(Request.ReloadRoutingTable)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("Core device does not support DRTIO")
+        elif ty != Reply.Success:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.Success))
+
+    def set_analyzer_capture(self, destination, enable):
+        """Let the satellite at ``destination`` capture RTIO analyzer data
+        only while subkernels run. Each run then clears the analyzer buffer
+        and the data of the last run stays available, to be read with the
+        rest of the analyzer data, until the next one starts. When disabled,
+        the satellite records continuously as the core device does."""
+        self._write_header(Request.SetAnalyzerCapture)
+        self._write_int8(destination)
+        self._write_bool(enable)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("Destination {} is not a reachable satellite".format(destination))
+        elif ty == Reply.Error:
+            raise IOError("Failed to set analyzer capture. More information may be available in the log.")
+        elif ty != Reply.Success:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.Success))
+
+    SUBKERNEL_STATS_FIELDS = [
+        "session", "uploaded", "upload_skipped", "upload_bytes", "runs",
+        "finished", "average_run_us", "messages_sent", "messages_received",
+        "failures"
+    ]
+
+    def get_subkernel_stats(self, clear=False):
+        """Return what the subkernels of the current host session did, or
+        those of the last session if none is connected: uploads, runs and
+        their average duration, messages exchanged with the main kernel and
+        failures (exceptions, lost communication and failed uploads)."""
+        self._write_header(Request.GetSubkernelStats)
+        self._write_bool(clear)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("Core device does not support DRTIO")
+        elif ty == Reply.Error:
+            raise IOError("Failed to retrieve subkernel statistics. More information may be available in the log.")
+        elif ty != Reply.SubkernelStats:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.SubkernelStats))
+        counters = [self._read_int32() for _ in range(self._read_int32())]
+        return dict(zip(self.SUBKERNEL_STATS_FIELDS, counters))
+
+    MEMORY_STATS_FIELDS = [
+        "heap_used", "heap_free", "heap_largest_free", "kernel_library",
+        "kernel_footprint", "kernel_stack_headroom"
+    ]
+
+    def get_satellite_memory_stats(self, destination):
+        """Return the memory use, in bytes, of the satellite at ``destination``:
+        its heap, used and free, and the largest block that can still be
+        allocated; the library of its current kernel, as uploaded and as laid
+        out in the kernel CPU memory, and an estimate of the space left from
+        there for the kernel stack."""
+        self._write_header(Request.GetSatelliteMemoryStats)
+        self._write_int8(destination)
+        ty = self._read_header()
+        if ty == Reply.Unavailable:
+            raise IOError("Destination {} is not a reachable satellite".format(destination))
+        elif ty == Reply.Error:
+            raise IOError("Failed to retrieve memory statistics. More information may be available in the log.")
+        elif ty != Reply.MemoryStats:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.MemoryStats))
+        counters = [self._read_int32() for _ in range(self._read_int32())]
+        return dict(zip(self.MEMORY_STATS_FIELDS, counters))
+
     def config_read(self, key):
         self._write_header(Request.ConfigRead)
         self._write_string(key)
@@ -5,7 +5,7 @@ from enum import Enum
 
 from sipyco.keepalive import async_open_connection
 
-__all__ = ["TTLProbe", "TTLOverride", "CommMonInj"]
+__all__ = ["TTLProbe", "TTLOverride", "SubkernelState", "CommMonInj"]
 
 
 logger = logging.getLogger(__name__)
@@ -22,11 +22,31 @@ class TTLOverride(Enum):
     oe = 2
 
 
+class SubkernelState(Enum):
+    not_loaded = 0
+    uploaded = 1
+    running = 2
+    finished = 3
+    comm_lost = 4
+    exception = 5
+    removed = 6
+
+
 class CommMonInj:
-    def __init__(self, monitor_cb, injection_status_cb, disconnect_cb=None):
+    """Connection to the moninj port of the core device.
+
+    Once :meth:`monitor_subkernels` is enabled, ``subkernel_cb(id,
+    destination, state)`` is called as the subkernels of the session on the
+    core device change state, ``state`` being a :class:`SubkernelState`, and
+    ``destination_cb(destination, up)`` as DRTIO destinations go up or down.
+    """
+    def __init__(self, monitor_cb, injection_status_cb, disconnect_cb=None,
+                 subkernel_cb=None, destination_cb=None):
         self.monitor_cb = monitor_cb
         self.injection_status_cb = injection_status_cb
         self.disconnect_cb = disconnect_cb
+        self.subkernel_cb = subkernel_cb
+        self.destination_cb = destination_cb
 
     async def connect(self, host, port=1383):
         self._reader, self._writer = await async_open_connection(
@@ -78,6 +98,10 @@ class CommMonInj:
         packet = struct.pack("<blb", 2, channel, override)
         self._writer.write(packet)
 
+    def monitor_subkernels(self, enable):
+        packet = struct.pack("<bb", 4, enable)
+        self._writer.write(packet)
+
     async def _receive_cr(self):
         try:
             while True:
@@ -92,6 +116,16 @@ class CommMonInj:
                     payload = await self._reader.readexactly(6)
                     channel, override, value = struct.unpack("<lbb", payload)
                     self.injection_status_cb(channel, override, value)
+                elif ty == b"\x02":
+                    payload = await self._reader.readexactly(6)
+                    id, destination, state = struct.unpack("<LBB", payload)
+                    if self.subkernel_cb is not None:
+                        self.subkernel_cb(id, destination, SubkernelState(state))
+                elif ty == b"\x03":
+                    payload = await self._reader.readexactly(2)
+                    destination, up = struct.unpack("<B?", payload)
+                    if self.destination_cb is not None:
+                        self.destination_cb(destination, up)
                 else:
                     raise ValueError("Unknown packet type", ty)
         except asyncio.CancelledError:
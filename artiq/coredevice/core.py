@@ -53,6 +53,26 @@ def rtio_get_destination_status(linkno: TInt32) -> TBool:
 def rtio_get_counter() -> TInt64:
     raise NotImplementedError("syscall not simulated")
 
+@syscall
+def subkernel_replay_exception() -> TNone:
+    raise NotImplementedError("syscall not simulated")
+
+@syscall(flags={"nowrite"})
+def master_time() -> TTuple([TInt64, TInt64]):
+    raise NotImplementedError("syscall not simulated")
+
+@syscall(flags={"nounwind", "nowrite"})
+def subkernel_message_time() -> TInt64:
+    raise NotImplementedError("syscall not simulated")
+
+@syscall(flags={"nowrite"})
+def moninj_read_probe(channel: TInt32, probe: TInt32) -> TInt64:
+    raise NotImplementedError("syscall not simulated")
+
+@syscall(flags={"nowrite"})
+def moninj_read_injection_status(channel: TInt32, overrd: TInt32) -> TInt32:
+    raise NotImplementedError("syscall not simulated")
+
 
 def get_target_cls(target):
     if target == "rv32g":
@@ -78,13 +98,16 @@ class Core:
     :param ref_multiplier: ratio between the RTIO fine timestamp frequency
         and the RTIO coarse timestamp frequency (e.g. SERDES multiplication
         factor).
+    :param auth_token: token presented to the core device, required when its
+        ``auth_token`` configuration key is set.
     """
 
     kernel_invariants = {
         "core", "ref_period", "coarse_ref_period", "ref_multiplier",
     }
 
-    def __init__(self, dmgr, host, ref_period, ref_multiplier=8, target="rv32g"):
+    def __init__(self, dmgr, host, ref_period, ref_multiplier=8, target="rv32g",
+                 auth_token=None):
         self.ref_period = ref_period
         self.ref_multiplier = ref_multiplier
         self.target_cls = get_target_cls(target)
@@ -92,7 +115,7 @@ class Core:
         if host is None:
             self.comm = CommKernelDummy()
         else:
-            self.comm = CommKernel(host)
+            self.comm = CommKernel(host, auth_token=auth_token)
 
         self.first_run = True
         self.dmgr = dmgr
@@ -102,15 +125,51 @@ class Core:
     def close(self):
         self.comm.close()
 
+    def set_watchdog(self, timeout):
+        """Bound the time, in seconds, that each kernel run afterwards may
+        take, time spent awaiting subkernels and their messages included. When
+        a kernel overruns it, the core device stops it and the subkernels it
+        started on satellites, and
+        :class:`artiq.coredevice.exceptions.WatchdogExpired` is raised.
+        ``None`` lifts the bound."""
+        self.comm.set_watchdog(timeout)
+
+    def set_heartbeat(self, timeout):
+        """Have the core device end the session when the host stays silent
+        for longer than ``timeout`` seconds while the core device waits on it,
+        e.g. for an RPC reply or the next kernel. It then stops the subkernels
+        of the session and drops their uploads and messages, rather than let a
+        hung host hold its satellites. Any request from the host counts, and
+        :meth:`heartbeat` lets long host computations between kernels keep the
+        session. ``None`` lifts the bound."""
+        self.comm.set_heartbeat(timeout)
+
+    def heartbeat(self):
+        """Keep the session, see :meth:`set_heartbeat`."""
+        self.comm.heartbeat()
+
+    def reattach(self):
+        """Connect to the core device again after the connection to it was
+        lost, and take back the subkernels the kernel running then started.
+        The core device keeps them running, and their results, for a few
+        seconds after the connection is lost; the kernel itself is ended.
+        A kernel run afterwards may then await them.
+
+        Return whether the subkernels were taken back."""
+        self.comm.close()
+        return self.comm.attach_session()
+
     def compile(self, function, args, kwargs, set_result=None,
                 attribute_writeback=True, print_as_rpc=True,
-                target=None, destination=0, subkernel_arg_types=[]):
+                target=None, destination=0, subkernel_arg_types=[],
+                first_object_key=0):
         try:
             engine = _DiagnosticEngine(all_errors_are_fatal=True)
 
             stitcher = Stitcher(engine=engine, core=self, dmgr=self.dmgr,
                                 print_as_rpc=print_as_rpc,
-                                destination=destination, subkernel_arg_types=subkernel_arg_types)
+                                destination=destination, subkernel_arg_types=subkernel_arg_types,
+                                first_object_key=first_object_key)
             stitcher.stitch_call(function, args, kwargs, set_result)
             stitcher.finalize()
 
@@ -132,6 +191,7 @@ class Core:
     def _run_compiled(self, kernel_library, embedding_map, symbolizer, demangler):
         if self.first_run:
             self.comm.check_system_info()
+            self.comm.attach_session()
             self.first_run = False
         self.comm.load(kernel_library)
         self.comm.run()
@@ -149,7 +209,13 @@ class Core:
         self._run_compiled(kernel_library, embedding_map, symbolizer, demangler)
         return result
 
-    def compile_subkernels(self, embedding_map, args, subkernel_arg_types):
+    def compile_subkernels(self, embedding_map, args, subkernel_arg_types, staged=False):
+        subkernels = self._compile_subkernels(embedding_map, args, subkernel_arg_types)
+        if subkernels:
+            self.comm.upload_subkernels(subkernels, staged=staged)
+
+    def _compile_subkernels(self, embedding_map, args, subkernel_arg_types):
+        subkernels = []
         for sid, subkernel_fn in embedding_map.subkernels().items():
             # pass self to subkernels (if applicable)
             # assuming the first argument is self
@@ -164,10 +230,46 @@ class Core:
             object_map, kernel_library, _, _, _ = \
                 self.compile(subkernel_fn, self_arg, {}, attribute_writeback=False,
                             print_as_rpc=False, target=target, destination=destination, 
-                            subkernel_arg_types=subkernel_arg_types.get(sid, []))
-            if object_map.has_rpc_or_subkernel():
-                raise ValueError("Subkernel must not use RPC or subkernels in other destinations")
-            self.comm.upload_subkernel(kernel_library, sid, destination)
+                            subkernel_arg_types=subkernel_arg_types.get(sid, []),
+                            first_object_key=embedding_map.object_current_key)
+            if object_map.subkernels():
+                raise ValueError("Subkernel must not use subkernels in other destinations")
+            # RPCs of the subkernel are forwarded by the master and served
+            # alongside those of the main kernel
+            embedding_map.merge_objects(object_map)
+            persistent = "persistent" in subkernel_fn.artiq_embedded.flags
+            subkernels.append((kernel_library, sid, destination,
+                               target.symbol_table(kernel_library), persistent))
+        return subkernels
+
+    def queue_experiment(self, function, *args, **kwargs):
+        """Compile a kernel, with its arguments, and leave it to the core device
+        to run once no host is connected, after the kernels queued before it.
+        This lets a scan proceed while the connection to the host is lost.
+
+        Queued kernels run without a host: they cannot use RPCs, their return
+        value is discarded, and object attributes are not written back. Their
+        exceptions are only logged by the core device. Each runs under the
+        watchdog set with :meth:`set_watchdog` when it was queued.
+
+        Return the number of kernels queued on the core device.
+        """
+        if not hasattr(function, "artiq_embedded"):
+            raise ValueError("Argument is not a kernel")
+
+        embedding_map, kernel_library, _, _, subkernel_arg_types = \
+            self.compile(function, args, kwargs, attribute_writeback=False,
+                         print_as_rpc=False)
+        subkernels = self._compile_subkernels(embedding_map, args, subkernel_arg_types)
+        if self.first_run:
+            self.comm.check_system_info()
+            self.first_run = False
+        return self.comm.queue_experiment(kernel_library, subkernels)
+
+    def clear_experiments(self):
+        """Drop the kernels queued with :meth:`queue_experiment` that have
+        not started running yet."""
+        self.comm.clear_experiments()
 
     def precompile(self, function, *args, **kwargs):
         """Precompile a kernel and return a callable that executes it on the core device
@@ -193,6 +295,22 @@ class Core:
 
         The callable may be called several times.
         """
+        return self._precompile(function, args, kwargs, staged=False)
+
+    def precompile_staged(self, function, *args, **kwargs):
+        """Like :meth:`precompile`, but the subkernels of the kernel are sent to
+        their satellites in the background, so that the call returns before they
+        are all uploaded.
+
+        This may be called while another kernel runs, e.g. from one of its
+        synchronous RPCs, to prepare the next kernel without waiting for its
+        subkernels between the two. The IDs of the new subkernels must then
+        differ from those of the subkernels of the running kernel.
+        Running the returned callable waits for any upload still in progress.
+        """
+        return self._precompile(function, args, kwargs, staged=True)
+
+    def _precompile(self, function, args, kwargs, staged):
         if not hasattr(function, "artiq_embedded"):
             raise ValueError("Argument is not a kernel")
 
@@ -204,7 +322,7 @@ class Core:
 
         embedding_map, kernel_library, symbolizer, demangler, subkernel_arg_types = \
             self.compile(function, args, kwargs, set_result, attribute_writeback=False)
-        self.compile_subkernels(embedding_map, args, subkernel_arg_types)
+        self.compile_subkernels(embedding_map, args, subkernel_arg_types, staged=staged)
 
         @wraps(function)
         def run_precompiled():
@@ -244,6 +362,61 @@ class Core:
         """
         return rtio_get_counter()
 
+    @kernel
+    def replay_subkernel_exception(self):
+        """Raise again the last exception stored on the satellite running
+        this subkernel, so that it can be inspected or handled on the device.
+
+        Replay must be enabled with ``artiq_coremgmt subkernel replay``.
+        Does nothing if it is disabled or no exception was stored.
+        """
+        subkernel_replay_exception()
+
+    @kernel
+    def get_master_time(self):
+        """Return the time of the master clock, in microseconds since the
+        master started, with the RTIO counter (see :meth:`get_rtio_counter_mu`)
+        at the same instant, so that subkernels on different satellites can
+        timestamp their results in a common timebase.
+
+        Satellites estimate the master clock from updates the master sends
+        every second, the estimate is accurate to the variation of the aux latency.
+        """
+        return master_time()
+
+    @kernel
+    def get_subkernel_message_time(self):
+        """Return the time of the master clock, in microseconds (see
+        :meth:`get_master_time`), when the last message received from a
+        subkernel was sent, so that messages from subkernels on different
+        satellites can be put in the order they were sent.
+
+        Satellites stamp the messages they send to the master with their own
+        clock, which the master converts with the offset and drift it measures
+        at each master time update. The time is 0 when the satellite has not
+        taken an update yet, and for messages received by subkernels.
+        """
+        return subkernel_message_time()
+
+    @kernel
+    def read_monitor(self, channel, probe):
+        """Return the value of a monitoring probe of an RTIO channel of
+        this device, as shown by the dashboard, without an input event.
+        On a satellite, a subkernel reads the channels of its own satellite.
+
+        The probes depend on the PHY, e.g. probe 0 of a TTL is its level.
+        """
+        return moninj_read_probe(channel, probe)
+
+    @kernel
+    def read_injection_status(self, channel, overrd):
+        """Return the value of an injection override of an RTIO channel of
+        this device, e.g. for a TTL, 0 whether it is overridden, 1 the
+        overriding level and 2 whether the output is enabled.
+        On a satellite, a subkernel reads the channels of its own satellite.
+        """
+        return moninj_read_injection_status(channel, overrd)
+
     @kernel
     def wait_until_mu(self, cursor_mu):
         """Block execution until the hardware RTIO counter reaches the given
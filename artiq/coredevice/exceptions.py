@@ -14,13 +14,23 @@ RuntimeError = builtins.RuntimeError
 AssertionError = builtins.AssertionError
 
 
+# severity the core device assigns to an exception, from the type of the
+# first one raised; lets tooling tell I/O hiccups from kernel logic errors
+SEVERITY_FATAL = 0
+SEVERITY_RECOVERABLE = 1
+SEVERITY_WARNING = 2
+
+
 class CoreException:
     """Information about an exception raised or passed through the core device."""
-    def __init__(self, exceptions, exception_info, traceback, stack_pointers):
+    def __init__(self, exceptions, exception_info, traceback, stack_pointers,
+                 kernel_log="", severity=SEVERITY_FATAL):
         self.exceptions = exceptions
         self.exception_info = exception_info
         self.traceback = list(traceback)
         self.stack_pointers = stack_pointers
+        self.kernel_log = kernel_log
+        self.severity = severity
 
         first_exception = exceptions[0]
         name = first_exception[0]
@@ -101,8 +111,12 @@ class CoreException:
         tracebacks = [self.single_traceback(i) for i in range(len(self.exceptions))]
         traceback_str = ('\n\nDuring handling of the above exception, ' +
                         'another exception occurred:\n\n').join(tracebacks)
+        log_str = ''
+        if self.kernel_log:
+            log_str = '\n\nLast subkernel log lines:\n' + self.kernel_log
         return 'Core Device Traceback:\n' +\
                 traceback_str +\
+                log_str +\
                 '\n\nEnd of Core Device Traceback\n'
 
 
@@ -151,14 +165,41 @@ class DMAError(Exception):
 class SubkernelError(Exception):
     """Raised when an operation regarding a subkernel is invalid 
     or cannot be completed.
+
+    When raised by a satellite for a runtime failure, the first entry of
+    ``artiq_core_exception.params`` holds one of the ``CODE_*`` values below
+    and the second one the subkernel id. For ``CODE_MALFORMED_MESSAGE``, the
+    third one is the offset of the first byte that could not be decoded.
     """
     artiq_builtin = True
 
+    CODE_LOAD_FAILED = 1
+    CODE_KERNEL_NOT_FOUND = 2
+    CODE_INVALID_POINTER = 3
+    CODE_UNEXPECTED = 4
+    CODE_NO_MESSAGE = 5
+    CODE_AWAITING_MESSAGE = 6
+    CODE_IO_ERROR = 7
+    CODE_KERNEL_EXCEPTION = 8
+    CODE_KERNEL_CPU_PANIC = 9
+    CODE_KERNEL_CPU_HANG = 10
+    CODE_STOPPED = 11
+    CODE_LOAD_TIMEOUT = 12
+    CODE_RPC_EXCEPTION = 13
+    CODE_MALFORMED_MESSAGE = 14
+    CODE_RECEIVE_ABORTED = 15
+
 
 class ClockFailure(Exception):
     """Raised when RTIO PLL has lost lock."""
 
 
+class WatchdogExpired(Exception):
+    """Raised when a kernel runs for longer than the watchdog set with
+    :meth:`artiq.coredevice.core.Core.set_watchdog` allows. The core device
+    stops the kernel and the subkernels it started."""
+
+
 class I2CError(Exception):
     """Raised when a I2C transaction fails."""
     pass
@@ -139,6 +139,7 @@ static mut API: &'static [(&'static str, *const ())] = &[
 
     api!(cache_get = ::cache_get),
     api!(cache_put = ::cache_put),
+    api!(cache_get_many = ::cache_get_many),
 
     /* direct syscalls */
     api!(rtio_init = ::rtio::init),
@@ -150,6 +151,7 @@ static mut API: &'static [(&'static str, *const ())] = &[
     api!(rtio_input_timestamp = ::rtio::input_timestamp),
     api!(rtio_input_data = ::rtio::input_data),
     api!(rtio_input_timestamped_data = ::rtio::input_timestamped_data),
+    api!(master_time = ::master_time),
 
     api!(dma_record_start = ::dma_record_start),
     api!(dma_record_stop = ::dma_record_stop),
@@ -159,8 +161,11 @@ static mut API: &'static [(&'static str, *const ())] = &[
 
     api!(subkernel_load_run = ::subkernel_load_run),
     api!(subkernel_send_message = ::subkernel_send_message),
+    api!(subkernel_send_message_async = ::subkernel_send_message_async),
     api!(subkernel_await_message = ::subkernel_await_message),
+    api!(subkernel_message_time = ::subkernel_message_time),
     api!(subkernel_await_finish = ::subkernel_await_finish),
+    api!(subkernel_replay_exception = ::subkernel_replay_exception),
 
     api!(i2c_start = ::nrt_bus::i2c::start),
     api!(i2c_restart = ::nrt_bus::i2c::restart),
@@ -172,4 +177,9 @@ static mut API: &'static [(&'static str, *const ())] = &[
     api!(spi_set_config = ::nrt_bus::spi::set_config),
     api!(spi_write = ::nrt_bus::spi::write),
     api!(spi_read = ::nrt_bus::spi::read),
+
+    api!(hwreq_inject_errors = ::nrt_bus::hwreq::inject_errors),
+
+    api!(moninj_read_probe = ::nrt_bus::moninj::read_probe),
+    api!(moninj_read_injection_status = ::nrt_bus::moninj::read_injection_status),
 ];
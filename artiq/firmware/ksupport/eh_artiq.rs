@@ -39,6 +39,8 @@ const EXCEPTION_CLASS: uw::_Unwind_Exception_Class = 0x4d_4c_42_53_41_52_54_51;
 
 const MAX_INFLIGHT_EXCEPTIONS: usize = 10;
 const MAX_BACKTRACE_SIZE: usize = 128;
+// words copied from the top of the stack of the frame that raised the exception
+const STACK_SNAPSHOT_SIZE: usize = 16;
 
 struct ExceptionBuffer {
     // we need n _Unwind_Exception, because each will have their own private data
@@ -53,6 +55,10 @@ struct ExceptionBuffer {
     stack_pointers: [StackPointerBacktrace; MAX_INFLIGHT_EXCEPTIONS + 1],
     // current allocated nested exceptions
     exception_count: usize,
+    // register and stack contents of the innermost kernel frame of the last raise,
+    // still useful when the backtrace itself is corrupt
+    registers: [usize; REGISTER_COUNT],
+    stack_snapshot: [usize; STACK_SNAPSHOT_SIZE],
 }
 
 static mut EXCEPTION_BUFFER: ExceptionBuffer = ExceptionBuffer {
@@ -70,7 +76,9 @@ static mut EXCEPTION_BUFFER: ExceptionBuffer = ExceptionBuffer {
         initial_backtrace_size: 0,
         current_backtrace_size: 0
     }; MAX_INFLIGHT_EXCEPTIONS + 1],
-    exception_count: 0
+    exception_count: 0,
+    registers: [0; REGISTER_COUNT],
+    stack_snapshot: [0; STACK_SNAPSHOT_SIZE],
 };
 
 pub unsafe extern fn reset_exception_buffer(payload_addr: usize) {
@@ -83,6 +91,8 @@ pub unsafe extern fn reset_exception_buffer(payload_addr: usize) {
     EXCEPTION_BUFFER.exception_stack = [-1; MAX_INFLIGHT_EXCEPTIONS + 1];
     EXCEPTION_BUFFER.backtrace_size = 0;
     EXCEPTION_BUFFER.exception_count = 0;
+    EXCEPTION_BUFFER.registers = [0; REGISTER_COUNT];
+    EXCEPTION_BUFFER.stack_snapshot = [0; STACK_SNAPSHOT_SIZE];
     PAYLOAD_ADDRESS = payload_addr;
 }
 
@@ -92,11 +102,15 @@ const UNWIND_DATA_REG: (i32, i32) = (0, 1); // RAX, RDX
 // actually this is not the SP, but frame pointer
 // but it serves its purpose, and getting SP will somehow cause segfault...
 const UNW_FP_REG: c_int = 12;
+#[cfg(target_arch = "x86_64")]
+const REGISTER_COUNT: usize = 16;
 
 #[cfg(any(target_arch = "riscv32"))]
 const UNWIND_DATA_REG: (i32, i32) = (10, 11); // X10, X11
 #[cfg(any(target_arch = "riscv32"))]
 const UNW_FP_REG: c_int = 2;
+#[cfg(any(target_arch = "riscv32"))]
+const REGISTER_COUNT: usize = 32;
 
 #[export_name="__artiq_personality"]
 pub extern fn personality(version: c_int,
@@ -297,7 +311,9 @@ fn uncaught_exception() -> ! {
         ::terminate(
             EXCEPTION_BUFFER.exceptions[..EXCEPTION_BUFFER.exception_count].as_ref(),
             EXCEPTION_BUFFER.stack_pointers[..EXCEPTION_BUFFER.exception_count].as_ref(),
-            EXCEPTION_BUFFER.backtrace[..EXCEPTION_BUFFER.backtrace_size].as_mut())
+            EXCEPTION_BUFFER.backtrace[..EXCEPTION_BUFFER.backtrace_size].as_mut(),
+            EXCEPTION_BUFFER.registers.as_ref(),
+            EXCEPTION_BUFFER.stack_snapshot.as_ref())
     }
 }
 
@@ -321,6 +337,10 @@ extern fn stop_fn(_version: c_int,
                 let last_index = EXCEPTION_BUFFER.exception_stack[EXCEPTION_BUFFER.exception_count - 1];
                 assert!(last_index != -1);
                 let sp_info = &mut EXCEPTION_BUFFER.stack_pointers[last_index as usize];
+                if sp_info.stack_pointer == 0 {
+                    // first kernel frame unwound for this raise
+                    snapshot_frame(context, fp);
+                }
                 sp_info.stack_pointer = fp;
                 sp_info.current_backtrace_size = backtrace_size + 1;
             }
@@ -333,7 +353,17 @@ extern fn stop_fn(_version: c_int,
     }
 }
 
-static EXCEPTION_ID_LOOKUP: [(&str, u32); 12] = [
+unsafe fn snapshot_frame(context: *mut uw::_Unwind_Context, sp: usize) {
+    for (index, register) in EXCEPTION_BUFFER.registers.iter_mut().enumerate() {
+        *register = uw::_Unwind_GetGR(context, index as c_int);
+    }
+    let stack = sp as *const usize;
+    for (index, word) in EXCEPTION_BUFFER.stack_snapshot.iter_mut().enumerate() {
+        *word = core::ptr::read_volatile(stack.add(index));
+    }
+}
+
+pub static EXCEPTION_ID_LOOKUP: [(&str, u32); 12] = [
     ("RuntimeError", 0),
     ("RTIOUnderflow", 1),
     ("RTIOOverflow", 2),
@@ -15,27 +15,67 @@ extern crate proto_artiq;
 extern crate riscv;
 
 use core::{mem, ptr, slice, str, convert::TryFrom};
-use cslice::CSlice;
+use cslice::{CSlice, CMutSlice};
 use io::Cursor;
 use dyld::Library;
-use board_artiq::{mailbox, rpc_queue};
+use board_artiq::{mailbox, mailbox_queue, kern_log_ring, rpc_queue};
 use proto_artiq::{kernel_proto, rpc_proto};
 use kernel_proto::*;
 use board_misoc::csr;
 use riscv::register::{mcause, mepc, mtval};
 
+static mut ENVELOPE: Envelope = Envelope { magic: 0, length: 0, message: ptr::null() };
+
 fn send(request: &Message) {
-    unsafe { mailbox::send(request as *const _ as usize) }
+    check_stop();
+    unsafe {
+        ENVELOPE.message = request as *const Message as *const Message<'static>;
+        ENVELOPE.length = mem::size_of::<Message>();
+        ptr::write_volatile(&mut ENVELOPE.magic, MESSAGE_MAGIC);
+        mailbox::send(&ENVELOPE as *const _ as usize)
+    }
     while !mailbox::acknowledged() {}
+    unsafe { ptr::write_volatile(&mut ENVELOPE.magic, 0) }
+}
+
+// Sends a request that needs no reply and refers to no data without waiting for
+// the comms CPU to take it, unless the message queue is full.
+fn post(request: &Message<'static>) {
+    check_stop();
+    let posted = mailbox_queue::post(|slot| {
+        let length = mem::size_of::<Message>();
+        if length > slot.len() {
+            return None
+        }
+        unsafe {
+            ptr::copy_nonoverlapping(request as *const Message as *const u8, slot.as_mut_ptr(), length);
+        }
+        Some(slot.as_ptr() as usize)
+    });
+    if !posted {
+        send(request)
+    }
 }
 
 fn recv<R, F: FnOnce(&Message) -> R>(f: F) -> R {
-    while mailbox::receive() == 0 {}
+    while mailbox::receive() == 0 {
+        check_stop();
+    }
     let result = f(unsafe { &*(mailbox::receive() as *const Message) });
     mailbox::acknowledge();
     result
 }
 
+/// Terminates the kernel if the comms CPU asked for it. Called at safe points,
+/// i.e. around mailbox exchanges and while blocked on RTIO input.
+pub fn check_stop() {
+    if mailbox::stop_requested() {
+        mailbox::clear_stop();
+        send(&RunStopped);
+        loop {}
+    }
+}
+
 macro_rules! recv {
     ($p:pat => $e:expr) => {
         recv(move |request| {
@@ -49,9 +89,50 @@ macro_rules! recv {
     }
 }
 
+static mut PANIC_MESSAGE: [u8; 256] = [0; 256];
+
+struct PanicMessageWriter {
+    length: usize
+}
+
+impl core::fmt::Write for PanicMessageWriter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        // silently truncated, the record is best effort
+        unsafe {
+            let count = core::cmp::min(PANIC_MESSAGE.len() - self.length, s.len());
+            PANIC_MESSAGE[self.length..self.length + count].copy_from_slice(&s.as_bytes()[..count]);
+            self.length += count;
+        }
+        Ok(())
+    }
+}
+
+fn record_panic(info: &core::panic::PanicInfo) {
+    use core::fmt::Write;
+
+    let mut writer = PanicMessageWriter { length: 0 };
+    let _ = match info.location() {
+        Some(location) => write!(writer, "panic at {}:{}:{}",
+                                 location.file(), location.line(), location.column()),
+        None => write!(writer, "panic at unknown location")
+    };
+    if let Some(message) = info.message() {
+        let _ = write!(writer, ": {}", message);
+    }
+    unsafe {
+        let record = KERNELCPU_PANIC_ADDRESS as *mut PanicRecord;
+        ptr::write_volatile(&mut (*record).message, PANIC_MESSAGE.as_ptr());
+        ptr::write_volatile(&mut (*record).length, writer.length);
+        ptr::write_volatile(&mut (*record).magic, KERNELCPU_PANIC_MAGIC);
+    }
+    board_misoc::cache::flush_cpu_dcache();
+}
+
 #[no_mangle] // https://github.com/rust-lang/rust/issues/{38281,51647}
 #[panic_handler]
 pub fn panic_fmt(info: &core::panic::PanicInfo) -> ! {
+    // recorded first, sending may never complete if the mailbox is stuck
+    record_panic(info);
     if let Some(location) = info.location() {
         send(&Log(format_args!("panic at {}:{}:{}",
                                location.file(), location.line(), location.column())));
@@ -68,7 +149,7 @@ pub fn panic_fmt(info: &core::panic::PanicInfo) -> ! {
 }
 
 macro_rules! print {
-    ($($arg:tt)*) => ($crate::send(&$crate::kernel_proto::Log(format_args!($($arg)*))));
+    ($($arg:tt)*) => ($crate::send_log_fmt(format_args!($($arg)*)));
 }
 
 macro_rules! println {
@@ -105,13 +186,35 @@ mod nrt_bus;
 
 static mut LIBRARY: Option<Library<'static>> = None;
 
+struct LogWriter;
+
+impl core::fmt::Write for LogWriter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        // the comms CPU drains the ring before looking at the mailbox, so order is kept
+        if !kern_log_ring::write(s.as_bytes()) {
+            send(&LogSlice(s))
+        }
+        Ok(())
+    }
+}
+
+fn send_log(text: &str) {
+    use core::fmt::Write;
+    let _ = LogWriter.write_str(text);
+}
+
+fn send_log_fmt(args: core::fmt::Arguments) {
+    use core::fmt::Write;
+    let _ = LogWriter.write_fmt(args);
+}
+
 #[no_mangle]
 pub extern fn send_to_core_log(text: CSlice<u8>) {
     match str::from_utf8(text.as_ref()) {
-        Ok(s) => send(&LogSlice(s)),
+        Ok(s) => send_log(s),
         Err(e) => {
-            send(&LogSlice(str::from_utf8(&text.as_ref()[..e.valid_up_to()]).unwrap()));
-            send(&LogSlice("(invalid utf-8)\n"));
+            send_log(str::from_utf8(&text.as_ref()[..e.valid_up_to()]).unwrap());
+            send_log("(invalid utf-8)\n");
         }
     }
 }
@@ -194,11 +297,16 @@ extern fn rpc_recv(slot: *mut ()) -> usize {
 
 fn terminate(exceptions: &'static [Option<eh_artiq::Exception<'static>>],
              stack_pointers: &'static [eh_artiq::StackPointerBacktrace],
-             backtrace: &mut [(usize, usize)]) -> ! {
+             backtrace: &mut [(usize, usize)],
+             registers: &'static [usize],
+             stack_snapshot: &'static [usize]) -> ! {
     send(&RunException {
         exceptions,
         stack_pointers,
-        backtrace
+        backtrace,
+        registers,
+        stack_snapshot,
+        rtio_counter: rtio::get_counter()
     });
     loop {}
 }
@@ -226,6 +334,35 @@ extern fn cache_put(key: &CSlice<u8>, list: &CSlice<i32>) {
     })
 }
 
+// Cache lookups posted per mailbox exchange by cache_get_many.
+const CACHE_BATCH_SIZE: usize = 16;
+const NO_REQUEST: Message<'static> = RpcFlush;
+
+#[unwind(allowed)]
+extern fn cache_get_many<'a>(keys: &CSlice<CSlice<u8>>, values: &mut CMutSlice<CSlice<'a, i32>>) {
+    if values.as_ref().len() < keys.as_ref().len() {
+        raise!("IndexError", "cache_get_many: fewer values ({0}) than keys ({1})",
+               values.as_ref().len() as i64, keys.as_ref().len() as i64, 0)
+    }
+    for (keys, values) in keys.as_ref().chunks(CACHE_BATCH_SIZE)
+                              .zip(values.as_mut().chunks_mut(CACHE_BATCH_SIZE)) {
+        let mut requests: [Message; CACHE_BATCH_SIZE] = [NO_REQUEST; CACHE_BATCH_SIZE];
+        for (request, key) in requests.iter_mut().zip(keys) {
+            *request = CacheGetRequest { key: str::from_utf8(key.as_ref()).unwrap() };
+        }
+        send(&Batch(&requests[..keys.len()]));
+        recv!(&BatchReply(replies) => {
+            for (value, reply) in values.iter_mut().zip(replies) {
+                match reply {
+                    // the cache keeps the value alive, as for cache_get
+                    &CacheGetReply { value: cached } => *value = unsafe { ptr::read(cached) },
+                    other => panic!("unexpected reply in batch: {:?}", other)
+                }
+            }
+        })
+    }
+}
+
 const DMA_BUFFER_SIZE: usize = 64 * 1024;
 
 struct DmaRecorder {
@@ -408,7 +545,7 @@ extern fn dma_playback(timestamp: i64, ptr: i32, _uses_ddma: bool) {
         csr::rtio_dma::enable_write(1);
         #[cfg(has_drtio)]
         if _uses_ddma {
-            send(&DmaStartRemoteRequest { id: ptr as i32, timestamp: timestamp });
+            post(&DmaStartRemoteRequest { id: ptr as i32, timestamp: timestamp });
         }
         while csr::rtio_dma::enable_read() != 0 {}
         csr::cri_con::selected_write(0);
@@ -453,10 +590,51 @@ extern fn dma_playback(timestamp: i64, ptr: i32, _uses_ddma: bool) {
     }
 }
 
+// On satellites, the DMA core belongs to the comms CPU, which plays the trace
+// back on behalf of the subkernel.
 #[cfg(not(kernel_has_rtio_dma))]
 #[unwind(allowed)]
-extern fn dma_playback(_timestamp: i64, _ptr: i32, _uses_ddma: bool) {
-    unimplemented!("not(kernel_has_rtio_dma)")
+extern fn dma_playback(timestamp: i64, ptr: i32, _uses_ddma: bool) {
+    assert!(ptr % 64 == 0);
+
+    post(&DmaStartRemoteRequest { id: ptr, timestamp: timestamp });
+    send(&DmaAwaitRemoteRequest { id: ptr });
+    recv!(&DmaAwaitRemoteReply { timeout, error, channel, timestamp } => {
+        if timeout {
+            raise!("DMAError",
+                "Error running DMA on satellite device, trace not found or timed out waiting for results");
+        }
+        if error & 1 != 0 {
+            raise!("RTIOUnderflow",
+                "RTIO underflow at channel {rtio_channel_info:0}, {1} mu",
+                channel as i64, timestamp as i64, 0);
+        }
+        if error & 2 != 0 {
+            raise!("RTIODestinationUnreachable",
+                "RTIO destination unreachable, output, at channel {rtio_channel_info:0}, {1} mu",
+                channel as i64, timestamp as i64, 0);
+        }
+    });
+}
+
+struct MasterTime {
+    master_us: i64,
+    rtio_counter: i64,
+}
+
+#[unwind(allowed)]
+extern fn master_time() -> MasterTime {
+    send(&MasterTimeRequest);
+    let master_us = recv!(&MasterTimeReply { valid, master_us } => {
+        if !valid {
+            raise!("RuntimeError", "The time of the master has not been received yet");
+        }
+        master_us
+    });
+    MasterTime {
+        master_us: master_us as i64,
+        rtio_counter: rtio::get_counter(),
+    }
 }
 
 #[unwind(allowed)]
@@ -470,6 +648,26 @@ extern fn subkernel_load_run(id: u32, run: bool) {
     });
 }
 
+#[unwind(allowed)]
+extern fn subkernel_replay_exception() {
+    send(&SubkernelReplayExceptionRequest);
+    recv!(&SubkernelReplayExceptionReply(ref exception) => {
+        if let &Some(ref exception) = exception {
+            unsafe {
+                eh_artiq::raise(&eh_artiq::Exception {
+                    id:       exception.id,
+                    file:     exception.file,
+                    line:     exception.line,
+                    column:   exception.column,
+                    function: exception.function,
+                    message:  exception.message,
+                    param:    exception.param
+                })
+            }
+        }
+    })
+}
+
 #[unwind(allowed)]
 extern fn subkernel_await_finish(id: u32, timeout: u64) {
     send(&SubkernelAwaitFinishRequest { id: id, timeout: timeout });
@@ -482,12 +680,34 @@ extern fn subkernel_await_finish(id: u32, timeout: u64) {
                 "Subkernel timed out"),
             SubkernelStatus::CommLost => raise!("SubkernelError",
                 "Lost communication with satellite"),
-            SubkernelStatus::OtherError => raise!("SubkernelError",
+            SubkernelStatus::OtherError | SubkernelStatus::DecodeError { .. } |
+            SubkernelStatus::ReceiveAborted => raise!("SubkernelError",
                 "An error occurred during subkernel operation")
         }
     })
 }
 
+#[unwind(aborts)]
+extern fn subkernel_send_message_async(id: u32, count: u8, tag: &CSlice<u8>, data: *const *const ()) {
+    while rpc_queue::full() {}
+    rpc_queue::enqueue(|mut slice| {
+        let length = {
+            let mut writer = Cursor::new(&mut slice[8..]);
+            rpc_proto::send_args(&mut writer, 0, tag.as_ref(), data)?;
+            writer.position()
+        };
+        // as for synchronous sends, the tag count replaces the end of the service number
+        slice[11] = count;
+        io::ProtoWrite::write_u32(&mut slice, length as u32 | rpc_queue::SUBKERNEL_MESSAGE)?;
+        io::ProtoWrite::write_u32(&mut slice, id)
+    }).unwrap_or_else(|err| {
+        assert!(err == io::Error::UnexpectedEnd);
+
+        while !rpc_queue::empty() {}
+        subkernel_send_message(id, count, tag, data)
+    })
+}
+
 #[unwind(aborts)]
 extern fn subkernel_send_message(id: u32, count: u8, tag: &CSlice<u8>, data: *const *const ()) {
     send(&SubkernelMsgSend { 
@@ -498,16 +718,20 @@ extern fn subkernel_send_message(id: u32, count: u8, tag: &CSlice<u8>, data: *co
     });
 }
 
+// master clock when the last message received was sent, see subkernel_message_time
+static mut MESSAGE_MASTER_US: u64 = 0;
+
 #[unwind(allowed)]
 extern fn subkernel_await_message(id: u32, timeout: u64, min: u8, max: u8) -> u8 {
     send(&SubkernelMsgRecvRequest { id: id, timeout: timeout });
-    recv!(SubkernelMsgRecvReply { status, count } => {
+    recv!(SubkernelMsgRecvReply { status, count, master_us } => {
         match status {
             SubkernelStatus::NoError => {
                 if count < &min || count > &max {
                     raise!("SubkernelError",
                         "Received less or more arguments than expected");
                 }
+                unsafe { MESSAGE_MASTER_US = *master_us }
                 *count
             }
             SubkernelStatus::IncorrectState => raise!("SubkernelError",
@@ -517,12 +741,22 @@ extern fn subkernel_await_message(id: u32, timeout: u64, min: u8, max: u8) -> u8
             SubkernelStatus::CommLost => raise!("SubkernelError",
                 "Lost communication with satellite"),
             SubkernelStatus::OtherError => raise!("SubkernelError",
-                "An error occurred during subkernel operation")
+                "An error occurred during subkernel operation"),
+            // the message is gone, the kernel may catch this and wait for the next one
+            SubkernelStatus::DecodeError { offset } => raise!("SubkernelError",
+                "Received malformed message data at offset {0}", *offset as i64, 0, 0),
+            SubkernelStatus::ReceiveAborted => raise!("SubkernelError",
+                "Message reception aborted before it was complete")
         }
     })
     // RpcRecvRequest should be called `count` times after this to receive message data
 }
 
+#[unwind(aborts)]
+extern fn subkernel_message_time() -> i64 {
+    unsafe { MESSAGE_MASTER_US as i64 }
+}
+
 unsafe fn attribute_writeback(typeinfo: *const ()) {
     struct Attr {
         offset: usize,
@@ -564,6 +798,41 @@ unsafe fn attribute_writeback(typeinfo: *const ()) {
 
 static mut STACK_GUARD_BASE: usize = 0x0;
 
+// Run in place of a kernel on satellites, see BenchmarkRequest. The payload area
+// is free, message data is sent from its start and received after it.
+unsafe fn benchmark(iterations: u32, message_size: usize) -> ! {
+    for _ in 0..iterations {
+        send(&BenchmarkPing);
+        recv!(&BenchmarkPong => ());
+    }
+    send(&BenchmarkPhaseDone);
+
+    let value = [0i32; 16];
+    for _ in 0..iterations {
+        send(&CachePutRequest { key: "benchmark", value: &value });
+        recv!(&CachePutReply { .. } => ());
+        send(&CacheGetRequest { key: "benchmark" });
+        recv!(&CacheGetReply { .. } => ());
+    }
+    send(&BenchmarkPhaseDone);
+
+    let payload = CSlice::new(KERNELCPU_PAYLOAD_ADDRESS as *const u8, message_size);
+    let scratch = (KERNELCPU_PAYLOAD_ADDRESS + message_size + 7) & !7;
+    let args = [&payload as *const CSlice<u8> as *const ()];
+    let tag = CSlice::new(b"B:n".as_ptr(), 3);
+    for _ in 0..iterations {
+        subkernel_send_message(0, 1, &tag, args.as_ptr());
+        subkernel_await_message(0, 10_000, 1, 1);
+        let mut received = [0usize; 2];
+        let mut slot = received.as_mut_ptr() as *mut ();
+        while rpc_recv(slot) != 0 {
+            slot = scratch as *mut ();
+        }
+    }
+    send(&BenchmarkPhaseDone);
+    loop {}
+}
+
 #[no_mangle]
 pub unsafe fn main() {
     eh_artiq::reset_exception_buffer(KERNELCPU_PAYLOAD_ADDRESS);
@@ -571,14 +840,38 @@ pub unsafe fn main() {
                                           kernel_proto::KERNELCPU_LAST_ADDRESS -
                                           kernel_proto::KERNELCPU_PAYLOAD_ADDRESS);
 
-    let library = recv!(&LoadRequest(library) => {
-        match Library::load(library, image, &api::resolve) {
+    // looked at before being taken, anything but a benchmark request is a load request
+    while mailbox::receive() == 0 {
+        check_stop();
+    }
+    if let &BenchmarkRequest { iterations, message_size } = &*(mailbox::receive() as *const Message) {
+        mailbox::acknowledge();
+        benchmark(iterations, message_size as usize)
+    }
+
+    let library = recv!(&LoadRequest { version, library, resident } => {
+        if version != KERNEL_PROTO_VERSION {
+            send(&LoadReply {
+                version: KERNEL_PROTO_VERSION,
+                result:  Err(dyld::Error::Parsing("kernel protocol version mismatch"))
+            });
+            loop {}
+        }
+        let loaded = if resident {
+            Library::reload(library, image, &api::resolve)
+        } else {
+            Library::load(library, image, &api::resolve)
+        };
+        match loaded {
             Err(error) => {
-                send(&LoadReply(Err(error)));
+                send(&LoadReply { version: KERNEL_PROTO_VERSION, result: Err(error) });
                 loop {}
             },
             Ok(library) => {
-                send(&LoadReply(Ok(())));
+                send(&LoadReply {
+                    version: KERNEL_PROTO_VERSION,
+                    result:  Ok(&eh_artiq::EXCEPTION_ID_LOOKUP)
+                });
                 library
             }
         }
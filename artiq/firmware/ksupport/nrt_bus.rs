@@ -86,3 +86,44 @@ pub mod spi {
         }) as i32
     }
 }
+
+pub mod moninj {
+    use ::send;
+    use ::recv;
+    use kernel_proto::*;
+
+    pub extern fn read_probe(channel: i32, probe: i32) -> i64 {
+        send(&MoninjProbeRequest { channel: channel as u32, probe: probe as u8 });
+        recv!(&MoninjProbeReply { succeeded, value } => {
+            if !succeeded {
+                raise!("RuntimeError", "Channel {0} cannot be monitored from this device",
+                       channel as i64, 0, 0);
+            }
+            value
+        }) as i64
+    }
+
+    pub extern fn read_injection_status(channel: i32, overrd: i32) -> i32 {
+        send(&MoninjInjectionStatusRequest { channel: channel as u32, overrd: overrd as u8 });
+        recv!(&MoninjInjectionStatusReply { succeeded, value } => {
+            if !succeeded {
+                raise!("RuntimeError", "Channel {0} cannot be monitored from this device",
+                       channel as i64, 0, 0);
+            }
+            value
+        }) as i32
+    }
+}
+
+pub mod hwreq {
+    use ::send;
+    use ::recv;
+    use kernel_proto::*;
+
+    pub extern fn inject_errors(count: i32) {
+        send(&HwreqErrorInjectRequest { count: count as u32 });
+        recv!(&HwreqErrorInjectReply { succeeded } => if !succeeded {
+            raise!("RuntimeError", "I2C/SPI error injection is not available on this device");
+        });
+    }
+}
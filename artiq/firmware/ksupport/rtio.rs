@@ -138,6 +138,8 @@ mod imp {
 
             let mut status = RTIO_I_STATUS_WAIT_STATUS;
             while status & RTIO_I_STATUS_WAIT_STATUS != 0 {
+                // without a timeout, this may wait forever
+                ::check_stop();
                 status = csr::rtio::i_status_read();
             }
 
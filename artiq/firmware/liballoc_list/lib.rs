@@ -19,6 +19,16 @@ pub struct ListAlloc {
 
 pub const EMPTY: ListAlloc = ListAlloc { root: 0 as *mut Header };
 
+/// Heap usage, in bytes, headers excluded.
+#[derive(Debug, Clone, Copy)]
+pub struct Stats {
+    pub used: usize,
+    pub free: usize,
+    /// Largest allocation that can succeed, free chunks next to each other being
+    /// joined as memory is allocated.
+    pub largest_free: usize
+}
+
 impl ListAlloc {
     pub unsafe fn add(&mut self, ptr: *mut u8, size: usize) {
         let header_size = mem::size_of::<Header>();
@@ -34,6 +44,35 @@ impl ListAlloc {
     pub unsafe fn add_range(&mut self, begin: *mut u8, end: *mut u8) {
         self.add(begin, end as usize - begin as usize)
     }
+
+    pub fn stats(&self) -> Stats {
+        let header_size = mem::size_of::<Header>();
+        let mut stats = Stats { used: 0, free: 0, largest_free: 0 };
+        let mut joined = None;
+        unsafe {
+            let mut curr = self.root;
+            while !curr.is_null() {
+                match (*curr).magic {
+                    MAGIC_FREE => {
+                        stats.free += (*curr).size;
+                        let chunk = match joined {
+                            Some(size) => size + header_size + (*curr).size,
+                            None => (*curr).size
+                        };
+                        stats.largest_free = stats.largest_free.max(chunk);
+                        joined = Some(chunk);
+                    },
+                    MAGIC_BUSY => {
+                        stats.used += (*curr).size;
+                        joined = None;
+                    },
+                    _ => break
+                }
+                curr = (*curr).next;
+            }
+        }
+        stats
+    }
 }
 
 unsafe impl GlobalAlloc for ListAlloc {
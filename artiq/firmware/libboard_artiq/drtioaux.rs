@@ -3,7 +3,7 @@ use crc;
 
 use io::{ProtoRead, ProtoWrite, Cursor, Error as IoError};
 use board_misoc::{csr::DRTIOAUX, mem::DRTIOAUX_MEM, clock};
-use proto_artiq::drtioaux_proto::Error as ProtocolError;
+use proto_artiq::drtioaux_proto::{Error as ProtocolError, AUX_PACKET_MAX_SIZE, LINK_ERROR_COUNT};
 
 pub use proto_artiq::drtioaux_proto::Packet;
 
@@ -41,6 +41,49 @@ impl<T> From<IoError<T>> for Error<T> {
     }
 }
 
+// per link, in the order of drtioaux_proto::LINK_ERROR_COUNT
+const RECEIVED: usize = 0;
+const CRC_ERRORS: usize = 1;
+const GATEWARE_ERRORS: usize = 2;
+const TIMEOUTS: usize = 3;
+const RETRIES: usize = 4;
+const UNSOLICITED: usize = 5;
+
+static mut LINK_ERRORS: [[u32; LINK_ERROR_COUNT]; DRTIOAUX.len()] = [[0; LINK_ERROR_COUNT]; DRTIOAUX.len()];
+
+fn count(linkno: u8, counter: usize) {
+    unsafe {
+        let counters = &mut LINK_ERRORS[linkno as usize];
+        counters[counter] = counters[counter].wrapping_add(1);
+    }
+}
+
+/// Counts a reply that did not arrive in time, for timeouts outside of `recv_timeout`.
+pub fn count_timeout(linkno: u8) {
+    count(linkno, TIMEOUTS)
+}
+
+/// Counts a request sent again because the previous one failed.
+pub fn count_retry(linkno: u8) {
+    count(linkno, RETRIES)
+}
+
+/// Counts a packet that arrived while nothing was expected, and that nothing handled.
+pub fn count_unsolicited(linkno: u8) {
+    count(linkno, UNSOLICITED)
+}
+
+/// Returns the counters of the link, see drtioaux_proto::LINK_ERROR_COUNT.
+pub fn link_errors(linkno: u8, clear: bool) -> [u32; LINK_ERROR_COUNT] {
+    unsafe {
+        let counters = LINK_ERRORS[linkno as usize];
+        if clear {
+            LINK_ERRORS[linkno as usize] = [0; LINK_ERROR_COUNT];
+        }
+        counters
+    }
+}
+
 pub fn reset(linkno: u8) {
     let linkno = linkno as usize;
     unsafe {
@@ -57,7 +100,8 @@ fn has_rx_error(linkno: u8) -> bool {
     unsafe {
         let error = (DRTIOAUX[linkno].aux_rx_error_read)() != 0;
         if error {
-            (DRTIOAUX[linkno].aux_rx_error_write)(1)
+            (DRTIOAUX[linkno].aux_rx_error_write)(1);
+            LINK_ERRORS[linkno][GATEWARE_ERRORS] = LINK_ERRORS[linkno][GATEWARE_ERRORS].wrapping_add(1);
         }
         error
     }
@@ -80,6 +124,11 @@ fn receive<F, T>(linkno: u8, f: F) -> Result<Option<T>, Error<!>>
     }
 }
 
+/// Whether a packet is waiting to be received, without receiving it.
+pub fn has_packet(linkno: u8) -> bool {
+    unsafe { (DRTIOAUX[linkno as usize].aux_rx_present_read)() == 1 }
+}
+
 pub fn recv(linkno: u8) -> Result<Option<Packet>, Error<!>> {
     if has_rx_error(linkno) {
         return Err(Error::GatewareError)
@@ -96,10 +145,12 @@ pub fn recv(linkno: u8) -> Result<Option<Packet>, Error<!>> {
         let checksum = crc::crc32::checksum_ieee(&reader.get_ref()[0..checksum_at]);
         reader.set_position(checksum_at);
         if reader.read_u32()? != checksum {
+            count(linkno, CRC_ERRORS);
             return Err(Error::CorruptedPacket)
         }
         reader.set_position(0);
 
+        count(linkno, RECEIVED);
         Ok(Packet::read_from(&mut reader)?)
     })
 }
@@ -113,6 +164,7 @@ pub fn recv_timeout(linkno: u8, timeout_ms: Option<u64>) -> Result<Packet, Error
             Some(packet) => return Ok(packet),
         }
     }
+    count(linkno, TIMEOUTS);
     Err(Error::TimedOut)
 }
 
@@ -131,6 +183,12 @@ fn transmit<F>(linkno: u8, f: F) -> Result<(), Error<!>>
     }
 }
 
+/// Largest aux packet the gateware of the link can hold, in either direction.
+pub fn packet_capacity(linkno: u8) -> usize {
+    let size = DRTIOAUX_MEM[linkno as usize].size / 2;
+    if size < AUX_PACKET_MAX_SIZE { size } else { AUX_PACKET_MAX_SIZE }
+}
+
 pub fn send(linkno: u8, packet: &Packet) -> Result<(), Error<!>> {
     transmit(linkno, |buffer| {
         let mut writer = Cursor::new(buffer);
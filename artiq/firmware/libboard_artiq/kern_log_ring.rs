@@ -0,0 +1,64 @@
+// Byte ring for kernel log output, written by the kernel CPU and drained by the
+// comms CPU, so that logging does not compete with other messages for the mailbox.
+// The comms CPU drains it before processing the message in the mailbox.
+
+use core::ptr::{read_volatile, write_volatile};
+use core::slice;
+use board_misoc::cache;
+
+// right after the background RPC queue, see rpc_queue
+const RING_BEGIN:  usize = 0x44ffe000;
+const WRITE_INDEX: *mut usize = RING_BEGIN as *mut usize;
+const READ_INDEX:  *mut usize = (RING_BEGIN + 0x10) as *mut usize;
+const DATA_BEGIN:  usize = RING_BEGIN + 0x20;
+const DATA_SIZE:   usize = 0x1000 - 0x20;
+
+pub unsafe fn init() {
+    write_volatile(WRITE_INDEX, 0);
+    write_volatile(READ_INDEX, 0);
+}
+
+fn free_space(write: usize, read: usize) -> usize {
+    (read + DATA_SIZE - write - 1) % DATA_SIZE
+}
+
+/// Called by the kernel CPU. Writes all of `data`, or nothing if there is not enough room.
+pub fn write(data: &[u8]) -> bool {
+    unsafe {
+        let write = read_volatile(WRITE_INDEX);
+        if free_space(write, read_volatile(READ_INDEX)) < data.len() {
+            // the read index may be stale in the cache
+            cache::flush_cpu_dcache();
+            if free_space(write, read_volatile(READ_INDEX)) < data.len() {
+                return false
+            }
+        }
+        let ring = slice::from_raw_parts_mut(DATA_BEGIN as *mut u8, DATA_SIZE);
+        let first = data.len().min(DATA_SIZE - write);
+        ring[write..write + first].copy_from_slice(&data[..first]);
+        ring[..data.len() - first].copy_from_slice(&data[first..]);
+        write_volatile(WRITE_INDEX, (write + data.len()) % DATA_SIZE);
+        true
+    }
+}
+
+/// Called by the comms CPU. Passes the pending bytes to `f`, in up to two parts,
+/// then frees them.
+pub fn drain<F: FnMut(&[u8])>(mut f: F) {
+    unsafe {
+        cache::flush_cpu_dcache();
+        let write = read_volatile(WRITE_INDEX);
+        let read = read_volatile(READ_INDEX);
+        if write == read {
+            return
+        }
+        let ring = slice::from_raw_parts(DATA_BEGIN as *const u8, DATA_SIZE);
+        if write > read {
+            f(&ring[read..write]);
+        } else {
+            f(&ring[read..]);
+            f(&ring[..write]);
+        }
+        write_volatile(READ_INDEX, write);
+    }
+}
@@ -21,6 +21,10 @@ pub mod spi;
 pub mod mailbox;
 #[cfg(has_kernel_cpu)]
 pub mod rpc_queue;
+#[cfg(has_kernel_cpu)]
+pub mod mailbox_queue;
+#[cfg(has_kernel_cpu)]
+pub mod kern_log_ring;
 
 #[cfg(has_si5324)]
 pub mod si5324;
@@ -1,7 +1,12 @@
 use core::ptr::{read_volatile, write_volatile};
-use board_misoc::{mem, cache};
+use board_misoc::{mem, cache, csr::CONFIG_DATA_WIDTH_BYTES};
+#[cfg(has_mailbox_notifier)]
+use board_misoc::csr;
 
 const MAILBOX: *mut usize = mem::MAILBOX_BASE as *mut usize;
+// after the two words of rpc_queue; uncached like the rest of the mailbox, so
+// the kernel can poll it without flushing its data cache
+const STOP_MAILBOX: *mut usize = (mem::MAILBOX_BASE + (CONFIG_DATA_WIDTH_BYTES * 3) as usize) as *mut usize;
 static mut LAST: usize = 0;
 
 pub unsafe fn send(data: usize) {
@@ -33,3 +38,32 @@ pub fn receive() -> usize {
 pub fn acknowledge() {
     unsafe { write_volatile(MAILBOX, 0) }
 }
+
+/// Whether the kernel CPU posted a message since the last call to `clear_posted`.
+#[cfg(has_mailbox_notifier)]
+pub fn posted() -> bool {
+    unsafe { csr::mailbox_notifier::pending_read() != 0 }
+}
+
+#[cfg(not(has_mailbox_notifier))]
+pub fn posted() -> bool {
+    true
+}
+
+/// Asks the kernel to stop at its next safe point, see `check_stop` in ksupport.
+pub fn request_stop() {
+    unsafe { write_volatile(STOP_MAILBOX, 1) }
+}
+
+pub fn stop_requested() -> bool {
+    unsafe { read_volatile(STOP_MAILBOX) != 0 }
+}
+
+pub fn clear_stop() {
+    unsafe { write_volatile(STOP_MAILBOX, 0) }
+}
+
+pub fn clear_posted() {
+    #[cfg(has_mailbox_notifier)]
+    unsafe { csr::mailbox_notifier::pending_write(1) }
+}
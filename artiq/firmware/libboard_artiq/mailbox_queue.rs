@@ -0,0 +1,72 @@
+// Ring of kernel CPU messages that do not need to wait for an acknowledgment
+// (e.g. requests with no reply). Each slot holds the message and the data it
+// refers to; the comms CPU processes queued messages before the one in the
+// mailbox, preserving order.
+
+use core::ptr::{read_volatile, write_volatile};
+use core::slice;
+use board_misoc::cache;
+
+// right after the kernel log ring, see kern_log_ring
+const QUEUE_BEGIN: usize = 0x44fff000;
+const WRITE_INDEX: *mut usize = QUEUE_BEGIN as *mut usize;
+const READ_INDEX:  *mut usize = (QUEUE_BEGIN + 0x10) as *mut usize;
+const RING:        *mut usize = (QUEUE_BEGIN + 0x20) as *mut usize;
+const SLOTS_BEGIN: usize = QUEUE_BEGIN + 0x40;
+
+pub const SLOT_COUNT: usize = 8;
+pub const SLOT_SIZE:  usize = 0x1e0;
+
+pub unsafe fn init() {
+    write_volatile(WRITE_INDEX, 0);
+    write_volatile(READ_INDEX, 0);
+}
+
+fn next(index: usize) -> usize {
+    (index + 1) % SLOT_COUNT
+}
+
+pub fn contains(addr: usize) -> bool {
+    addr >= SLOTS_BEGIN && addr < SLOTS_BEGIN + SLOT_COUNT * SLOT_SIZE
+}
+
+/// Called by the kernel CPU. `f` writes a message into the slot and returns its
+/// address, or None if it does not fit. Returns false if nothing was posted.
+pub fn post<F>(f: F) -> bool
+        where F: FnOnce(&mut [u8]) -> Option<usize> {
+    unsafe {
+        let write = read_volatile(WRITE_INDEX);
+        if next(write) == read_volatile(READ_INDEX) {
+            return false
+        }
+        let slot = slice::from_raw_parts_mut((SLOTS_BEGIN + write * SLOT_SIZE) as *mut u8, SLOT_SIZE);
+        match f(slot) {
+            Some(message) => {
+                write_volatile(RING.add(write), message);
+                write_volatile(WRITE_INDEX, next(write));
+                true
+            }
+            None => false
+        }
+    }
+}
+
+/// Called by the comms CPU, returns the address of the oldest queued message or 0.
+pub fn receive() -> usize {
+    unsafe {
+        cache::flush_cpu_dcache();
+        let read = read_volatile(READ_INDEX);
+        if read == read_volatile(WRITE_INDEX) {
+            0
+        } else {
+            read_volatile(RING.add(read))
+        }
+    }
+}
+
+pub fn acknowledge() {
+    unsafe {
+        let read = read_volatile(READ_INDEX);
+        write_volatile(READ_INDEX, next(read));
+    }
+}
@@ -6,9 +6,15 @@ const SEND_MAILBOX: *mut usize = (mem::MAILBOX_BASE + CONFIG_DATA_WIDTH_BYTES as
 const RECV_MAILBOX: *mut usize = (mem::MAILBOX_BASE + (CONFIG_DATA_WIDTH_BYTES * 2) as usize) as *mut usize;
 
 const QUEUE_BEGIN: usize = 0x44000000;
-const QUEUE_END:   usize = 0x44ffff80;
+// followed by the kernel log ring and the mailbox message queue,
+// see kern_log_ring and mailbox_queue
+const QUEUE_END:   usize = 0x44ffe000;
 const QUEUE_CHUNK: usize = 0x1000;
 
+// Set in the length word of entries holding a subkernel message rather than an RPC;
+// such entries carry the subkernel id in the next word.
+pub const SUBKERNEL_MESSAGE: u32 = 1 << 31;
+
 pub unsafe fn init() {
     write_volatile(SEND_MAILBOX, QUEUE_BEGIN);
     write_volatile(RECV_MAILBOX, QUEUE_BEGIN);
@@ -4,6 +4,18 @@ use log::{Log, LevelFilter, Metadata, Record, set_logger, set_max_level};
 use clock;
 use uart_console::Console;
 
+const TARGET_FILTER_SLOTS: usize = 8;
+
+static mut TARGET_FILTERS: [Option<(&'static str, LevelFilter)>; TARGET_FILTER_SLOTS] =
+    [None; TARGET_FILTER_SLOTS];
+
+static mut RECORD_HOOK: Option<fn(&Record)> = None;
+
+pub fn target_matches(target: &str, prefix: &str) -> bool {
+    prefix.is_empty() || target == prefix ||
+        (target.starts_with(prefix) && target[prefix.len()..].starts_with("::"))
+}
+
 pub struct ConsoleLogger;
 
 impl ConsoleLogger {
@@ -12,11 +24,42 @@ impl ConsoleLogger {
         set_logger(&LOGGER).expect("global logger can only be initialized once");
         set_max_level(LevelFilter::Trace);
     }
+
+    /// Registers a function called with every record that passes the filters,
+    /// after it has been printed.
+    pub fn set_record_hook(hook: fn(&Record)) {
+        unsafe { RECORD_HOOK = Some(hook) }
+    }
+
+    /// Limits records whose target is `prefix`, or a module below it, to `level`.
+    /// An empty prefix matches every target; the longest matching prefix wins.
+    /// Returns false if all filter slots are already taken.
+    pub fn set_target_level(prefix: &'static str, level: LevelFilter) -> bool {
+        let filters = unsafe { &mut TARGET_FILTERS };
+        let index = filters.iter()
+            .position(|slot| slot.map_or(false, |(p, _)| p == prefix))
+            .or_else(|| filters.iter().position(|slot| slot.is_none()));
+        match index {
+            Some(index) => {
+                filters[index] = Some((prefix, level));
+                true
+            }
+            None => false
+        }
+    }
 }
 
 impl Log for ConsoleLogger {
-    fn enabled(&self, _metadata: &Metadata) -> bool {
-        true
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let filters = unsafe { &TARGET_FILTERS };
+        let mut matched: Option<(&str, LevelFilter)> = None;
+        for &(prefix, level) in filters.iter().flatten() {
+            if target_matches(metadata.target(), prefix) &&
+                    matched.map_or(true, |(p, _)| p.len() < prefix.len()) {
+                matched = Some((prefix, level));
+            }
+        }
+        matched.map_or(true, |(_, level)| metadata.level() <= level)
     }
 
     fn log(&self, record: &Record) {
@@ -27,6 +70,10 @@ impl Log for ConsoleLogger {
 
             let _ = writeln!(Console, "[{:6}.{:06}s] {:>5}({}): {}",
                              seconds, micros, record.level(), record.target(), record.args());
+
+            if let Some(hook) = unsafe { RECORD_HOOK } {
+                hook(record);
+            }
         }
     }
 
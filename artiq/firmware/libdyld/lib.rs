@@ -215,9 +215,12 @@ impl<'a> Library<'a> {
         Ok(())
     }
 
-    fn resolve_rela(&self, relas: &[Elf32_Rela], resolve: &dyn Fn(&[u8]) -> Option<Elf32_Word>)
-            -> Result<(), Error<'a>> {
+    fn resolve_rela(&self, relas: &[Elf32_Rela], resolve: &dyn Fn(&[u8]) -> Option<Elf32_Word>,
+                    skip: &dyn Fn(&Elf32_Rela) -> bool) -> Result<(), Error<'a>> {
         for rela in relas {
+            if skip(rela) {
+                continue
+            }
             let sym;
             if ELF32_R_SYM(rela.r_info) == 0 {
                 sym = None;
@@ -282,6 +285,19 @@ impl<'a> Library<'a> {
 
     pub fn load(data: &[u8], image: &'a mut [u8], resolve: &dyn Fn(&[u8]) -> Option<Elf32_Word>)
             -> Result<Library<'a>, Error<'a>> {
+        Library::load_segments(data, image, resolve, false)
+    }
+
+    /// Same as [load](#method.load) for the library `data` that was last loaded into
+    /// `image` and is still laid out there. Only its writable segments, which running
+    /// it may have changed, are copied and relocated again; the rest is used in place.
+    pub fn reload(data: &[u8], image: &'a mut [u8], resolve: &dyn Fn(&[u8]) -> Option<Elf32_Word>)
+            -> Result<Library<'a>, Error<'a>> {
+        Library::load_segments(data, image, resolve, true)
+    }
+
+    fn load_segments(data: &[u8], image: &'a mut [u8], resolve: &dyn Fn(&[u8]) -> Option<Elf32_Word>,
+                     resident: bool) -> Result<Library<'a>, Error<'a>> {
         #![allow(unused_assignments)]
 
         let ehdr = read_unaligned::<Elf32_Ehdr>(data, 0)
@@ -306,6 +322,7 @@ impl<'a> Library<'a> {
                                       .map_err(|()| "cannot read program header")?;
 
             match phdr.p_type {
+                PT_LOAD if resident && phdr.p_flags as usize & PF_W == 0 => (),
                 PT_LOAD => {
                     if (phdr.p_vaddr + phdr.p_filesz) as usize > image.len() ||
                             (phdr.p_offset + phdr.p_filesz) as usize > data.len() {
@@ -413,8 +430,18 @@ impl<'a> Library<'a> {
         // we never write to the memory they refer to, so it's safe.
         mem::drop(image);
 
-        library.resolve_rela(rela, resolve)?;
-        library.resolve_rela(pltrel, resolve)?;
+        // relocations in read-only segments of a resident library were applied when it was loaded
+        let writable = |offset: Elf32_Addr| (0..ehdr.e_phnum).any(|i| {
+            let phdr_off = ehdr.e_phoff as usize + mem::size_of::<Elf32_Phdr>() * i as usize;
+            match read_unaligned::<Elf32_Phdr>(data, phdr_off) {
+                Ok(phdr) => phdr.p_type == PT_LOAD && phdr.p_flags as usize & PF_W != 0 &&
+                    offset >= phdr.p_vaddr && offset < phdr.p_vaddr + phdr.p_memsz,
+                Err(()) => false
+            }
+        });
+        let skip = |rela: &Elf32_Rela| resident && !writable(rela.r_offset);
+        library.resolve_rela(rela, resolve, &skip)?;
+        library.resolve_rela(pltrel, resolve, &skip)?;
 
         Ok(library)
     }
@@ -0,0 +1,125 @@
+// Small LZ77-style codec for payloads sent over DRTIO aux (e.g. subkernel exceptions).
+//
+// The compressed stream is a sequence of tokens:
+//   0x00..=0x7f: literal run of (token + 1) bytes, which follow;
+//   0x80..=0xff: copy of ((token & 0x7f) + MIN_MATCH) bytes, starting at
+//                a big-endian u16 distance back in the output.
+
+use alloc::vec::Vec;
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 0x7f + MIN_MATCH;
+const MAX_LITERALS: usize = 0x80;
+// search window is kept short, compression runs on the comms CPU
+const WINDOW: usize = 512;
+
+fn flush_literals(output: &mut Vec<u8>, literals: &[u8]) {
+    for chunk in literals.chunks(MAX_LITERALS) {
+        output.push((chunk.len() - 1) as u8);
+        output.extend_from_slice(chunk);
+    }
+}
+
+fn longest_match(data: &[u8], pos: usize) -> Option<(usize, usize)> {
+    let max_length = MAX_MATCH.min(data.len() - pos);
+    if max_length < MIN_MATCH {
+        return None
+    }
+    let mut best: Option<(usize, usize)> = None;
+    for start in pos.saturating_sub(WINDOW)..pos {
+        let length = data[start..].iter().zip(&data[pos..pos + max_length])
+            .take_while(|&(a, b)| a == b)
+            .count();
+        if length >= MIN_MATCH && best.map_or(true, |(_, best_length)| length > best_length) {
+            best = Some((pos - start, length));
+            if length == max_length {
+                break
+            }
+        }
+    }
+    best
+}
+
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len());
+    let mut literal_start = 0;
+    let mut pos = 0;
+    while pos < data.len() {
+        match longest_match(data, pos) {
+            Some((distance, length)) => {
+                flush_literals(&mut output, &data[literal_start..pos]);
+                output.push(0x80 | (length - MIN_MATCH) as u8);
+                output.push((distance >> 8) as u8);
+                output.push(distance as u8);
+                pos += length;
+                literal_start = pos;
+            }
+            None => pos += 1
+        }
+    }
+    flush_literals(&mut output, &data[literal_start..]);
+    output
+}
+
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let mut output = Vec::with_capacity(data.len() * 2);
+    let mut pos = 0;
+    while pos < data.len() {
+        let token = data[pos] as usize;
+        pos += 1;
+        if token < 0x80 {
+            let end = pos + token + 1;
+            if end > data.len() {
+                return Err("truncated literal run in compressed data")
+            }
+            output.extend_from_slice(&data[pos..end]);
+            pos = end;
+        } else {
+            if pos + 2 > data.len() {
+                return Err("truncated match in compressed data")
+            }
+            let length = (token & 0x7f) + MIN_MATCH;
+            let distance = ((data[pos] as usize) << 8) | data[pos + 1] as usize;
+            pos += 2;
+            if distance == 0 || distance > output.len() {
+                return Err("invalid match distance in compressed data")
+            }
+            // copied bytes may overlap the ones being written
+            let start = output.len() - distance;
+            for i in 0..length {
+                let byte = output[start + i];
+                output.push(byte);
+            }
+        }
+    }
+    Ok(output)
+}
+
+// set in the header byte of a subkernel message or forwarded RPC (argument count,
+// async flag or reply kind) when the bytes after it are compressed
+pub const HEADER_COMPRESSED: u8 = 0x80;
+// smaller payloads fit in a few aux packets, not worth the comms CPU time
+pub const PAYLOAD_THRESHOLD: usize = 1024;
+
+/// Compresses the bytes after the header byte of a large payload, if that saves space.
+pub fn compress_payload(data: Vec<u8>) -> Vec<u8> {
+    if data.len() <= PAYLOAD_THRESHOLD || data[0] & HEADER_COMPRESSED != 0 {
+        return data
+    }
+    let mut compressed = Vec::with_capacity(data.len());
+    compressed.push(data[0] | HEADER_COMPRESSED);
+    compressed.extend_from_slice(&compress(&data[1..]));
+    if compressed.len() < data.len() { compressed } else { data }
+}
+
+/// Undoes compress_payload, leaving uncompressed payloads as they are.
+pub fn decompress_payload(data: Vec<u8>) -> Result<Vec<u8>, &'static str> {
+    match data.first() {
+        Some(&header) if header & HEADER_COMPRESSED != 0 => {
+            let mut output = decompress(&data[1..])?;
+            output.insert(0, header & !HEADER_COMPRESSED);
+            Ok(output)
+        }
+        _ => Ok(data)
+    }
+}
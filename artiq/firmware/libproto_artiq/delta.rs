@@ -0,0 +1,166 @@
+// Binary deltas between two versions of a subkernel library, so that a satellite
+// still holding the old version only receives what changed.
+//
+// A delta starts with the length and checksum of the library it applies to, then
+// those of the result, each a big-endian u32, followed by a sequence of operations:
+//   0x00, offset, length: copy `length` bytes of the old library, from `offset`;
+//   0x01, length:         insert the `length` bytes that follow.
+// Offsets and lengths are big-endian u32.
+
+use alloc::collections::btree_map::BTreeMap;
+use alloc::vec::Vec;
+use crc;
+
+const OP_COPY: u8 = 0x00;
+const OP_INSERT: u8 = 0x01;
+const HEADER_SIZE: usize = 16;
+// shortest run of unchanged bytes that is looked for, shorter ones are inserted
+const BLOCK: usize = 32;
+
+/// CRC-32 (IEEE) of `data`, to check that a delta is applied to the library it was made for.
+pub fn checksum(data: &[u8]) -> u32 {
+    crc::crc32::checksum_ieee(data)
+}
+
+fn push_u32(output: &mut Vec<u8>, value: u32) {
+    output.extend_from_slice(&[(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8]);
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Result<u32, &'static str> {
+    match data.get(pos..pos + 4) {
+        Some(bytes) => Ok(((bytes[0] as u32) << 24) | ((bytes[1] as u32) << 16) |
+                          ((bytes[2] as u32) << 8) | bytes[3] as u32),
+        None => Err("truncated delta")
+    }
+}
+
+// weak rolling checksum of a block, as used by rsync
+struct Rolling {
+    a: u32,
+    b: u32
+}
+
+impl Rolling {
+    fn new(block: &[u8]) -> Rolling {
+        let mut rolling = Rolling { a: 0, b: 0 };
+        for (i, &byte) in block.iter().enumerate() {
+            rolling.a = rolling.a.wrapping_add(byte as u32);
+            rolling.b = rolling.b.wrapping_add((block.len() - i) as u32 * byte as u32);
+        }
+        rolling
+    }
+
+    fn roll(&mut self, out: u8, into: u8) {
+        self.a = self.a.wrapping_sub(out as u32).wrapping_add(into as u32);
+        self.b = self.b.wrapping_sub(BLOCK as u32 * out as u32).wrapping_add(self.a);
+    }
+
+    fn hash(&self) -> u32 {
+        (self.b << 16) ^ self.a
+    }
+}
+
+fn flush_insert(output: &mut Vec<u8>, data: &[u8]) {
+    if !data.is_empty() {
+        output.push(OP_INSERT);
+        push_u32(output, data.len() as u32);
+        output.extend_from_slice(data);
+    }
+}
+
+/// Describes `target` as changes to `base`.
+pub fn encode(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    push_u32(&mut output, base.len() as u32);
+    push_u32(&mut output, checksum(base));
+    push_u32(&mut output, target.len() as u32);
+    push_u32(&mut output, checksum(target));
+
+    let mut blocks = BTreeMap::new();
+    for (i, block) in base.chunks(BLOCK).enumerate() {
+        if block.len() == BLOCK {
+            blocks.entry(Rolling::new(block).hash()).or_insert(i * BLOCK);
+        }
+    }
+
+    let mut literal_start = 0;
+    let mut pos = 0;
+    let mut rolling = if target.len() >= BLOCK { Some(Rolling::new(&target[..BLOCK])) } else { None };
+    while pos + BLOCK <= target.len() {
+        let hash = rolling.as_ref().map_or(0, Rolling::hash);
+        match blocks.get(&hash) {
+            Some(&offset) if base[offset..offset + BLOCK] == target[pos..pos + BLOCK] => {
+                let length = BLOCK + base[offset + BLOCK..].iter().zip(&target[pos + BLOCK..])
+                    .take_while(|&(a, b)| a == b)
+                    .count();
+                flush_insert(&mut output, &target[literal_start..pos]);
+                output.push(OP_COPY);
+                push_u32(&mut output, offset as u32);
+                push_u32(&mut output, length as u32);
+                pos += length;
+                literal_start = pos;
+                if pos + BLOCK <= target.len() {
+                    rolling = Some(Rolling::new(&target[pos..pos + BLOCK]));
+                }
+            }
+            _ => {
+                if pos + BLOCK < target.len() {
+                    rolling.as_mut().map(|r| r.roll(target[pos], target[pos + BLOCK]));
+                }
+                pos += 1;
+            }
+        }
+    }
+    flush_insert(&mut output, &target[literal_start..]);
+    output
+}
+
+/// Rebuilds the library described by `delta` from `base`, which must be the one it was made for.
+pub fn apply(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, &'static str> {
+    apply_with(base, delta, checksum)
+}
+
+/// Same as `apply`, with the CRC-32 computed by `checksum`, e.g. in gateware.
+pub fn apply_with(base: &[u8], delta: &[u8], checksum: fn(&[u8]) -> u32) -> Result<Vec<u8>, &'static str> {
+    if delta.len() < HEADER_SIZE {
+        return Err("truncated delta header")
+    }
+    if read_u32(delta, 0)? as usize != base.len() || read_u32(delta, 4)? != checksum(base) {
+        return Err("delta was made for another library")
+    }
+    let length = read_u32(delta, 8)? as usize;
+    let mut output = Vec::with_capacity(length);
+    let mut pos = HEADER_SIZE;
+    while pos < delta.len() {
+        let op = delta[pos];
+        pos += 1;
+        match op {
+            OP_COPY => {
+                let offset = read_u32(delta, pos)? as usize;
+                let count = read_u32(delta, pos + 4)? as usize;
+                pos += 8;
+                match base.get(offset..offset.saturating_add(count)) {
+                    Some(bytes) => output.extend_from_slice(bytes),
+                    None => return Err("delta copies past the end of the library")
+                }
+            }
+            OP_INSERT => {
+                let count = read_u32(delta, pos)? as usize;
+                pos += 4;
+                match delta.get(pos..pos.saturating_add(count)) {
+                    Some(bytes) => output.extend_from_slice(bytes),
+                    None => return Err("truncated insertion in delta")
+                }
+                pos += count;
+            }
+            _ => return Err("unknown operation in delta")
+        }
+        if output.len() > length {
+            return Err("delta result is longer than announced")
+        }
+    }
+    if output.len() != length || checksum(&output) != read_u32(delta, 12)? {
+        return Err("delta result does not match its checksum")
+    }
+    Ok(output)
+}
@@ -1,4 +1,4 @@
-use io::{Read, ProtoRead, Write, ProtoWrite, Error as IoError};
+use io::{Cursor, Read, ProtoRead, Write, ProtoWrite, Error as IoError};
 
 #[derive(Fail, Debug)]
 pub enum Error<T> {
@@ -14,11 +14,160 @@ impl<T> From<IoError<T>> for Error<T> {
     }
 }
 
-// maximum size of arbitrary payloads
+// size of aux packets supported by all gateware, used until a larger one is agreed on
+// with PacketSizeRequest
+pub const AUX_PACKET_DEFAULT_SIZE: usize = 512;
+// largest size that can be agreed on, payload buffers of packets are sized for it
+// must not exceed max_packet of the gateware (drtio/aux_controller.py)
+pub const AUX_PACKET_MAX_SIZE: usize = 1024;
+
+// size of arbitrary payloads fitting in aux packets of the given size
 // used by satellite -> master analyzer, subkernel exceptions
-pub const SAT_PAYLOAD_MAX_SIZE: usize  = /*max size*/512 - /*CRC*/4 - /*packet ID*/1 - /*last*/1 - /*length*/2;
+pub fn sat_payload_size(packet_size: usize) -> usize {
+    packet_size - /*CRC*/4 - /*packet ID*/1 - /*last*/1 - /*length*/2
+}
 // used by DDMA, subkernel program data (need to provide extra ID and destination)
+pub fn master_payload_size(packet_size: usize) -> usize {
+    sat_payload_size(packet_size) - /*destination*/1 - /*ID*/4
+}
+
+// maximum size of arbitrary payloads
+pub const SAT_PAYLOAD_MAX_SIZE: usize  = AUX_PACKET_MAX_SIZE - /*CRC*/4 - /*packet ID*/1 - /*last*/1 - /*length*/2;
 pub const MASTER_PAYLOAD_MAX_SIZE: usize = SAT_PAYLOAD_MAX_SIZE - /*destination*/1 - /*ID*/4;
+// messages between subkernels on two satellites pass links that may have agreed on
+// different packet sizes, they stick to the default one
+pub const PEER_PAYLOAD_SIZE: usize = AUX_PACKET_DEFAULT_SIZE - /*CRC*/4 - /*packet ID*/1 - /*last*/1 - /*length*/2
+    - /*destination*/1 - /*source*/1 - /*ID*/4;
+
+// log targets of a satellite, as used by log filters and forwarded log records
+pub const LOG_TARGET_ALL: u8 = 0;
+pub const LOG_TARGET_KERNEL: u8 = 1;
+pub const LOG_TARGET_DRTIO: u8 = 2;
+pub const LOG_TARGET_MANAGER: u8 = 3;
+
+// actions of SatelliteLogControlRequest
+pub const LOG_CONTROL_PAUSE: u8 = 0;
+pub const LOG_CONTROL_RESUME: u8 = 1;
+pub const LOG_CONTROL_CLEAR: u8 = 2;
+
+// severity of kernel exceptions, also passed to the host with them
+pub const EXCEPTION_SEVERITY_FATAL: u8 = 0;
+pub const EXCEPTION_SEVERITY_RECOVERABLE: u8 = 1;
+pub const EXCEPTION_SEVERITY_WARNING: u8 = 2;
+
+// first byte of the data of a SubkernelRpcReply: the return value of the RPC
+// (tag, then value) follows, or the ID of the exception raised on the host
+pub const RPC_REPLY_VALUE: u8 = 0;
+pub const RPC_REPLY_EXCEPTION: u8 = 1;
+
+// subkernel messages sent to the master carry, after the tag count, the satellite
+// clock in us when the kernel sent them
+pub const SUBKERNEL_MESSAGE_STAMP_SIZE: usize = 8;
+
+// messages a subkernel may send to the master before the kernel there takes them, as of
+// loading; the master grants more with SubkernelMessageCredit once it has taken some
+pub const SUBKERNEL_MESSAGE_CREDITS: u8 = 8;
+
+pub fn exception_severity_name(severity: u8) -> &'static str {
+    match severity {
+        EXCEPTION_SEVERITY_FATAL => "fatal",
+        EXCEPTION_SEVERITY_RECOVERABLE => "recoverable",
+        EXCEPTION_SEVERITY_WARNING => "warning",
+        _ => "unknown"
+    }
+}
+
+// severity of a kernel exception going by the id of the first exception raised
+pub fn exception_severity(id: u32) -> u8 {
+    match id {
+        // RTIOUnderflow, RTIOOverflow: often tripped on purpose while tuning timings
+        1 | 2 => EXCEPTION_SEVERITY_WARNING,
+        // RTIODestinationUnreachable, DMAError, I2CError, SPIError
+        3 | 4 | 5 | 7 => EXCEPTION_SEVERITY_RECOVERABLE,
+        _ => EXCEPTION_SEVERITY_FATAL
+    }
+}
+
+// kernel CPU mailbox counters of a satellite, in this order: messages received
+// (logs, cache, RPC, DMA, subkernel, hardware requests, other), acknowledgments,
+// replies sent, total and longest wait for a reply to be taken (ms, us), messages
+// acknowledged before being processed and the kernel CPU time this saved (us)
+pub const MAILBOX_STATS_COUNT: usize = 13;
+
+// subkernel message and RPC throughput counters of a satellite, in this order:
+// messages sent, messages received, RPCs sent, RPC replies received, bytes
+// serialized, bytes received, slices sent, slices received, total and longest
+// time spent serializing and deserializing (ms, us), total and longest time
+// from the first slice of an outgoing message to its acknowledgment (ms, us),
+// message buffers reused from the pool, allocated because the pool ran out
+// and allocated because they were too large for it
+pub const MESSAGE_STATS_COUNT: usize = 15;
+
+// results of a satellite benchmark, in this order: mailbox round trip, cache put
+// and get, subkernel message loopback (each averaged, in ns), message throughput (kB/s)
+pub const BENCHMARK_RESULT_COUNT: usize = 4;
+
+// satman main loop timings, for kernel processing, aux packet handling and whole passes
+// in this order, each: number of passes, median, 99th percentile and longest time (us)
+pub const LOOP_STATS_COUNT: usize = 12;
+
+// aux error counters of a link, in this order: packets received, CRC errors, gateware
+// receive errors, replies that timed out, requests sent again, unsolicited packets
+pub const LINK_ERROR_COUNT: usize = 6;
+
+// satellite memory use, in bytes and in this order: heap used, free and its largest free
+// block, the library of the current kernel, as sent and as laid out in the kernel CPU
+// memory, and the space left between it and the start of the kernel stack
+pub const MEMORY_STATS_COUNT: usize = 6;
+
+pub const LOG_RECORD_HEADER_SIZE: usize = /*level*/1 + /*target*/1 + /*timestamp*/8 + /*length*/2;
+
+// log record forwarded from a satellite, several of which are packed
+// in the data of a SubkernelLogForward packet
+#[derive(PartialEq, Debug)]
+pub struct LogRecord<'a> {
+    pub level: u8,
+    pub target: u8,
+    pub timestamp: u64,
+    pub message: &'a [u8]
+}
+
+impl<'a> LogRecord<'a> {
+    // returns the record at the start of data and its size,
+    // or None if data does not hold a complete record
+    pub fn read_from(data: &'a [u8]) -> Option<(LogRecord<'a>, usize)> {
+        let mut reader = Cursor::new(data);
+        let level = reader.read_u8().ok()?;
+        let target = reader.read_u8().ok()?;
+        let timestamp = reader.read_u64().ok()?;
+        let length = reader.read_u16().ok()? as usize;
+        let size = LOG_RECORD_HEADER_SIZE + length;
+        if data.len() < size {
+            return None
+        }
+        Some((LogRecord {
+            level: level,
+            target: target,
+            timestamp: timestamp,
+            message: &data[LOG_RECORD_HEADER_SIZE..size]
+        }, size))
+    }
+
+    pub fn write_to<W>(&self, writer: &mut W) -> Result<(), IoError<W::WriteError>>
+        where W: Write + ?Sized
+    {
+        writer.write_u8(self.level)?;
+        writer.write_u8(self.target)?;
+        writer.write_u64(self.timestamp)?;
+        writer.write_u16(self.message.len() as u16)?;
+        writer.write_all(self.message)?;
+        Ok(())
+    }
+
+    pub fn size(&self) -> usize {
+        LOG_RECORD_HEADER_SIZE + self.message.len()
+    }
+}
 
 #[derive(PartialEq, Debug)]
 pub enum Packet {
@@ -65,6 +214,8 @@ pub enum Packet {
     AnalyzerHeader { sent_bytes: u32, total_byte_count: u64, overflow_occurred: bool },
     AnalyzerDataRequest { destination: u8 },
     AnalyzerData { last: bool, length: u16, data: [u8; SAT_PAYLOAD_MAX_SIZE]},
+    AnalyzerCaptureRequest { destination: u8, enable: bool },
+    AnalyzerCaptureReply { succeeded: bool },
 
     DmaAddTraceRequest { destination: u8, id: u32, last: bool, length: u16, trace: [u8; MASTER_PAYLOAD_MAX_SIZE] },
     DmaAddTraceReply { succeeded: bool },
@@ -76,13 +227,79 @@ pub enum Packet {
 
     SubkernelAddDataRequest { destination: u8, id: u32, last: bool, length: u16, data: [u8; MASTER_PAYLOAD_MAX_SIZE] },
     SubkernelAddDataReply { succeeded: bool },
+    SubkernelAddSymbolsRequest { destination: u8, id: u32, last: bool, length: u16, data: [u8; MASTER_PAYLOAD_MAX_SIZE] },
     SubkernelLoadRunRequest { destination: u8, id: u32, run: bool },
     SubkernelLoadRunReply { succeeded: bool },
-    SubkernelFinished { id: u32, with_exception: bool },
-    SubkernelExceptionRequest { destination: u8 },
+    SubkernelStopRequest { destination: u8, id: u32 },
+    SubkernelStopReply { succeeded: bool },
+    // pushed by the satellite as the subkernel finishes, with the start of its exception if any
+    SubkernelRunDone { destination: u8, id: u32, with_exception: bool, async_errors: u8, last: bool, length: u16, data: [u8; SAT_PAYLOAD_MAX_SIZE] },
+    SubkernelExceptionRequest { destination: u8, id: u32 },
     SubkernelException { last: bool, length: u16, data: [u8; SAT_PAYLOAD_MAX_SIZE] },
     SubkernelMessage { destination: u8, id: u32, last: bool, length: u16, data: [u8; MASTER_PAYLOAD_MAX_SIZE] },
     SubkernelMessageAck { destination: u8 },
+    SubkernelLogRequest { destination: u8, filter: bool, id: u32 },
+    SubkernelLog { last: bool, length: u16, data: [u8; SAT_PAYLOAD_MAX_SIZE] },
+    SubkernelLogForward { destination: u8, id: u32, length: u16, data: [u8; MASTER_PAYLOAD_MAX_SIZE] },
+    SubkernelExceptionSuppressRequest { destination: u8, match_id: bool, exception_id: u32, length: u16, prefix: [u8; MASTER_PAYLOAD_MAX_SIZE] },
+    SubkernelExceptionSuppressReply { succeeded: bool },
+    SubkernelExceptionSuppressedRequest { destination: u8, clear: bool },
+    SubkernelExceptionSuppressedReply { count: u32 },
+    // removes all suppression rules, answered with SubkernelExceptionSuppressReply
+    SubkernelExceptionUnsuppressRequest { destination: u8 },
+    SubkernelExceptionReplayRequest { destination: u8, enable: bool },
+    SubkernelExceptionReplayReply { succeeded: bool },
+    SubkernelMailboxStatsRequest { destination: u8, clear: bool },
+    SubkernelMailboxStatsReply { counters: [u32; MAILBOX_STATS_COUNT] },
+    SubkernelCommTimeoutsRequest { destination: u8, slot_ms: u32, await_poll_ms: u32 },
+    SubkernelCommTimeoutsReply { succeeded: bool },
+    SubkernelRpcRequest { destination: u8, id: u32, last: bool, length: u16, data: [u8; MASTER_PAYLOAD_MAX_SIZE] },
+    SubkernelRpcReply { destination: u8, id: u32, last: bool, length: u16, data: [u8; MASTER_PAYLOAD_MAX_SIZE] },
+    SubkernelInjectRequest { destination: u8, id: u32, last: bool, length: u16, data: [u8; MASTER_PAYLOAD_MAX_SIZE] },
+    SubkernelInjectReply { succeeded: bool },
+    SubkernelMessageStatsRequest { destination: u8, clear: bool },
+    SubkernelMessageStatsReply { counters: [u32; MESSAGE_STATS_COUNT] },
+    SubkernelMessageCancelRequest { destination: u8 },
+    SubkernelMessageCancelReply { cancelled: bool },
+    SubkernelAddDeltaRequest { destination: u8, id: u32, last: bool, length: u16, data: [u8; MASTER_PAYLOAD_MAX_SIZE] },
+
+    PacketSizeRequest { destination: u8, size: u16 },
+    PacketSizeReply { size: u16 },
+
+    SubkernelBenchmarkRequest { destination: u8, iterations: u32, message_size: u32 },
+    SubkernelBenchmarkReply { succeeded: bool },
+    SubkernelBenchmarkResultRequest { destination: u8 },
+    SubkernelBenchmarkResultReply { ready: bool, results: [u32; BENCHMARK_RESULT_COUNT] },
+
+    SatelliteLoopStatsRequest { destination: u8, clear: bool },
+    SatelliteLoopStatsReply { counters: [u32; LOOP_STATS_COUNT] },
+    SatelliteWorkBudgetRequest { destination: u8, budget_us: u32 },
+    SatelliteWorkBudgetReply { succeeded: bool },
+    SatelliteMemoryStatsRequest { destination: u8 },
+    SatelliteMemoryStatsReply { counters: [u32; MEMORY_STATS_COUNT] },
+    SatelliteLinkErrorsRequest { destination: u8, clear: bool },
+    SatelliteLinkErrorsReply { counters: [u32; LINK_ERROR_COUNT] },
+    TimestampedPingRequest { destination: u8, timestamp: u64 },
+    TimestampedPingReply { timestamp: u64 },
+    // between subkernels on two satellites, relayed by the master; acknowledged with SubkernelMessageAck
+    SubkernelPeerMessage { destination: u8, source: u8, id: u32, last: bool, length: u16, data: [u8; MASTER_PAYLOAD_MAX_SIZE] },
+    SubkernelPeerAddRequest { destination: u8, id: u32, peer: u8 },
+    SubkernelPeerAddReply { succeeded: bool },
+    // time of the master clock, sent to satellites periodically for subkernels
+    MasterTimeUpdate { destination: u8, master_us: u64 },
+    // with the satellite clock, in us, when the update was taken, see runtime rtio_mgt::drtio::master_time_of
+    MasterTimeAck { satellite_us: u64 },
+    SubkernelMessageCredit { destination: u8, id: u32, credits: u8 },
+    SubkernelMessageCreditAck,
+    // sent periodically by the master for each subkernel it runs, see runtime kernel::subkernel::keepalive
+    SubkernelKeepaliveRequest { destination: u8, id: u32 },
+    SubkernelKeepaliveReply { running: bool },
+
+    SatelliteLogFilterRequest { destination: u8, target: u8, level: u8 },
+    SatelliteLogRateLimitRequest { destination: u8, rate: u32 },
+    SatelliteLogOutputRequest { destination: u8, uart: bool, forward: bool },
+    SatelliteLogControlRequest { destination: u8, action: u8 },
+    SatelliteLogBasicReply { succeeded: bool },
 }
 
 impl Packet {
@@ -236,6 +453,13 @@ impl Packet {
                     data: data
                 }
             },
+            0xa4 => Packet::AnalyzerCaptureRequest {
+                destination: reader.read_u8()?,
+                enable: reader.read_bool()?
+            },
+            0xa5 => Packet::AnalyzerCaptureReply {
+                succeeded: reader.read_bool()?
+            },
 
             0xb0 => { 
                 let destination = reader.read_u8()?;
@@ -296,6 +520,21 @@ impl Packet {
             0xc1 => Packet::SubkernelAddDataReply {
                 succeeded: reader.read_bool()?
             },
+            0xc2 => {
+                let destination = reader.read_u8()?;
+                let id = reader.read_u32()?;
+                let last = reader.read_bool()?;
+                let length = reader.read_u16()?;
+                let mut data: [u8; MASTER_PAYLOAD_MAX_SIZE] = [0; MASTER_PAYLOAD_MAX_SIZE];
+                reader.read_exact(&mut data[0..length as usize])?;
+                Packet::SubkernelAddSymbolsRequest {
+                    destination: destination,
+                    id: id,
+                    last: last,
+                    length: length as u16,
+                    data: data,
+                }
+            },
             0xc4 => Packet::SubkernelLoadRunRequest {
                 destination: reader.read_u8()?,
                 id: reader.read_u32()?,
@@ -304,12 +543,35 @@ impl Packet {
             0xc5 => Packet::SubkernelLoadRunReply {
                 succeeded: reader.read_bool()?
             },
-            0xc8 => Packet::SubkernelFinished {
-                id: reader.read_u32()?,
-                with_exception: reader.read_bool()?,
+            0xc6 => Packet::SubkernelStopRequest {
+                destination: reader.read_u8()?,
+                id: reader.read_u32()?
+            },
+            0xc7 => Packet::SubkernelStopReply {
+                succeeded: reader.read_bool()?
+            },
+            0xc8 => {
+                let destination = reader.read_u8()?;
+                let id = reader.read_u32()?;
+                let with_exception = reader.read_bool()?;
+                let async_errors = reader.read_u8()?;
+                let last = reader.read_bool()?;
+                let length = reader.read_u16()?;
+                let mut data: [u8; SAT_PAYLOAD_MAX_SIZE] = [0; SAT_PAYLOAD_MAX_SIZE];
+                reader.read_exact(&mut data[0..length as usize])?;
+                Packet::SubkernelRunDone {
+                    destination: destination,
+                    id: id,
+                    with_exception: with_exception,
+                    async_errors: async_errors,
+                    last: last,
+                    length: length,
+                    data: data
+                }
             },
             0xc9 => Packet::SubkernelExceptionRequest {
-                destination: reader.read_u8()?
+                destination: reader.read_u8()?,
+                id: reader.read_u32()?
             },
             0xca => {
                 let last = reader.read_bool()?;
@@ -340,6 +602,317 @@ impl Packet {
             0xcc => Packet::SubkernelMessageAck {
                 destination: reader.read_u8()?
             },
+            0xcd => Packet::SubkernelLogRequest {
+                destination: reader.read_u8()?,
+                filter: reader.read_bool()?,
+                id: reader.read_u32()?
+            },
+            0xce => {
+                let last = reader.read_bool()?;
+                let length = reader.read_u16()?;
+                let mut data: [u8; SAT_PAYLOAD_MAX_SIZE] = [0; SAT_PAYLOAD_MAX_SIZE];
+                reader.read_exact(&mut data[0..length as usize])?;
+                Packet::SubkernelLog {
+                    last: last,
+                    length: length,
+                    data: data
+                }
+            },
+
+            0xcf => {
+                let destination = reader.read_u8()?;
+                let id = reader.read_u32()?;
+                let length = reader.read_u16()?;
+                let mut data: [u8; MASTER_PAYLOAD_MAX_SIZE] = [0; MASTER_PAYLOAD_MAX_SIZE];
+                reader.read_exact(&mut data[0..length as usize])?;
+                Packet::SubkernelLogForward {
+                    destination: destination,
+                    id: id,
+                    length: length,
+                    data: data
+                }
+            },
+
+            0xd5 => {
+                let destination = reader.read_u8()?;
+                let match_id = reader.read_bool()?;
+                let exception_id = reader.read_u32()?;
+                let length = reader.read_u16()?;
+                let mut prefix: [u8; MASTER_PAYLOAD_MAX_SIZE] = [0; MASTER_PAYLOAD_MAX_SIZE];
+                reader.read_exact(&mut prefix[0..length as usize])?;
+                Packet::SubkernelExceptionSuppressRequest {
+                    destination: destination,
+                    match_id: match_id,
+                    exception_id: exception_id,
+                    length: length,
+                    prefix: prefix
+                }
+            },
+            0xd6 => Packet::SubkernelExceptionSuppressReply {
+                succeeded: reader.read_bool()?
+            },
+            0xd7 => Packet::SubkernelExceptionSuppressedRequest {
+                destination: reader.read_u8()?,
+                clear: reader.read_bool()?
+            },
+            0xd8 => Packet::SubkernelExceptionSuppressedReply {
+                count: reader.read_u32()?
+            },
+            0xc3 => Packet::SubkernelExceptionUnsuppressRequest {
+                destination: reader.read_u8()?
+            },
+            0xdb => Packet::SubkernelMailboxStatsRequest {
+                destination: reader.read_u8()?,
+                clear: reader.read_bool()?
+            },
+            0xdc => {
+                let mut counters = [0; MAILBOX_STATS_COUNT];
+                for counter in counters.iter_mut() {
+                    *counter = reader.read_u32()?;
+                }
+                Packet::SubkernelMailboxStatsReply {
+                    counters: counters
+                }
+            },
+            0xdd => Packet::SubkernelCommTimeoutsRequest {
+                destination: reader.read_u8()?,
+                slot_ms: reader.read_u32()?,
+                await_poll_ms: reader.read_u32()?
+            },
+            0xde => Packet::SubkernelCommTimeoutsReply {
+                succeeded: reader.read_bool()?
+            },
+            0xdf => {
+                let destination = reader.read_u8()?;
+                let id = reader.read_u32()?;
+                let last = reader.read_bool()?;
+                let length = reader.read_u16()?;
+                let mut data: [u8; MASTER_PAYLOAD_MAX_SIZE] = [0; MASTER_PAYLOAD_MAX_SIZE];
+                reader.read_exact(&mut data[0..length as usize])?;
+                Packet::SubkernelRpcRequest {
+                    destination: destination,
+                    id: id,
+                    last: last,
+                    length: length as u16,
+                    data: data,
+                }
+            },
+            0xe0 => {
+                let destination = reader.read_u8()?;
+                let id = reader.read_u32()?;
+                let last = reader.read_bool()?;
+                let length = reader.read_u16()?;
+                let mut data: [u8; MASTER_PAYLOAD_MAX_SIZE] = [0; MASTER_PAYLOAD_MAX_SIZE];
+                reader.read_exact(&mut data[0..length as usize])?;
+                Packet::SubkernelRpcReply {
+                    destination: destination,
+                    id: id,
+                    last: last,
+                    length: length as u16,
+                    data: data,
+                }
+            },
+            0xe1 => {
+                let destination = reader.read_u8()?;
+                let id = reader.read_u32()?;
+                let last = reader.read_bool()?;
+                let length = reader.read_u16()?;
+                let mut data: [u8; MASTER_PAYLOAD_MAX_SIZE] = [0; MASTER_PAYLOAD_MAX_SIZE];
+                reader.read_exact(&mut data[0..length as usize])?;
+                Packet::SubkernelInjectRequest {
+                    destination: destination,
+                    id: id,
+                    last: last,
+                    length: length as u16,
+                    data: data,
+                }
+            },
+            0xe2 => Packet::SubkernelInjectReply {
+                succeeded: reader.read_bool()?
+            },
+            0xe3 => Packet::SubkernelMessageStatsRequest {
+                destination: reader.read_u8()?,
+                clear: reader.read_bool()?
+            },
+            0xe4 => {
+                let mut counters = [0; MESSAGE_STATS_COUNT];
+                for counter in counters.iter_mut() {
+                    *counter = reader.read_u32()?;
+                }
+                Packet::SubkernelMessageStatsReply {
+                    counters: counters
+                }
+            },
+            0xe5 => Packet::SubkernelMessageCancelRequest {
+                destination: reader.read_u8()?
+            },
+            0xe6 => Packet::SubkernelMessageCancelReply {
+                cancelled: reader.read_bool()?
+            },
+            0xe7 => {
+                let destination = reader.read_u8()?;
+                let id = reader.read_u32()?;
+                let last = reader.read_bool()?;
+                let length = reader.read_u16()?;
+                let mut data: [u8; MASTER_PAYLOAD_MAX_SIZE] = [0; MASTER_PAYLOAD_MAX_SIZE];
+                reader.read_exact(&mut data[0..length as usize])?;
+                Packet::SubkernelAddDeltaRequest {
+                    destination: destination,
+                    id: id,
+                    last: last,
+                    length: length as u16,
+                    data: data,
+                }
+            },
+            0xe8 => Packet::PacketSizeRequest {
+                destination: reader.read_u8()?,
+                size: reader.read_u16()?
+            },
+            0xe9 => Packet::PacketSizeReply {
+                size: reader.read_u16()?
+            },
+            0xea => Packet::SubkernelBenchmarkRequest {
+                destination: reader.read_u8()?,
+                iterations: reader.read_u32()?,
+                message_size: reader.read_u32()?
+            },
+            0xeb => Packet::SubkernelBenchmarkReply {
+                succeeded: reader.read_bool()?
+            },
+            0xec => Packet::SubkernelBenchmarkResultRequest {
+                destination: reader.read_u8()?
+            },
+            0xed => {
+                let ready = reader.read_bool()?;
+                let mut results = [0; BENCHMARK_RESULT_COUNT];
+                for result in results.iter_mut() {
+                    *result = reader.read_u32()?;
+                }
+                Packet::SubkernelBenchmarkResultReply {
+                    ready: ready,
+                    results: results
+                }
+            },
+            0xee => Packet::SatelliteLoopStatsRequest {
+                destination: reader.read_u8()?,
+                clear: reader.read_bool()?
+            },
+            0xef => {
+                let mut counters = [0; LOOP_STATS_COUNT];
+                for counter in counters.iter_mut() {
+                    *counter = reader.read_u32()?;
+                }
+                Packet::SatelliteLoopStatsReply { counters: counters }
+            },
+            0xa6 => Packet::SatelliteMemoryStatsRequest {
+                destination: reader.read_u8()?
+            },
+            0xa7 => {
+                let mut counters = [0; MEMORY_STATS_COUNT];
+                for counter in counters.iter_mut() {
+                    *counter = reader.read_u32()?;
+                }
+                Packet::SatelliteMemoryStatsReply { counters: counters }
+            },
+            0xf0 => Packet::SatelliteWorkBudgetRequest {
+                destination: reader.read_u8()?,
+                budget_us: reader.read_u32()?
+            },
+            0xf1 => Packet::SatelliteWorkBudgetReply {
+                succeeded: reader.read_bool()?
+            },
+            0xf2 => Packet::SatelliteLinkErrorsRequest {
+                destination: reader.read_u8()?,
+                clear: reader.read_bool()?
+            },
+            0xf3 => {
+                let mut counters = [0; LINK_ERROR_COUNT];
+                for counter in counters.iter_mut() {
+                    *counter = reader.read_u32()?;
+                }
+                Packet::SatelliteLinkErrorsReply { counters: counters }
+            },
+            0xf4 => Packet::TimestampedPingRequest {
+                destination: reader.read_u8()?,
+                timestamp: reader.read_u64()?
+            },
+            0xf5 => Packet::TimestampedPingReply {
+                timestamp: reader.read_u64()?
+            },
+            0xf6 => {
+                let destination = reader.read_u8()?;
+                let source = reader.read_u8()?;
+                let id = reader.read_u32()?;
+                let last = reader.read_bool()?;
+                let length = reader.read_u16()?;
+                let mut data: [u8; MASTER_PAYLOAD_MAX_SIZE] = [0; MASTER_PAYLOAD_MAX_SIZE];
+                reader.read_exact(&mut data[0..length as usize])?;
+                Packet::SubkernelPeerMessage {
+                    destination: destination,
+                    source: source,
+                    id: id,
+                    last: last,
+                    length: length as u16,
+                    data: data,
+                }
+            },
+            0xf7 => Packet::SubkernelPeerAddRequest {
+                destination: reader.read_u8()?,
+                id: reader.read_u32()?,
+                peer: reader.read_u8()?
+            },
+            0xf8 => Packet::SubkernelPeerAddReply {
+                succeeded: reader.read_bool()?
+            },
+            0xf9 => Packet::MasterTimeUpdate {
+                destination: reader.read_u8()?,
+                master_us: reader.read_u64()?
+            },
+            0xfa => Packet::MasterTimeAck {
+                satellite_us: reader.read_u64()?
+            },
+            0xfb => Packet::SubkernelMessageCredit {
+                destination: reader.read_u8()?,
+                id: reader.read_u32()?,
+                credits: reader.read_u8()?
+            },
+            0xfc => Packet::SubkernelMessageCreditAck,
+            0xfd => Packet::SubkernelKeepaliveRequest {
+                destination: reader.read_u8()?,
+                id: reader.read_u32()?
+            },
+            0xfe => Packet::SubkernelKeepaliveReply {
+                running: reader.read_bool()?
+            },
+            0xd9 => Packet::SubkernelExceptionReplayRequest {
+                destination: reader.read_u8()?,
+                enable: reader.read_bool()?
+            },
+            0xda => Packet::SubkernelExceptionReplayReply {
+                succeeded: reader.read_bool()?
+            },
+
+            0xd0 => Packet::SatelliteLogFilterRequest {
+                destination: reader.read_u8()?,
+                target: reader.read_u8()?,
+                level: reader.read_u8()?
+            },
+            0xd2 => Packet::SatelliteLogRateLimitRequest {
+                destination: reader.read_u8()?,
+                rate: reader.read_u32()?
+            },
+            0xd3 => Packet::SatelliteLogOutputRequest {
+                destination: reader.read_u8()?,
+                uart: reader.read_bool()?,
+                forward: reader.read_bool()?
+            },
+            0xd4 => Packet::SatelliteLogControlRequest {
+                destination: reader.read_u8()?,
+                action: reader.read_u8()?
+            },
+            0xd1 => Packet::SatelliteLogBasicReply {
+                succeeded: reader.read_bool()?
+            },
 
             ty => return Err(Error::UnknownPacket(ty))
         })
@@ -520,6 +1093,15 @@ impl Packet {
                 writer.write_u16(length)?;
                 writer.write_all(&data[0..length as usize])?;
             },
+            Packet::AnalyzerCaptureRequest { destination, enable } => {
+                writer.write_u8(0xa4)?;
+                writer.write_u8(destination)?;
+                writer.write_bool(enable)?;
+            },
+            Packet::AnalyzerCaptureReply { succeeded } => {
+                writer.write_u8(0xa5)?;
+                writer.write_bool(succeeded)?;
+            },
 
             Packet::DmaAddTraceRequest { destination, id, last, trace, length } => {
                 writer.write_u8(0xb0)?;
@@ -575,6 +1157,14 @@ impl Packet {
                 writer.write_u8(0xc1)?;
                 writer.write_bool(succeeded)?;
             },
+            Packet::SubkernelAddSymbolsRequest { destination, id, last, data, length } => {
+                writer.write_u8(0xc2)?;
+                writer.write_u8(destination)?;
+                writer.write_u32(id)?;
+                writer.write_bool(last)?;
+                writer.write_u16(length)?;
+                writer.write_all(&data[0..length as usize])?;
+            },
             Packet::SubkernelLoadRunRequest { destination, id, run } => {
                 writer.write_u8(0xc4)?;
                 writer.write_u8(destination)?;
@@ -585,14 +1175,29 @@ impl Packet {
                 writer.write_u8(0xc5)?;
                 writer.write_bool(succeeded)?;
             },
-            Packet::SubkernelFinished { id, with_exception } => {
+            Packet::SubkernelStopRequest { destination, id } => {
+                writer.write_u8(0xc6)?;
+                writer.write_u8(destination)?;
+                writer.write_u32(id)?;
+            },
+            Packet::SubkernelStopReply { succeeded } => {
+                writer.write_u8(0xc7)?;
+                writer.write_bool(succeeded)?;
+            },
+            Packet::SubkernelRunDone { destination, id, with_exception, async_errors, last, length, data } => {
                 writer.write_u8(0xc8)?;
+                writer.write_u8(destination)?;
                 writer.write_u32(id)?;
                 writer.write_bool(with_exception)?;
+                writer.write_u8(async_errors)?;
+                writer.write_bool(last)?;
+                writer.write_u16(length)?;
+                writer.write_all(&data[0..length as usize])?;
             },
-            Packet::SubkernelExceptionRequest { destination } => {
+            Packet::SubkernelExceptionRequest { destination, id } => {
                 writer.write_u8(0xc9)?;
                 writer.write_u8(destination)?;
+                writer.write_u32(id)?;
             },
             Packet::SubkernelException { last, length, data } => {
                 writer.write_u8(0xca)?;
@@ -612,7 +1217,364 @@ impl Packet {
                 writer.write_u8(0xcc)?;
                 writer.write_u8(destination)?;
             },
+            Packet::SubkernelLogRequest { destination, filter, id } => {
+                writer.write_u8(0xcd)?;
+                writer.write_u8(destination)?;
+                writer.write_bool(filter)?;
+                writer.write_u32(id)?;
+            },
+            Packet::SubkernelLog { last, length, data } => {
+                writer.write_u8(0xce)?;
+                writer.write_bool(last)?;
+                writer.write_u16(length)?;
+                writer.write_all(&data[0..length as usize])?;
+            },
+
+            Packet::SubkernelLogForward { destination, id, length, data } => {
+                writer.write_u8(0xcf)?;
+                writer.write_u8(destination)?;
+                writer.write_u32(id)?;
+                writer.write_u16(length)?;
+                writer.write_all(&data[0..length as usize])?;
+            },
+
+            Packet::SatelliteLogFilterRequest { destination, target, level } => {
+                writer.write_u8(0xd0)?;
+                writer.write_u8(destination)?;
+                writer.write_u8(target)?;
+                writer.write_u8(level)?;
+            },
+            Packet::SatelliteLogRateLimitRequest { destination, rate } => {
+                writer.write_u8(0xd2)?;
+                writer.write_u8(destination)?;
+                writer.write_u32(rate)?;
+            },
+            Packet::SatelliteLogOutputRequest { destination, uart, forward } => {
+                writer.write_u8(0xd3)?;
+                writer.write_u8(destination)?;
+                writer.write_bool(uart)?;
+                writer.write_bool(forward)?;
+            },
+            Packet::SatelliteLogControlRequest { destination, action } => {
+                writer.write_u8(0xd4)?;
+                writer.write_u8(destination)?;
+                writer.write_u8(action)?;
+            },
+            Packet::SatelliteLogBasicReply { succeeded } => {
+                writer.write_u8(0xd1)?;
+                writer.write_bool(succeeded)?;
+            },
+
+            Packet::SubkernelExceptionSuppressRequest { destination, match_id, exception_id, length, prefix } => {
+                writer.write_u8(0xd5)?;
+                writer.write_u8(destination)?;
+                writer.write_bool(match_id)?;
+                writer.write_u32(exception_id)?;
+                writer.write_u16(length)?;
+                writer.write_all(&prefix[0..length as usize])?;
+            },
+            Packet::SubkernelExceptionSuppressReply { succeeded } => {
+                writer.write_u8(0xd6)?;
+                writer.write_bool(succeeded)?;
+            },
+            Packet::SubkernelExceptionSuppressedRequest { destination, clear } => {
+                writer.write_u8(0xd7)?;
+                writer.write_u8(destination)?;
+                writer.write_bool(clear)?;
+            },
+            Packet::SubkernelExceptionSuppressedReply { count } => {
+                writer.write_u8(0xd8)?;
+                writer.write_u32(count)?;
+            },
+            Packet::SubkernelExceptionUnsuppressRequest { destination } => {
+                writer.write_u8(0xc3)?;
+                writer.write_u8(destination)?;
+            },
+            Packet::SubkernelExceptionReplayRequest { destination, enable } => {
+                writer.write_u8(0xd9)?;
+                writer.write_u8(destination)?;
+                writer.write_bool(enable)?;
+            },
+            Packet::SubkernelExceptionReplayReply { succeeded } => {
+                writer.write_u8(0xda)?;
+                writer.write_bool(succeeded)?;
+            },
+            Packet::SubkernelMailboxStatsRequest { destination, clear } => {
+                writer.write_u8(0xdb)?;
+                writer.write_u8(destination)?;
+                writer.write_bool(clear)?;
+            },
+            Packet::SubkernelMailboxStatsReply { counters } => {
+                writer.write_u8(0xdc)?;
+                for counter in counters.iter() {
+                    writer.write_u32(*counter)?;
+                }
+            },
+            Packet::SubkernelCommTimeoutsRequest { destination, slot_ms, await_poll_ms } => {
+                writer.write_u8(0xdd)?;
+                writer.write_u8(destination)?;
+                writer.write_u32(slot_ms)?;
+                writer.write_u32(await_poll_ms)?;
+            },
+            Packet::SubkernelCommTimeoutsReply { succeeded } => {
+                writer.write_u8(0xde)?;
+                writer.write_bool(succeeded)?;
+            },
+            Packet::SubkernelRpcRequest { destination, id, last, data, length } => {
+                writer.write_u8(0xdf)?;
+                writer.write_u8(destination)?;
+                writer.write_u32(id)?;
+                writer.write_bool(last)?;
+                writer.write_u16(length)?;
+                writer.write_all(&data[0..length as usize])?;
+            },
+            Packet::SubkernelRpcReply { destination, id, last, data, length } => {
+                writer.write_u8(0xe0)?;
+                writer.write_u8(destination)?;
+                writer.write_u32(id)?;
+                writer.write_bool(last)?;
+                writer.write_u16(length)?;
+                writer.write_all(&data[0..length as usize])?;
+            },
+            Packet::SubkernelInjectRequest { destination, id, last, data, length } => {
+                writer.write_u8(0xe1)?;
+                writer.write_u8(destination)?;
+                writer.write_u32(id)?;
+                writer.write_bool(last)?;
+                writer.write_u16(length)?;
+                writer.write_all(&data[0..length as usize])?;
+            },
+            Packet::SubkernelInjectReply { succeeded } => {
+                writer.write_u8(0xe2)?;
+                writer.write_bool(succeeded)?;
+            },
+            Packet::SubkernelMessageStatsRequest { destination, clear } => {
+                writer.write_u8(0xe3)?;
+                writer.write_u8(destination)?;
+                writer.write_bool(clear)?;
+            },
+            Packet::SubkernelMessageStatsReply { counters } => {
+                writer.write_u8(0xe4)?;
+                for counter in counters.iter() {
+                    writer.write_u32(*counter)?;
+                }
+            },
+            Packet::SubkernelMessageCancelRequest { destination } => {
+                writer.write_u8(0xe5)?;
+                writer.write_u8(destination)?;
+            },
+            Packet::SubkernelMessageCancelReply { cancelled } => {
+                writer.write_u8(0xe6)?;
+                writer.write_bool(cancelled)?;
+            },
+            Packet::SubkernelAddDeltaRequest { destination, id, last, data, length } => {
+                writer.write_u8(0xe7)?;
+                writer.write_u8(destination)?;
+                writer.write_u32(id)?;
+                writer.write_bool(last)?;
+                writer.write_u16(length)?;
+                writer.write_all(&data[0..length as usize])?;
+            },
+            Packet::PacketSizeRequest { destination, size } => {
+                writer.write_u8(0xe8)?;
+                writer.write_u8(destination)?;
+                writer.write_u16(size)?;
+            },
+            Packet::PacketSizeReply { size } => {
+                writer.write_u8(0xe9)?;
+                writer.write_u16(size)?;
+            },
+            Packet::SubkernelBenchmarkRequest { destination, iterations, message_size } => {
+                writer.write_u8(0xea)?;
+                writer.write_u8(destination)?;
+                writer.write_u32(iterations)?;
+                writer.write_u32(message_size)?;
+            },
+            Packet::SubkernelBenchmarkReply { succeeded } => {
+                writer.write_u8(0xeb)?;
+                writer.write_bool(succeeded)?;
+            },
+            Packet::SubkernelBenchmarkResultRequest { destination } => {
+                writer.write_u8(0xec)?;
+                writer.write_u8(destination)?;
+            },
+            Packet::SubkernelBenchmarkResultReply { ready, ref results } => {
+                writer.write_u8(0xed)?;
+                writer.write_bool(ready)?;
+                for result in results.iter() {
+                    writer.write_u32(*result)?;
+                }
+            },
+            Packet::SatelliteLoopStatsRequest { destination, clear } => {
+                writer.write_u8(0xee)?;
+                writer.write_u8(destination)?;
+                writer.write_bool(clear)?;
+            },
+            Packet::SatelliteLoopStatsReply { counters } => {
+                writer.write_u8(0xef)?;
+                for counter in counters.iter() {
+                    writer.write_u32(*counter)?;
+                }
+            },
+            Packet::SatelliteMemoryStatsRequest { destination } => {
+                writer.write_u8(0xa6)?;
+                writer.write_u8(destination)?;
+            },
+            Packet::SatelliteMemoryStatsReply { counters } => {
+                writer.write_u8(0xa7)?;
+                for counter in counters.iter() {
+                    writer.write_u32(*counter)?;
+                }
+            },
+            Packet::SatelliteWorkBudgetRequest { destination, budget_us } => {
+                writer.write_u8(0xf0)?;
+                writer.write_u8(destination)?;
+                writer.write_u32(budget_us)?;
+            },
+            Packet::SatelliteWorkBudgetReply { succeeded } => {
+                writer.write_u8(0xf1)?;
+                writer.write_bool(succeeded)?;
+            },
+            Packet::SatelliteLinkErrorsRequest { destination, clear } => {
+                writer.write_u8(0xf2)?;
+                writer.write_u8(destination)?;
+                writer.write_bool(clear)?;
+            },
+            Packet::SatelliteLinkErrorsReply { counters } => {
+                writer.write_u8(0xf3)?;
+                for counter in counters.iter() {
+                    writer.write_u32(*counter)?;
+                }
+            },
+            Packet::TimestampedPingRequest { destination, timestamp } => {
+                writer.write_u8(0xf4)?;
+                writer.write_u8(destination)?;
+                writer.write_u64(timestamp)?;
+            },
+            Packet::TimestampedPingReply { timestamp } => {
+                writer.write_u8(0xf5)?;
+                writer.write_u64(timestamp)?;
+            },
+            Packet::SubkernelPeerMessage { destination, source, id, last, data, length } => {
+                writer.write_u8(0xf6)?;
+                writer.write_u8(destination)?;
+                writer.write_u8(source)?;
+                writer.write_u32(id)?;
+                writer.write_bool(last)?;
+                writer.write_u16(length)?;
+                writer.write_all(&data[0..length as usize])?;
+            },
+            Packet::SubkernelPeerAddRequest { destination, id, peer } => {
+                writer.write_u8(0xf7)?;
+                writer.write_u8(destination)?;
+                writer.write_u32(id)?;
+                writer.write_u8(peer)?;
+            },
+            Packet::SubkernelPeerAddReply { succeeded } => {
+                writer.write_u8(0xf8)?;
+                writer.write_bool(succeeded)?;
+            },
+            Packet::MasterTimeUpdate { destination, master_us } => {
+                writer.write_u8(0xf9)?;
+                writer.write_u8(destination)?;
+                writer.write_u64(master_us)?;
+            },
+            Packet::MasterTimeAck { satellite_us } => {
+                writer.write_u8(0xfa)?;
+                writer.write_u64(satellite_us)?;
+            },
+            Packet::SubkernelMessageCredit { destination, id, credits } => {
+                writer.write_u8(0xfb)?;
+                writer.write_u8(destination)?;
+                writer.write_u32(id)?;
+                writer.write_u8(credits)?;
+            },
+            Packet::SubkernelMessageCreditAck => writer.write_u8(0xfc)?,
+            Packet::SubkernelKeepaliveRequest { destination, id } => {
+                writer.write_u8(0xfd)?;
+                writer.write_u8(destination)?;
+                writer.write_u32(id)?;
+            },
+            Packet::SubkernelKeepaliveReply { running } => {
+                writer.write_u8(0xfe)?;
+                writer.write_bool(running)?;
+            },
         }
         Ok(())
     }
+
+    /// Whether the request stops or watches over work in progress, and so goes ahead of
+    /// uploads and message slices on both ends.
+    pub fn is_priority(&self) -> bool {
+        match *self {
+            Packet::ResetRequest |
+            Packet::SubkernelStopRequest { .. } |
+            Packet::SubkernelMessageCancelRequest { .. } |
+            Packet::SubkernelKeepaliveRequest { .. } => true,
+            _ => false
+        }
+    }
+
+    /// Destination a request is meant for, `None` for link-level packets and replies.
+    pub fn destination(&self) -> Option<u8> {
+        match *self {
+            Packet::DestinationStatusRequest { destination, .. } |
+            Packet::RoutingSetPath { destination, .. } |
+            Packet::MonitorRequest { destination, .. } |
+            Packet::InjectionRequest { destination, .. } |
+            Packet::InjectionStatusRequest { destination, .. } |
+            Packet::I2cStartRequest { destination, .. } |
+            Packet::I2cRestartRequest { destination, .. } |
+            Packet::I2cStopRequest { destination, .. } |
+            Packet::I2cWriteRequest { destination, .. } |
+            Packet::I2cReadRequest { destination, .. } |
+            Packet::I2cSwitchSelectRequest { destination, .. } |
+            Packet::SpiSetConfigRequest { destination, .. } |
+            Packet::SpiWriteRequest { destination, .. } |
+            Packet::SpiReadRequest { destination, .. } |
+            Packet::AnalyzerHeaderRequest { destination, .. } |
+            Packet::AnalyzerDataRequest { destination, .. } |
+            Packet::AnalyzerCaptureRequest { destination, .. } |
+            Packet::DmaAddTraceRequest { destination, .. } |
+            Packet::DmaRemoveTraceRequest { destination, .. } |
+            Packet::DmaPlaybackRequest { destination, .. } |
+            Packet::SubkernelAddDataRequest { destination, .. } |
+            Packet::SubkernelAddSymbolsRequest { destination, .. } |
+            Packet::SubkernelLoadRunRequest { destination, .. } |
+            Packet::SubkernelStopRequest { destination, .. } |
+            Packet::SubkernelExceptionRequest { destination, .. } |
+            Packet::SubkernelMessage { destination, .. } |
+            Packet::SubkernelLogRequest { destination, .. } |
+            Packet::SubkernelExceptionSuppressRequest { destination, .. } |
+            Packet::SubkernelExceptionSuppressedRequest { destination, .. } |
+            Packet::SubkernelExceptionUnsuppressRequest { destination } |
+            Packet::SubkernelExceptionReplayRequest { destination, .. } |
+            Packet::SubkernelMailboxStatsRequest { destination, .. } |
+            Packet::SubkernelCommTimeoutsRequest { destination, .. } |
+            Packet::SubkernelRpcRequest { destination, .. } |
+            Packet::SubkernelRpcReply { destination, .. } |
+            Packet::SubkernelInjectRequest { destination, .. } |
+            Packet::SubkernelMessageStatsRequest { destination, .. } |
+            Packet::SubkernelMessageCancelRequest { destination, .. } |
+            Packet::SubkernelAddDeltaRequest { destination, .. } |
+            Packet::PacketSizeRequest { destination, .. } |
+            Packet::SubkernelBenchmarkRequest { destination, .. } |
+            Packet::SubkernelBenchmarkResultRequest { destination, .. } |
+            Packet::SatelliteLoopStatsRequest { destination, .. } |
+            Packet::SatelliteWorkBudgetRequest { destination, .. } |
+            Packet::SatelliteMemoryStatsRequest { destination, .. } |
+            Packet::SatelliteLinkErrorsRequest { destination, .. } |
+            Packet::TimestampedPingRequest { destination, .. } |
+            Packet::SubkernelPeerMessage { destination, .. } |
+            Packet::SubkernelPeerAddRequest { destination, .. } |
+            Packet::MasterTimeUpdate { destination, .. } |
+            Packet::SubkernelMessageCredit { destination, .. } |
+            Packet::SubkernelKeepaliveRequest { destination, .. } |
+            Packet::SatelliteLogFilterRequest { destination, .. } |
+            Packet::SatelliteLogRateLimitRequest { destination, .. } |
+            Packet::SatelliteLogOutputRequest { destination, .. } |
+            Packet::SatelliteLogControlRequest { destination, .. } => Some(destination),
+            _ => None
+        }
+    }
 }
@@ -0,0 +1,168 @@
+// Logical payloads larger than an aux packet, such as kernel libraries, exceptions or
+// subkernel messages, are carried one fragment at a time, each packet with the length
+// of its fragment and whether it is the last one.
+//
+// Both ends number the fragments of a payload from 0 as they go. A payload that gets
+// no new fragment for a while is considered abandoned by its sender, so that it can
+// be dropped, or restarted, rather than have the next payload appended to it.
+
+use alloc::borrow::Cow;
+use alloc::vec::Vec;
+use core::cmp::min;
+
+/// Largest logical payload, past which reassembly fails rather than exhausting the heap.
+pub const PAYLOAD_MAX_SIZE: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fragment {
+    pub seq: u32,
+    pub len: usize,
+    pub last: bool
+}
+
+/// A payload being sent, owned or borrowed from where it is kept.
+#[derive(Debug)]
+pub struct Fragmenter<'a> {
+    data: Cow<'a, [u8]>,
+    position: usize,
+    seq: u32
+}
+
+impl Fragmenter<'static> {
+    pub fn new(data: Vec<u8>) -> Fragmenter<'static> {
+        Fragmenter {
+            data: Cow::Owned(data),
+            position: 0,
+            seq: 0
+        }
+    }
+}
+
+impl<'a> Fragmenter<'a> {
+    /// Continues sending `data` from `position`, where a previous fragmenter left off.
+    pub fn borrowed(data: &'a [u8], position: usize) -> Fragmenter<'a> {
+        Fragmenter {
+            data: Cow::Borrowed(data),
+            position: position,
+            seq: 0
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn into_vec(self) -> Vec<u8> {
+        self.data.into_owned()
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Number of fragments taken so far, i.e. the sequence number of the next one.
+    pub fn seq(&self) -> u32 {
+        self.seq
+    }
+
+    /// Starts sending the payload again from its first fragment.
+    pub fn rewind(&mut self) {
+        self.position = 0;
+        self.seq = 0;
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.position == self.data.len()
+    }
+
+    /// Copies the next fragment, of at most `size` bytes, to the start of `buffer`.
+    /// An empty payload is sent as a single empty fragment; past the last fragment,
+    /// further ones are empty as well.
+    pub fn next(&mut self, buffer: &mut [u8], size: usize) -> Fragment {
+        let len = min(min(size, buffer.len()), self.data.len() - self.position);
+        let last = self.position + len == self.data.len();
+        buffer[..len].copy_from_slice(&self.data[self.position..self.position + len]);
+        let fragment = Fragment { seq: self.seq, len: len, last: last };
+        self.position += len;
+        self.seq += 1;
+        fragment
+    }
+}
+
+/// A payload being received.
+#[derive(Debug)]
+pub struct Reassembler {
+    data: Vec<u8>,
+    // fragments received of the payload, 0 before its first one
+    seq: u32,
+    complete: bool,
+    updated_ms: u64
+}
+
+impl Reassembler {
+    pub fn new() -> Reassembler {
+        Reassembler::with_buffer(Vec::new())
+    }
+
+    /// Reassembles into `buffer`, e.g. taken from a pool, or kept to avoid reallocating.
+    pub fn with_buffer(mut buffer: Vec<u8>) -> Reassembler {
+        buffer.clear();
+        Reassembler {
+            data: buffer,
+            seq: 0,
+            complete: false,
+            updated_ms: 0
+        }
+    }
+
+    /// Whether some, but not all, fragments of a payload arrived.
+    pub fn in_progress(&self) -> bool {
+        self.seq > 0 && !self.complete
+    }
+
+    /// Number of fragments received of the current payload.
+    pub fn fragments(&self) -> u32 {
+        self.seq
+    }
+
+    /// Adds the next fragment, received at `now_ms`. Returns whether it completed the
+    /// payload, which is then available until the next fragment starts another one.
+    pub fn push(&mut self, now_ms: u64, last: bool, fragment: &[u8]) -> Result<bool, &'static str> {
+        if self.complete {
+            self.clear();
+        }
+        if self.data.len() + fragment.len() > PAYLOAD_MAX_SIZE {
+            self.clear();
+            return Err("payload exceeds the maximum size")
+        }
+        self.data.extend_from_slice(fragment);
+        self.seq += 1;
+        self.updated_ms = now_ms;
+        self.complete = last;
+        Ok(last)
+    }
+
+    /// Whether a payload in progress got no new fragment for more than `timeout_ms`.
+    pub fn stalled(&self, now_ms: u64, timeout_ms: u64) -> bool {
+        self.in_progress() && now_ms > self.updated_ms + timeout_ms
+    }
+
+    /// The payload received so far.
+    pub fn payload(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Takes the payload, leaving an empty buffer for the next one.
+    pub fn take(&mut self) -> Vec<u8> {
+        self.seq = 0;
+        self.complete = false;
+        ::core::mem::replace(&mut self.data, Vec::new())
+    }
+
+    /// Drops the payload, keeping the buffer allocated for the next one.
+    pub fn clear(&mut self) {
+        self.data.clear();
+        self.seq = 0;
+        self.complete = false;
+    }
+}
@@ -1,4 +1,4 @@
-use core::fmt;
+use core::{fmt, mem};
 use cslice::CSlice;
 use dyld;
 
@@ -10,19 +10,94 @@ pub const KERNELCPU_LAST_ADDRESS:    usize = 0x4fffffff;
 // section in ksupport.elf.
 pub const KSUPPORT_HEADER_SIZE: usize = 0x74;
 
+// Exchanged at load time, so that firmware and ksupport built from different
+// sources do not try to decode each other's messages. Bump on any change to Message.
+pub const KERNEL_PROTO_VERSION: u32 = 7;
+
+// ksupport posts the address of an Envelope rather than of the message itself;
+// the magic is written last, so a half-filled envelope is never accepted.
+pub const MESSAGE_MAGIC: u32 = 0x4b4d5347;
+
+#[repr(C)]
+pub struct Envelope {
+    pub magic:   u32,
+    pub length:  usize,
+    pub message: *const Message<'static>
+}
+
+/// Checks the envelope at `addr` and returns the address of the message it carries.
+/// `valid` tells whether an address lies within kernel CPU memory.
+pub unsafe fn open_envelope<F: Fn(usize) -> bool>(addr: usize, valid: F) -> Result<usize, &'static str> {
+    if !valid(addr) {
+        return Err("envelope outside of kernel CPU memory")
+    }
+    let envelope = &*(addr as *const Envelope);
+    if envelope.magic != MESSAGE_MAGIC {
+        return Err("bad message magic")
+    }
+    if envelope.length != mem::size_of::<Message>() {
+        return Err("message length mismatch")
+    }
+    let message = envelope.message as usize;
+    if !valid(message) {
+        return Err("message outside of kernel CPU memory")
+    }
+    Ok(message)
+}
+
+// The kernel stack starts 16 bytes below the end of main RAM; the ksupport panic
+// handler leaves a PanicRecord in these words, so that the comms CPU can detect
+// the panic even if the mailbox is stuck.
+pub const KERNELCPU_PANIC_ADDRESS: usize = KERNELCPU_LAST_ADDRESS - 15;
+pub const KERNELCPU_PANIC_MAGIC:   u32   = 0x50414e43;
+
+#[repr(C)]
+pub struct PanicRecord {
+    pub magic:   u32,
+    pub message: *const u8,
+    pub length:  usize
+}
+
+// Exceptions raised by the comms CPU on behalf of a kernel, with the ids assumed
+// until ksupport reports its own exception id table in LoadReply.
+pub const FIRMWARE_EXCEPTIONS: &[(&str, u32)] = &[
+    ("SubkernelError", 11)
+];
+
 #[derive(Debug)]
 pub enum SubkernelStatus {
     NoError,
     Timeout,
     IncorrectState,
     CommLost,
-    OtherError
+    OtherError,
+    // the message was dropped, offset of the first byte that could not be decoded
+    DecodeError { offset: u32 },
+    // the message stopped arriving or its reception was cancelled, and was dropped
+    ReceiveAborted
 }
 
 #[derive(Debug)]
 pub enum Message<'a> {
-    LoadRequest(&'a [u8]),
-    LoadReply(Result<(), dyld::Error<'a>>),
+    LoadRequest {
+        version: u32,
+        library: &'a [u8],
+        // the library is still laid out at KERNELCPU_PAYLOAD_ADDRESS from the last load,
+        // only its writable segments need to be restored
+        resident: bool
+    },
+    LoadReply {
+        version: u32,
+        // on success, carries the (name, id) table of exceptions known to ksupport
+        result:  Result<&'a [(&'a str, u32)], dyld::Error<'a>>
+    },
+    // sent instead of LoadRequest, ksupport then exercises the firmware in phases
+    // each ended by BenchmarkPhaseDone: mailbox round trips, cache writes and reads,
+    // subkernel messages looped back by satman
+    BenchmarkRequest { iterations: u32, message_size: u32 },
+    BenchmarkPing,
+    BenchmarkPong,
+    BenchmarkPhaseDone,
 
     RtioInitRequest,
 
@@ -68,9 +143,16 @@ pub enum Message<'a> {
     RunException {
         exceptions: &'a [Option<eh::eh_artiq::Exception<'a>>],
         stack_pointers: &'a [eh::eh_artiq::StackPointerBacktrace],
-        backtrace: &'a [(usize, usize)]
+        backtrace: &'a [(usize, usize)],
+        // innermost kernel frame: registers as seen by the unwinder, top of its stack
+        registers: &'a [usize],
+        stack_snapshot: &'a [usize],
+        // RTIO counter at the time the exception reached the top of the kernel
+        rtio_counter: i64
     },
     RunAborted,
+    // the kernel reached a safe point after a stop was requested
+    RunStopped,
 
     RpcSend {
         async: bool,
@@ -103,16 +185,37 @@ pub enum Message<'a> {
     SpiReadReply { succeeded: bool, data: u32 },
     SpiBasicReply { succeeded: bool },
 
+    HwreqErrorInjectRequest { count: u32 },
+    HwreqErrorInjectReply { succeeded: bool },
+
+    MoninjProbeRequest { channel: u32, probe: u8 },
+    MoninjProbeReply { succeeded: bool, value: u64 },
+    MoninjInjectionStatusRequest { channel: u32, overrd: u8 },
+    MoninjInjectionStatusReply { succeeded: bool, value: u8 },
+
+    // the master clock, in us, as estimated on satellites from the updates of the master
+    MasterTimeRequest,
+    MasterTimeReply { valid: bool, master_us: u64 },
+
     SubkernelLoadRunRequest { id: u32, run: bool },
     SubkernelLoadRunReply { succeeded: bool },
     SubkernelAwaitFinishRequest { id: u32, timeout: u64 },
     SubkernelAwaitFinishReply { status: SubkernelStatus },
     SubkernelMsgSend { id: u32, count: u8, tag: &'a [u8], data: *const *const () },
     SubkernelMsgRecvRequest { id: u32, timeout: u64 },
-    SubkernelMsgRecvReply { status: SubkernelStatus, count: u8 },
+    // master_us: the master clock when a subkernel sent the message, 0 if unknown
+    SubkernelMsgRecvReply { status: SubkernelStatus, count: u8, master_us: u64 },
+    // debugging aid: last exception stored on the satellite, raised again in the kernel
+    SubkernelReplayExceptionRequest,
+    SubkernelReplayExceptionReply(Option<eh::eh_artiq::Exception<'a>>),
 
     Log(fmt::Arguments<'a>),
-    LogSlice(&'a str)
+    LogSlice(&'a str),
+
+    // several requests posted in one mailbox exchange, answered by a single
+    // BatchReply holding, in order, the replies of the requests that have one
+    Batch(&'a [Message<'a>]),
+    BatchReply(&'a [Message<'a>])
 }
 
 pub use self::Message::*;
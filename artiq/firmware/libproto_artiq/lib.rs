@@ -11,6 +11,7 @@ extern crate cslice;
 extern crate log;
 
 extern crate byteorder;
+extern crate crc;
 extern crate io;
 extern crate dyld;
 extern crate eh;
@@ -29,3 +30,10 @@ pub mod moninj_proto;
 #[cfg(feature = "alloc")]
 pub mod session_proto;
 pub mod rpc_proto;
+
+#[cfg(feature = "alloc")]
+pub mod compression;
+#[cfg(feature = "alloc")]
+pub mod delta;
+#[cfg(feature = "alloc")]
+pub mod fragment;
@@ -48,15 +48,61 @@ pub fn read_magic<R>(reader: &mut R) -> Result<(), Error<R::ReadError>>
     }
 }
 
+/// Reads the Authenticate request a host has to start with when the core device requires
+/// a token. Returns the token, or None if the host sent any other request or a token longer
+/// than `max_size`, which are not read further.
+pub fn read_auth_token<R>(reader: &mut R, max_size: usize) -> Result<Option<Vec<u8>>, Error<R::ReadError>>
+    where R: Read + ?Sized
+{
+    if reader.read_u8()? != 37 {
+        return Ok(None)
+    }
+    let length = reader.read_u32()? as usize;
+    if length > max_size {
+        return Ok(None)
+    }
+    let mut token = Vec::new();
+    token.resize(length, 0);
+    reader.read_exact(&mut token)?;
+    Ok(Some(token))
+}
+
 #[derive(Debug)]
 pub enum Request {
     GetLog,
     ClearLog,
     PullLog,
+    GetSatelliteLog { destination: u8, id: Option<u32> },
     #[cfg(feature = "log")]
     SetLogFilter(log::LevelFilter),
     #[cfg(feature = "log")]
     SetUartLogFilter(log::LevelFilter),
+    #[cfg(feature = "log")]
+    SetSatelliteLogFilter { destination: u8, target: u8, level: log::LevelFilter },
+    SetSatelliteLogRateLimit { destination: u8, rate: u32 },
+    SetSatelliteLogOutputs { destination: u8, uart: bool, forward: bool },
+    SatelliteLogControl { destination: u8, action: u8 },
+
+    SuppressSatelliteException { destination: u8, exception_id: Option<u32>, prefix: String },
+    GetSatelliteSuppressedCount { destination: u8, clear: bool },
+    UnsuppressSatelliteExceptions { destination: u8 },
+    SetSatelliteExceptionReplay { destination: u8, enable: bool },
+    GetSatelliteMailboxStats { destination: u8, clear: bool },
+    SetSatelliteCommTimeouts { destination: u8, slot_ms: u32, await_poll_ms: u32 },
+    InjectSubkernelMessage { destination: u8, id: u32, data: Vec<u8> },
+    GetSatelliteMessageStats { destination: u8, clear: bool },
+    CancelSubkernelMessages { destination: u8 },
+    RunSatelliteBenchmark { destination: u8, iterations: u32, message_size: u32 },
+    GetSatelliteLoopStats { destination: u8, clear: bool },
+    SetSatelliteWorkBudget { destination: u8, budget_us: u32 },
+    GetLinkErrors { destination: u8, clear: bool },
+    MeasureAuxLatency { destination: u8, count: u32 },
+    ReloadRoutingTable,
+    SetAnalyzerCapture { destination: u8, enable: bool },
+    GetSubkernelStats { clear: bool },
+    GetSatelliteMemoryStats { destination: u8 },
+    // required first when the core device has an auth_token set
+    Authenticate { token: Vec<u8> },
 
     ConfigRead   { key: String },
     ConfigWrite  { key: String, value: Vec<u8> },
@@ -77,6 +123,17 @@ pub enum Reply<'a> {
 
     ConfigData(&'a [u8]),
 
+    SuppressedCount(u32),
+    MailboxStats(&'a [u32]),
+    MessageStats(&'a [u32]),
+    BenchmarkResults(&'a [u32]),
+    LoopStats(&'a [u32]),
+    LinkErrors(&'a [u32]),
+    AuxLatency(&'a [u32]),
+    SubkernelStats(&'a [u32]),
+    Unauthorized,
+    MemoryStats(&'a [u32]),
+
     RebootImminent,
 }
 
@@ -102,10 +159,106 @@ impl Request {
             1  => Request::GetLog,
             2  => Request::ClearLog,
             7  => Request::PullLog,
+            17 => Request::GetSatelliteLog {
+                destination: reader.read_u8()?,
+                id: if reader.read_bool()? { Some(reader.read_u32()?) } else { None }
+            },
             #[cfg(feature = "log")]
             3 => Request::SetLogFilter(read_log_level_filter(reader)?),
             #[cfg(feature = "log")]
             6 => Request::SetUartLogFilter(read_log_level_filter(reader)?),
+            #[cfg(feature = "log")]
+            16 => Request::SetSatelliteLogFilter {
+                destination: reader.read_u8()?,
+                target: reader.read_u8()?,
+                level: read_log_level_filter(reader)?
+            },
+            18 => Request::SetSatelliteLogRateLimit {
+                destination: reader.read_u8()?,
+                rate: reader.read_u32()?
+            },
+            19 => Request::SetSatelliteLogOutputs {
+                destination: reader.read_u8()?,
+                uart: reader.read_bool()?,
+                forward: reader.read_bool()?
+            },
+            20 => Request::SatelliteLogControl {
+                destination: reader.read_u8()?,
+                action: reader.read_u8()?
+            },
+
+            21 => Request::SuppressSatelliteException {
+                destination: reader.read_u8()?,
+                exception_id: if reader.read_bool()? { Some(reader.read_u32()?) } else { None },
+                prefix: reader.read_string()?
+            },
+            22 => Request::GetSatelliteSuppressedCount {
+                destination: reader.read_u8()?,
+                clear: reader.read_bool()?
+            },
+            23 => Request::SetSatelliteExceptionReplay {
+                destination: reader.read_u8()?,
+                enable: reader.read_bool()?
+            },
+            24 => Request::GetSatelliteMailboxStats {
+                destination: reader.read_u8()?,
+                clear: reader.read_bool()?
+            },
+            25 => Request::SetSatelliteCommTimeouts {
+                destination: reader.read_u8()?,
+                slot_ms: reader.read_u32()?,
+                await_poll_ms: reader.read_u32()?
+            },
+            26 => Request::InjectSubkernelMessage {
+                destination: reader.read_u8()?,
+                id: reader.read_u32()?,
+                data: reader.read_bytes()?
+            },
+            27 => Request::GetSatelliteMessageStats {
+                destination: reader.read_u8()?,
+                clear: reader.read_bool()?
+            },
+            28 => Request::CancelSubkernelMessages {
+                destination: reader.read_u8()?
+            },
+            29 => Request::RunSatelliteBenchmark {
+                destination: reader.read_u8()?,
+                iterations: reader.read_u32()?,
+                message_size: reader.read_u32()?
+            },
+            30 => Request::GetSatelliteLoopStats {
+                destination: reader.read_u8()?,
+                clear: reader.read_bool()?
+            },
+            31 => Request::SetSatelliteWorkBudget {
+                destination: reader.read_u8()?,
+                budget_us: reader.read_u32()?
+            },
+            32 => Request::GetLinkErrors {
+                destination: reader.read_u8()?,
+                clear: reader.read_bool()?
+            },
+            33 => Request::MeasureAuxLatency {
+                destination: reader.read_u8()?,
+                count: reader.read_u32()?
+            },
+            34 => Request::ReloadRoutingTable,
+            35 => Request::SetAnalyzerCapture {
+                destination: reader.read_u8()?,
+                enable: reader.read_bool()?
+            },
+            36 => Request::GetSubkernelStats {
+                clear: reader.read_bool()?
+            },
+            37 => Request::Authenticate {
+                token: reader.read_bytes()?
+            },
+            38 => Request::GetSatelliteMemoryStats {
+                destination: reader.read_u8()?
+            },
+            39 => Request::UnsuppressSatelliteExceptions {
+                destination: reader.read_u8()?
+            },
 
             12 => Request::ConfigRead {
                 key: reader.read_string()?
@@ -154,6 +307,71 @@ impl<'a> Reply<'a> {
                 writer.write_bytes(bytes)?;
             },
 
+            Reply::SuppressedCount(count) => {
+                writer.write_u8(8)?;
+                writer.write_u32(count)?;
+            },
+
+            Reply::MailboxStats(counters) => {
+                writer.write_u8(9)?;
+                writer.write_u32(counters.len() as u32)?;
+                for counter in counters.iter() {
+                    writer.write_u32(*counter)?;
+                }
+            },
+            Reply::MessageStats(counters) => {
+                writer.write_u8(10)?;
+                writer.write_u32(counters.len() as u32)?;
+                for counter in counters.iter() {
+                    writer.write_u32(*counter)?;
+                }
+            },
+            Reply::BenchmarkResults(results) => {
+                writer.write_u8(11)?;
+                writer.write_u32(results.len() as u32)?;
+                for result in results.iter() {
+                    writer.write_u32(*result)?;
+                }
+            },
+            Reply::LoopStats(counters) => {
+                writer.write_u8(12)?;
+                writer.write_u32(counters.len() as u32)?;
+                for counter in counters.iter() {
+                    writer.write_u32(*counter)?;
+                }
+            },
+            Reply::LinkErrors(counters) => {
+                writer.write_u8(13)?;
+                writer.write_u32(counters.len() as u32)?;
+                for counter in counters.iter() {
+                    writer.write_u32(*counter)?;
+                }
+            },
+            Reply::AuxLatency(latency) => {
+                writer.write_u8(14)?;
+                writer.write_u32(latency.len() as u32)?;
+                for time in latency.iter() {
+                    writer.write_u32(*time)?;
+                }
+            },
+            Reply::SubkernelStats(counters) => {
+                writer.write_u8(15)?;
+                writer.write_u32(counters.len() as u32)?;
+                for counter in counters.iter() {
+                    writer.write_u32(*counter)?;
+                }
+            },
+            Reply::Unauthorized => {
+                writer.write_u8(16)?;
+            },
+            Reply::MemoryStats(counters) => {
+                writer.write_u8(17)?;
+                writer.write_u32(counters.len() as u32)?;
+                for counter in counters.iter() {
+                    writer.write_u32(*counter)?;
+                }
+            },
+
             Reply::RebootImminent => {
                 writer.write_u8(3)?;
             }
@@ -30,18 +30,31 @@ pub fn read_magic<R>(reader: &mut R) -> Result<(), Error<R::ReadError>>
     }
 }
 
+// states in DeviceMessage::SubkernelStatus
+pub const SUBKERNEL_NOT_LOADED: u8 = 0;
+pub const SUBKERNEL_UPLOADED: u8 = 1;
+pub const SUBKERNEL_RUNNING: u8 = 2;
+pub const SUBKERNEL_FINISHED: u8 = 3;
+pub const SUBKERNEL_COMM_LOST: u8 = 4;
+pub const SUBKERNEL_EXCEPTION: u8 = 5;
+// no longer part of the active session
+pub const SUBKERNEL_REMOVED: u8 = 6;
+
 #[derive(Debug)]
 pub enum HostMessage {
     MonitorProbe { enable: bool, channel: u32, probe: u8 },
     MonitorInjection { enable: bool, channel: u32, overrd: u8 },
     Inject { channel: u32, overrd: u8, value: u8 },
-    GetInjectionStatus { channel: u32, overrd: u8 }
+    GetInjectionStatus { channel: u32, overrd: u8 },
+    MonitorSubkernels { enable: bool }
 }
 
 #[derive(Debug)]
 pub enum DeviceMessage {
     MonitorStatus { channel: u32, probe: u8, value: u64 },
-    InjectionStatus { channel: u32, overrd: u8, value: u8 }
+    InjectionStatus { channel: u32, overrd: u8, value: u8 },
+    SubkernelStatus { id: u32, destination: u8, state: u8 },
+    DestinationStatus { destination: u8, up: bool }
 }
 
 impl HostMessage {
@@ -68,6 +81,9 @@ impl HostMessage {
                 channel: reader.read_u32()?,
                 overrd: reader.read_u8()?
             },
+            4 => HostMessage::MonitorSubkernels {
+                enable: if reader.read_u8()? == 0 { false } else { true }
+            },
             ty => return Err(Error::UnknownPacket(ty))
         })
     }
@@ -89,6 +105,17 @@ impl DeviceMessage {
                 writer.write_u32(channel)?;
                 writer.write_u8(overrd)?;
                 writer.write_u8(value)?;
+            },
+            DeviceMessage::SubkernelStatus { id, destination, state } => {
+                writer.write_u8(2)?;
+                writer.write_u32(id)?;
+                writer.write_u8(destination)?;
+                writer.write_u8(state)?;
+            },
+            DeviceMessage::DestinationStatus { destination, up } => {
+                writer.write_u8(3)?;
+                writer.write_u8(destination)?;
+                writer.write_bool(up)?;
             }
         }
         Ok(())
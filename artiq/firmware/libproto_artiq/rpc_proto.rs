@@ -1,9 +1,11 @@
-use core::str;
+use core::{str, fmt};
 use core::slice;
 use cslice::{CSlice, CMutSlice};
 use byteorder::{NativeEndian, ByteOrder};
-use io::{ProtoRead, Read, Write, ProtoWrite, Error};
+use io::{ProtoRead, Read, Write, ProtoWrite, Error, Cursor};
 use self::tag::{Tag, TagIterator, split_tag};
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
 
 #[inline]
 fn round_up(val: usize, power_of_two: usize) -> usize {
@@ -164,16 +166,19 @@ unsafe fn recv_value<R, E>(reader: &mut R, tag: Tag, data: &mut *mut (),
             consume_value!(*mut (), |buffer| {
                 // Deserialize length along each dimension and compute total number of
                 // elements.
+                // A shape too large for memory is treated as corrupted data rather than
+                // wrapping around and underallocating the element storage.
                 let mut total_len: usize = 1;
                 for _ in 0..num_dims {
                     let len = reader.read_u32()? as usize;
-                    total_len *= len;
+                    total_len = total_len.checked_mul(len).ok_or(Error::<R::ReadError>::UnexpectedEnd)?;
                     consume_value!(usize, |ptr| *ptr = len )
                 }
 
                 // Allocate backing storage for elements; deserialize them.
                 let elt_tag = it.clone().next().expect("truncated tag");
-                *buffer = alloc(elt_tag.size() * total_len)?;
+                let size = elt_tag.size().checked_mul(total_len).ok_or(Error::<R::ReadError>::UnexpectedEnd)?;
+                *buffer = alloc(size)?;
                 recv_elements(reader, elt_tag, total_len, *buffer, alloc)
             })
         }
@@ -207,6 +212,475 @@ pub fn recv_return<R, E>(reader: &mut R, tag_bytes: &[u8], data: *mut (),
     Ok(())
 }
 
+#[cfg(feature = "alloc")]
+fn copy_bytes<R>(reader: &mut R, length: usize, out: &mut Vec<u8>)
+                -> Result<(), Error<R::ReadError>>
+    where R: Read + ?Sized
+{
+    let start = out.len();
+    out.resize(start + length, 0);
+    reader.read_exact(&mut out[start..])
+}
+
+#[cfg(feature = "alloc")]
+fn copy_length<R>(reader: &mut R, out: &mut Vec<u8>) -> Result<usize, Error<R::ReadError>>
+    where R: Read + ?Sized
+{
+    let start = out.len();
+    copy_bytes(reader, 4, out)?;
+    Ok(NativeEndian::read_u32(&out[start..]) as usize)
+}
+
+#[cfg(feature = "alloc")]
+fn copy_elements<R>(reader: &mut R, elt_tag: Tag, length: usize, out: &mut Vec<u8>)
+                   -> Result<(), Error<R::ReadError>>
+    where R: Read + ?Sized
+{
+    match elt_tag {
+        Tag::Bool => copy_bytes(reader, length, out),
+        Tag::Int32 => copy_bytes(reader, length * 4, out),
+        Tag::Int64 | Tag::Float64 => copy_bytes(reader, length * 8, out),
+        _ => {
+            for _ in 0..length {
+                copy_value(reader, elt_tag, out)?
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+fn copy_value<R>(reader: &mut R, tag: Tag, out: &mut Vec<u8>) -> Result<(), Error<R::ReadError>>
+    where R: Read + ?Sized
+{
+    match tag {
+        Tag::None => Ok(()),
+        Tag::Bool => copy_bytes(reader, 1, out),
+        Tag::Int32 => copy_bytes(reader, 4, out),
+        Tag::Int64 | Tag::Float64 => copy_bytes(reader, 8, out),
+        Tag::String | Tag::Bytes | Tag::ByteArray => {
+            let length = copy_length(reader, out)?;
+            copy_bytes(reader, length, out)
+        }
+        Tag::Tuple(it, arity) => {
+            let mut it = it.clone();
+            for _ in 0..arity {
+                let tag = it.next().expect("truncated tag");
+                copy_value(reader, tag, out)?
+            }
+            Ok(())
+        }
+        Tag::List(it) => {
+            let tag = it.clone().next().expect("truncated tag");
+            let length = copy_length(reader, out)?;
+            copy_elements(reader, tag, length, out)
+        }
+        Tag::Array(it, num_dims) => {
+            let mut total_len: usize = 1;
+            for _ in 0..num_dims {
+                total_len = total_len.checked_mul(copy_length(reader, out)?).ok_or(Error::UnexpectedEnd)?;
+            }
+            let elt_tag = it.clone().next().expect("truncated tag");
+            copy_elements(reader, elt_tag, total_len, out)
+        }
+        Tag::Range(it) => {
+            let tag = it.clone().next().expect("truncated tag");
+            copy_value(reader, tag, out)?;
+            copy_value(reader, tag, out)?;
+            copy_value(reader, tag, out)
+        }
+        Tag::Keyword(_) => unreachable!(),
+        Tag::Object => unreachable!()
+    }
+}
+
+/// Reads a return value in the format of `recv_return` and appends its bytes,
+/// unchanged, to `out`, e.g. to pass it on to a kernel running elsewhere.
+#[cfg(feature = "alloc")]
+pub fn copy_return<R>(reader: &mut R, tag_bytes: &[u8], out: &mut Vec<u8>)
+                     -> Result<(), Error<R::ReadError>>
+    where R: Read + ?Sized
+{
+    let tag = TagIterator::new(tag_bytes).next().expect("truncated tag");
+    copy_value(reader, tag, out)
+}
+
+type CursorError<T> = Error<<Cursor<T> as Read>::ReadError>;
+
+fn u32_at(data: &[u8], offset: usize) -> Option<usize> {
+    data.get(offset..offset + 4).map(|bytes| NativeEndian::read_u32(bytes) as usize)
+}
+
+fn leaf_tag(tag_byte: u8) -> Option<Tag<'static>> {
+    match tag_byte {
+        b'n' => Some(Tag::None),
+        b'b' => Some(Tag::Bool),
+        b'i' => Some(Tag::Int32),
+        b'I' => Some(Tag::Int64),
+        b'f' => Some(Tag::Float64),
+        b's' => Some(Tag::String),
+        b'B' => Some(Tag::Bytes),
+        b'A' => Some(Tag::ByteArray),
+        _ => None
+    }
+}
+
+/// Why a value serialized with its tags inline cannot be received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TagError {
+    /// offset of the offending byte
+    pub offset: usize,
+    /// the offending byte, None if the data ended there
+    pub found: Option<u8>,
+    pub expected: &'static str
+}
+
+impl TagError {
+    fn at(data: &[u8], offset: usize, expected: &'static str) -> TagError {
+        TagError { offset: offset, found: data.get(offset).cloned(), expected: expected }
+    }
+
+    fn shifted(self, by: usize) -> TagError {
+        TagError { offset: self.offset + by, ..self }
+    }
+}
+
+impl fmt::Display for TagError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // no braces, the text may end up in an exception message
+        match self.found {
+            Some(byte) if byte.is_ascii_alphanumeric() =>
+                write!(f, "found '{}' (0x{:02x}) at offset {}, expected {}",
+                       byte as char, byte, self.offset, self.expected),
+            Some(byte) =>
+                write!(f, "found 0x{:02x} at offset {}, expected {}", byte, self.offset, self.expected),
+            None =>
+                write!(f, "data ended at offset {}, expected {}", self.offset, self.expected)
+        }
+    }
+}
+
+/// Walks the value at the start of `data`, serialized by [send_value] with its tags
+/// inline, and returns its alignment and size in kernel memory, followed by the length
+/// of its serialized form. Fails if the value is truncated or cannot be received.
+fn tagged_layout(data: &[u8]) -> Result<(usize, usize, usize), TagError> {
+    let u32_at = |offset: usize, expected| {
+        u32_at(data, offset).ok_or_else(|| TagError::at(data, data.len(), expected))
+    };
+    let nested = |offset: usize| tagged_layout(&data[offset..]).map_err(|e| e.shifted(offset));
+
+    let tag_byte = *data.get(0).ok_or_else(|| TagError::at(data, 0, "value tag"))?;
+    if let Some(tag) = leaf_tag(tag_byte) {
+        let length = match tag {
+            Tag::None => 0,
+            Tag::Bool => 1,
+            Tag::Int32 => 4,
+            Tag::Int64 | Tag::Float64 => 8,
+            _ => u32_at(1, "string length")?.saturating_add(4)
+        };
+        if length >= data.len() {
+            return Err(TagError::at(data, data.len(), "value data"))
+        }
+        return Ok((tag.alignment(), tag.size(), 1 + length))
+    }
+    match tag_byte {
+        b't' => {
+            let arity = *data.get(1).ok_or_else(|| TagError::at(data, 1, "tuple arity"))?;
+            let (mut alignment, mut size, mut offset) = (1, 0, 2);
+            for _ in 0..arity {
+                let (elt_alignment, elt_size, length) = nested(offset)?;
+                alignment = core::cmp::max(alignment, elt_alignment);
+                size = round_up(size, elt_alignment) + elt_size;
+                offset += length;
+            }
+            Ok((alignment, round_up(size, alignment), offset))
+        }
+        b'l' => {
+            let length = u32_at(1, "list length")?;
+            let elements = tagged_elements_length(&data[5..], length).map_err(|e| e.shifted(5))?;
+            Ok((core::mem::align_of::<CSlice<()>>(), 8, 5 + elements))
+        }
+        b'a' => {
+            let num_dims = *data.get(1).ok_or_else(|| TagError::at(data, 1, "array dimension count"))? as usize;
+            let mut total_len: usize = 1;
+            for dim in 0..num_dims {
+                total_len = total_len.checked_mul(u32_at(2 + 4 * dim, "array dimension")?)
+                    .ok_or_else(|| TagError::at(data, 2 + 4 * dim, "array size within range"))?;
+            }
+            let offset = 2 + 4 * num_dims;
+            let elements = tagged_elements_length(&data[offset..], total_len).map_err(|e| e.shifted(offset))?;
+            Ok((core::mem::align_of::<CSlice<()>>(), 4 * (1 + num_dims), offset + elements))
+        }
+        b'r' => {
+            let (alignment, size, mut offset) = nested(1)?;
+            offset += 1;
+            for _ in 0..2 {
+                offset += nested(offset)?.2;
+            }
+            Ok((alignment, size * 3, offset))
+        }
+        b'k' => {
+            let offset = u32_at(1, "keyword name length")?.saturating_add(5);
+            if offset > data.len() {
+                return Err(TagError::at(data, data.len(), "keyword name"))
+            }
+            let (alignment, size, length) = nested(offset)?;
+            Ok((alignment, size, offset + length))
+        }
+        _ => Err(TagError::at(data, 0, "tag of a value a kernel can receive"))
+    }
+}
+
+/// Returns the serialized length of `length` elements written by [send_elements].
+fn tagged_elements_length(data: &[u8], length: usize) -> Result<usize, TagError> {
+    let fixed = |size: Option<usize>| {
+        match size.and_then(|size| size.checked_add(1)) {
+            Some(end) if end <= data.len() => Ok(end),
+            _ => Err(TagError::at(data, data.len(), "element data"))
+        }
+    };
+    match *data.get(0).ok_or_else(|| TagError::at(data, 0, "element tag"))? {
+        b'b' => fixed(Some(length)),
+        b'i' => fixed(length.checked_mul(4)),
+        b'I' | b'f' => fixed(length.checked_mul(8)),
+        _ => {
+            let mut offset = 1;
+            for _ in 0..length {
+                offset += tagged_layout(&data[offset..]).map_err(|e| e.shifted(offset))?.2;
+            }
+            Ok(offset)
+        }
+    }
+}
+
+/// Reads `length` elements written by [send_elements]. `storage` is given the total
+/// size and the alignment of the elements, and returns where they are to be written.
+unsafe fn recv_tagged_elements<T, E>(
+    reader: &mut Cursor<T>,
+    length: usize,
+    storage: &mut dyn FnMut(usize, usize) -> Result<*mut (), E>,
+    alloc: &dyn Fn(usize) -> Result<*mut (), E>,
+) -> Result<(), E>
+where
+    T: AsRef<[u8]>,
+    E: From<CursorError<T>>,
+{
+    let elt_tag = reader.read_u8()?;
+    match leaf_tag(elt_tag) {
+        Some(tag @ Tag::Bool) | Some(tag @ Tag::Int32) |
+        Some(tag @ Tag::Int64) | Some(tag @ Tag::Float64) => {
+            let size = tag.size().checked_mul(length).ok_or(CursorError::<T>::UnexpectedEnd)?;
+            let dest = storage(size, tag.alignment())?;
+            recv_elements(reader, tag, length, dest, alloc)
+        }
+        _ => {
+            // every element carries its own tags, take the layout from the first one
+            let (alignment, size) = if length == 0 {
+                (1, 0)
+            } else {
+                let rest = &reader.get_ref().as_ref()[reader.position()..];
+                let (alignment, size, _) = tagged_layout(rest).map_err(|_| CursorError::<T>::UnexpectedEnd)?;
+                (alignment, size.checked_mul(length).ok_or(CursorError::<T>::UnexpectedEnd)?)
+            };
+            let mut data = storage(size, alignment)?;
+            for _ in 0..length {
+                recv_tagged_value(reader, &mut data, alloc)?
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Same as [recv_value], but for a value serialized by [send_value], which carries its
+/// tags inline instead of having them sent separately.
+unsafe fn recv_tagged_value<T, E>(reader: &mut Cursor<T>, data: &mut *mut (),
+                                  alloc: &dyn Fn(usize) -> Result<*mut (), E>)
+                                 -> Result<(), E>
+    where T: AsRef<[u8]>,
+          E: From<CursorError<T>>
+{
+    macro_rules! consume_value {
+        ($ty:ty, |$ptr:ident| $map:expr) => ({
+            let $ptr = align_ptr_mut::<$ty>(*data) as *mut $ty;
+            *data = $ptr.offset(1) as *mut ();
+            $map
+        })
+    }
+
+    let start = reader.position();
+    let tag_byte = reader.read_u8()?;
+    if let Some(tag) = leaf_tag(tag_byte) {
+        return recv_value(reader, tag, data, alloc)
+    }
+    match tag_byte {
+        b't' | b'r' => {
+            let (alignment, _, _) = tagged_layout(&reader.get_ref().as_ref()[start..])
+                .map_err(|_| CursorError::<T>::UnexpectedEnd)?;
+            let count = if tag_byte == b't' { reader.read_u8()? } else { 3 };
+            *data = round_up_mut(*data, alignment);
+            for _ in 0..count {
+                recv_tagged_value(reader, data, alloc)?
+            }
+            if tag_byte == b't' {
+                *data = round_up_mut(*data, alignment);
+            }
+            Ok(())
+        }
+        b'l' => {
+            #[repr(C)]
+            struct List { elements: *mut (), length: usize }
+            consume_value!(*mut List, |ptr_to_list| {
+                let length = reader.read_u32()? as usize;
+                // see recv_value, the list and its elements share one allocation
+                recv_tagged_elements(reader, length, &mut |size, alignment| {
+                    let storage_offset = round_up(4 + 4, alignment);
+                    let allocation = alloc(storage_offset + size)? as *mut u8;
+                    let storage = allocation.offset(storage_offset as isize) as *mut ();
+                    *ptr_to_list = allocation as *mut List;
+                    (**ptr_to_list).length = length;
+                    (**ptr_to_list).elements = storage;
+                    Ok(storage)
+                }, alloc)
+            })
+        }
+        b'a' => {
+            consume_value!(*mut (), |buffer| {
+                let num_dims = reader.read_u8()?;
+                let mut total_len: usize = 1;
+                for _ in 0..num_dims {
+                    let len = reader.read_u32()? as usize;
+                    total_len = total_len.checked_mul(len).ok_or(CursorError::<T>::UnexpectedEnd)?;
+                    consume_value!(usize, |ptr| *ptr = len)
+                }
+                recv_tagged_elements(reader, total_len, &mut |size, _| {
+                    *buffer = alloc(size)?;
+                    Ok(*buffer)
+                }, alloc)
+            })
+        }
+        b'k' => {
+            // keyword arguments are received by value, the name is not needed
+            let name_length = reader.read_u32()? as usize;
+            let position = reader.position() + name_length;
+            if position > reader.get_ref().as_ref().len() {
+                return Err(CursorError::<T>::UnexpectedEnd.into())
+            }
+            reader.set_position(position);
+            recv_tagged_value(reader, data, alloc)
+        }
+        _ => Err(CursorError::<T>::UnexpectedEnd.into())
+    }
+}
+
+/// Reads one argument of a subkernel message, as written by [send_args] with its
+/// tags inline, into the kernel-side buffer `data`; see [recv_value] for `alloc`.
+/// Data that is malformed or cannot be received by a kernel (e.g. host objects)
+/// is reported as an unexpected end of stream, see [tagged_error] for the details.
+pub fn recv_tagged<T, E>(reader: &mut Cursor<T>, data: *mut (),
+                         alloc: &dyn Fn(usize) -> Result<*mut (), E>)
+                        -> Result<(), E>
+    where T: AsRef<[u8]>,
+          E: From<CursorError<T>>
+{
+    let mut data = data;
+    unsafe { recv_tagged_value(reader, &mut data, alloc) }
+}
+
+/// Explains why the argument starting at `offset` in `data` could not be received
+/// by [recv_tagged], with offsets from the start of `data`. Returns None if the
+/// argument is well-formed, i.e. receiving it failed for another reason.
+pub fn tagged_error(data: &[u8], offset: usize) -> Option<TagError> {
+    match data.get(offset..) {
+        Some(rest) => tagged_layout(rest).err().map(|e| e.shifted(offset)),
+        None => Some(TagError { offset: offset, found: None, expected: "value tag" })
+    }
+}
+
+/// Checks that `data` starts with `count` arguments that [recv_tagged] can receive,
+/// so that a malformed message can be dropped before the kernel takes any of it.
+pub fn check_tagged(data: &[u8], count: usize) -> Result<(), TagError> {
+    let mut offset = 0;
+    for _ in 0..count {
+        let rest = data.get(offset..).unwrap_or(&[]);
+        offset += tagged_layout(rest).map_err(|e| e.shifted(offset))?.2;
+    }
+    Ok(())
+}
+
+/// Checks whether `data`, the start of an argument serialized by [send_args], is a list
+/// or array of booleans or numbers, or a bytes or bytearray value, whose contents can be
+/// written into the kernel as they arrive. Returns the length of its header and that of
+/// its contents.
+pub fn stream_header_length(data: &[u8]) -> Option<(usize, usize)> {
+    let (length, elt_at) = match *data.get(0)? {
+        // already packed, copied as is without looking at the elements
+        b'B' | b'A' => return Some((5, u32_at(data, 1)?)),
+        b'l' => (u32_at(data, 1)?, 5),
+        b'a' => {
+            let num_dims = *data.get(1)? as usize;
+            let mut total_len: usize = 1;
+            for dim in 0..num_dims {
+                total_len = total_len.checked_mul(u32_at(data, 2 + 4 * dim)?)?;
+            }
+            (total_len, 2 + 4 * num_dims)
+        }
+        _ => return None
+    };
+    let elt_size = match *data.get(elt_at)? {
+        b'b' => 1,
+        b'i' => 4,
+        b'I' | b'f' => 8,
+        _ => return None
+    };
+    Some((elt_at + 1, length.checked_mul(elt_size)?))
+}
+
+/// Writes the value described by `header` (see [stream_header_length]) into the
+/// kernel-side buffer `data`, allocating its storage with `alloc` (see [recv_value]).
+/// Returns where its contents are to be copied, as is, once received.
+pub fn recv_stream_header<E>(header: &[u8], data: *mut (),
+                             alloc: &dyn Fn(usize) -> Result<*mut (), E>)
+                            -> Result<*mut u8, E>
+{
+    let count = |offset: usize| u32_at(header, offset).expect("truncated header");
+    unsafe {
+        if header[0] == b'B' || header[0] == b'A' {
+            let length = count(1);
+            let ptr = align_ptr_mut::<CMutSlice<u8>>(data) as *mut CMutSlice<u8>;
+            let storage = alloc(length)? as *mut u8;
+            *ptr = CMutSlice::new(storage, length);
+            return Ok(storage)
+        }
+        let elt_tag = leaf_tag(header[header.len() - 1]).expect("truncated tag");
+        if header[0] == b'l' {
+            #[repr(C)]
+            struct List { elements: *mut (), length: usize }
+            let length = count(1);
+            // see recv_value, the list and its elements share one allocation
+            let ptr_to_list = align_ptr_mut::<*mut List>(data) as *mut *mut List;
+            let storage_offset = round_up(4 + 4, elt_tag.alignment());
+            let allocation = alloc(storage_offset + elt_tag.size() * length)? as *mut u8;
+            let storage = allocation.offset(storage_offset as isize);
+            *ptr_to_list = allocation as *mut List;
+            (**ptr_to_list).length = length;
+            (**ptr_to_list).elements = storage as *mut ();
+            Ok(storage)
+        } else {
+            let buffer = align_ptr_mut::<*mut ()>(data) as *mut *mut ();
+            let mut dims = buffer.offset(1) as *mut usize;
+            let mut total_len = 1;
+            for dim in 0..header[1] as usize {
+                let len = count(2 + 4 * dim);
+                *dims = len;
+                dims = dims.offset(1);
+                total_len *= len;
+            }
+            *buffer = alloc(elt_tag.size() * total_len)?;
+            Ok(*buffer as *mut u8)
+        }
+    }
+}
+
 unsafe fn send_elements<W>(writer: &mut W, elt_tag: Tag, length: usize, data: *const ())
                           -> Result<(), Error<W::WriteError>>
     where W: Write + ?Sized
@@ -85,7 +85,37 @@ pub enum Request {
         function: u32,
     },
 
-    UploadSubkernel { id: u32, destination: u8, kernel: Vec<u8> },
+    // persistent: kept uploaded past the session, for later ones to use as is
+    UploadSubkernel { id: u32, destination: u8, kernel: Vec<u8>, symbols: Vec<u8>, persistent: bool },
+    // uploaded to their satellites concurrently, rather than one after the other
+    UploadSubkernels(Vec<SubkernelUpload>),
+    // longest time each kernel of the session may run, awaiting subkernels included; 0 for none
+    SetWatchdog { timeout_ms: u32 },
+
+    // run by the core device, one after the other, once no host session is open
+    QueueExperiment { kernel: Vec<u8>, subkernels: Vec<SubkernelUpload> },
+    ClearExperiments,
+
+    // takes back the subkernels of the session whose connection was lost; 0 names no session
+    Reattach { session: u32 },
+
+    // required first when the core device has an auth_token set
+    Authenticate { token: Vec<u8> },
+
+    // longest the host may stay silent while the core device waits on it; 0 for no bound
+    Heartbeat { timeout_ms: u32 },
+
+    // as UploadSubkernels, but uploaded in the background, the kernel running may go on
+    StageSubkernels(Vec<SubkernelUpload>),
+}
+
+#[derive(Debug)]
+pub struct SubkernelUpload {
+    pub id: u32,
+    pub destination: u8,
+    pub kernel: Vec<u8>,
+    pub symbols: Vec<u8>,
+    pub persistent: bool
 }
 
 #[derive(Debug)]
@@ -106,12 +136,47 @@ pub enum Reply<'a> {
         exceptions: &'a [Option<Exception<'a>>],
         stack_pointers: &'a [StackPointerBacktrace],
         backtrace: &'a [(usize, usize)],
-        async_errors: u8
+        async_errors: u8,
+        // last lines printed by a subkernel, empty for kernels run by master
+        kernel_log: &'a str,
+        // see drtioaux_proto::EXCEPTION_SEVERITY_*
+        severity: u8
     },
 
     RpcRequest { async: bool },
 
     ClockFailure,
+    WatchdogExpired,
+
+    ExperimentQueue { pending: u32 },
+    ExperimentQueueFull,
+
+    SessionAttached { session: u32, reattached: bool },
+
+    Authenticated,
+    Unauthorized,
+
+    SessionExpired,
+}
+
+/// Reads the Authenticate request a host has to start with when the core device requires
+/// a token. Returns the token, or None if the host sent any other request or a token longer
+/// than `max_size`, which are not read further.
+pub fn read_auth_token<R>(reader: &mut R, max_size: usize) -> Result<Option<Vec<u8>>, Error<R::ReadError>>
+    where R: Read + ?Sized
+{
+    read_sync(reader)?;
+    if reader.read_u8()? != 15 {
+        return Ok(None)
+    }
+    let length = reader.read_u32()? as usize;
+    if length > max_size {
+        return Ok(None)
+    }
+    let mut token = Vec::new();
+    token.resize(length, 0);
+    reader.read_exact(&mut token)?;
+    Ok(Some(token))
 }
 
 impl Request {
@@ -142,14 +207,56 @@ impl Request {
             9 => Request::UploadSubkernel {
                 id: reader.read_u32()?,
                 destination: reader.read_u8()?,
-                kernel: reader.read_bytes()?
+                kernel: reader.read_bytes()?,
+                symbols: reader.read_bytes()?,
+                persistent: reader.read_bool()?
+            },
+            10 => Request::UploadSubkernels(read_subkernel_uploads(reader)?),
+            11 => Request::SetWatchdog {
+                timeout_ms: reader.read_u32()?
+            },
+            12 => Request::QueueExperiment {
+                kernel: reader.read_bytes()?,
+                subkernels: read_subkernel_uploads(reader)?
+            },
+            13 => Request::ClearExperiments,
+
+            14 => Request::Reattach {
+                session: reader.read_u32()?
+            },
+
+            15 => Request::Authenticate {
+                token: reader.read_bytes()?
             },
 
+            16 => Request::Heartbeat {
+                timeout_ms: reader.read_u32()?
+            },
+
+            17 => Request::StageSubkernels(read_subkernel_uploads(reader)?),
+
             ty  => return Err(Error::UnknownPacket(ty))
         })
     }
 }
 
+fn read_subkernel_uploads<R>(reader: &mut R) -> Result<Vec<SubkernelUpload>, IoError<R::ReadError>>
+    where R: Read + ?Sized
+{
+    let count = reader.read_u32()?;
+    let mut subkernels = Vec::new();
+    for _ in 0..count {
+        subkernels.push(SubkernelUpload {
+            id: reader.read_u32()?,
+            destination: reader.read_u8()?,
+            kernel: reader.read_bytes()?,
+            symbols: reader.read_bytes()?,
+            persistent: reader.read_bool()?
+        });
+    }
+    Ok(subkernels)
+}
+
 fn write_exception_string<'a, W>(writer: &mut W, s: &CSlice<'a, u8>) -> Result<(), IoError<W::WriteError>>
     where W: Write + ?Sized
 {
@@ -194,7 +301,9 @@ impl<'a> Reply<'a> {
                 exceptions,
                 stack_pointers,
                 backtrace,
-                async_errors
+                async_errors,
+                kernel_log,
+                severity
             } => {
                 writer.write_u8(9)?;
                 writer.write_u32(exceptions.len() as u32)?;
@@ -230,6 +339,8 @@ impl<'a> Reply<'a> {
                     writer.write_u32(sp as u32)?;
                 }
                 writer.write_u8(async_errors)?;
+                writer.write_string(kernel_log)?;
+                writer.write_u8(severity)?;
             },
 
             Reply::RpcRequest { async } => {
@@ -240,6 +351,34 @@ impl<'a> Reply<'a> {
             Reply::ClockFailure => {
                 writer.write_u8(15)?;
             },
+            Reply::WatchdogExpired => {
+                writer.write_u8(14)?;
+            },
+
+            Reply::ExperimentQueue { pending } => {
+                writer.write_u8(16)?;
+                writer.write_u32(pending)?;
+            },
+            Reply::ExperimentQueueFull => {
+                writer.write_u8(17)?;
+            },
+
+            Reply::SessionAttached { session, reattached } => {
+                writer.write_u8(18)?;
+                writer.write_u32(session)?;
+                writer.write_bool(reattached)?;
+            },
+
+            Reply::Authenticated => {
+                writer.write_u8(19)?;
+            },
+            Reply::Unauthorized => {
+                writer.write_u8(20)?;
+            },
+
+            Reply::SessionExpired => {
+                writer.write_u8(21)?;
+            },
         }
         Ok(())
     }
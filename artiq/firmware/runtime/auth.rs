@@ -0,0 +1,34 @@
+// When the auth_token config key is set, hosts must present it first thing on session and
+// management connections, so that the core device, and the satellites it controls, cannot be
+// driven by any host that can reach its ports. It is checked before the connection replaces
+// the running session, nothing else is read from a host that does not present it.
+// The token travels in clear; it keeps stray hosts out, not eavesdroppers.
+
+use board_misoc::config;
+
+const CONFIG_KEY: &'static str = "auth_token";
+
+/// Longest token read from a host, any longer one is refused unread.
+pub const TOKEN_MAX_SIZE: usize = 256;
+
+/// Whether connections have to present a token.
+pub fn required() -> bool {
+    config::read(CONFIG_KEY, |result| result.map_or(false, |token| !token.is_empty()))
+}
+
+/// Whether `token` is the one configured, or none is.
+pub fn check(token: &[u8]) -> bool {
+    config::read(CONFIG_KEY, |result| {
+        match result {
+            Ok(expected) if !expected.is_empty() => {
+                // takes as long whatever the first differing byte
+                let mut difference = (expected.len() != token.len()) as u8;
+                for (i, &byte) in expected.iter().enumerate() {
+                    difference |= byte ^ token.get(i).cloned().unwrap_or(0);
+                }
+                difference == 0
+            }
+            _ => true
+        }
+    })
+}
@@ -1,7 +1,7 @@
 use board_misoc::csr;
 use core::{ptr, slice};
 use mailbox;
-use rpc_queue;
+use {rpc_queue, mailbox_queue, kern_log_ring};
 
 use kernel_proto::{KERNELCPU_EXEC_ADDRESS, KERNELCPU_LAST_ADDRESS, KSUPPORT_HEADER_SIZE};
 
@@ -28,6 +28,10 @@ pub unsafe fn start() {
         panic!("failed to load kernel CPU image (ksupport.elf): {}", msg);
     }
 
+    mailbox_queue::init();
+    kern_log_ring::init();
+    mailbox::clear_stop();
+
     csr::kernel_cpu::reset_write(0);
 
     rpc_queue::init();
@@ -92,13 +96,16 @@ pub fn validate(ptr: usize) -> bool {
 #[cfg(has_drtio)]
 pub mod subkernel {
     use alloc::{vec::Vec, collections::btree_map::BTreeMap, string::String, string::ToString};
-    use core::str;
-    use board_artiq::drtio_routing::RoutingTable;
-    use board_misoc::clock;
-    use proto_artiq::{drtioaux_proto::MASTER_PAYLOAD_MAX_SIZE, rpc_proto as rpc};
+    use core::{str, mem, cmp::min};
+    use board_artiq::drtio_routing::{RoutingTable, DEST_COUNT};
+    use board_misoc::{clock, config};
+    use proto_artiq::{drtioaux_proto::MASTER_PAYLOAD_MAX_SIZE, rpc_proto as rpc, compression, delta};
+    use proto_artiq::fragment::Reassembler;
+    use proto_artiq::drtioaux_proto::{SUBKERNEL_MESSAGE_CREDITS, SUBKERNEL_MESSAGE_STAMP_SIZE};
+    use proto_artiq::drtioaux_proto::{LogRecord, LOG_TARGET_KERNEL, LOG_TARGET_DRTIO, LOG_TARGET_MANAGER};
     use io::Cursor;
     use rtio_mgt::drtio;
-    use sched::{Io, Mutex, Error as SchedError};
+    use sched::{Io, Mutex, Event, Error as SchedError};
 
     #[derive(Debug, PartialEq, Clone, Copy)]
     pub enum FinishStatus {
@@ -131,6 +138,20 @@ pub mod subkernel {
         RpcIoError,
         #[fail(display = "subkernel finished prematurely")]
         SubkernelFinished,
+        #[fail(display = "RPC from subkernel waiting to be served")]
+        RpcPending,
+        #[fail(display = "Message from subkernel was dropped before it was complete")]
+        ReceiveAborted,
+        #[fail(display = "Route to the subkernel destination changed, it must be uploaded again")]
+        RouteChanged,
+        #[fail(display = "Session watchdog expired while waiting for subkernel")]
+        WatchdogExpired,
+        #[fail(display = "Subkernel #{} is in use by the kernel running, it cannot be replaced", _0)]
+        InUse(u32),
+        #[fail(display = "Subkernels for destination {} would take {} bytes, over the quota of {}", _0, _1, _2)]
+        StorageQuotaExceeded(u8, usize, usize),
+        #[fail(display = "{} subkernels are running already, the most the quota allows", _0)]
+        RunningQuotaExceeded(usize),
     }
 
     impl From<&str> for Error {
@@ -163,89 +184,664 @@ pub mod subkernel {
     struct Subkernel {
         pub destination: u8,
         pub data: Vec<u8>,
-        pub state: SubkernelState
+        pub symbols: Vec<u8>,
+        pub state: SubkernelState,
+        // head of the exception, pushed by the satellite along with the finish notification
+        pub exception: Vec<u8>,
+        pub exception_complete: bool,
+        // messages the satellite may still send, as far as we know, see SUBKERNEL_MESSAGE_CREDITS
+        pub message_credits: u8,
+        // keepalives in a row the satellite did not answer while running it
+        pub missed_keepalives: u8,
+        // kept uploaded once its session ends, see PERSISTENT
+        pub persistent: bool,
+        // when it was last run, for the run time in STATS
+        pub started_us: u64,
+        // uploaded in the background, see `stage`
+        pub staged: bool
     }
 
     impl Subkernel {
-        pub fn new(destination: u8, data: Vec<u8>) -> Self {
+        pub fn new(destination: u8, data: Vec<u8>, symbols: Vec<u8>, persistent: bool) -> Self {
             Subkernel {
                 destination: destination,
                 data: data,
-                state: SubkernelState::NotLoaded
+                symbols: symbols,
+                state: SubkernelState::NotLoaded,
+                exception: Vec::new(),
+                exception_complete: false,
+                message_credits: SUBKERNEL_MESSAGE_CREDITS,
+                missed_keepalives: 0,
+                persistent: persistent,
+                started_us: 0,
+                staged: false
             }
         }
     }
 
+    // subkernels of the active session, the one whose kernel runs, by ID
     static mut SUBKERNELS: BTreeMap<u32, Subkernel> = BTreeMap::new();
+    // a subkernel is known by the tag of the session that uploaded it and its ID; those of
+    // sessions replaced by a newer one are set aside until their session ends, so that
+    // a session ending late cannot clear the subkernels of the one that replaced it
+    static mut ACTIVE_SESSION: u32 = 0;
+    static mut NEXT_SESSION: u32 = 1;
+    static mut INACTIVE_SESSIONS: BTreeMap<u32, BTreeMap<u32, Subkernel>> = BTreeMap::new();
+    // the active session lost its host, it is left as it is until then for the host
+    // to reattach to once it reconnects, see `detach_session`
+    static mut DETACHED_UNTIL_MS: Option<u64> = None;
+    // subkernels uploaded as persistent by sessions that ended, still on their satellites;
+    // a later session adding the same one finds it uploaded already, through the idle kernel
+    static mut PERSISTENT: BTreeMap<u32, Subkernel> = BTreeMap::new();
+    // last library uploaded under each id and its destination, kept across sessions
+    // so that a changed subkernel can be sent as a delta against it
+    static mut UPLOADED: BTreeMap<u32, (u8, Vec<u8>)> = BTreeMap::new();
+    // destinations whose route differs in a reloaded routing table that is not in effect yet
+    static mut MOVED_DESTINATIONS: [bool; DEST_COUNT] = [false; DEST_COUNT];
+    // destinations told where the subkernels of the session on other satellites are
+    static mut PEERS_SENT: [bool; DEST_COUNT] = [false; DEST_COUNT];
+    static mut NEXT_KEEPALIVE_MS: u64 = 0;
+    // subkernels of the next kernel the link thread uploads while the current one runs
+    static mut STAGED: Vec<u32> = Vec::new();
+    // notified as a subkernel finishes, or its satellite is lost, as a message or an RPC
+    // from one arrives, and as a message is dropped, any of which may end an await
+    static mut AWAIT_EVENT: Event = Event::new();
+    // longest a kernel awaiting a message sleeps between checks for messages that stalled,
+    // read from the subkernel_await_poll_ms config key as each session opens
+    static mut AWAIT_POLL_MS: u64 = AWAIT_POLL_DEFAULT_MS;
+
+    // limits on what the subkernels of a session take up, so that one experiment cannot
+    // exhaust the memory of the satellites, or of the core device, for the others;
+    // read from the config as each session opens, 0 leaves one unlimited
+    struct Quotas {
+        // library and symbol bytes kept on a destination, persistent subkernels included
+        destination_bytes: usize,
+        // complete messages from subkernels the kernel did not take yet
+        queued_messages: usize,
+        // subkernels running at once
+        running: usize
+    }
+
+    const NO_QUOTAS: Quotas = Quotas { destination_bytes: 0, queued_messages: 0, running: 0 };
+    static mut QUOTAS: Quotas = NO_QUOTAS;
+
+    const KEEPALIVE_PERIOD_MS: u64 = 500;
+    const KEEPALIVE_MISSED_MAX: u8 = 3;
+    // how long a session ending waits for the satellites to stop its subkernels
+    const TEARDOWN_TIMEOUT_MS: u64 = 200;
+    const TEARDOWN_POLL_MS: u64 = 10;
+    const AWAIT_POLL_DEFAULT_MS: u64 = 10;
+    const AWAIT_POLL_MAX_MS: u64 = 1000;
+    const REATTACH_GRACE_MS: u64 = 10_000;
 
-    pub fn add_subkernel(io: &Io, subkernel_mutex: &Mutex, id: u32, destination: u8, kernel: Vec<u8>) {
+    // what the subkernels of a session did, kept until the next session starts, see `stats`
+    struct SessionStats {
+        session: u32,
+        uploaded: u32,
+        upload_skipped: u32,
+        upload_bytes: u64,
+        runs: u32,
+        finished: u32,
+        run_time_us: u64,
+        messages_sent: u32,
+        messages_received: u32,
+        // exceptions, lost communication and failed uploads
+        failures: u32
+    }
+
+    const NO_STATS: SessionStats = SessionStats {
+        session: 0, uploaded: 0, upload_skipped: 0, upload_bytes: 0, runs: 0, finished: 0,
+        run_time_us: 0, messages_sent: 0, messages_received: 0, failures: 0
+    };
+    static mut STATS: SessionStats = NO_STATS;
+
+    pub const STATS_COUNT: usize = 10;
+
+    /// Counters of the subkernels of the current session, or of the last one if none is
+    /// running: the session tag, subkernels uploaded, uploads skipped as the subkernel was
+    /// still on its satellite, bytes uploaded, runs, runs finished, their average duration
+    /// in us, messages sent and received and failures.
+    pub fn stats(io: &Io, subkernel_mutex: &Mutex, clear: bool) -> Result<[u32; STATS_COUNT], Error> {
+        let _lock = subkernel_mutex.lock(io)?;
+        let stats = unsafe { &mut STATS };
+        let average_run_us = match stats.finished {
+            0 => 0,
+            finished => stats.run_time_us / finished as u64
+        };
+        let counters = [
+            stats.session,
+            stats.uploaded,
+            stats.upload_skipped,
+            stats.upload_bytes.min(u32::max_value() as u64) as u32,
+            stats.runs,
+            stats.finished,
+            average_run_us.min(u32::max_value() as u64) as u32,
+            stats.messages_sent,
+            stats.messages_received,
+            stats.failures
+        ];
+        if clear {
+            *stats = SessionStats { session: stats.session, ..NO_STATS };
+        }
+        Ok(counters)
+    }
+
+    /// IDs, destinations and states of the subkernels of the active session. Does not
+    /// block, so may be read without the subkernel mutex, e.g. by the moninj feed.
+    pub fn states() -> Vec<(u32, u8, SubkernelState)> {
+        unsafe { SUBKERNELS.iter() }
+            .map(|(&id, subkernel)| (id, subkernel.destination, subkernel.state))
+            .collect()
+    }
+
+    fn count_upload(bytes: usize) {
+        let stats = unsafe { &mut STATS };
+        stats.uploaded += 1;
+        stats.upload_bytes += bytes as u64;
+    }
+
+    fn count_failure() {
+        unsafe { STATS.failures += 1 }
+    }
+
+    fn check_route(destination: u8) -> Result<(), Error> {
+        match unsafe { MOVED_DESTINATIONS[destination as usize] } {
+            true => Err(Error::RouteChanged),
+            false => Ok(())
+        }
+    }
+
+    // bytes the subkernels other than `id` take up on the destination
+    fn stored_bytes(destination: u8, id: u32) -> usize {
+        unsafe { SUBKERNELS.iter().chain(PERSISTENT.iter()) }
+            .filter(|&(&other, subkernel)| other != id && subkernel.destination == destination)
+            .map(|(_, subkernel)| subkernel.data.len() + subkernel.symbols.len())
+            .sum()
+    }
+
+    pub fn add_subkernel(io: &Io, subkernel_mutex: &Mutex, id: u32, destination: u8,
+            kernel: Vec<u8>, symbols: Vec<u8>, persistent: bool) -> Result<(), Error> {
         let _lock = subkernel_mutex.lock(io).unwrap();
-        unsafe { SUBKERNELS.insert(id, Subkernel::new(destination, kernel)); }
+        let quota = unsafe { QUOTAS.destination_bytes };
+        if quota != 0 {
+            let stored = stored_bytes(destination, id) + kernel.len() + symbols.len();
+            if stored > quota {
+                return Err(Error::StorageQuotaExceeded(destination, stored, quota))
+            }
+        }
+        // any other library uploaded under the ID replaces the one kept on the satellite
+        let warm = match unsafe { PERSISTENT.remove(&id) } {
+            Some(kept) => kept.destination == destination && kept.data == kernel && kept.symbols == symbols,
+            None => false
+        };
+        let mut subkernel = Subkernel::new(destination, kernel, symbols, persistent);
+        if warm {
+            debug!("subkernel #{} is still uploaded, skipping its upload", id);
+            subkernel.state = SubkernelState::Uploaded;
+            unsafe { STATS.upload_skipped += 1 }
+        }
+        unsafe { SUBKERNELS.insert(id, subkernel); }
+        Ok(())
+    }
+
+    fn upload_with_symbols(io: &Io, routing_table: &RoutingTable,
+            id: u32, subkernel: &Subkernel) -> Result<(), &'static str> {
+        let patch = match unsafe { UPLOADED.get(&id) } {
+            Some(&(destination, ref base)) if destination == subkernel.destination =>
+                Some(delta::encode(base, &subkernel.data)),
+            _ => None
+        };
+        let patched = match patch {
+            // unrelated libraries share little, they are cheaper to send whole
+            Some(ref patch) if patch.len() < subkernel.data.len() => {
+                match drtio::subkernel_upload_delta(io, routing_table, id,
+                        subkernel.destination, patch) {
+                    Ok(()) => {
+                        count_upload(patch.len() + subkernel.symbols.len());
+                        true
+                    }
+                    Err(e) => {
+                        warn!("delta upload of subkernel #{} failed ({}), sending it whole", id, e);
+                        false
+                    }
+                }
+            },
+            _ => false
+        };
+        if !patched {
+            unsafe { UPLOADED.remove(&id); }
+            drtio::subkernel_upload(io, routing_table, id,
+                subkernel.destination, &subkernel.data)?;
+            count_upload(subkernel.data.len() + subkernel.symbols.len());
+        }
+        unsafe { UPLOADED.insert(id, (subkernel.destination, subkernel.data.clone())); }
+        // symbol table is optional, only used to annotate backtraces
+        if !subkernel.symbols.is_empty() {
+            drtio::subkernel_upload_symbols(io, routing_table, id,
+                subkernel.destination, &subkernel.symbols)?;
+        }
+        Ok(())
     }
 
-    pub fn upload(io: &Io, aux_mutex: &Mutex, subkernel_mutex: &Mutex, 
+    pub fn upload(io: &Io, subkernel_mutex: &Mutex, 
              routing_table: &RoutingTable, id: u32) -> Result<(), Error> {
         let _lock = subkernel_mutex.lock(io).unwrap();
         let subkernel = unsafe { SUBKERNELS.get_mut(&id).unwrap() };
-        drtio::subkernel_upload(io, aux_mutex, routing_table, id, 
-            subkernel.destination, &subkernel.data)?;
+        check_route(subkernel.destination)?;
+        if subkernel.state == SubkernelState::Uploaded {
+            return Ok(())
+        }
+        if let Err(e) = upload_with_symbols(io, routing_table, id, subkernel) {
+            count_failure();
+            return Err(e.into())
+        }
         subkernel.state = SubkernelState::Uploaded; 
         Ok(()) 
     }
 
-    pub fn load(io: &Io, aux_mutex: &Mutex, subkernel_mutex: &Mutex, routing_table: &RoutingTable,
+    /// Uploads several subkernels, those sent whole going to their satellites concurrently,
+    /// see drtio::subkernel_upload_broadcast.
+    pub fn upload_many(io: &Io, subkernel_mutex: &Mutex,
+             routing_table: &RoutingTable, ids: &[u32]) -> Result<(), Error> {
+        let _lock = subkernel_mutex.lock(io).unwrap();
+        let mut whole = Vec::new();
+        for &id in ids {
+            let subkernel = unsafe { SUBKERNELS.get_mut(&id).unwrap() };
+            check_route(subkernel.destination)?;
+            if subkernel.state == SubkernelState::Uploaded {
+                continue
+            }
+            match unsafe { UPLOADED.get(&id) } {
+                // sent as a delta against the previous library, on its own
+                Some(&(destination, _)) if destination == subkernel.destination => {
+                    if let Err(e) = upload_with_symbols(io, routing_table, id, subkernel) {
+                        count_failure();
+                        return Err(e.into())
+                    }
+                    subkernel.state = SubkernelState::Uploaded;
+                }
+                _ => whole.push(id)
+            }
+        }
+        {
+            let uploads: Vec<(u32, u8, &[u8])> = whole.iter()
+                .map(|&id| {
+                    let subkernel = unsafe { SUBKERNELS.get(&id).unwrap() };
+                    (id, subkernel.destination, &subkernel.data[..])
+                })
+                .collect();
+            if let Err(e) = drtio::subkernel_upload_broadcast(io, routing_table, &uploads) {
+                count_failure();
+                return Err(e.into())
+            }
+        }
+        for id in whole {
+            let subkernel = unsafe { SUBKERNELS.get_mut(&id).unwrap() };
+            count_upload(subkernel.data.len() + subkernel.symbols.len());
+            unsafe { UPLOADED.insert(id, (subkernel.destination, subkernel.data.clone())); }
+            if !subkernel.symbols.is_empty() {
+                drtio::subkernel_upload_symbols(io, routing_table, id,
+                    subkernel.destination, &subkernel.symbols)?;
+            }
+            subkernel.state = SubkernelState::Uploaded;
+        }
+        Ok(())
+    }
+
+    /// Checks that none of `ids` is used by the kernel running, which may still start or
+    /// await them, before they are replaced by those of the next kernel.
+    pub fn check_replaceable(io: &Io, subkernel_mutex: &Mutex, ids: &[u32]) -> Result<(), Error> {
+        let _lock = subkernel_mutex.lock(io)?;
+        for &id in ids {
+            match unsafe { SUBKERNELS.get(&id).map(|subkernel| subkernel.state) } {
+                Some(SubkernelState::Running) | Some(SubkernelState::Finished { .. }) =>
+                    return Err(Error::InUse(id)),
+                _ => ()
+            }
+        }
+        Ok(())
+    }
+
+    /// Leaves the upload of the subkernels `ids`, added already, to the link thread, see
+    /// `upload_staged`, rather than have the kernel running wait for it. Loading one whose
+    /// upload did not happen yet uploads it then.
+    pub fn stage(io: &Io, subkernel_mutex: &Mutex, ids: &[u32]) {
+        let _lock = subkernel_mutex.lock(io).unwrap();
+        for &id in ids {
+            let subkernel = unsafe { SUBKERNELS.get_mut(&id).unwrap() };
+            if subkernel.state == SubkernelState::NotLoaded {
+                subkernel.staged = true;
+                unsafe { STAGED.push(id) }
+            }
+        }
+    }
+
+    /// Uploads the next staged subkernel, called by the link thread; one at a time, so that
+    /// the links, and the subkernels of the kernel running, are served in between.
+    pub fn upload_staged(io: &Io, subkernel_mutex: &Mutex, routing_table: &RoutingTable) {
+        if unsafe { STAGED.is_empty() } {
+            return
+        }
+        let _lock = match subkernel_mutex.lock(io) {
+            Ok(lock) => lock,
+            Err(_) => return
+        };
+        let id = unsafe { STAGED.remove(0) };
+        let subkernel = match unsafe { SUBKERNELS.get_mut(&id) } {
+            // may have been loaded, replaced, or ended with its session meanwhile
+            Some(subkernel) if subkernel.staged && subkernel.state == SubkernelState::NotLoaded => subkernel,
+            _ => return
+        };
+        if check_route(subkernel.destination).is_err() {
+            // reported as the kernel loads it
+            return
+        }
+        match upload_with_symbols(io, routing_table, id, subkernel) {
+            Ok(()) => {
+                subkernel.staged = false;
+                subkernel.state = SubkernelState::Uploaded;
+            }
+            Err(e) => {
+                count_failure();
+                warn!("[DEST#{}] staged upload of subkernel #{} failed ({}), retrying as it is loaded",
+                      subkernel.destination, id, e);
+            }
+        }
+    }
+
+    // messages of subkernels on the destination to those on other satellites are relayed there
+    fn send_peers(io: &Io, routing_table: &RoutingTable, destination: u8) -> Result<(), Error> {
+        let peers: Vec<(u32, u8)> = unsafe { SUBKERNELS.iter() }
+            .filter(|&(_, subkernel)| subkernel.destination != destination)
+            .map(|(&id, subkernel)| (id, subkernel.destination))
+            .collect();
+        for (id, peer) in peers {
+            drtio::subkernel_add_peer(io, routing_table, destination, id, peer)?;
+        }
+        Ok(())
+    }
+
+    pub fn load(io: &Io, subkernel_mutex: &Mutex, routing_table: &RoutingTable,
             id: u32, run: bool) -> Result<(), Error> {
         let _lock = subkernel_mutex.lock(io).unwrap();
         let subkernel = unsafe { SUBKERNELS.get_mut(&id).unwrap() };
+        check_route(subkernel.destination)?;
+        if subkernel.staged && subkernel.state == SubkernelState::NotLoaded {
+            if let Err(e) = upload_with_symbols(io, routing_table, id, subkernel) {
+                count_failure();
+                return Err(e.into())
+            }
+            subkernel.staged = false;
+            subkernel.state = SubkernelState::Uploaded;
+        }
         if subkernel.state != SubkernelState::Uploaded {
             return Err(Error::IncorrectState);
         }
-        drtio::subkernel_load(io, aux_mutex, routing_table, id, subkernel.destination, run)?;
+        let quota = unsafe { QUOTAS.running };
+        if run && quota != 0 {
+            let running = unsafe { SUBKERNELS.values() }
+                .filter(|subkernel| subkernel.state == SubkernelState::Running)
+                .count();
+            if running >= quota {
+                return Err(Error::RunningQuotaExceeded(running))
+            }
+        }
+        if run && !unsafe { PEERS_SENT[subkernel.destination as usize] } {
+            send_peers(io, routing_table, subkernel.destination)?;
+            unsafe { PEERS_SENT[subkernel.destination as usize] = true; }
+        }
+        drtio::subkernel_load(io, routing_table, id, subkernel.destination, run)?;
+        // the satellite starts over with the initial credits
+        subkernel.message_credits = SUBKERNEL_MESSAGE_CREDITS;
+        unsafe { PENDING_CREDITS.remove(&id) };
         if run {
             subkernel.state = SubkernelState::Running;
+            subkernel.missed_keepalives = 0;
+            subkernel.started_us = clock::get_us();
+            unsafe { STATS.runs += 1 }
         }
         Ok(())
     }
 
-    pub fn clear_subkernels(io: &Io, subkernel_mutex: &Mutex) {
+    /// Starts the namespace of a new host session, which becomes the active one.
+    /// Returns its tag, to be given to `close_session` when the session ends.
+    pub fn open_session(io: &Io, subkernel_mutex: &Mutex,
+            routing_table: &RoutingTable) -> u32 {
         let _lock = subkernel_mutex.lock(io).unwrap();
+        stop_running(io, routing_table);
+        keep_persistent();
+        let previous = unsafe { mem::replace(&mut SUBKERNELS, BTreeMap::new()) };
+        unsafe {
+            DETACHED_UNTIL_MS = None;
+            if ACTIVE_SESSION != 0 {
+                INACTIVE_SESSIONS.insert(ACTIVE_SESSION, previous);
+            }
+            ACTIVE_SESSION = NEXT_SESSION;
+            NEXT_SESSION = NEXT_SESSION.wrapping_add(1).max(1);
+            AWAIT_POLL_MS = read_await_poll_ms();
+            QUOTAS = read_quotas();
+            STATS = SessionStats { session: ACTIVE_SESSION, ..NO_STATS };
+            clear_traffic();
+            ACTIVE_SESSION
+        }
+    }
+
+    /// Drops the subkernels of the session `tag`, stopping those still running
+    /// if it is the active session.
+    pub fn close_session(io: &Io, subkernel_mutex: &Mutex,
+            routing_table: &RoutingTable, tag: u32) {
+        let _lock = subkernel_mutex.lock(io).unwrap();
+        if tag != unsafe { ACTIVE_SESSION } {
+            // replaced by a newer session, its subkernels were stopped then
+            unsafe { INACTIVE_SESSIONS.remove(&tag) };
+            return
+        }
+        stop_running(io, routing_table);
+        keep_persistent();
         unsafe {
             SUBKERNELS = BTreeMap::new();
-            MESSAGE_QUEUE = Vec::new();
-            CURRENT_MESSAGES = BTreeMap::new();
+            ACTIVE_SESSION = 0;
+            DETACHED_UNTIL_MS = None;
+            clear_traffic();
+        }
+    }
+
+    /// Ends the session `tag` as its host is gone. Its subkernels are left running, and
+    /// their results and messages kept, for REATTACH_GRACE_MS, for the host to take them
+    /// back with `reattach_session` if it reconnects; past that, they are stopped.
+    pub fn detach_session(io: &Io, subkernel_mutex: &Mutex,
+            routing_table: &RoutingTable, tag: u32) {
+        {
+            let _lock = subkernel_mutex.lock(io).unwrap();
+            let pending = tag == unsafe { ACTIVE_SESSION } && unsafe {
+                SUBKERNELS.values().any(|subkernel| match subkernel.state {
+                    SubkernelState::Running | SubkernelState::Finished { .. } => true,
+                    _ => false
+                }) || !MESSAGE_QUEUE.is_empty() || !RPC_QUEUE.is_empty()
+            };
+            if pending {
+                info!("keeping subkernels of session {} for {} ms for its host to reattach",
+                      tag, REATTACH_GRACE_MS);
+                unsafe { DETACHED_UNTIL_MS = Some(clock::get_ms() + REATTACH_GRACE_MS) }
+                return
+            }
+        }
+        close_session(io, subkernel_mutex, routing_table, tag)
+    }
+
+    /// Makes the detached session `tag` the session of the host that reconnected, with
+    /// its subkernels as they are. Returns false if no such session waits for its host.
+    pub fn reattach_session(io: &Io, subkernel_mutex: &Mutex, tag: u32) -> bool {
+        let _lock = subkernel_mutex.lock(io).unwrap();
+        let detached = unsafe { DETACHED_UNTIL_MS }.map_or(false, |until| clock::get_ms() <= until);
+        if tag == 0 || tag != unsafe { ACTIVE_SESSION } || !detached {
+            return false
         }
+        info!("host reattached to session {}", tag);
+        unsafe { DETACHED_UNTIL_MS = None }
+        true
     }
 
-    pub fn subkernel_finished(io: &Io, subkernel_mutex: &Mutex, id: u32, with_exception: bool) {
+    // stops the subkernels of a detached session once no host reattached in time
+    fn expire_detached(io: &Io, subkernel_mutex: &Mutex, routing_table: &RoutingTable) {
+        if !unsafe { DETACHED_UNTIL_MS }.map_or(false, |until| clock::get_ms() > until) {
+            return
+        }
+        let tag = unsafe { ACTIVE_SESSION };
+        warn!("no host reattached to session {}, stopping its subkernels", tag);
+        close_session(io, subkernel_mutex, routing_table, tag)
+    }
+
+    // subkernels left running by a session would hold their satellites, with nobody to
+    // await them; satellites stop them asynchronously, so they are then asked whether
+    // they still run them until they all comply or TEARDOWN_TIMEOUT_MS passes
+    fn stop_running(io: &Io, routing_table: &RoutingTable) {
+        let mut stopping = Vec::new();
+        for (id, subkernel) in unsafe { SUBKERNELS.iter() } {
+            if subkernel.state != SubkernelState::Running {
+                continue
+            }
+            match drtio::subkernel_stop(io, routing_table, *id, subkernel.destination) {
+                Ok(()) => stopping.push((*id, subkernel.destination)),
+                Err(e) => warn!("[DEST#{}] cannot stop subkernel #{}: {}", subkernel.destination, id, e)
+            }
+        }
+        let deadline = clock::get_ms() + TEARDOWN_TIMEOUT_MS;
+        while !stopping.is_empty() {
+            // an aux error leaves the subkernel to be asked again
+            stopping.retain(|&(id, destination)|
+                drtio::subkernel_keepalive(io, routing_table, id, destination).unwrap_or(true));
+            if stopping.is_empty() || clock::get_ms() > deadline || io.sleep(TEARDOWN_POLL_MS).is_err() {
+                break
+            }
+        }
+        for (id, destination) in stopping {
+            error!("[DEST#{}] subkernel #{} is still running after its session ended", destination, id);
+        }
+    }
+
+    // takes the persistent subkernels of the active session that made it to their satellites
+    fn keep_persistent() {
+        let ids: Vec<u32> = unsafe { SUBKERNELS.iter() }
+            .filter(|&(_, subkernel)| subkernel.persistent && match subkernel.state {
+                SubkernelState::NotLoaded | SubkernelState::Finished { status: FinishStatus::CommLost } => false,
+                _ => true
+            })
+            .map(|(&id, _)| id)
+            .collect();
+        for id in ids {
+            let mut subkernel = unsafe { SUBKERNELS.remove(&id).unwrap() };
+            subkernel.state = SubkernelState::Uploaded;
+            subkernel.exception = Vec::new();
+            subkernel.exception_complete = false;
+            unsafe { PERSISTENT.insert(id, subkernel); }
+        }
+    }
+
+    fn forget_persistent(destination: u8) {
+        let ids: Vec<u32> = unsafe { PERSISTENT.iter() }
+            .filter(|&(_, subkernel)| subkernel.destination == destination)
+            .map(|(&id, _)| id)
+            .collect();
+        for id in ids {
+            unsafe { PERSISTENT.remove(&id); }
+        }
+    }
+
+    fn read_await_poll_ms() -> u64 {
+        config::read_str("subkernel_await_poll_ms", |result| {
+            match result.map(|value| value.parse::<u64>()) {
+                Ok(Ok(poll_ms)) if poll_ms > 0 => poll_ms.min(AWAIT_POLL_MAX_MS),
+                Ok(_) => {
+                    warn!("invalid subkernel_await_poll_ms, using {} ms", AWAIT_POLL_DEFAULT_MS);
+                    AWAIT_POLL_DEFAULT_MS
+                }
+                Err(_) => AWAIT_POLL_DEFAULT_MS
+            }
+        })
+    }
+
+    fn read_quotas() -> Quotas {
+        fn read_quota(key: &str) -> usize {
+            config::read_str(key, |result| {
+                match result.map(|value| value.parse::<usize>()) {
+                    Ok(Ok(quota)) => quota,
+                    Ok(Err(_)) => {
+                        warn!("invalid {}, leaving it unlimited", key);
+                        0
+                    }
+                    Err(_) => 0
+                }
+            })
+        }
+
+        Quotas {
+            destination_bytes: read_quota("subkernel_quota_bytes"),
+            queued_messages: read_quota("subkernel_quota_messages"),
+            running: read_quota("subkernel_quota_running")
+        }
+    }
+
+    // messages, RPCs and staged uploads in flight belong to the active session
+    unsafe fn clear_traffic() {
+        MESSAGE_QUEUE = Vec::new();
+        CURRENT_MESSAGES = BTreeMap::new();
+        ABORTED_MESSAGES = Vec::new();
+        PENDING_CREDITS = BTreeMap::new();
+        RPC_QUEUE = Vec::new();
+        CURRENT_RPCS = BTreeMap::new();
+        PEERS_SENT = [false; DEST_COUNT];
+        STAGED = Vec::new();
+    }
+
+    pub fn subkernel_finished(io: &Io, subkernel_mutex: &Mutex, id: u32, with_exception: bool,
+            exception_head: &[u8], last: bool) {
         // called upon receiving DRTIO SubkernelRunDone
         let _lock = subkernel_mutex.lock(io).unwrap();
         let subkernel = unsafe { SUBKERNELS.get_mut(&id) };
         // may be None if session ends and is cleared
         if let Some(subkernel) = subkernel {
+            let stats = unsafe { &mut STATS };
+            stats.finished += 1;
+            stats.run_time_us += clock::get_us().saturating_sub(subkernel.started_us);
+            if with_exception {
+                stats.failures += 1;
+            }
+            subkernel.exception = exception_head.to_vec();
+            subkernel.exception_complete = with_exception && last;
             subkernel.state = SubkernelState::Finished {
                 status: match with_exception {
                 true => FinishStatus::Exception,
                 false => FinishStatus::Ok,
                 }
-            }
+            };
+            unsafe { AWAIT_EVENT.notify() }
         }
     }
 
-    pub fn destination_changed(io: &Io, aux_mutex: &Mutex, subkernel_mutex: &Mutex,
+    pub fn destination_changed(io: &Io, subkernel_mutex: &Mutex,
              routing_table: &RoutingTable, destination: u8, up: bool) {
         let _lock = subkernel_mutex.lock(io).unwrap();
+        // kept subkernels may be gone as well
+        forget_persistent(destination);
         let subkernels_iter = unsafe { SUBKERNELS.iter_mut() };
         for (id, subkernel) in subkernels_iter {
             if subkernel.destination == destination {
+                // may have restarted and lost them
+                unsafe { PEERS_SENT[destination as usize] = false; }
                 if up {
-                    match drtio::subkernel_upload(io, aux_mutex, routing_table, *id, destination, &subkernel.data)
+                    match upload_with_symbols(io, routing_table, *id, subkernel)
                     {
                         Ok(_) => subkernel.state = SubkernelState::Uploaded,
                         Err(e) => error!("Error adding subkernel on destination {}: {}", destination, e)
                     }
                 } else {
                     subkernel.state = match subkernel.state {
-                        SubkernelState::Running => SubkernelState::Finished { status: FinishStatus::CommLost },
+                        SubkernelState::Running => {
+                            count_failure();
+                            unsafe { AWAIT_EVENT.notify() }
+                            SubkernelState::Finished { status: FinishStatus::CommLost }
+                        },
                         _ => SubkernelState::NotLoaded,
                     }
                 }
@@ -253,69 +849,308 @@ pub mod subkernel {
         }
     }
 
-    pub fn retrieve_finish_status(io: &Io, aux_mutex: &Mutex, subkernel_mutex: &Mutex,
+    /// Checks that the satellites running subkernels still answer, called by the link thread.
+    /// A subkernel whose satellite misses KEEPALIVE_MISSED_MAX keepalives in a row is finished
+    /// as CommLost, so that a kernel awaiting it does not have to wait for its own timeout.
+    pub fn keepalive(io: &Io, subkernel_mutex: &Mutex, routing_table: &RoutingTable) {
+        if clock::get_ms() < unsafe { NEXT_KEEPALIVE_MS } {
+            return
+        }
+        unsafe { NEXT_KEEPALIVE_MS = clock::get_ms() + KEEPALIVE_PERIOD_MS }
+        expire_detached(io, subkernel_mutex, routing_table);
+        let running: Vec<(u32, u8)> = {
+            let _lock = match subkernel_mutex.lock(io) {
+                Ok(lock) => lock,
+                Err(_) => return
+            };
+            unsafe { SUBKERNELS.iter() }
+                .filter(|&(_, subkernel)| subkernel.state == SubkernelState::Running)
+                .map(|(&id, subkernel)| (id, subkernel.destination))
+                .collect()
+        };
+        for (id, destination) in running {
+            // the satellite is not asked while we hold the lock, others may need it meanwhile
+            let answered = match drtio::subkernel_keepalive(io, routing_table, id, destination) {
+                Ok(_) => true,
+                Err(e) => {
+                    warn!("[DEST#{}] no keepalive from subkernel #{} ({})", destination, id, e);
+                    false
+                }
+            };
+            let _lock = match subkernel_mutex.lock(io) {
+                Ok(lock) => lock,
+                Err(_) => return
+            };
+            let subkernel = match unsafe { SUBKERNELS.get_mut(&id) } {
+                Some(subkernel) if subkernel.state == SubkernelState::Running => subkernel,
+                _ => continue
+            };
+            if answered {
+                subkernel.missed_keepalives = 0;
+                continue
+            }
+            subkernel.missed_keepalives += 1;
+            if subkernel.missed_keepalives >= KEEPALIVE_MISSED_MAX {
+                error!("[DEST#{}] lost communication with subkernel #{}, {} keepalives missed",
+                       destination, id, KEEPALIVE_MISSED_MAX);
+                subkernel.state = SubkernelState::Finished { status: FinishStatus::CommLost };
+                count_failure();
+                unsafe { AWAIT_EVENT.notify() }
+            }
+        }
+    }
+
+    pub fn routing_table_changed(io: &Io, subkernel_mutex: &Mutex,
+            old_table: &RoutingTable, new_table: &RoutingTable) {
+        // called when a new routing table is read, before it comes into effect
+        let _lock = subkernel_mutex.lock(io).unwrap();
+        for destination in 0..DEST_COUNT {
+            if old_table.0[destination] != new_table.0[destination] {
+                unsafe { MOVED_DESTINATIONS[destination] = true; }
+                forget_persistent(destination as u8);
+            }
+        }
+        for (id, subkernel) in unsafe { SUBKERNELS.iter_mut() } {
+            if unsafe { MOVED_DESTINATIONS[subkernel.destination as usize] } {
+                subkernel.state = match subkernel.state {
+                    SubkernelState::Running => {
+                        warn!("route to subkernel #{} changed while it was running", id);
+                        count_failure();
+                        unsafe { AWAIT_EVENT.notify() }
+                        SubkernelState::Finished { status: FinishStatus::CommLost }
+                    },
+                    state @ SubkernelState::Finished { .. } => state,
+                    _ => SubkernelState::NotLoaded
+                }
+            }
+        }
+    }
+
+    pub fn routing_table_applied(io: &Io, subkernel_mutex: &Mutex) {
+        // moved destinations are surveyed again, their subkernels uploaded once they are up
+        let _lock = subkernel_mutex.lock(io).unwrap();
+        unsafe { MOVED_DESTINATIONS = [false; DEST_COUNT]; }
+    }
+
+    pub fn print_forwarded_log(destination: u8, id: u32, mut data: &[u8]) {
+        // log records pushed by satellite, so they end up in the core device log
+        while let Some((record, size)) = LogRecord::read_from(data) {
+            let level = match record.level {
+                1 => ::log::Level::Error,
+                2 => ::log::Level::Warn,
+                3 => ::log::Level::Info,
+                4 => ::log::Level::Debug,
+                _ => ::log::Level::Trace
+            };
+            let target = match record.target {
+                LOG_TARGET_KERNEL => "kernel",
+                LOG_TARGET_DRTIO => "drtio",
+                LOG_TARGET_MANAGER => "manager",
+                _ => "satman"
+            };
+            log!(target: "kernel", level, "[DEST#{}][SUBKERNEL#{}] [{:6}.{:03}s] {}: {}",
+                 destination, id, record.timestamp / 1000, record.timestamp % 1000, target,
+                 String::from_utf8_lossy(record.message));
+            data = &data[size..];
+        }
+    }
+
+    pub fn retrieve_finish_status(io: &Io, subkernel_mutex: &Mutex,
         routing_table: &RoutingTable, id: u32) -> Result<SubkernelFinished, Error> {
         let _lock = subkernel_mutex.lock(io)?;
         let mut subkernel = unsafe { SUBKERNELS.get_mut(&id).unwrap() };
         match subkernel.state {
             SubkernelState::Finished { status } => {
                 subkernel.state = SubkernelState::Uploaded;
+                let exception = if status == FinishStatus::Exception {
+                    let head = mem::replace(&mut subkernel.exception, Vec::new());
+                    Some(drtio::subkernel_retrieve_exception(io,
+                        routing_table, subkernel.destination, id, head, subkernel.exception_complete)?)
+                } else { None };
                 Ok(SubkernelFinished {
                     id: id,
                     comm_lost: status == FinishStatus::CommLost,
-                    exception: if status == FinishStatus::Exception { 
-                        Some(drtio::subkernel_retrieve_exception(io, aux_mutex,
-                            routing_table, subkernel.destination)?) 
-                    } else { None }
+                    exception: exception
                 })
             },
             _ => Err(Error::IncorrectState)
         }
     }
 
-    pub fn await_finish(io: &Io, aux_mutex: &Mutex, subkernel_mutex: &Mutex,
-        routing_table: &RoutingTable, id: u32, timeout: u64) -> Result<SubkernelFinished, Error> {
-        {
-            let _lock = subkernel_mutex.lock(io)?;
-            match unsafe { SUBKERNELS.get(&id).unwrap().state } {
-                SubkernelState::Running | SubkernelState::Finished { .. } => (),
-                _ => return Err(Error::IncorrectState)
-            }
+    fn watchdog_expired(watchdog: Option<u64>) -> bool {
+        watchdog.map_or(false, |deadline| clock::get_ms() > deadline)
+    }
+
+    /// Stops subkernel `id` as the session watchdog expired while the kernel awaited it,
+    /// see the `watchdog` deadline of `await_finish` and `message_await`. The session
+    /// then ends, confirming that the satellite stopped it along with the others.
+    pub fn abort(io: &Io, subkernel_mutex: &Mutex, routing_table: &RoutingTable,
+            id: u32) {
+        let _lock = match subkernel_mutex.lock(io) {
+            Ok(lock) => lock,
+            Err(_) => return
+        };
+        let subkernel = match unsafe { SUBKERNELS.get_mut(&id) } {
+            Some(subkernel) => subkernel,
+            None => return
+        };
+        if subkernel.state != SubkernelState::Running {
+            return
+        }
+        warn!("[DEST#{}] session watchdog expired, stopping subkernel #{}", subkernel.destination, id);
+        if let Err(e) = drtio::subkernel_stop(io, routing_table, id, subkernel.destination) {
+            error!("[DEST#{}] cannot stop subkernel #{}: {}", subkernel.destination, id, e);
         }
+        count_failure();
+    }
+
+    pub fn await_finish(io: &Io, subkernel_mutex: &Mutex,
+        routing_table: &RoutingTable, id: u32, timeout: u64, watchdog: Option<u64>
+    ) -> Result<SubkernelFinished, Error> {
         let max_time = clock::get_ms() + timeout as u64;
-        let _res = io.until(|| {
-            if clock::get_ms() > max_time {
-                return true;
+        let deadline = watchdog.map_or(max_time, |watchdog| min(watchdog, max_time));
+        loop {
+            {
+                let _lock = subkernel_mutex.lock(io)?;
+                if rpc_pending() {
+                    // the subkernel may be waiting on it, so it has to be served before finishing
+                    return Err(Error::RpcPending);
+                }
+                match unsafe { SUBKERNELS.get(&id).map(|subkernel| subkernel.state) } {
+                    Some(SubkernelState::Finished { .. }) => break,
+                    Some(SubkernelState::Running) => (),
+                    _ => return Err(Error::IncorrectState)
+                }
             }
-            if subkernel_mutex.test_lock() {
-                // cannot lock again within io.until - scheduler guarantees
-                // that it will not be interrupted - so only test the lock
-                return false;
+            if watchdog_expired(watchdog) {
+                return Err(Error::WatchdogExpired);
             }
-            let subkernel = unsafe { SUBKERNELS.get(&id).unwrap() };
-            match subkernel.state {
-                SubkernelState::Finished { .. } => true,
-                _ => false
+            if clock::get_ms() > max_time {
+                error!("Remote subkernel finish await timed out");
+                return Err(Error::Timeout);
             }
-        })?;
-        if clock::get_ms() > max_time {
-            error!("Remote subkernel finish await timed out");
-            return Err(Error::Timeout);
+            // nothing else may end the wait, see AWAIT_EVENT
+            io.wait(unsafe { &AWAIT_EVENT }, Some(deadline + 1))?;
         }
-        retrieve_finish_status(io, aux_mutex, subkernel_mutex, routing_table, id)
+        retrieve_finish_status(io, subkernel_mutex, routing_table, id)
     }
 
     pub struct Message {
         from_id: u32,
         pub tag_count: u8,
-        pub tag: u8,
+        // master clock when the subkernel sent it, 0 if the satellite clock is not known yet
+        pub master_us: u64,
+        // arguments, each with its tags inline
         pub data: Vec<u8>
     }
 
+    struct PartialMessage {
+        tag_count: u8,
+        master_us: u64,
+        fragments: Reassembler
+    }
+
     // FIFO queue of messages
     static mut MESSAGE_QUEUE: Vec<Message> = Vec::new();
     // currently under construction message(s) (can be from multiple sources)
-    static mut CURRENT_MESSAGES: BTreeMap<u32, Message> = BTreeMap::new();
+    static mut CURRENT_MESSAGES: BTreeMap<u32, PartialMessage> = BTreeMap::new();
+    // subkernels whose message under construction was dropped, not yet told to the kernel
+    static mut ABORTED_MESSAGES: Vec<u32> = Vec::new();
+
+    // credits to grant to subkernels, sent by the link thread
+    static mut PENDING_CREDITS: BTreeMap<u32, u8> = BTreeMap::new();
+
+    // a message with no new slice for that long is considered abandoned by the satellite
+    const MESSAGE_STALL_TIMEOUT_MS: u64 = 2000;
+
+    fn abort_message(id: u32) {
+        unsafe {
+            CURRENT_MESSAGES.remove(&id);
+            if !ABORTED_MESSAGES.contains(&id) {
+                ABORTED_MESSAGES.push(id);
+            }
+            AWAIT_EVENT.notify()
+        }
+        refill_credits(id);
+    }
+
+    // gives back the credits of messages taken or dropped, batched by half the window
+    // rather than one for every message, unless the satellite has none left
+    fn refill_credits(id: u32) {
+        let subkernel = match unsafe { SUBKERNELS.get_mut(&id) } {
+            Some(subkernel) => subkernel,
+            None => return
+        };
+        let pending = unsafe { PENDING_CREDITS.get(&id).cloned().unwrap_or(0) };
+        let held = subkernel.message_credits.saturating_add(pending);
+        let queued = unsafe { MESSAGE_QUEUE.iter() }.filter(|message| message.from_id == id).count() +
+            unsafe { CURRENT_MESSAGES.contains_key(&id) } as usize;
+        let owed = (SUBKERNEL_MESSAGE_CREDITS as usize).saturating_sub(held as usize + queued) as u8;
+        if owed > 0 && (held == 0 || owed >= SUBKERNEL_MESSAGE_CREDITS / 2) {
+            unsafe { PENDING_CREDITS.insert(id, pending + owed) };
+        }
+    }
+
+    /// Whether credits wait to be granted, may be called within io.until.
+    pub fn message_credits_pending() -> bool {
+        unsafe { !PENDING_CREDITS.is_empty() }
+    }
+
+    pub fn grant_message_credits(io: &Io, subkernel_mutex: &Mutex,
+            routing_table: &RoutingTable) {
+        let grants: Vec<(u32, u8, u8)> = {
+            let _lock = match subkernel_mutex.lock(io) {
+                Ok(lock) => lock,
+                Err(_) => return
+            };
+            let pending = unsafe { mem::replace(&mut PENDING_CREDITS, BTreeMap::new()) };
+            pending.into_iter()
+                .filter_map(|(id, credits)| unsafe { SUBKERNELS.get_mut(&id) }.map(|subkernel| {
+                    subkernel.message_credits = subkernel.message_credits.saturating_add(credits);
+                    (id, subkernel.destination, credits)
+                }))
+                .collect()
+        };
+        for (id, destination, credits) in grants {
+            if let Err(e) = drtio::subkernel_grant_credits(io, routing_table, id, destination, credits) {
+                warn!("cannot grant message credits to subkernel #{}: {}", id, e);
+            }
+        }
+    }
+
+    fn take_aborted_message(id: u32) -> bool {
+        unsafe {
+            match ABORTED_MESSAGES.iter().position(|&aborted| aborted == id) {
+                Some(index) => { ABORTED_MESSAGES.remove(index); true }
+                None => false
+            }
+        }
+    }
+
+    fn discard_stalled_messages(now: u64) {
+        let stalled: Vec<u32> = unsafe { CURRENT_MESSAGES.iter() }
+            .filter(|&(_, message)| message.fragments.stalled(now, MESSAGE_STALL_TIMEOUT_MS))
+            .map(|(&id, _)| id)
+            .collect();
+        for id in stalled {
+            warn!("dropping incomplete message from subkernel #{}, no data for {} ms", id, MESSAGE_STALL_TIMEOUT_MS);
+            abort_message(id);
+        }
+    }
+
+    /// Drops the messages still being received from subkernels on `destination`,
+    /// a kernel waiting for one of them gets an error instead. Returns how many were dropped.
+    pub fn message_cancel_incoming(io: &Io, subkernel_mutex: &Mutex, destination: u8) -> Result<usize, Error> {
+        let _lock = subkernel_mutex.lock(io)?;
+        let cancelled: Vec<u32> = unsafe { CURRENT_MESSAGES.keys() }
+            .filter(|id| unsafe { SUBKERNELS.get(id) }.map_or(true, |s| s.destination == destination))
+            .cloned()
+            .collect();
+        for &id in cancelled.iter() {
+            abort_message(id);
+        }
+        Ok(cancelled.len())
+    }
 
     pub fn message_handle_incoming(io: &Io, subkernel_mutex: &Mutex, 
         id: u32, last: bool, length: usize, data: &[u8; MASTER_PAYLOAD_MAX_SIZE]) {
@@ -329,26 +1164,77 @@ pub mod subkernel {
             // do not add messages for non-existing or deleted subkernels
             return
         }
-        match unsafe { CURRENT_MESSAGES.get_mut(&id) } {
-            Some(message) => message.data.extend(&data[..length]),
-            None => unsafe {
-                CURRENT_MESSAGES.insert(id, Message {
-                    from_id: id,
-                    tag_count: data[0],
-                    tag: data[1],
-                    data: data[2..length].to_vec()
-                });
+        let mut start = 0;
+        if unsafe { !CURRENT_MESSAGES.contains_key(&id) } {
+            // each message takes a credit, which comes back once it is taken or dropped
+            let subkernel = unsafe { SUBKERNELS.get_mut(&id).unwrap() };
+            subkernel.message_credits = subkernel.message_credits.saturating_sub(1);
+            // the first fragment starts with the tag count and the satellite clock when it was sent
+            start = 1 + SUBKERNEL_MESSAGE_STAMP_SIZE;
+            if length < start {
+                error!("dropping message from subkernel #{}: first fragment too short", id);
+                refill_credits(id);
+                return
             }
-        };
-        if last {
-            unsafe { 
+            let sent_us = data[1..start].iter().fold(0, |stamp, &byte| stamp << 8 | byte as u64);
+            let master_us = drtio::master_time_of(subkernel.destination, sent_us).unwrap_or(0);
+            unsafe { CURRENT_MESSAGES.insert(id, PartialMessage {
+                tag_count: data[0],
+                master_us: master_us,
+                fragments: Reassembler::new()
+            }) };
+        }
+        let message = unsafe { CURRENT_MESSAGES.get_mut(&id).unwrap() };
+        match message.fragments.push(clock::get_ms(), last, &data[start..length]) {
+            Ok(true) => {
                 // when done, remove from working queue
-                MESSAGE_QUEUE.push(CURRENT_MESSAGES.remove(&id).unwrap());
-            };
+                let partial = unsafe { CURRENT_MESSAGES.remove(&id).unwrap() };
+                queue_message(id, partial)
+            }
+            Ok(false) => (),
+            Err(e) => {
+                error!("dropping message from subkernel #{}: {}", id, e);
+                abort_message(id)
+            }
         }
     }
 
-    pub fn message_await(io: &Io, subkernel_mutex: &Mutex, id: u32, timeout: u64
+    fn queue_message(id: u32, mut partial: PartialMessage) {
+        let mut message = Message {
+            from_id: id,
+            tag_count: partial.tag_count,
+            master_us: partial.master_us,
+            data: partial.fragments.take()
+        };
+        if message.tag_count & compression::HEADER_COMPRESSED != 0 {
+            match compression::decompress(&message.data) {
+                Ok(data) => {
+                    message.tag_count &= !compression::HEADER_COMPRESSED;
+                    message.data = data;
+                }
+                Err(e) => {
+                    error!("dropping message from subkernel #{}: {}", id, e);
+                    refill_credits(id);
+                    return
+                }
+            }
+        }
+        let quota = unsafe { QUOTAS.queued_messages };
+        if quota != 0 && unsafe { MESSAGE_QUEUE.len() } >= quota {
+            // the kernel awaiting it is told it was dropped
+            error!("dropping message from subkernel #{}: {} messages are queued already, the most the quota allows",
+                   id, quota);
+            abort_message(id);
+            return
+        }
+        unsafe {
+            STATS.messages_received += 1;
+            MESSAGE_QUEUE.push(message);
+            AWAIT_EVENT.notify()
+        }
+    }
+
+    pub fn message_await(io: &Io, subkernel_mutex: &Mutex, id: u32, timeout: u64, watchdog: Option<u64>
     ) -> Result<Message, Error> {
         {
             let _lock = subkernel_mutex.lock(io)?;
@@ -359,57 +1245,161 @@ pub mod subkernel {
             }
         }
         let max_time = clock::get_ms() + timeout as u64;
-        let message = io.until_ok(|| {
-            if clock::get_ms() > max_time {
-                return Ok(None);
-            }
-            if subkernel_mutex.test_lock() {
-                return Err(());
-            }
-            let msg_len = unsafe { MESSAGE_QUEUE.len() };
-            for i in 0..msg_len {
-                let msg = unsafe { &MESSAGE_QUEUE[i] };
-                if msg.from_id == id {
-                    return Ok(Some(unsafe { MESSAGE_QUEUE.remove(i) }));
+        let deadline = watchdog.map_or(max_time, |watchdog| min(watchdog, max_time));
+        loop {
+            {
+                let _lock = subkernel_mutex.lock(io)?;
+                if rpc_pending() {
+                    return Err(Error::RpcPending);
+                }
+                discard_stalled_messages(clock::get_ms());
+                if take_aborted_message(id) {
+                    return Err(Error::ReceiveAborted);
+                }
+                let queued = unsafe { MESSAGE_QUEUE.iter() }.position(|message| message.from_id == id);
+                if let Some(index) = queued {
+                    let message = unsafe { MESSAGE_QUEUE.remove(index) };
+                    refill_credits(id);
+                    return Ok(message);
+                }
+                match unsafe { SUBKERNELS.get(&id).map(|subkernel| subkernel.state) } {
+                    Some(SubkernelState::Running) => (),
+                    Some(SubkernelState::Finished { .. }) => return Err(Error::SubkernelFinished),
+                    _ => return Err(Error::IncorrectState)
                 }
             }
-            match unsafe { SUBKERNELS.get(&id).unwrap().state } {
-                SubkernelState::Finished { .. } => return Ok(None),
-                _ => ()
+            if watchdog_expired(watchdog) {
+                return Err(Error::WatchdogExpired);
             }
-            Err(())
-        });
-        match message {
-            Ok(Some(message)) => Ok(message),
-            Ok(None) => {
-                if clock::get_ms() > max_time {
-                    Err(Error::Timeout)
-                } else {
-                    let _lock = subkernel_mutex.lock(io)?;
-                    match unsafe { SUBKERNELS.get(&id).unwrap().state } {
-                        SubkernelState::Finished { .. } => Err(Error::SubkernelFinished),
-                        _ => Err(Error::IncorrectState)
-                    }
-                }
+            if clock::get_ms() > max_time {
+                return Err(Error::Timeout);
             }
-            Err(e) => Err(Error::SchedError(e)),
+            // sleeps rather than polls, leaving the scheduler to the other threads,
+            // but wakes now and then to drop messages whose satellite went quiet
+            let poll = clock::get_ms() + unsafe { AWAIT_POLL_MS };
+            io.wait(unsafe { &AWAIT_EVENT }, Some(min(deadline + 1, poll)))?;
         }
     }
 
-    pub fn message_send<'a>(io: &Io, aux_mutex: &Mutex, subkernel_mutex: &Mutex,
+    pub fn message_send<'a>(io: &Io, subkernel_mutex: &Mutex,
         routing_table: &RoutingTable, id: u32, count: u8, tag: &'a [u8], message: *const *const ()
     ) -> Result<(), Error> {
         let mut writer = Cursor::new(Vec::new());
         let _lock = subkernel_mutex.lock(io).unwrap();
         let destination = unsafe { SUBKERNELS.get(&id).unwrap().destination };
+        check_route(destination)?;
 
         // reuse rpc code for sending arbitrary data
         rpc::send_args(&mut writer, 0, tag, message)?;
         // skip service tag, but overwrite first byte with tag count
-        let data = &mut writer.into_inner()[3..];
+        let mut data = writer.into_inner().split_off(3);
         data[0] = count;
-        Ok(drtio::subkernel_send_message(
-            io, aux_mutex, routing_table, id, destination, data
+        let data = compression::compress_payload(data);
+        drtio::subkernel_send_message(io, routing_table, id, destination, &data)?;
+        unsafe { STATS.messages_sent += 1 }
+        Ok(())
+    }
+
+    /// Sends a message already serialized by the kernel CPU, see rpc_queue::SUBKERNEL_MESSAGE.
+    pub fn message_send_serialized(io: &Io, subkernel_mutex: &Mutex,
+        routing_table: &RoutingTable, id: u32, data: &[u8]
+    ) -> Result<(), Error> {
+        let destination = {
+            let _lock = subkernel_mutex.lock(io).unwrap();
+            match unsafe { SUBKERNELS.get(&id) } {
+                Some(subkernel) => subkernel.destination,
+                None => return Err(Error::IncorrectState)
+            }
+        };
+        check_route(destination)?;
+        let data = compression::compress_payload(data.to_vec());
+        drtio::subkernel_send_message(io, routing_table, id, destination, &data)?;
+        let _lock = subkernel_mutex.lock(io)?;
+        unsafe { STATS.messages_sent += 1 }
+        Ok(())
+    }
+
+    /// RPC forwarded from a subkernel, to be served by the session talking to the host.
+    pub struct ForwardedRpc {
+        pub id: u32,
+        pub async: bool,
+        // service and arguments, as written by rpc::send_args
+        pub data: Vec<u8>
+    }
+
+    static mut RPC_QUEUE: Vec<ForwardedRpc> = Vec::new();
+    static mut CURRENT_RPCS: BTreeMap<u32, Reassembler> = BTreeMap::new();
+
+    pub fn rpc_handle_incoming(io: &Io, subkernel_mutex: &Mutex, id: u32, last: bool, data: &[u8]) {
+        // called when receiving an RPC from satellite
+        let _lock = match subkernel_mutex.lock(io) {
+            Ok(lock) => lock,
+            Err(_) => return,
+        };
+        if unsafe { SUBKERNELS.get(&id).is_none() } {
+            return
+        }
+        let pushed = unsafe { CURRENT_RPCS.entry(id) }.or_insert_with(Reassembler::new)
+            .push(clock::get_ms(), last, data);
+        match pushed {
+            Ok(true) => (),
+            Ok(false) => return,
+            Err(e) => {
+                error!("dropping RPC from subkernel #{}: {}", id, e);
+                unsafe { CURRENT_RPCS.remove(&id) };
+                return
+            }
+        }
+        let data = match compression::decompress_payload(unsafe { CURRENT_RPCS.remove(&id).unwrap().take() }) {
+            Ok(ref data) if data.is_empty() => {
+                warn!("dropping empty RPC from subkernel #{}", id);
+                return
+            }
+            Ok(data) => data,
+            Err(e) => {
+                error!("dropping RPC from subkernel #{}: {}", id, e);
+                return
+            }
+        };
+        unsafe {
+            RPC_QUEUE.push(ForwardedRpc {
+                id: id,
+                async: data[0] != 0,
+                data: data[1..].to_vec()
+            });
+            AWAIT_EVENT.notify()
+        }
+    }
+
+    /// Whether an RPC is waiting, may be called within io.until.
+    pub fn rpc_pending() -> bool {
+        unsafe { !RPC_QUEUE.is_empty() }
+    }
+
+    pub fn rpc_take(io: &Io, subkernel_mutex: &Mutex) -> Result<Option<ForwardedRpc>, Error> {
+        let _lock = subkernel_mutex.lock(io)?;
+        if unsafe { RPC_QUEUE.is_empty() } {
+            Ok(None)
+        } else {
+            Ok(Some(unsafe { RPC_QUEUE.remove(0) }))
+        }
+    }
+
+    /// Sends the host's reply to a synchronous RPC back to the waiting subkernel,
+    /// see drtioaux_proto::RPC_REPLY_VALUE.
+    pub fn rpc_reply_send(io: &Io, subkernel_mutex: &Mutex,
+        routing_table: &RoutingTable, id: u32, reply: &[u8]
+    ) -> Result<(), Error> {
+        let destination = {
+            let _lock = subkernel_mutex.lock(io)?;
+            match unsafe { SUBKERNELS.get(&id) } {
+                Some(subkernel) => subkernel.destination,
+                None => return Err(Error::IncorrectState)
+            }
+        };
+        let reply = compression::compress_payload(reply.to_vec());
+        Ok(drtio::subkernel_send_rpc_reply(
+            io, routing_table, id, destination, &reply
         )?)
     }
 }
\ No newline at end of file
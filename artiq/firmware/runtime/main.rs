@@ -38,7 +38,7 @@ use board_misoc::net_settings::{Ipv4AddrConfig};
 #[cfg(has_drtio)]
 use board_artiq::drtioaux;
 use board_artiq::drtio_routing;
-use board_artiq::{mailbox, rpc_queue};
+use board_artiq::{mailbox, mailbox_queue, kern_log_ring, rpc_queue};
 use proto_artiq::{mgmt_proto, moninj_proto, rpc_proto, session_proto, kernel_proto};
 #[cfg(has_drtio_eem)]
 use board_artiq::drtio_eem;
@@ -66,6 +66,7 @@ mod moninj;
 #[cfg(has_rtio_analyzer)]
 mod analyzer;
 mod dhcp;
+mod auth;
 mod ip_addr_storage;
 
 #[cfg(has_grabber)]
@@ -186,7 +187,6 @@ fn startup() {
         [false; drtio_routing::DEST_COUNT]));
     #[cfg(has_drtio_routing)]
     drtio_routing::interconnect_disable_all();
-    let aux_mutex = sched::Mutex::new();
 
     let ddma_mutex = sched::Mutex::new();
     let subkernel_mutex = sched::Mutex::new();
@@ -198,29 +198,31 @@ fn startup() {
         io.spawn(4096, dhcp::dhcp_thread);
     }
 
-    rtio_mgt::startup(&io, &aux_mutex, &drtio_routing_table, &up_destinations, &ddma_mutex, &subkernel_mutex);
+    rtio_mgt::startup(&io, &drtio_routing_table, &up_destinations, &ddma_mutex, &subkernel_mutex);
 
-    io.spawn(4096, mgmt::thread);
     {
-        let aux_mutex = aux_mutex.clone();
+        let drtio_routing_table = drtio_routing_table.clone();
+        let subkernel_mutex = subkernel_mutex.clone();
+        io.spawn(4096, move |io| { mgmt::thread(io, &drtio_routing_table, &subkernel_mutex) });
+    }
+    {
         let drtio_routing_table = drtio_routing_table.clone();
         let up_destinations = up_destinations.clone();
         let ddma_mutex = ddma_mutex.clone();
         let subkernel_mutex = subkernel_mutex.clone();
-        io.spawn(32768, move |io| { session::thread(io, &aux_mutex, &drtio_routing_table, &up_destinations, &ddma_mutex, &subkernel_mutex) });
+        io.spawn(32768, move |io| { session::thread(io, &drtio_routing_table, &up_destinations, &ddma_mutex, &subkernel_mutex) });
     }
     #[cfg(any(has_rtio_moninj, has_drtio))]
     {
-        let aux_mutex = aux_mutex.clone();
         let drtio_routing_table = drtio_routing_table.clone();
-        io.spawn(4096, move |io| { moninj::thread(io, &aux_mutex, &drtio_routing_table) });
+        let up_destinations = up_destinations.clone();
+        io.spawn(4096, move |io| { moninj::thread(io, &drtio_routing_table, &up_destinations) });
     }
     #[cfg(has_rtio_analyzer)]
     {
-        let aux_mutex = aux_mutex.clone();
         let drtio_routing_table = drtio_routing_table.clone();
         let up_destinations = up_destinations.clone();
-        io.spawn(8192, move |io| { analyzer::thread(io, &aux_mutex, &drtio_routing_table, &up_destinations) });
+        io.spawn(8192, move |io| { analyzer::thread(io, &drtio_routing_table, &up_destinations) });
     }
 
     #[cfg(has_grabber)]
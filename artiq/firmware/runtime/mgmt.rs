@@ -1,10 +1,14 @@
+use core::cell::RefCell;
 use log::{self, LevelFilter};
 
 use io::{Write, ProtoWrite, Error as IoError};
 use board_misoc::{config, spiflash};
+use board_artiq::drtio_routing;
 use logger_artiq::BufferLogger;
 use mgmt_proto::*;
-use sched::{Io, TcpListener, TcpStream, Error as SchedError};
+use sched::{Io, Mutex, TcpListener, TcpStream, Error as SchedError};
+use urc::Urc;
+use auth;
 
 impl From<SchedError> for Error<SchedError> {
     fn from(value: SchedError) -> Error<SchedError> {
@@ -12,13 +16,38 @@ impl From<SchedError> for Error<SchedError> {
     }
 }
 
-fn worker(io: &Io, stream: &mut TcpStream) -> Result<(), Error<SchedError>> {
+fn worker(io: &Io, routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
+        subkernel_mutex: &Mutex, stream: &mut TcpStream) -> Result<(), Error<SchedError>> {
     read_magic(stream)?;
     Write::write_all(stream, "e".as_bytes())?;
     info!("new connection from {}", stream.remote_endpoint());
+    // nothing else is read from a host that does not present the token first
+    if auth::required() {
+        let authenticated = match read_auth_token(stream, auth::TOKEN_MAX_SIZE)? {
+            Some(token) => auth::check(&token),
+            None => false
+        };
+        if !authenticated {
+            warn!("unauthenticated connection from {}, closing", stream.remote_endpoint());
+            Reply::Unauthorized.write_to(stream)?;
+            return Ok(())
+        }
+        Reply::Success.write_to(stream)?;
+    }
 
     loop {
-        match Request::read_from(stream)? {
+        let request = Request::read_from(stream)?;
+        match request {
+            // sent by hosts holding a token when the core device requires none
+            Request::Authenticate { ref token } => {
+                if !auth::check(token) {
+                    warn!("wrong authentication token from {}, closing", stream.remote_endpoint());
+                    Reply::Unauthorized.write_to(stream)?;
+                    return Ok(())
+                }
+                Reply::Success.write_to(stream)?;
+            }
+
             Request::GetLog => {
                 BufferLogger::with(|logger| {
                     let mut buffer = io.until_ok(|| logger.buffer())?;
@@ -62,6 +91,26 @@ fn worker(io: &Io, stream: &mut TcpStream) -> Result<(), Error<SchedError>> {
                     }
                 })?;
             }
+            #[cfg(has_drtio)]
+            Request::GetSatelliteLog { destination, id } => {
+                let routing_table = routing_table.borrow();
+                if routing_table.0[destination as usize][0] == 0 {
+                    Reply::Unavailable.write_to(stream)?;
+                    continue
+                }
+                match ::rtio_mgt::drtio::subkernel_retrieve_log(io,
+                        &routing_table, destination, id) {
+                    Ok(log) => Reply::LogContent(&::alloc::string::String::from_utf8_lossy(&log)).write_to(stream),
+                    Err(e) => {
+                        error!("error retrieving satellite log: {}", e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            #[cfg(not(has_drtio))]
+            Request::GetSatelliteLog { .. } => {
+                Reply::Unavailable.write_to(stream)?;
+            }
             Request::SetLogFilter(level) => {
                 info!("changing log level to {}", level);
                 log::set_max_level(level);
@@ -73,6 +122,406 @@ fn worker(io: &Io, stream: &mut TcpStream) -> Result<(), Error<SchedError>> {
                     logger.set_uart_log_level(level));
                 Reply::Success.write_to(stream)?;
             }
+            #[cfg(has_drtio)]
+            Request::SetSatelliteLogFilter { destination, target, level } => {
+                info!("changing log level of target {} on destination {} to {}",
+                      target, destination, level);
+                let routing_table = routing_table.borrow();
+                if routing_table.0[destination as usize][0] == 0 {
+                    Reply::Unavailable.write_to(stream)?;
+                    continue
+                }
+                match ::rtio_mgt::drtio::satellite_set_log_filter(io,
+                        &routing_table, destination, target, level as u8) {
+                    Ok(()) => Reply::Success.write_to(stream),
+                    Err(e) => {
+                        error!("error changing satellite log level: {}", e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            #[cfg(has_drtio)]
+            Request::SetSatelliteLogRateLimit { destination, rate } => {
+                info!("changing kernel log rate limit on destination {} to {}", destination, rate);
+                let routing_table = routing_table.borrow();
+                if routing_table.0[destination as usize][0] == 0 {
+                    Reply::Unavailable.write_to(stream)?;
+                    continue
+                }
+                match ::rtio_mgt::drtio::satellite_set_log_rate_limit(io,
+                        &routing_table, destination, rate) {
+                    Ok(()) => Reply::Success.write_to(stream),
+                    Err(e) => {
+                        error!("error changing satellite log rate limit: {}", e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            #[cfg(has_drtio)]
+            Request::SetSatelliteLogOutputs { destination, uart, forward } => {
+                info!("changing kernel log outputs on destination {} to UART: {}, master: {}",
+                      destination, uart, forward);
+                let routing_table = routing_table.borrow();
+                if routing_table.0[destination as usize][0] == 0 {
+                    Reply::Unavailable.write_to(stream)?;
+                    continue
+                }
+                match ::rtio_mgt::drtio::satellite_set_log_outputs(io,
+                        &routing_table, destination, uart, forward) {
+                    Ok(()) => Reply::Success.write_to(stream),
+                    Err(e) => {
+                        error!("error changing satellite log outputs: {}", e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            #[cfg(has_drtio)]
+            Request::SatelliteLogControl { destination, action } => {
+                info!("kernel log control action {} on destination {}", action, destination);
+                let routing_table = routing_table.borrow();
+                if routing_table.0[destination as usize][0] == 0 {
+                    Reply::Unavailable.write_to(stream)?;
+                    continue
+                }
+                match ::rtio_mgt::drtio::satellite_log_control(io,
+                        &routing_table, destination, action) {
+                    Ok(()) => Reply::Success.write_to(stream),
+                    Err(e) => {
+                        error!("error on satellite log control: {}", e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            #[cfg(has_drtio)]
+            Request::SuppressSatelliteException { destination, exception_id, ref prefix } => {
+                info!("suppressing subkernel exceptions (id: {:?}, prefix: {:?}) on destination {}",
+                      exception_id, prefix, destination);
+                let routing_table = routing_table.borrow();
+                if routing_table.0[destination as usize][0] == 0 {
+                    Reply::Unavailable.write_to(stream)?;
+                    continue
+                }
+                match ::rtio_mgt::drtio::subkernel_suppress_exception(io,
+                        &routing_table, destination, exception_id, prefix) {
+                    Ok(()) => Reply::Success.write_to(stream),
+                    Err(e) => {
+                        error!("error installing exception suppression rule: {}", e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            #[cfg(has_drtio)]
+            Request::GetSatelliteSuppressedCount { destination, clear } => {
+                let routing_table = routing_table.borrow();
+                if routing_table.0[destination as usize][0] == 0 {
+                    Reply::Unavailable.write_to(stream)?;
+                    continue
+                }
+                match ::rtio_mgt::drtio::subkernel_suppressed_count(io,
+                        &routing_table, destination, clear) {
+                    Ok(count) => Reply::SuppressedCount(count).write_to(stream),
+                    Err(e) => {
+                        error!("error retrieving suppressed exception count: {}", e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            #[cfg(has_drtio)]
+            Request::UnsuppressSatelliteExceptions { destination } => {
+                info!("removing subkernel exception suppression rules on destination {}", destination);
+                let routing_table = routing_table.borrow();
+                if routing_table.0[destination as usize][0] == 0 {
+                    Reply::Unavailable.write_to(stream)?;
+                    continue
+                }
+                match ::rtio_mgt::drtio::subkernel_unsuppress_exceptions(io, &routing_table, destination) {
+                    Ok(()) => Reply::Success.write_to(stream),
+                    Err(e) => {
+                        error!("error removing exception suppression rules: {}", e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            #[cfg(has_drtio)]
+            Request::GetSatelliteMailboxStats { destination, clear } => {
+                let routing_table = routing_table.borrow();
+                if routing_table.0[destination as usize][0] == 0 {
+                    Reply::Unavailable.write_to(stream)?;
+                    continue
+                }
+                match ::rtio_mgt::drtio::subkernel_mailbox_stats(io,
+                        &routing_table, destination, clear) {
+                    Ok(counters) => Reply::MailboxStats(&counters).write_to(stream),
+                    Err(e) => {
+                        error!("error retrieving mailbox statistics: {}", e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            #[cfg(has_drtio)]
+            Request::GetSatelliteMessageStats { destination, clear } => {
+                let routing_table = routing_table.borrow();
+                if routing_table.0[destination as usize][0] == 0 {
+                    Reply::Unavailable.write_to(stream)?;
+                    continue
+                }
+                match ::rtio_mgt::drtio::subkernel_message_stats(io,
+                        &routing_table, destination, clear) {
+                    Ok(counters) => Reply::MessageStats(&counters).write_to(stream),
+                    Err(e) => {
+                        error!("error retrieving message statistics: {}", e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            #[cfg(has_drtio)]
+            Request::CancelSubkernelMessages { destination } => {
+                info!("cancelling incomplete subkernel messages to and from destination {}", destination);
+                let routing_table = routing_table.borrow();
+                if routing_table.0[destination as usize][0] == 0 {
+                    Reply::Unavailable.write_to(stream)?;
+                    continue
+                }
+                // both the messages from its subkernels held here and those held by the satellite
+                let count = match ::kernel::subkernel::message_cancel_incoming(io, subkernel_mutex, destination) {
+                    Ok(count) => count,
+                    Err(e) => {
+                        error!("error cancelling subkernel messages: {}", e);
+                        Reply::Error.write_to(stream)?;
+                        continue
+                    }
+                };
+                match ::rtio_mgt::drtio::subkernel_cancel_messages(io, &routing_table, destination) {
+                    Ok(on_satellite) => {
+                        info!("{} message(s) cancelled on master, {} on satellite",
+                              count, if on_satellite { 1 } else { 0 });
+                        Reply::Success.write_to(stream)
+                    }
+                    Err(e) => {
+                        error!("error cancelling subkernel messages: {}", e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            #[cfg(has_drtio)]
+            Request::SetSatelliteWorkBudget { destination, budget_us } => {
+                info!("setting main loop work budget of destination {} to {} us", destination, budget_us);
+                let routing_table = routing_table.borrow();
+                if routing_table.0[destination as usize][0] == 0 {
+                    Reply::Unavailable.write_to(stream)?;
+                    continue
+                }
+                match ::rtio_mgt::drtio::satellite_set_work_budget(io,
+                        &routing_table, destination, budget_us) {
+                    Ok(()) => Reply::Success.write_to(stream),
+                    Err(e) => {
+                        error!("error setting work budget: {}", e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            #[cfg(has_drtio)]
+            Request::MeasureAuxLatency { destination, count } => {
+                let routing_table = routing_table.borrow();
+                if routing_table.0[destination as usize][0] == 0 {
+                    Reply::Unavailable.write_to(stream)?;
+                    continue
+                }
+                // a count of 0 only asks for the last measurement
+                let latency = if count == 0 {
+                    ::rtio_mgt::drtio::aux_latency(destination).ok_or("destination was never measured")
+                } else {
+                    ::rtio_mgt::drtio::measure_aux_latency(io, &routing_table, destination, count)
+                };
+                match latency {
+                    Ok(latency) => {
+                        info!("aux round trip to destination {}: min {} us, avg {} us, max {} us",
+                              destination, latency[0], latency[1], latency[2]);
+                        Reply::AuxLatency(&latency).write_to(stream)
+                    }
+                    Err(e) => {
+                        error!("error measuring aux latency: {}", e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            #[cfg(has_drtio)]
+            Request::ReloadRoutingTable => {
+                // swapped in by the link thread once no kernel uses the current one
+                ::rtio_mgt::drtio::reload_routing_table(io, subkernel_mutex, &routing_table.borrow());
+                Reply::Success.write_to(stream)?;
+            }
+            #[cfg(has_drtio)]
+            Request::SetAnalyzerCapture { destination, enable } => {
+                info!("{} analyzer capture of subkernel runs on destination {}",
+                      if enable { "enabling" } else { "disabling" }, destination);
+                let routing_table = routing_table.borrow();
+                if routing_table.0[destination as usize][0] == 0 {
+                    Reply::Unavailable.write_to(stream)?;
+                    continue
+                }
+                match ::rtio_mgt::drtio::analyzer_set_capture(io,
+                        &routing_table, destination, enable) {
+                    Ok(()) => Reply::Success.write_to(stream),
+                    Err(e) => {
+                        error!("error setting analyzer capture: {}", e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            #[cfg(has_drtio)]
+            Request::GetSubkernelStats { clear } => {
+                match ::kernel::subkernel::stats(io, subkernel_mutex, clear) {
+                    Ok(counters) => Reply::SubkernelStats(&counters).write_to(stream),
+                    Err(e) => {
+                        error!("error retrieving subkernel statistics: {}", e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            #[cfg(has_drtio)]
+            Request::GetLinkErrors { destination, clear } => {
+                let routing_table = routing_table.borrow();
+                if routing_table.0[destination as usize][0] == 0 {
+                    Reply::Unavailable.write_to(stream)?;
+                    continue
+                }
+                match ::rtio_mgt::drtio::link_errors(io, &routing_table, destination, clear) {
+                    Ok(counters) => Reply::LinkErrors(&counters).write_to(stream),
+                    Err(e) => {
+                        error!("error retrieving link error counters: {}", e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            #[cfg(has_drtio)]
+            Request::GetSatelliteLoopStats { destination, clear } => {
+                let routing_table = routing_table.borrow();
+                if routing_table.0[destination as usize][0] == 0 {
+                    Reply::Unavailable.write_to(stream)?;
+                    continue
+                }
+                match ::rtio_mgt::drtio::satellite_loop_stats(io,
+                        &routing_table, destination, clear) {
+                    Ok(counters) => Reply::LoopStats(&counters).write_to(stream),
+                    Err(e) => {
+                        error!("error retrieving main loop statistics: {}", e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            #[cfg(has_drtio)]
+            Request::GetSatelliteMemoryStats { destination } => {
+                let routing_table = routing_table.borrow();
+                if routing_table.0[destination as usize][0] == 0 {
+                    Reply::Unavailable.write_to(stream)?;
+                    continue
+                }
+                match ::rtio_mgt::drtio::satellite_memory_stats(io, &routing_table, destination) {
+                    Ok(counters) => Reply::MemoryStats(&counters).write_to(stream),
+                    Err(e) => {
+                        error!("error retrieving memory statistics: {}", e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            #[cfg(has_drtio)]
+            Request::RunSatelliteBenchmark { destination, iterations, message_size } => {
+                info!("running benchmark on destination {}: {} iterations, {} byte messages",
+                      destination, iterations, message_size);
+                let routing_table = routing_table.borrow();
+                if routing_table.0[destination as usize][0] == 0 {
+                    Reply::Unavailable.write_to(stream)?;
+                    continue
+                }
+                match ::rtio_mgt::drtio::subkernel_benchmark(io,
+                        &routing_table, destination, iterations, message_size) {
+                    Ok(results) => Reply::BenchmarkResults(&results).write_to(stream),
+                    Err(e) => {
+                        error!("error running satellite benchmark: {}", e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            #[cfg(has_drtio)]
+            Request::SetSatelliteExceptionReplay { destination, enable } => {
+                info!("{} subkernel exception replay on destination {}",
+                      if enable { "enabling" } else { "disabling" }, destination);
+                let routing_table = routing_table.borrow();
+                if routing_table.0[destination as usize][0] == 0 {
+                    Reply::Unavailable.write_to(stream)?;
+                    continue
+                }
+                match ::rtio_mgt::drtio::subkernel_set_exception_replay(io,
+                        &routing_table, destination, enable) {
+                    Ok(()) => Reply::Success.write_to(stream),
+                    Err(e) => {
+                        error!("error setting exception replay: {}", e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            #[cfg(has_drtio)]
+            Request::SetSatelliteCommTimeouts { destination, slot_ms, await_poll_ms } => {
+                info!("changing subkernel communication timeouts on destination {} to {} ms per value, {} ms polling",
+                      destination, slot_ms, await_poll_ms);
+                let routing_table = routing_table.borrow();
+                if routing_table.0[destination as usize][0] == 0 {
+                    Reply::Unavailable.write_to(stream)?;
+                    continue
+                }
+                match ::rtio_mgt::drtio::subkernel_set_comm_timeouts(io,
+                        &routing_table, destination, slot_ms, await_poll_ms) {
+                    Ok(()) => Reply::Success.write_to(stream),
+                    Err(e) => {
+                        error!("error changing communication timeouts: {}", e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            #[cfg(has_drtio)]
+            Request::InjectSubkernelMessage { destination, id, ref data } => {
+                info!("injecting message into subkernel #{} on destination {}", id, destination);
+                let routing_table = routing_table.borrow();
+                if routing_table.0[destination as usize][0] == 0 {
+                    Reply::Unavailable.write_to(stream)?;
+                    continue
+                }
+                match ::rtio_mgt::drtio::subkernel_inject_message(io,
+                        &routing_table, id, destination, data) {
+                    Ok(()) => Reply::Success.write_to(stream),
+                    Err(e) => {
+                        error!("error injecting subkernel message: {}", e);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            #[cfg(not(has_drtio))]
+            Request::SetSatelliteLogFilter { .. } |
+            Request::SetSatelliteLogRateLimit { .. } |
+            Request::SetSatelliteLogOutputs { .. } |
+            Request::SatelliteLogControl { .. } |
+            Request::SuppressSatelliteException { .. } |
+            Request::GetSatelliteSuppressedCount { .. } |
+            Request::UnsuppressSatelliteExceptions { .. } |
+            Request::SetSatelliteExceptionReplay { .. } |
+            Request::GetSatelliteMailboxStats { .. } |
+            Request::SetSatelliteCommTimeouts { .. } |
+            Request::InjectSubkernelMessage { .. } |
+            Request::GetSatelliteMessageStats { .. } |
+            Request::CancelSubkernelMessages { .. } |
+            Request::RunSatelliteBenchmark { .. } |
+            Request::GetSatelliteLoopStats { .. } |
+            Request::GetSatelliteMemoryStats { .. } |
+            Request::SetSatelliteWorkBudget { .. } |
+            Request::GetLinkErrors { .. } |
+            Request::MeasureAuxLatency { .. } |
+            Request::ReloadRoutingTable |
+            Request::SetAnalyzerCapture { .. } |
+            Request::GetSubkernelStats { .. } => {
+                let _ = (routing_table, subkernel_mutex);
+                Reply::Unavailable.write_to(stream)?;
+            }
 
             Request::ConfigRead { ref key } => {
                 config::read(key, |result| {
@@ -117,16 +566,19 @@ fn worker(io: &Io, stream: &mut TcpStream) -> Result<(), Error<SchedError>> {
     }
 }
 
-pub fn thread(io: Io) {
+pub fn thread(io: Io, routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
+        subkernel_mutex: &Mutex) {
     let listener = TcpListener::new(&io, 8192);
     listener.listen(1380).expect("mgmt: cannot listen");
     info!("management interface active");
 
     loop {
+        let routing_table = routing_table.clone();
+        let subkernel_mutex = subkernel_mutex.clone();
         let stream = listener.accept().expect("mgmt: cannot accept").into_handle();
         io.spawn(4096, move |io| {
             let mut stream = TcpStream::from_handle(&io, stream);
-            match worker(&io, &mut stream) {
+            match worker(&io, &routing_table, &subkernel_mutex, &mut stream) {
                 Ok(()) => (),
                 Err(Error::Io(IoError::UnexpectedEnd)) => (),
                 Err(err) => error!("aborted: {}", err)
@@ -3,13 +3,13 @@ use core::cell::RefCell;
 
 use io::Error as IoError;
 use moninj_proto::*;
-use sched::{Io, Mutex, TcpListener, TcpStream, Error as SchedError};
+use sched::{Io, TcpListener, TcpStream, Error as SchedError};
 use urc::Urc;
 use board_misoc::clock;
 use board_artiq::drtio_routing;
 
 #[cfg(has_rtio_moninj)]
-mod local_moninj {
+pub mod local_moninj {
     use board_misoc::csr;
 
     pub fn read_probe(channel: u16, probe: u8) -> u64 {
@@ -39,7 +39,7 @@ mod local_moninj {
 }
 
 #[cfg(not(has_rtio_moninj))]
-mod local_moninj {
+pub mod local_moninj {
     pub fn read_probe(_channel: u16, _probe: u8) -> u64 { 0 }
 
     pub fn inject(_channel: u16, _overrd: u8, _value: u8) { }
@@ -51,11 +51,11 @@ mod local_moninj {
 mod remote_moninj {
     use drtioaux;
     use rtio_mgt::drtio;
-    use sched::{Io, Mutex};
+    use sched::Io;
 
-    pub fn read_probe(io: &Io, aux_mutex: &Mutex, linkno: u8,
+    pub fn read_probe(io: &Io, linkno: u8,
         destination: u8, channel: u16, probe: u8) -> u64 {
-        let reply = drtio::aux_transact(io, aux_mutex, linkno, 
+        let reply = drtio::aux_transact(io, linkno, 
             &drtioaux::Packet::MonitorRequest { 
                 destination: destination,
                 channel: channel,
@@ -69,9 +69,9 @@ mod remote_moninj {
         0
     }
 
-    pub fn inject(io: &Io, aux_mutex: &Mutex, linkno: u8, 
+    pub fn inject(io: &Io, linkno: u8, 
         destination: u8, channel: u16, overrd: u8, value: u8) {
-        let _lock = aux_mutex.lock(io).unwrap();
+        let _lock = drtio::lock_link(io, linkno, Some(destination));
         drtioaux::send(linkno, &drtioaux::Packet::InjectionRequest {
             destination: destination,
             channel: channel,
@@ -80,9 +80,9 @@ mod remote_moninj {
         }).unwrap();
     }
 
-    pub fn read_injection_status(io: &Io, aux_mutex: &Mutex, linkno: u8,
+    pub fn read_injection_status(io: &Io, linkno: u8,
         destination: u8, channel: u16, overrd: u8) -> u8 {
-        let reply = drtio::aux_transact(io, aux_mutex, linkno, 
+        let reply = drtio::aux_transact(io, linkno, 
             &drtioaux::Packet::InjectionStatusRequest {
                 destination: destination,
                 channel: channel,
@@ -97,33 +97,103 @@ mod remote_moninj {
     }
 }
 
+#[cfg(has_drtio)]
+fn subkernel_states() -> BTreeMap<u32, (u8, u8)> {
+    use kernel::subkernel::{self, SubkernelState, FinishStatus};
+
+    subkernel::states().into_iter()
+        .map(|(id, destination, state)| {
+            let state = match state {
+                SubkernelState::NotLoaded => SUBKERNEL_NOT_LOADED,
+                SubkernelState::Uploaded => SUBKERNEL_UPLOADED,
+                SubkernelState::Running => SUBKERNEL_RUNNING,
+                SubkernelState::Finished { status: FinishStatus::Ok } => SUBKERNEL_FINISHED,
+                SubkernelState::Finished { status: FinishStatus::CommLost } => SUBKERNEL_COMM_LOST,
+                SubkernelState::Finished { status: FinishStatus::Exception } => SUBKERNEL_EXCEPTION
+            };
+            (id, (destination, state))
+        })
+        .collect()
+}
+
+#[cfg(not(has_drtio))]
+fn subkernel_states() -> BTreeMap<u32, (u8, u8)> {
+    BTreeMap::new()
+}
+
+// sends what changed in the subkernels of the active session, and in which destinations
+// are up, since `previous`, the last sent
+fn publish_subkernels(stream: &mut TcpStream, routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
+    up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
+    previous: &mut (BTreeMap<u32, (u8, u8)>, [Option<bool>; drtio_routing::DEST_COUNT])
+) -> Result<(), Error<SchedError>> {
+    let (ref mut subkernels, ref mut destinations) = *previous;
+
+    let current = subkernel_states();
+    for (&id, &(destination, state)) in current.iter() {
+        if subkernels.get(&id) != Some(&(destination, state)) {
+            let message = DeviceMessage::SubkernelStatus { id: id, destination: destination, state: state };
+            trace!("moninj->host {:?}", message);
+            message.write_to(stream)?;
+        }
+    }
+    for (&id, &(destination, _)) in subkernels.iter() {
+        if !current.contains_key(&id) {
+            let message = DeviceMessage::SubkernelStatus {
+                id: id, destination: destination, state: SUBKERNEL_REMOVED };
+            trace!("moninj->host {:?}", message);
+            message.write_to(stream)?;
+        }
+    }
+    *subkernels = current;
+
+    for destination in 0..drtio_routing::DEST_COUNT {
+        // only destinations in the routing table are reported
+        let up = match routing_table.borrow().0[destination][0] {
+            drtio_routing::INVALID_HOP => None,
+            _ => Some(up_destinations.borrow()[destination])
+        };
+        if up.is_some() && up != destinations[destination] {
+            let message = DeviceMessage::DestinationStatus { destination: destination as u8, up: up.unwrap() };
+            trace!("moninj->host {:?}", message);
+            message.write_to(stream)?;
+        }
+        destinations[destination] = up;
+    }
+    Ok(())
+}
+
 #[cfg(has_drtio)]
 macro_rules! dispatch {
-    ($io:ident, $aux_mutex:ident, $routing_table:ident, $channel:expr, $func:ident $(, $param:expr)*) => {{
+    ($io:ident, $routing_table:ident, $channel:expr, $func:ident $(, $param:expr)*) => {{
         let destination = ($channel >> 16) as u8;
         let channel = $channel as u16;
-        let hop = $routing_table.0[destination as usize][0];
+        // looked up every time, the routing table can be reloaded while connected
+        let hop = $routing_table.borrow().0[destination as usize][0];
         if hop == 0 {
             local_moninj::$func(channel, $($param, )*)
         } else {
             let linkno = hop - 1;
-            remote_moninj::$func($io, $aux_mutex, linkno, destination, channel, $($param, )*)
+            remote_moninj::$func($io, linkno, destination, channel, $($param, )*)
         }
     }}
 }
 
 #[cfg(not(has_drtio))]
 macro_rules! dispatch {
-    ($io:ident, $aux_mutex:ident, $routing_table:ident, $channel:expr, $func:ident $(, $param:expr)*) => {{
+    ($io:ident, $routing_table:ident, $channel:expr, $func:ident $(, $param:expr)*) => {{
         let channel = $channel as u16;
         local_moninj::$func(channel, $($param, )*)
     }}
 }
 
-fn connection_worker(io: &Io, _aux_mutex: &Mutex, _routing_table: &drtio_routing::RoutingTable,
+fn connection_worker(io: &Io, _routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
+    up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
     mut stream: &mut TcpStream) -> Result<(), Error<SchedError>> {
     let mut probe_watch_list = BTreeMap::new();
     let mut inject_watch_list = BTreeMap::new();
+    // what was last sent of the subkernels and destinations, when the host watches them
+    let mut subkernel_watch = None;
     let mut next_check = 0;
 
     read_magic(&mut stream)?;
@@ -150,9 +220,9 @@ fn connection_worker(io: &Io, _aux_mutex: &Mutex, _routing_table: &drtio_routing
                     }
                 },
                 HostMessage::Inject { channel, overrd, value } => dispatch!(
-                    io, _aux_mutex, _routing_table, channel, inject, overrd, value),
+                    io, _routing_table, channel, inject, overrd, value),
                 HostMessage::GetInjectionStatus { channel, overrd } => {
-                    let value = dispatch!(io, _aux_mutex, _routing_table, channel, read_injection_status, overrd);
+                    let value = dispatch!(io, _routing_table, channel, read_injection_status, overrd);
                     let reply = DeviceMessage::InjectionStatus {
                         channel: channel,
                         overrd: overrd,
@@ -161,6 +231,14 @@ fn connection_worker(io: &Io, _aux_mutex: &Mutex, _routing_table: &drtio_routing
 
                     trace!("moninj->host {:?}", reply);
                     reply.write_to(stream)?;
+                },
+                HostMessage::MonitorSubkernels { enable } => {
+                    subkernel_watch = if enable {
+                        // everything is sent again, for the host to start from
+                        Some((BTreeMap::new(), [None; drtio_routing::DEST_COUNT]))
+                    } else {
+                        None
+                    };
                 }
             }
         } else if !stream.may_recv() {
@@ -169,7 +247,7 @@ fn connection_worker(io: &Io, _aux_mutex: &Mutex, _routing_table: &drtio_routing
 
         if clock::get_ms() > next_check {
             for (&(channel, probe), previous) in probe_watch_list.iter_mut() {
-                let current = dispatch!(io, _aux_mutex, _routing_table, channel, read_probe, probe);
+                let current = dispatch!(io, _routing_table, channel, read_probe, probe);
                 if previous.is_none() || previous.unwrap() != current {
                     let message = DeviceMessage::MonitorStatus {
                         channel: channel,
@@ -184,7 +262,7 @@ fn connection_worker(io: &Io, _aux_mutex: &Mutex, _routing_table: &drtio_routing
                 }
             }
             for (&(channel, overrd), previous) in inject_watch_list.iter_mut() {
-                let current = dispatch!(io, _aux_mutex, _routing_table, channel, read_injection_status, overrd);
+                let current = dispatch!(io, _routing_table, channel, read_injection_status, overrd);
                 if previous.is_none() || previous.unwrap() != current {
                     let message = DeviceMessage::InjectionStatus {
                         channel: channel,
@@ -198,6 +276,9 @@ fn connection_worker(io: &Io, _aux_mutex: &Mutex, _routing_table: &drtio_routing
                     *previous = Some(current);
                 }
             }
+            if let Some(ref mut previous) = subkernel_watch {
+                publish_subkernels(stream, _routing_table, up_destinations, previous)?;
+            }
             next_check = clock::get_ms() + 200;
         }
 
@@ -205,18 +286,18 @@ fn connection_worker(io: &Io, _aux_mutex: &Mutex, _routing_table: &drtio_routing
     }
 }
 
-pub fn thread(io: Io, aux_mutex: &Mutex, routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>) {
+pub fn thread(io: Io, routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
+              up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>) {
     let listener = TcpListener::new(&io, 2047);
     listener.listen(1383).expect("moninj: cannot listen");
 
     loop {
-        let aux_mutex = aux_mutex.clone();
         let routing_table = routing_table.clone();
+        let up_destinations = up_destinations.clone();
         let stream = listener.accept().expect("moninj: cannot accept").into_handle();
         io.spawn(16384, move |io| {
-            let routing_table = routing_table.borrow();
             let mut stream = TcpStream::from_handle(&io, stream);
-            match connection_worker(&io, &aux_mutex, &routing_table, &mut stream) {
+            match connection_worker(&io, &routing_table, &up_destinations, &mut stream) {
                 Ok(()) => {},
                 Err(err) => error!("moninj aborted: {}", err)
             }
@@ -91,12 +91,12 @@ pub mod remote_dma {
         Ok(playback_state)
     }
 
-    pub fn erase(io: &Io, aux_mutex: &Mutex, ddma_mutex: &Mutex,
+    pub fn erase(io: &Io, ddma_mutex: &Mutex,
             routing_table: &RoutingTable, id: u32) {
         let _lock = ddma_mutex.lock(io).unwrap();
         let destinations = unsafe { TRACES.get(&id).unwrap() };
         for destination in destinations.keys() {
-            match drtio::ddma_send_erase(io, aux_mutex, routing_table, id, *destination) {
+            match drtio::ddma_send_erase(io, routing_table, id, *destination) {
                 Ok(_) => (),
                 Err(e) => error!("Error erasing trace on DMA: {}", e)
             } 
@@ -104,12 +104,12 @@ pub mod remote_dma {
         unsafe { TRACES.remove(&id); }
     }
 
-    pub fn upload_traces(io: &Io, aux_mutex: &Mutex, ddma_mutex: &Mutex,
+    pub fn upload_traces(io: &Io, ddma_mutex: &Mutex,
             routing_table: &RoutingTable, id: u32) {
         let _lock = ddma_mutex.lock(io);
         let traces = unsafe { TRACES.get_mut(&id).unwrap() };
         for (destination, mut trace) in traces {
-            match drtio::ddma_upload_trace(io, aux_mutex, routing_table, id, *destination, trace.get_trace())
+            match drtio::ddma_upload_trace(io, routing_table, id, *destination, trace.get_trace())
             {
                 Ok(_) => trace.state = RemoteState::Loaded,
                 Err(e) => error!("Error adding DMA trace on destination {}: {}", destination, e)
@@ -117,7 +117,7 @@ pub mod remote_dma {
         }
     }
 
-    pub fn playback(io: &Io, aux_mutex: &Mutex, ddma_mutex: &Mutex,
+    pub fn playback(io: &Io, ddma_mutex: &Mutex,
             routing_table: &RoutingTable, id: u32, timestamp: u64) {
         // triggers playback on satellites
         let destinations = unsafe { 
@@ -133,7 +133,7 @@ pub mod remote_dma {
                     continue;
                 }
             }
-            match drtio::ddma_send_playback(io, aux_mutex, routing_table, id, *destination, timestamp) {
+            match drtio::ddma_send_playback(io, routing_table, id, *destination, timestamp) {
                 Ok(_) => (),
                 Err(e) => error!("Error during remote DMA playback: {}", e)
             }
@@ -152,7 +152,7 @@ pub mod remote_dma {
         };
     }
 
-    pub fn destination_changed(io: &Io, aux_mutex: &Mutex, ddma_mutex: &Mutex,
+    pub fn destination_changed(io: &Io, ddma_mutex: &Mutex,
         routing_table: &RoutingTable, destination: u8, up: bool) {
         // update state of the destination, resend traces if it's up
         let _lock = ddma_mutex.lock(io).unwrap();
@@ -160,7 +160,7 @@ pub mod remote_dma {
         for (id, dest_traces) in traces_iter {
             if let Some(trace) = dest_traces.get_mut(&destination) {
                 if up {
-                    match drtio::ddma_upload_trace(io, aux_mutex, routing_table, *id, destination, trace.get_trace())
+                    match drtio::ddma_upload_trace(io, routing_table, *id, destination, trace.get_trace())
                     {
                         Ok(_) => trace.state = RemoteState::Loaded,
                         Err(e) => error!("Error adding DMA trace on destination {}: {}", destination, e)
@@ -16,28 +16,103 @@ const ASYNC_ERROR_SEQUENCE_ERROR: u8 = 1 << 2;
 pub mod drtio {
     use super::*;
     use alloc::vec::Vec;
+    use core::mem;
     use drtioaux;
-    use proto_artiq::drtioaux_proto::MASTER_PAYLOAD_MAX_SIZE;
+    use proto_artiq::drtioaux_proto::{MASTER_PAYLOAD_MAX_SIZE, MAILBOX_STATS_COUNT, MESSAGE_STATS_COUNT, BENCHMARK_RESULT_COUNT, LOOP_STATS_COUNT, LINK_ERROR_COUNT, MEMORY_STATS_COUNT, exception_severity_name};
+    use proto_artiq::drtioaux_proto::{AUX_PACKET_DEFAULT_SIZE, master_payload_size};
+    use proto_artiq::compression;
+    use proto_artiq::fragment::{Fragmenter, Reassembler};
+    use byteorder::{ByteOrder, NetworkEndian};
     use rtio_dma::remote_dma;
     #[cfg(has_rtio_analyzer)]
     use analyzer::remote_analyzer::RemoteBuffer;
     use kernel::subkernel;
 
-    pub fn startup(io: &Io, aux_mutex: &Mutex,
+    pub fn startup(io: &Io,
             routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
             up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
             ddma_mutex: &Mutex, subkernel_mutex: &Mutex) {
-        let aux_mutex = aux_mutex.clone();
         let routing_table = routing_table.clone();
         let up_destinations = up_destinations.clone();
         let ddma_mutex = ddma_mutex.clone();
         let subkernel_mutex = subkernel_mutex.clone();
         io.spawn(8192, move |io| {
-            let routing_table = routing_table.borrow();
-            link_thread(io, &aux_mutex, &routing_table, &up_destinations, &ddma_mutex, &subkernel_mutex);
+            link_thread(io, &routing_table, &up_destinations, &ddma_mutex, &subkernel_mutex);
         });
     }
 
+    // Aux transactions are scheduled per link, the only thing they have to share: those with
+    // satellites on different links proceed concurrently, while the destinations behind a link
+    // take turns, each in the order of its requests, so that a long upload or a slow satellite
+    // does not hold back the others.
+    // Link-level packets (ping, TSC, pushed packets) queue up in a slot of their link, and
+    // priority requests (see Packet::is_priority) in another, served ahead of all others.
+    const LINK_SLOTS: usize = drtio_routing::DEST_COUNT;
+    const PRIORITY_SLOTS: usize = LINK_SLOTS + csr::DRTIO.len();
+    const AUX_SLOT_COUNT: usize = PRIORITY_SLOTS + csr::DRTIO.len();
+
+    static mut LINK_BUSY: [bool; csr::DRTIO.len()] = [false; csr::DRTIO.len()];
+    // slot last served on each link
+    static mut LINK_LAST_SLOT: [usize; csr::DRTIO.len()] = [0; csr::DRTIO.len()];
+    static mut SLOT_LINK: [u8; AUX_SLOT_COUNT] = [0; AUX_SLOT_COUNT];
+    static mut SLOT_TICKETS_ISSUED: [u32; AUX_SLOT_COUNT] = [0; AUX_SLOT_COUNT];
+    static mut SLOT_TICKETS_SERVED: [u32; AUX_SLOT_COUNT] = [0; AUX_SLOT_COUNT];
+
+    pub struct LinkGuard {
+        linkno: u8
+    }
+
+    impl Drop for LinkGuard {
+        fn drop(&mut self) {
+            unsafe { LINK_BUSY[self.linkno as usize] = false }
+        }
+    }
+
+    // next slot with requests waiting on the link, after the one served last
+    fn next_slot(linkno: u8) -> Option<usize> {
+        let priority = PRIORITY_SLOTS + linkno as usize;
+        if unsafe { SLOT_TICKETS_ISSUED[priority] != SLOT_TICKETS_SERVED[priority] } {
+            return Some(priority)
+        }
+        let last = unsafe { LINK_LAST_SLOT[linkno as usize] };
+        (1..AUX_SLOT_COUNT + 1)
+            .map(|offset| (last + offset) % AUX_SLOT_COUNT)
+            .find(|&slot| unsafe {
+                SLOT_LINK[slot] == linkno && SLOT_TICKETS_ISSUED[slot] != SLOT_TICKETS_SERVED[slot]
+            })
+    }
+
+    /// Waits for the turn of `destination` on the link, for packets not sent with aux_transact.
+    pub fn lock_link(io: &Io, linkno: u8, destination: Option<u8>) -> LinkGuard {
+        let slot = match destination {
+            Some(destination) => destination as usize,
+            None => LINK_SLOTS + linkno as usize
+        };
+        lock_slot(io, linkno, slot)
+    }
+
+    fn lock_slot(io: &Io, linkno: u8, slot: usize) -> LinkGuard {
+        let ticket = unsafe {
+            let ticket = SLOT_TICKETS_ISSUED[slot];
+            SLOT_TICKETS_ISSUED[slot] = ticket.wrapping_add(1);
+            SLOT_LINK[slot] = linkno;
+            ticket
+        };
+        io.until(|| unsafe {
+            !LINK_BUSY[linkno as usize] && SLOT_TICKETS_SERVED[slot] == ticket &&
+                next_slot(linkno) == Some(slot)
+        }).unwrap();
+        unsafe {
+            LINK_BUSY[linkno as usize] = true;
+            if slot < PRIORITY_SLOTS {
+                // priority requests do not take a turn from the others
+                LINK_LAST_SLOT[linkno as usize] = slot;
+            }
+            SLOT_TICKETS_SERVED[slot] = ticket.wrapping_add(1);
+        }
+        LinkGuard { linkno: linkno }
+    }
+
     fn link_rx_up(linkno: u8) -> bool {
         let linkno = linkno as usize;
         unsafe {
@@ -52,9 +127,13 @@ pub mod drtio {
                 return Err("link went down");
             }
             if clock::get_ms() > max_time {
+                drtioaux::count_timeout(linkno);
                 return Err("timeout");
             }
             match drtioaux::recv(linkno) {
+                // pushed by the satellite, not the reply
+                Ok(Some(packet @ drtioaux::Packet::SubkernelRunDone { .. })) =>
+                    unsafe { PUSHED_PACKETS.push(packet) },
                 Ok(Some(packet)) => return Ok(packet),
                 Ok(None) => (),
                 Err(_) => return Err("aux packet error")
@@ -63,6 +142,24 @@ pub mod drtio {
         }
     }
 
+    // received while waiting for the reply to a request, handled by the link thread
+    static mut PUSHED_PACKETS: Vec<drtioaux::Packet> = Vec::new();
+
+    fn process_pushed_packets(io: &Io, ddma_mutex: &Mutex, subkernel_mutex: &Mutex) {
+        let packets = unsafe { mem::replace(&mut PUSHED_PACKETS, Vec::new()) };
+        for packet in packets {
+            // link number only matters for packets that are acknowledged
+            if let Some(packet) = process_async_packets(io, ddma_mutex, subkernel_mutex, 0, packet) {
+                warn!("unexpected pushed aux packet: {:?}", packet);
+            }
+        }
+    }
+
+    fn pushed_packet_pending(up_links: &[bool]) -> bool {
+        unsafe { !PUSHED_PACKETS.is_empty() } ||
+            (0..csr::DRTIO.len()).any(|linkno| up_links[linkno] && drtioaux::has_packet(linkno as u8))
+    }
+
     fn process_async_packets(io: &Io, ddma_mutex: &Mutex, subkernel_mutex: &Mutex, linkno: u8,
             packet: drtioaux::Packet) -> Option<drtioaux::Packet> {
         // returns None if an async packet has been consumed
@@ -71,8 +168,16 @@ pub mod drtio {
                 remote_dma::playback_done(io, ddma_mutex, id, destination, error, channel, timestamp);
                 None
             },
-            drtioaux::Packet::SubkernelFinished { id, with_exception } => {
-                subkernel::subkernel_finished(io, subkernel_mutex, id, with_exception);
+            drtioaux::Packet::SubkernelRunDone { destination: from, id, with_exception, async_errors, last, length, data } => {
+                if with_exception {
+                    info!("[DEST#{}] subkernel {} finished with an exception", from, id);
+                }
+                if async_errors != 0 {
+                    // reported to the host along with the ones of the main kernel
+                    warn!("RTIO async errors (0x{:02x}) during subkernel {}", async_errors, id);
+                    unsafe { super::add_async_errors(async_errors) }
+                }
+                subkernel::subkernel_finished(io, subkernel_mutex, id, with_exception, &data[..length as usize], last);
                 None
             },
             drtioaux::Packet::SubkernelMessage { id, destination: from, last, length, data } => {
@@ -83,19 +188,34 @@ pub mod drtio {
                 ).unwrap();
                 None
             }
+            drtioaux::Packet::SubkernelRpcRequest { id, destination: from, last, length, data } => {
+                subkernel::rpc_handle_incoming(io, subkernel_mutex, id, last, &data[..length as usize]);
+                drtioaux::send(linkno,
+                    &drtioaux::Packet::SubkernelMessageAck { destination: from }
+                ).unwrap();
+                None
+            }
+            drtioaux::Packet::SubkernelLogForward { destination: from, id, length, data } => {
+                subkernel::print_forwarded_log(from, id, &data[..length as usize]);
+                None
+            }
             other => Some(other)
         }
     }
 
-    pub fn aux_transact(io: &Io, aux_mutex: &Mutex, linkno: u8, request: &drtioaux::Packet
+    pub fn aux_transact(io: &Io, linkno: u8, request: &drtioaux::Packet
     ) -> Result<drtioaux::Packet, &'static str> {
-        let _lock = aux_mutex.lock(io).unwrap();
+        let _lock = if request.is_priority() {
+            lock_slot(io, linkno, PRIORITY_SLOTS + linkno as usize)
+        } else {
+            lock_link(io, linkno, request.destination())
+        };
         drtioaux::send(linkno, request).unwrap();
         let reply = recv_aux_timeout(io, linkno, 200)?;
         Ok(reply)
     }
 
-    fn ping_remote(io: &Io, aux_mutex: &Mutex, linkno: u8) -> u32 {
+    fn ping_remote(io: &Io, linkno: u8) -> u32 {
         let mut count = 0;
         loop {
             if !link_rx_up(linkno) {
@@ -105,7 +225,10 @@ pub mod drtio {
             if count > 100 {
                 return 0;
             }
-            let reply = aux_transact(io, aux_mutex, linkno, &drtioaux::Packet::EchoRequest);
+            if count > 1 {
+                drtioaux::count_retry(linkno);
+            }
+            let reply = aux_transact(io, linkno, &drtioaux::Packet::EchoRequest);
             match reply {
                 Ok(drtioaux::Packet::EchoReply) => {
                     // make sure receive buffer is drained
@@ -124,8 +247,8 @@ pub mod drtio {
         }
     }
 
-    fn sync_tsc(io: &Io, aux_mutex: &Mutex, linkno: u8) -> Result<(), &'static str> {
-        let _lock = aux_mutex.lock(io).unwrap();
+    fn sync_tsc(io: &Io, linkno: u8) -> Result<(), &'static str> {
+        let _lock = lock_link(io, linkno, None);
 
         unsafe {
             (csr::DRTIO[linkno as usize].set_time_write)(1);
@@ -141,10 +264,10 @@ pub mod drtio {
         }
     }
 
-    fn load_routing_table(io: &Io, aux_mutex: &Mutex,
+    fn load_routing_table(io: &Io,
         linkno: u8, routing_table: &drtio_routing::RoutingTable) -> Result<(), &'static str> {
         for i in 0..drtio_routing::DEST_COUNT {
-            let reply = aux_transact(io, aux_mutex, linkno, &drtioaux::Packet::RoutingSetPath {
+            let reply = aux_transact(io, linkno, &drtioaux::Packet::RoutingSetPath {
                 destination: i as u8,
                 hops: routing_table.0[i]
             })?;
@@ -155,9 +278,9 @@ pub mod drtio {
         Ok(())
     }
 
-    fn set_rank(io: &Io, aux_mutex: &Mutex,
+    fn set_rank(io: &Io,
         linkno: u8, rank: u8) -> Result<(), &'static str> {
-        let reply = aux_transact(io, aux_mutex, linkno,
+        let reply = aux_transact(io, linkno,
             &drtioaux::Packet::RoutingSetRank {
                 rank: rank
             })?;
@@ -180,16 +303,73 @@ pub mod drtio {
         }
     }
 
-    fn process_unsolicited_aux(io: &Io, aux_mutex: &Mutex, ddma_mutex: &Mutex, subkernel_mutex: &Mutex, linkno: u8) {
-        let _lock = aux_mutex.lock(io).unwrap();
-        match drtioaux::recv(linkno) {
-            Ok(Some(packet)) => {
-                if let Some(packet) = process_async_packets(io, ddma_mutex, subkernel_mutex, linkno, packet) {
-                    warn!("[LINK#{}] unsolicited aux packet: {:?}", linkno, packet);
+    // size of aux packets agreed on with each destination
+    static mut PACKET_SIZES: [usize; drtio_routing::DEST_COUNT] = [AUX_PACKET_DEFAULT_SIZE; drtio_routing::DEST_COUNT];
+
+    fn negotiate_packet_size(io: &Io, destination: u8, linkno: u8) {
+        // every hop on the way lowers the proposal to what its links can carry,
+        // satellites that predate the request do not answer and keep the default
+        let proposed = drtioaux::packet_capacity(linkno);
+        let reply = aux_transact(io, linkno,
+            &drtioaux::Packet::PacketSizeRequest { destination: destination, size: proposed as u16 });
+        let size = match reply {
+            Ok(drtioaux::Packet::PacketSizeReply { size }) if size as usize <= proposed => size as usize,
+            _ => AUX_PACKET_DEFAULT_SIZE
+        };
+        unsafe { PACKET_SIZES[destination as usize] = size; }
+        info!("[DEST#{}] aux packets of up to {} bytes", destination, size);
+    }
+
+    fn payload_size(destination: u8) -> usize {
+        master_payload_size(unsafe { PACKET_SIZES[destination as usize] })
+    }
+
+    // a slice of a message between subkernels on two satellites, passed on to the satellite
+    // it is meant for, and its acknowledgement back to the one that sent it
+    fn relay_peer_message(io: &Io, routing_table: &drtio_routing::RoutingTable,
+            linkno: u8, packet: &drtioaux::Packet) {
+        let (destination, source) = match packet {
+            &drtioaux::Packet::SubkernelPeerMessage { destination, source, .. } => (destination, source),
+            _ => unreachable!()
+        };
+        let hop = routing_table.0[destination as usize][0];
+        let reply = if hop == 0 || hop as usize > csr::DRTIO.len() {
+            Err("destination is not a satellite")
+        } else {
+            aux_transact(io, hop - 1, packet)
+        };
+        match reply {
+            Ok(ack @ drtioaux::Packet::SubkernelMessageAck { .. }) => {
+                let _lock = lock_link(io, linkno, Some(source));
+                drtioaux::send(linkno, &ack).unwrap();
+            }
+            Ok(packet) => error!("[DEST#{}] received unexpected aux packet relaying message from destination {}: {:?}",
+                destination, source, packet),
+            Err(e) => error!("[DEST#{}] cannot relay message from destination {} ({})", destination, source, e)
+        }
+    }
+
+    fn process_unsolicited_aux(io: &Io, routing_table: &drtio_routing::RoutingTable,
+            ddma_mutex: &Mutex, subkernel_mutex: &Mutex, linkno: u8) {
+        let packet = {
+            let _lock = lock_link(io, linkno, None);
+            match drtioaux::recv(linkno) {
+                Ok(Some(packet)) => process_async_packets(io, ddma_mutex, subkernel_mutex, linkno, packet),
+                Ok(None) => None,
+                Err(_) => {
+                    warn!("[LINK#{}] aux packet error", linkno);
+                    None
                 }
             }
-            Ok(None) => (),
-            Err(_) => warn!("[LINK#{}] aux packet error", linkno)
+        };
+        match packet {
+            Some(packet @ drtioaux::Packet::SubkernelPeerMessage { .. }) =>
+                relay_peer_message(io, routing_table, linkno, &packet),
+            Some(packet) => {
+                drtioaux::count_unsolicited(linkno);
+                warn!("[LINK#{}] unsolicited aux packet: {:?}", linkno, packet);
+            }
+            None => ()
         }
     }
 
@@ -224,6 +404,7 @@ pub mod drtio {
             info!("[DEST#{}] destination is up", destination);
         } else {
             drtio_routing::interconnect_disable(destination);
+            unsafe { PACKET_SIZES[destination as usize] = AUX_PACKET_DEFAULT_SIZE; }
             info!("[DEST#{}] destination is down", destination);
         }
     }
@@ -233,7 +414,7 @@ pub mod drtio {
         up_destinations[destination as usize]
     }
 
-    fn destination_survey(io: &Io, aux_mutex: &Mutex, routing_table: &drtio_routing::RoutingTable,
+    fn destination_survey(io: &Io, routing_table: &drtio_routing::RoutingTable,
             up_links: &[bool],
             up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
             ddma_mutex: &Mutex, subkernel_mutex: &Mutex) {
@@ -251,7 +432,7 @@ pub mod drtio {
                 if destination_up(up_destinations, destination) {
                     if up_links[linkno as usize] {
                         loop {
-                            let reply = aux_transact(io, aux_mutex, linkno, 
+                            let reply = aux_transact(io, linkno, 
                                 &drtioaux::Packet::DestinationStatusRequest {
                                     destination: destination
                                 });
@@ -260,8 +441,8 @@ pub mod drtio {
                                 match reply {
                                     Some(drtioaux::Packet::DestinationDownReply) => {
                                         destination_set_up(routing_table, up_destinations, destination, false);
-                                        remote_dma::destination_changed(io, aux_mutex, ddma_mutex, routing_table, destination, false);
-                                        subkernel::destination_changed(io, aux_mutex, subkernel_mutex, routing_table, destination, false);
+                                        remote_dma::destination_changed(io, ddma_mutex, routing_table, destination, false);
+                                        subkernel::destination_changed(io, subkernel_mutex, routing_table, destination, false);
                                     }
                                     Some(drtioaux::Packet::DestinationOkReply) => (),
                                     Some(drtioaux::Packet::DestinationSequenceErrorReply { channel }) => {
@@ -276,6 +457,10 @@ pub mod drtio {
                                         error!("[DEST#{}] RTIO busy error involving channel 0x{:04x}:{}", destination, channel, resolve_channel_name(channel as u32));
                                         unsafe { SEEN_ASYNC_ERRORS |= ASYNC_ERROR_BUSY };
                                     }
+                                    Some(packet @ drtioaux::Packet::SubkernelPeerMessage { .. }) => {
+                                        relay_peer_message(io, routing_table, linkno, &packet);
+                                        continue;
+                                    }
                                     Some(packet) => error!("[DEST#{}] received unexpected aux packet: {:?}", destination, packet),
                                     None => { 
                                         // continue asking until we get Destination...Reply or error out
@@ -291,12 +476,12 @@ pub mod drtio {
                         }
                     } else {
                         destination_set_up(routing_table, up_destinations, destination, false);
-                        remote_dma::destination_changed(io, aux_mutex, ddma_mutex, routing_table, destination, false);
-                        subkernel::destination_changed(io, aux_mutex, subkernel_mutex, routing_table, destination, false);
+                        remote_dma::destination_changed(io, ddma_mutex, routing_table, destination, false);
+                        subkernel::destination_changed(io, subkernel_mutex, routing_table, destination, false);
                     }
                 } else {
                     if up_links[linkno as usize] {
-                        let reply = aux_transact(io, aux_mutex, linkno, 
+                        let reply = aux_transact(io, linkno, 
                             &drtioaux::Packet::DestinationStatusRequest {
                                 destination: destination
                             });
@@ -305,8 +490,12 @@ pub mod drtio {
                             Ok(drtioaux::Packet::DestinationOkReply) => {
                                 destination_set_up(routing_table, up_destinations, destination, true);
                                 init_buffer_space(destination as u8, linkno);
-                                remote_dma::destination_changed(io, aux_mutex, ddma_mutex, routing_table, destination, true);
-                                subkernel::destination_changed(io, aux_mutex, subkernel_mutex, routing_table, destination, true);
+                                negotiate_packet_size(io, destination, linkno);
+                                clear_clock_samples(destination);
+                                // messages of its subkernels are stamped from the first one on
+                                update_master_time(io, linkno, destination as usize);
+                                remote_dma::destination_changed(io, ddma_mutex, routing_table, destination, true);
+                                subkernel::destination_changed(io, subkernel_mutex, routing_table, destination, true);
                             },
                             Ok(packet) => error!("[DEST#{}] received unexpected aux packet: {:?}", destination, packet),
                             Err(e) => error!("[DEST#{}] communication failed ({})", destination, e)
@@ -317,18 +506,146 @@ pub mod drtio {
         }
     }
 
-    pub fn link_thread(io: Io, aux_mutex: &Mutex,
-            routing_table: &drtio_routing::RoutingTable,
+    // routing table read back from the config at runtime, until the link thread swaps it in
+    static mut PENDING_ROUTING_TABLE: Option<drtio_routing::RoutingTable> = None;
+
+    /// Reads the routing table from the config again. It comes into effect once
+    /// no kernel is running with the current one; until then, subkernels on
+    /// destinations whose route changes cannot be used.
+    pub fn reload_routing_table(io: &Io, subkernel_mutex: &Mutex,
+            routing_table: &drtio_routing::RoutingTable) {
+        let new_table = drtio_routing::config_routing_table(csr::DRTIO.len());
+        subkernel::routing_table_changed(io, subkernel_mutex, routing_table, &new_table);
+        unsafe { PENDING_ROUTING_TABLE = Some(new_table); }
+    }
+
+    fn apply_pending_routing_table(io: &Io,
+            routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
+            up_links: &[bool],
+            up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
+            ddma_mutex: &Mutex, subkernel_mutex: &Mutex) {
+        if unsafe { PENDING_ROUTING_TABLE.is_none() } {
+            return
+        }
+        let old_table = match routing_table.try_borrow_mut() {
+            Ok(mut current) => mem::replace(&mut *current, unsafe { PENDING_ROUTING_TABLE.take().unwrap() }),
+            // still in use by a kernel, retried on the next pass
+            Err(_) => return
+        };
+        let routing_table = routing_table.borrow();
+        info!("routing table reloaded: {}", *routing_table);
+        for linkno in 0..csr::DRTIO.len() {
+            if up_links[linkno] {
+                if let Err(e) = load_routing_table(io, linkno as u8, &routing_table) {
+                    error!("[LINK#{}] failed to load routing table ({})", linkno, e);
+                }
+            }
+        }
+        for destination in 0..drtio_routing::DEST_COUNT {
+            let moved = old_table.0[destination] != routing_table.0[destination];
+            let destination = destination as u8;
+            if moved && destination_up(up_destinations, destination) {
+                // the survey brings it back up along its new route, uploading subkernels again
+                destination_set_up(&routing_table, up_destinations, destination, false);
+                remote_dma::destination_changed(io, ddma_mutex, &routing_table, destination, false);
+                subkernel::destination_changed(io, subkernel_mutex, &routing_table, destination, false);
+            }
+        }
+        subkernel::routing_table_applied(io, subkernel_mutex);
+    }
+
+    // how often satellites are sent the time of the master clock
+    const MASTER_TIME_PERIOD_MS: u64 = 1000;
+    static mut NEXT_MASTER_TIME_MS: u64 = 0;
+    // half the round trip of the last update to each destination, the time it takes to arrive
+    static mut MASTER_TIME_DELAYS_US: [u64; drtio_routing::DEST_COUNT] = [0; drtio_routing::DEST_COUNT];
+    // (satellite clock, master clock) at the last two updates taken by each destination, latest first
+    static mut CLOCK_SAMPLES: [[Option<(u64, u64)>; 2]; drtio_routing::DEST_COUNT] =
+        [[None; 2]; drtio_routing::DEST_COUNT];
+    // past this, the drift of the satellite clock is not extrapolated, only its offset
+    const DRIFT_EXTRAPOLATION_MAX_US: u64 = 60_000_000;
+
+    fn record_clock_sample(destination: usize, satellite_us: u64, master_us: u64) {
+        let samples = unsafe { &mut CLOCK_SAMPLES[destination] };
+        match samples[0] {
+            // the satellite restarted, its earlier clock tells nothing about the current one
+            Some((previous_us, _)) if previous_us >= satellite_us => samples[1] = None,
+            previous => samples[1] = previous
+        }
+        samples[0] = Some((satellite_us, master_us));
+    }
+
+    fn clear_clock_samples(destination: u8) {
+        unsafe { CLOCK_SAMPLES[destination as usize] = [None; 2] }
+    }
+
+    /// Converts a time of the satellite clock of `destination`, e.g. the stamp of a subkernel
+    /// message, to the master clock, with the offset measured at the last master time update
+    /// and the drift between the last two. None before the satellite took any update.
+    pub fn master_time_of(destination: u8, satellite_us: u64) -> Option<u64> {
+        let samples = unsafe { &CLOCK_SAMPLES[destination as usize] };
+        let (latest_satellite_us, latest_master_us) = samples[0]?;
+        let elapsed_us = satellite_us as i64 - latest_satellite_us as i64;
+        let corrected_us = match samples[1] {
+            Some((previous_satellite_us, previous_master_us))
+                    if (elapsed_us.abs() as u64) < DRIFT_EXTRAPOLATION_MAX_US => {
+                let satellite_span = (latest_satellite_us - previous_satellite_us) as i64;
+                let master_span = latest_master_us as i64 - previous_master_us as i64;
+                elapsed_us * master_span / satellite_span
+            }
+            _ => elapsed_us
+        };
+        Some((latest_master_us as i64 + corrected_us).max(0) as u64)
+    }
+
+    fn propagate_master_time(io: &Io, routing_table: &drtio_routing::RoutingTable,
+            up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>) {
+        if clock::get_ms() < unsafe { NEXT_MASTER_TIME_MS } {
+            return
+        }
+        unsafe { NEXT_MASTER_TIME_MS = clock::get_ms() + MASTER_TIME_PERIOD_MS }
+        for destination in 0..drtio_routing::DEST_COUNT {
+            let hop = routing_table.0[destination][0];
+            if hop == 0 || hop as usize > csr::DRTIO.len() || !destination_up(up_destinations, destination as u8) {
+                continue
+            }
+            update_master_time(io, hop - 1, destination);
+        }
+    }
+
+    fn update_master_time(io: &Io, linkno: u8, destination: usize) {
+        let delay = unsafe { &mut MASTER_TIME_DELAYS_US[destination] };
+        let sent = clock::get_us();
+        let reply = aux_transact(io, linkno, &drtioaux::Packet::MasterTimeUpdate {
+            destination: destination as u8, master_us: sent + *delay
+        });
+        match reply {
+            Ok(drtioaux::Packet::MasterTimeAck { satellite_us }) => {
+                *delay = (clock::get_us() - sent) / 2;
+                // the update arrived about half the round trip after it was sent
+                record_clock_sample(destination, satellite_us, sent + *delay);
+            }
+            Ok(packet) => error!("[DEST#{}] received unexpected aux packet on master time update: {:?}",
+                                 destination, packet),
+            Err(e) => error!("[DEST#{}] master time update failed ({})", destination, e)
+        }
+    }
+
+    pub fn link_thread(io: Io,
+            routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
             up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
             ddma_mutex: &Mutex, subkernel_mutex: &Mutex) {
         let mut up_links = [false; csr::DRTIO.len()];
         loop {
+            apply_pending_routing_table(&io, routing_table, &up_links, up_destinations,
+                ddma_mutex, subkernel_mutex);
+            let routing_table = routing_table.borrow();
             for linkno in 0..csr::DRTIO.len() {
                 let linkno = linkno as u8;
                 if up_links[linkno as usize] {
                     /* link was previously up */
                     if link_rx_up(linkno) {
-                        process_unsolicited_aux(&io, aux_mutex, ddma_mutex, subkernel_mutex, linkno);
+                        process_unsolicited_aux(&io, &routing_table, ddma_mutex, subkernel_mutex, linkno);
                         process_local_errors(linkno);
                     } else {
                         info!("[LINK#{}] link is down", linkno);
@@ -338,17 +655,17 @@ pub mod drtio {
                     /* link was previously down */
                     if link_rx_up(linkno) {
                         info!("[LINK#{}] link RX became up, pinging", linkno);
-                        let ping_count = ping_remote(&io, aux_mutex, linkno);
+                        let ping_count = ping_remote(&io, linkno);
                         if ping_count > 0 {
                             info!("[LINK#{}] remote replied after {} packets", linkno, ping_count);
                             up_links[linkno as usize] = true;
-                            if let Err(e) = sync_tsc(&io, aux_mutex, linkno) {
+                            if let Err(e) = sync_tsc(&io, linkno) {
                                 error!("[LINK#{}] failed to sync TSC ({})", linkno, e);
                             }
-                            if let Err(e) = load_routing_table(&io, aux_mutex, linkno, routing_table) {
+                            if let Err(e) = load_routing_table(&io, linkno, &routing_table) {
                                 error!("[LINK#{}] failed to load routing table ({})", linkno, e);
                             }
-                            if let Err(e) = set_rank(&io, aux_mutex, linkno, 1) {
+                            if let Err(e) = set_rank(&io, linkno, 1) {
                                 error!("[LINK#{}] failed to set rank ({})", linkno, e);
                             }
                             info!("[LINK#{}] link initialization completed", linkno);
@@ -358,12 +675,30 @@ pub mod drtio {
                     }
                 }
             }
-            destination_survey(&io, aux_mutex, routing_table, &up_links, up_destinations, ddma_mutex, subkernel_mutex);
-            io.sleep(200).unwrap();
+            destination_survey(&io, &routing_table, &up_links, up_destinations, ddma_mutex, subkernel_mutex);
+            propagate_master_time(&io, &routing_table, up_destinations);
+            subkernel::keepalive(&io, subkernel_mutex, &routing_table);
+            subkernel::upload_staged(&io, subkernel_mutex, &routing_table);
+            // packets pushed by satellites, such as finished subkernels, are handled as they arrive
+            let next_pass = clock::get_ms() + 200;
+            loop {
+                process_pushed_packets(&io, ddma_mutex, subkernel_mutex);
+                subkernel::grant_message_credits(&io, subkernel_mutex, &routing_table);
+                io.until(|| clock::get_ms() >= next_pass || pushed_packet_pending(&up_links) ||
+                    subkernel::message_credits_pending()).unwrap();
+                if clock::get_ms() >= next_pass {
+                    break
+                }
+                for linkno in 0..csr::DRTIO.len() {
+                    if up_links[linkno] {
+                        process_unsolicited_aux(&io, &routing_table, ddma_mutex, subkernel_mutex, linkno as u8);
+                    }
+                }
+            }
         }
     }
 
-    pub fn reset(io: &Io, aux_mutex: &Mutex) {
+    pub fn reset(io: &Io) {
         for linkno in 0..csr::DRTIO.len() {
             unsafe {
                 (csr::DRTIO[linkno].reset_write)(1);
@@ -379,7 +714,7 @@ pub mod drtio {
         for linkno in 0..csr::DRTIO.len() {
             let linkno = linkno as u8;
             if link_rx_up(linkno) {
-                let reply = aux_transact(io, aux_mutex, linkno,
+                let reply = aux_transact(io, linkno,
                     &drtioaux::Packet::ResetRequest);
                 match reply {
                     Ok(drtioaux::Packet::ResetAck) => (),
@@ -390,26 +725,24 @@ pub mod drtio {
         }
     }
 
-    fn partition_data<F>(data: &[u8], send_f: F) -> Result<(), &'static str>
+    fn partition_data<F>(data: &[u8], destination: u8, send_f: F) -> Result<(), &'static str>
             where F: Fn(&[u8; MASTER_PAYLOAD_MAX_SIZE], bool, usize) -> Result<(), &'static str> {
-            let mut i = 0;
-            while i < data.len() {
-                let mut slice: [u8; MASTER_PAYLOAD_MAX_SIZE] = [0; MASTER_PAYLOAD_MAX_SIZE];
-                let len: usize = if i + MASTER_PAYLOAD_MAX_SIZE < data.len() { MASTER_PAYLOAD_MAX_SIZE } else { data.len() - i } as usize;
-                let last = i + len == data.len();
-                slice[..len].clone_from_slice(&data[i..i+len]);
-                i += len;
-                send_f(&slice, last, len)?;
+            let size = payload_size(destination);
+            let mut fragments = Fragmenter::borrowed(data, 0);
+            let mut slice: [u8; MASTER_PAYLOAD_MAX_SIZE] = [0; MASTER_PAYLOAD_MAX_SIZE];
+            while !fragments.is_done() {
+                let fragment = fragments.next(&mut slice, size);
+                send_f(&slice, fragment.last, fragment.len)?;
             }
             Ok(())
         }
 
-    pub fn ddma_upload_trace(io: &Io, aux_mutex: &Mutex,
+    pub fn ddma_upload_trace(io: &Io,
             routing_table: &drtio_routing::RoutingTable,
             id: u32, destination: u8, trace: &[u8]) -> Result<(), &'static str> {
         let linkno = routing_table.0[destination as usize][0] - 1;
-        partition_data(trace, |slice, last, len: usize| {
-            let reply = aux_transact(io, aux_mutex, linkno, 
+        partition_data(trace, destination, |slice, last, len: usize| {
+            let reply = aux_transact(io, linkno, 
                 &drtioaux::Packet::DmaAddTraceRequest {
                     id: id, destination: destination, last: last, length: len as u16, trace: *slice});
             match reply {
@@ -422,10 +755,10 @@ pub mod drtio {
         })
     }
 
-    pub fn ddma_send_erase(io: &Io, aux_mutex: &Mutex, routing_table: &drtio_routing::RoutingTable, 
+    pub fn ddma_send_erase(io: &Io, routing_table: &drtio_routing::RoutingTable, 
             id: u32, destination: u8) -> Result<(), &'static str> {
         let linkno = routing_table.0[destination as usize][0] - 1;
-        let reply = aux_transact(io, aux_mutex, linkno, 
+        let reply = aux_transact(io, linkno, 
             &drtioaux::Packet::DmaRemoveTraceRequest { id: id, destination: destination });
         match reply {
             Ok(drtioaux::Packet::DmaRemoveTraceReply { succeeded: true }) => Ok(()),
@@ -435,10 +768,10 @@ pub mod drtio {
         }
     }
 
-    pub fn ddma_send_playback(io: &Io, aux_mutex: &Mutex, routing_table: &drtio_routing::RoutingTable,
+    pub fn ddma_send_playback(io: &Io, routing_table: &drtio_routing::RoutingTable,
             id: u32, destination: u8, timestamp: u64) -> Result<(), &'static str> {
         let linkno = routing_table.0[destination as usize][0] - 1;
-        let reply = aux_transact(io, aux_mutex, linkno, 
+        let reply = aux_transact(io, linkno, 
             &drtioaux::Packet::DmaPlaybackRequest{ id: id, destination: destination, timestamp: timestamp });
         match reply {
             Ok(drtioaux::Packet::DmaPlaybackReply { succeeded: true }) => return Ok(()),
@@ -450,10 +783,10 @@ pub mod drtio {
     }
 
     #[cfg(has_rtio_analyzer)]
-    fn analyzer_get_data(io: &Io, aux_mutex: &Mutex, routing_table: &drtio_routing::RoutingTable,
+    fn analyzer_get_data(io: &Io, routing_table: &drtio_routing::RoutingTable,
         destination: u8) -> Result<RemoteBuffer, &'static str> {
         let linkno = routing_table.0[destination as usize][0] - 1;
-        let reply = aux_transact(io, aux_mutex, linkno, 
+        let reply = aux_transact(io, linkno, 
             &drtioaux::Packet::AnalyzerHeaderRequest { destination: destination });
         let (sent, total, overflow) = match reply {
             Ok(drtioaux::Packet::AnalyzerHeader { 
@@ -467,7 +800,7 @@ pub mod drtio {
         if sent > 0 {
             let mut last_packet = false;
             while !last_packet {
-                let reply = aux_transact(io, aux_mutex, linkno, 
+                let reply = aux_transact(io, linkno, 
                     &drtioaux::Packet::AnalyzerDataRequest { destination: destination });
                 match reply {
                     Ok(drtioaux::Packet::AnalyzerData { last, length, data }) => { 
@@ -489,23 +822,23 @@ pub mod drtio {
     }
 
     #[cfg(has_rtio_analyzer)]
-    pub fn analyzer_query(io: &Io, aux_mutex: &Mutex, routing_table: &drtio_routing::RoutingTable,
+    pub fn analyzer_query(io: &Io, routing_table: &drtio_routing::RoutingTable,
         up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>
     ) -> Result<Vec<RemoteBuffer>, &'static str> {
         let mut remote_buffers: Vec<RemoteBuffer> = Vec::new();
         for i in 1..drtio_routing::DEST_COUNT {
             if destination_up(up_destinations, i as u8) {
-                remote_buffers.push(analyzer_get_data(io, aux_mutex, routing_table, i as u8)?);
+                remote_buffers.push(analyzer_get_data(io, routing_table, i as u8)?);
             }
         }
         Ok(remote_buffers)
     }
 
-    pub fn subkernel_upload(io: &Io, aux_mutex: &Mutex, routing_table: &drtio_routing::RoutingTable,
+    pub fn subkernel_upload(io: &Io, routing_table: &drtio_routing::RoutingTable,
             id: u32, destination: u8, data: &Vec<u8>) -> Result<(), &'static str> {
         let linkno = routing_table.0[destination as usize][0] - 1;
-        partition_data(data, |slice, last, len: usize| {
-            let reply = aux_transact(io, aux_mutex, linkno, 
+        partition_data(data, destination, |slice, last, len: usize| {
+            let reply = aux_transact(io, linkno, 
                 &drtioaux::Packet::SubkernelAddDataRequest {
                     id: id, destination: destination, last: last, length: len as u16, data: *slice});
             match reply {
@@ -518,10 +851,93 @@ pub mod drtio {
         })
     }
 
-    pub fn subkernel_load(io: &Io, aux_mutex: &Mutex, routing_table: &drtio_routing::RoutingTable,
+    /// Uploads libraries to several destinations at once. Each round sends the next slice
+    /// of one upload on every link before waiting for the replies, so that satellites on
+    /// different links receive their copy concurrently; those sharing a link take turns.
+    pub fn subkernel_upload_broadcast(io: &Io, routing_table: &drtio_routing::RoutingTable,
+            uploads: &[(u32, u8, &[u8])]) -> Result<(), &'static str> {
+        let mut fragments: Vec<(u32, u8, u8, Fragmenter)> = uploads.iter()
+            .map(|&(id, destination, data)|
+                (id, destination, routing_table.0[destination as usize][0] - 1, Fragmenter::borrowed(data, 0)))
+            .collect();
+        let mut slice: [u8; MASTER_PAYLOAD_MAX_SIZE] = [0; MASTER_PAYLOAD_MAX_SIZE];
+        loop {
+            // the first unfinished upload of each link, in link order
+            let mut round: Vec<usize> = Vec::new();
+            for (index, &(_, _, linkno, ref data)) in fragments.iter().enumerate() {
+                if !data.is_done() && !round.iter().any(|&other| fragments[other].2 == linkno) {
+                    round.push(index);
+                }
+            }
+            if round.is_empty() {
+                return Ok(())
+            }
+            round.sort_by_key(|&index| fragments[index].2);
+            let mut guards = Vec::new();
+            for &index in round.iter() {
+                let upload = &mut fragments[index];
+                let (id, destination, linkno) = (upload.0, upload.1, upload.2);
+                let fragment = upload.3.next(&mut slice, payload_size(destination));
+                guards.push(lock_link(io, linkno, Some(destination)));
+                drtioaux::send(linkno, &drtioaux::Packet::SubkernelAddDataRequest {
+                    id: id, destination: destination, last: fragment.last,
+                    length: fragment.len as u16, data: slice
+                }).unwrap();
+            }
+            // every reply is taken before giving up, none is left behind on the links
+            let mut result = Ok(());
+            for &index in round.iter() {
+                let reply = match recv_aux_timeout(io, fragments[index].2, 200) {
+                    Ok(drtioaux::Packet::SubkernelAddDataReply { succeeded: true }) => Ok(()),
+                    Ok(drtioaux::Packet::SubkernelAddDataReply { succeeded: false }) =>
+                        Err("error adding subkernel on satellite"),
+                    Ok(_) => Err("adding subkernel failed, unexpected aux packet"),
+                    Err(_) => Err("adding subkernel failed, aux error")
+                };
+                result = result.and(reply);
+            }
+            result?;
+        }
+    }
+
+    pub fn subkernel_upload_delta(io: &Io, routing_table: &drtio_routing::RoutingTable,
+            id: u32, destination: u8, delta: &Vec<u8>) -> Result<(), &'static str> {
+        let linkno = routing_table.0[destination as usize][0] - 1;
+        partition_data(delta, destination, |slice, last, len: usize| {
+            let reply = aux_transact(io, linkno,
+                &drtioaux::Packet::SubkernelAddDeltaRequest {
+                    id: id, destination: destination, last: last, length: len as u16, data: *slice});
+            match reply {
+                Ok(drtioaux::Packet::SubkernelAddDataReply { succeeded: true }) => Ok(()),
+                Ok(drtioaux::Packet::SubkernelAddDataReply { succeeded: false }) =>
+                    Err("satellite could not apply the delta"),
+                Ok(_) => Err("delta upload failed, unexpected aux packet"),
+                Err(_) => Err("delta upload failed, aux error")
+            }
+        })
+    }
+
+    pub fn subkernel_upload_symbols(io: &Io, routing_table: &drtio_routing::RoutingTable,
+            id: u32, destination: u8, symbols: &Vec<u8>) -> Result<(), &'static str> {
+        let linkno = routing_table.0[destination as usize][0] - 1;
+        partition_data(symbols, destination, |slice, last, len: usize| {
+            let reply = aux_transact(io, linkno,
+                &drtioaux::Packet::SubkernelAddSymbolsRequest {
+                    id: id, destination: destination, last: last, length: len as u16, data: *slice});
+            match reply {
+                Ok(drtioaux::Packet::SubkernelAddDataReply { succeeded: true }) => Ok(()),
+                Ok(drtioaux::Packet::SubkernelAddDataReply { succeeded: false }) =>
+                    Err("error adding subkernel symbols on satellite"),
+                Ok(_) => Err("adding subkernel symbols failed, unexpected aux packet"),
+                Err(_) => Err("adding subkernel symbols failed, aux error")
+            }
+        })
+    }
+
+    pub fn subkernel_load(io: &Io, routing_table: &drtio_routing::RoutingTable,
             id: u32, destination: u8, run: bool) -> Result<(), &'static str> {
         let linkno = routing_table.0[destination as usize][0] - 1;
-        let reply = aux_transact(io, aux_mutex, linkno, 
+        let reply = aux_transact(io, linkno, 
             &drtioaux::Packet::SubkernelLoadRunRequest{ id: id, destination: destination, run: run });
         match reply {
             Ok(drtioaux::Packet::SubkernelLoadRunReply { succeeded: true }) => return Ok(()),
@@ -532,33 +948,119 @@ pub mod drtio {
         }
     }
 
-    pub fn subkernel_retrieve_exception(io: &Io, aux_mutex: &Mutex,
-        routing_table: &drtio_routing::RoutingTable, destination: u8
+    pub fn subkernel_grant_credits(io: &Io, routing_table: &drtio_routing::RoutingTable,
+            id: u32, destination: u8, credits: u8) -> Result<(), &'static str> {
+        let linkno = routing_table.0[destination as usize][0] - 1;
+        let reply = aux_transact(io, linkno,
+            &drtioaux::Packet::SubkernelMessageCredit { destination: destination, id: id, credits: credits });
+        match reply {
+            Ok(drtioaux::Packet::SubkernelMessageCreditAck) => Ok(()),
+            Ok(_) => Err("granting message credits failed, unexpected aux packet"),
+            Err(_) => Err("granting message credits failed, aux error")
+        }
+    }
+
+    pub fn subkernel_keepalive(io: &Io, routing_table: &drtio_routing::RoutingTable,
+            id: u32, destination: u8) -> Result<bool, &'static str> {
+        let linkno = routing_table.0[destination as usize][0] - 1;
+        let reply = aux_transact(io, linkno,
+            &drtioaux::Packet::SubkernelKeepaliveRequest { destination: destination, id: id });
+        match reply {
+            Ok(drtioaux::Packet::SubkernelKeepaliveReply { running }) => Ok(running),
+            Ok(_) => Err("subkernel keepalive failed, unexpected aux packet"),
+            Err(_) => Err("subkernel keepalive failed, aux error")
+        }
+    }
+
+    pub fn subkernel_add_peer(io: &Io, routing_table: &drtio_routing::RoutingTable,
+            destination: u8, id: u32, peer: u8) -> Result<(), &'static str> {
+        let linkno = routing_table.0[destination as usize][0] - 1;
+        let reply = aux_transact(io, linkno,
+            &drtioaux::Packet::SubkernelPeerAddRequest { destination: destination, id: id, peer: peer });
+        match reply {
+            Ok(drtioaux::Packet::SubkernelPeerAddReply { succeeded: true }) => Ok(()),
+            Ok(drtioaux::Packet::SubkernelPeerAddReply { succeeded: false }) =>
+                Err("satellite rejected subkernel peer"),
+            Ok(_) => Err("received unexpected aux packet while adding subkernel peer"),
+            Err(_) => Err("aux error on adding subkernel peer")
+        }
+    }
+
+    pub fn subkernel_stop(io: &Io, routing_table: &drtio_routing::RoutingTable,
+            id: u32, destination: u8) -> Result<(), &'static str> {
+        let linkno = routing_table.0[destination as usize][0] - 1;
+        let reply = aux_transact(io, linkno,
+            &drtioaux::Packet::SubkernelStopRequest { destination: destination, id: id });
+        match reply {
+            Ok(drtioaux::Packet::SubkernelStopReply { succeeded: true }) => Ok(()),
+            Ok(drtioaux::Packet::SubkernelStopReply { succeeded: false }) =>
+                Err("subkernel was not running"),
+            Ok(_) => Err("received unexpected aux packet during subkernel stop"),
+            Err(_) => Err("aux error on subkernel stop")
+        }
+    }
+
+    pub fn subkernel_retrieve_exception(io: &Io,
+        routing_table: &drtio_routing::RoutingTable, destination: u8, id: u32,
+        head: Vec<u8>, complete: bool
+    ) -> Result<Vec<u8>, &'static str> {
+        // head holds whatever was already pushed with the exception notification
+        let linkno = routing_table.0[destination as usize][0] - 1;
+        let mut exception = Reassembler::new();
+        let mut last = exception.push(clock::get_ms(), complete, &head)?;
+        while !last {
+            let reply = aux_transact(io, linkno, 
+                &drtioaux::Packet::SubkernelExceptionRequest { destination: destination, id: id });
+            match reply {
+                Ok(drtioaux::Packet::SubkernelException { last: last_slice, length, data }) => { 
+                    last = exception.push(clock::get_ms(), last_slice, &data[0..length as usize])?;
+                },
+                Ok(_) => return Err("received unexpected aux packet during subkernel exception request"),
+                Err(e) => return Err(e)
+            }
+        }
+        let mut remote_data = exception.take();
+        // exception data follows the satellite timestamp, severity and compression flag
+        if remote_data.len() < 10 {
+            return Err("no exception stored for subkernel");
+        }
+        let timestamp = NetworkEndian::read_u64(&remote_data[..8]);
+        info!("[DEST#{}] subkernel {} raised a {} exception at {} ms satellite time",
+              destination, id, exception_severity_name(remote_data[8]), timestamp);
+        if remote_data[9] != 0 {
+            return compression::decompress(&remote_data[10..]);
+        }
+        Ok(remote_data.split_off(10))
+    }
+
+    pub fn subkernel_retrieve_log(io: &Io,
+        routing_table: &drtio_routing::RoutingTable, destination: u8, id: Option<u32>
     ) -> Result<Vec<u8>, &'static str> {
         let linkno = routing_table.0[destination as usize][0] - 1;
         let mut remote_data: Vec<u8> = Vec::new();
         loop {
-            let reply = aux_transact(io, aux_mutex, linkno, 
-                &drtioaux::Packet::SubkernelExceptionRequest { destination: destination });
+            let reply = aux_transact(io, linkno, 
+                &drtioaux::Packet::SubkernelLogRequest {
+                    destination: destination, filter: id.is_some(), id: id.unwrap_or(0) });
             match reply {
-                Ok(drtioaux::Packet::SubkernelException { last, length, data }) => { 
+                Ok(drtioaux::Packet::SubkernelLog { last, length, data }) => { 
                     remote_data.extend(&data[0..length as usize]);
                     if last {
                         return Ok(remote_data);
                     }
                 },
-                Ok(_) => return Err("received unexpected aux packet during subkernel exception request"),
+                Ok(_) => return Err("received unexpected aux packet during subkernel log request"),
                 Err(e) => return Err(e)
             }
         }
     }
 
-    pub fn subkernel_send_message(io: &Io, aux_mutex: &Mutex,
+    pub fn subkernel_send_message(io: &Io,
         routing_table: &drtio_routing::RoutingTable, id: u32, destination: u8, message: &[u8]
     ) -> Result<(), &'static str> {
         let linkno = routing_table.0[destination as usize][0] - 1;
-        partition_data(message, |slice, last, len: usize| {
-            let reply = aux_transact(io, aux_mutex, linkno, 
+        partition_data(message, destination, |slice, last, len: usize| {
+            let reply = aux_transact(io, linkno, 
                 &drtioaux::Packet::SubkernelMessage {
                     destination: destination, id: id, last: last, length: len as u16, data: *slice});
             match reply {
@@ -569,21 +1071,383 @@ pub mod drtio {
         })
     }
 
+    pub fn subkernel_send_rpc_reply(io: &Io,
+        routing_table: &drtio_routing::RoutingTable, id: u32, destination: u8, reply: &[u8]
+    ) -> Result<(), &'static str> {
+        let linkno = routing_table.0[destination as usize][0] - 1;
+        partition_data(reply, destination, |slice, last, len: usize| {
+            let reply = aux_transact(io, linkno,
+                &drtioaux::Packet::SubkernelRpcReply {
+                    destination: destination, id: id, last: last, length: len as u16, data: *slice});
+            match reply {
+                Ok(drtioaux::Packet::SubkernelMessageAck { .. }) => Ok(()),
+                Ok(_) => Err("sending RPC reply to subkernel failed, unexpected aux packet"),
+                Err(_) => Err("sending RPC reply to subkernel, aux error")
+            }
+        })
+    }
+
+    pub fn subkernel_inject_message(io: &Io,
+        routing_table: &drtio_routing::RoutingTable, id: u32, destination: u8, message: &[u8]
+    ) -> Result<(), &'static str> {
+        let linkno = routing_table.0[destination as usize][0] - 1;
+        partition_data(message, destination, |slice, last, len: usize| {
+            let reply = aux_transact(io, linkno,
+                &drtioaux::Packet::SubkernelInjectRequest {
+                    destination: destination, id: id, last: last, length: len as u16, data: *slice});
+            match reply {
+                Ok(drtioaux::Packet::SubkernelInjectReply { succeeded: true }) => Ok(()),
+                Ok(drtioaux::Packet::SubkernelInjectReply { succeeded: false }) =>
+                    Err("subkernel is not running on the satellite"),
+                Ok(_) => Err("received unexpected aux packet during message injection"),
+                Err(_) => Err("aux error on message injection")
+            }
+        })
+    }
+
+    pub fn satellite_set_log_filter(io: &Io,
+        routing_table: &drtio_routing::RoutingTable, destination: u8, target: u8, level: u8
+    ) -> Result<(), &'static str> {
+        let linkno = routing_table.0[destination as usize][0] - 1;
+        let reply = aux_transact(io, linkno,
+            &drtioaux::Packet::SatelliteLogFilterRequest {
+                destination: destination, target: target, level: level });
+        match reply {
+            Ok(drtioaux::Packet::SatelliteLogBasicReply { succeeded: true }) => Ok(()),
+            Ok(drtioaux::Packet::SatelliteLogBasicReply { succeeded: false }) =>
+                Err("satellite rejected log level change"),
+            Ok(_) => Err("received unexpected aux packet during log level change"),
+            Err(_) => Err("aux error on log level change")
+        }
+    }
+
+    pub fn satellite_set_log_outputs(io: &Io,
+        routing_table: &drtio_routing::RoutingTable, destination: u8, uart: bool, forward: bool
+    ) -> Result<(), &'static str> {
+        let linkno = routing_table.0[destination as usize][0] - 1;
+        let reply = aux_transact(io, linkno,
+            &drtioaux::Packet::SatelliteLogOutputRequest {
+                destination: destination, uart: uart, forward: forward });
+        match reply {
+            Ok(drtioaux::Packet::SatelliteLogBasicReply { succeeded: true }) => Ok(()),
+            Ok(drtioaux::Packet::SatelliteLogBasicReply { succeeded: false }) =>
+                Err("satellite rejected log output change"),
+            Ok(_) => Err("received unexpected aux packet during log output change"),
+            Err(_) => Err("aux error on log output change")
+        }
+    }
+
+    pub fn satellite_log_control(io: &Io,
+        routing_table: &drtio_routing::RoutingTable, destination: u8, action: u8
+    ) -> Result<(), &'static str> {
+        let linkno = routing_table.0[destination as usize][0] - 1;
+        let reply = aux_transact(io, linkno,
+            &drtioaux::Packet::SatelliteLogControlRequest { destination: destination, action: action });
+        match reply {
+            Ok(drtioaux::Packet::SatelliteLogBasicReply { succeeded: true }) => Ok(()),
+            Ok(drtioaux::Packet::SatelliteLogBasicReply { succeeded: false }) =>
+                Err("satellite rejected log control request"),
+            Ok(_) => Err("received unexpected aux packet during log control request"),
+            Err(_) => Err("aux error on log control request")
+        }
+    }
+
+    pub fn subkernel_suppress_exception(io: &Io,
+        routing_table: &drtio_routing::RoutingTable, destination: u8,
+        exception_id: Option<u32>, prefix: &str
+    ) -> Result<(), &'static str> {
+        let linkno = routing_table.0[destination as usize][0] - 1;
+        let length = prefix.len().min(payload_size(destination));
+        let mut data: [u8; MASTER_PAYLOAD_MAX_SIZE] = [0; MASTER_PAYLOAD_MAX_SIZE];
+        data[..length].copy_from_slice(&prefix.as_bytes()[..length]);
+        let reply = aux_transact(io, linkno,
+            &drtioaux::Packet::SubkernelExceptionSuppressRequest {
+                destination: destination, match_id: exception_id.is_some(),
+                exception_id: exception_id.unwrap_or(0), length: length as u16, prefix: data });
+        match reply {
+            Ok(drtioaux::Packet::SubkernelExceptionSuppressReply { succeeded: true }) => Ok(()),
+            Ok(drtioaux::Packet::SubkernelExceptionSuppressReply { succeeded: false }) =>
+                Err("satellite rejected exception suppression rule"),
+            Ok(_) => Err("received unexpected aux packet during exception suppression request"),
+            Err(_) => Err("aux error on exception suppression request")
+        }
+    }
+
+    pub fn subkernel_mailbox_stats(io: &Io,
+        routing_table: &drtio_routing::RoutingTable, destination: u8, clear: bool
+    ) -> Result<[u32; MAILBOX_STATS_COUNT], &'static str> {
+        let linkno = routing_table.0[destination as usize][0] - 1;
+        let reply = aux_transact(io, linkno,
+            &drtioaux::Packet::SubkernelMailboxStatsRequest { destination: destination, clear: clear });
+        match reply {
+            Ok(drtioaux::Packet::SubkernelMailboxStatsReply { counters }) => Ok(counters),
+            Ok(_) => Err("received unexpected aux packet during mailbox statistics request"),
+            Err(_) => Err("aux error on mailbox statistics request")
+        }
+    }
+
+    pub fn subkernel_message_stats(io: &Io,
+        routing_table: &drtio_routing::RoutingTable, destination: u8, clear: bool
+    ) -> Result<[u32; MESSAGE_STATS_COUNT], &'static str> {
+        let linkno = routing_table.0[destination as usize][0] - 1;
+        let reply = aux_transact(io, linkno,
+            &drtioaux::Packet::SubkernelMessageStatsRequest { destination: destination, clear: clear });
+        match reply {
+            Ok(drtioaux::Packet::SubkernelMessageStatsReply { counters }) => Ok(counters),
+            Ok(_) => Err("received unexpected aux packet during message statistics request"),
+            Err(_) => Err("aux error on message statistics request")
+        }
+    }
+
+    // last aux round trip times measured to each destination: min, average, max (us)
+    static mut AUX_LATENCIES: [Option<[u32; 3]>; drtio_routing::DEST_COUNT] = [None; drtio_routing::DEST_COUNT];
+
+    /// Times `count` aux round trips to the destination, returning and keeping
+    /// the shortest, average and longest (us).
+    pub fn measure_aux_latency(io: &Io,
+        routing_table: &drtio_routing::RoutingTable, destination: u8, count: u32
+    ) -> Result<[u32; 3], &'static str> {
+        if count == 0 {
+            return Err("no round trip to time")
+        }
+        let linkno = routing_table.0[destination as usize][0] - 1;
+        let (mut min, mut total, mut max) = (u64::max_value(), 0, 0);
+        for _ in 0..count {
+            let sent = clock::get_us();
+            let reply = aux_transact(io, linkno,
+                &drtioaux::Packet::TimestampedPingRequest { destination: destination, timestamp: sent });
+            let elapsed = clock::get_us() - sent;
+            match reply {
+                Ok(drtioaux::Packet::TimestampedPingReply { timestamp }) if timestamp == sent => (),
+                Ok(drtioaux::Packet::TimestampedPingReply { .. }) => return Err("received reply to an earlier ping"),
+                Ok(_) => return Err("received unexpected aux packet during latency measurement"),
+                Err(_) => return Err("aux error on latency measurement")
+            }
+            min = min.min(elapsed);
+            max = max.max(elapsed);
+            total += elapsed;
+        }
+        let latency = [min as u32, (total / count as u64) as u32, max as u32];
+        unsafe { AUX_LATENCIES[destination as usize] = Some(latency) }
+        Ok(latency)
+    }
+
+    /// Round trip times of the last measurement to the destination, if any.
+    pub fn aux_latency(destination: u8) -> Option<[u32; 3]> {
+        unsafe { AUX_LATENCIES[destination as usize] }
+    }
+
+    /// Returns the aux error counters of the link towards the destination, those kept
+    /// by the master followed by those kept by the satellite on its uplink.
+    pub fn link_errors(io: &Io,
+        routing_table: &drtio_routing::RoutingTable, destination: u8, clear: bool
+    ) -> Result<[u32; 2 * LINK_ERROR_COUNT], &'static str> {
+        let linkno = routing_table.0[destination as usize][0] - 1;
+        let mut counters = [0; 2 * LINK_ERROR_COUNT];
+        // the request itself is not counted in what is cleared
+        let reply = aux_transact(io, linkno,
+            &drtioaux::Packet::SatelliteLinkErrorsRequest { destination: destination, clear: clear });
+        counters[..LINK_ERROR_COUNT].copy_from_slice(&drtioaux::link_errors(linkno, clear));
+        match reply {
+            Ok(drtioaux::Packet::SatelliteLinkErrorsReply { counters: satellite }) => {
+                counters[LINK_ERROR_COUNT..].copy_from_slice(&satellite);
+                Ok(counters)
+            }
+            Ok(_) => Err("received unexpected aux packet during link error counters request"),
+            Err(_) => Err("aux error on link error counters request")
+        }
+    }
+
+    pub fn satellite_set_work_budget(io: &Io,
+        routing_table: &drtio_routing::RoutingTable, destination: u8, budget_us: u32
+    ) -> Result<(), &'static str> {
+        let linkno = routing_table.0[destination as usize][0] - 1;
+        let reply = aux_transact(io, linkno,
+            &drtioaux::Packet::SatelliteWorkBudgetRequest { destination: destination, budget_us: budget_us });
+        match reply {
+            Ok(drtioaux::Packet::SatelliteWorkBudgetReply { succeeded: true }) => Ok(()),
+            Ok(drtioaux::Packet::SatelliteWorkBudgetReply { succeeded: false }) =>
+                Err("satellite rejected work budget"),
+            Ok(_) => Err("received unexpected aux packet during work budget change"),
+            Err(_) => Err("aux error on work budget change")
+        }
+    }
+
+    pub fn analyzer_set_capture(io: &Io,
+        routing_table: &drtio_routing::RoutingTable, destination: u8, enable: bool
+    ) -> Result<(), &'static str> {
+        let linkno = routing_table.0[destination as usize][0] - 1;
+        let reply = aux_transact(io, linkno,
+            &drtioaux::Packet::AnalyzerCaptureRequest { destination: destination, enable: enable });
+        match reply {
+            Ok(drtioaux::Packet::AnalyzerCaptureReply { succeeded: true }) => Ok(()),
+            Ok(drtioaux::Packet::AnalyzerCaptureReply { succeeded: false }) =>
+                Err("satellite rejected analyzer capture change"),
+            Ok(_) => Err("received unexpected aux packet during analyzer capture change"),
+            Err(_) => Err("aux error on analyzer capture change")
+        }
+    }
+
+    pub fn satellite_loop_stats(io: &Io,
+        routing_table: &drtio_routing::RoutingTable, destination: u8, clear: bool
+    ) -> Result<[u32; LOOP_STATS_COUNT], &'static str> {
+        let linkno = routing_table.0[destination as usize][0] - 1;
+        let reply = aux_transact(io, linkno,
+            &drtioaux::Packet::SatelliteLoopStatsRequest { destination: destination, clear: clear });
+        match reply {
+            Ok(drtioaux::Packet::SatelliteLoopStatsReply { counters }) => Ok(counters),
+            Ok(_) => Err("received unexpected aux packet during main loop statistics request"),
+            Err(_) => Err("aux error on main loop statistics request")
+        }
+    }
+
+    pub fn satellite_memory_stats(io: &Io,
+        routing_table: &drtio_routing::RoutingTable, destination: u8
+    ) -> Result<[u32; MEMORY_STATS_COUNT], &'static str> {
+        let linkno = routing_table.0[destination as usize][0] - 1;
+        let reply = aux_transact(io, linkno,
+            &drtioaux::Packet::SatelliteMemoryStatsRequest { destination: destination });
+        match reply {
+            Ok(drtioaux::Packet::SatelliteMemoryStatsReply { counters }) => Ok(counters),
+            Ok(_) => Err("received unexpected aux packet during memory statistics request"),
+            Err(_) => Err("aux error on memory statistics request")
+        }
+    }
+
+    pub fn subkernel_benchmark(io: &Io,
+        routing_table: &drtio_routing::RoutingTable, destination: u8, iterations: u32, message_size: u32
+    ) -> Result<[u32; BENCHMARK_RESULT_COUNT], &'static str> {
+        let linkno = routing_table.0[destination as usize][0] - 1;
+        let reply = aux_transact(io, linkno, &drtioaux::Packet::SubkernelBenchmarkRequest {
+            destination: destination, iterations: iterations, message_size: message_size });
+        match reply {
+            Ok(drtioaux::Packet::SubkernelBenchmarkReply { succeeded: true }) => (),
+            Ok(drtioaux::Packet::SubkernelBenchmarkReply { succeeded: false }) =>
+                return Err("satellite could not start the benchmark"),
+            Ok(_) => return Err("received unexpected aux packet during benchmark request"),
+            Err(_) => return Err("aux error on benchmark request")
+        }
+        let max_time = clock::get_ms() + 30_000;
+        loop {
+            io.sleep(10).unwrap();
+            let reply = aux_transact(io, linkno,
+                &drtioaux::Packet::SubkernelBenchmarkResultRequest { destination: destination });
+            match reply {
+                Ok(drtioaux::Packet::SubkernelBenchmarkResultReply { ready: true, results }) => return Ok(results),
+                Ok(drtioaux::Packet::SubkernelBenchmarkResultReply { ready: false, .. }) => (),
+                Ok(_) => return Err("received unexpected aux packet during benchmark"),
+                Err(_) => return Err("aux error on benchmark result request")
+            }
+            if clock::get_ms() > max_time {
+                return Err("benchmark timed out")
+            }
+        }
+    }
+
+    pub fn subkernel_cancel_messages(io: &Io,
+        routing_table: &drtio_routing::RoutingTable, destination: u8
+    ) -> Result<bool, &'static str> {
+        let linkno = routing_table.0[destination as usize][0] - 1;
+        let reply = aux_transact(io, linkno,
+            &drtioaux::Packet::SubkernelMessageCancelRequest { destination: destination });
+        match reply {
+            Ok(drtioaux::Packet::SubkernelMessageCancelReply { cancelled }) => Ok(cancelled),
+            Ok(_) => Err("received unexpected aux packet during message cancellation"),
+            Err(_) => Err("aux error on message cancellation")
+        }
+    }
+
+    pub fn subkernel_suppressed_count(io: &Io,
+        routing_table: &drtio_routing::RoutingTable, destination: u8, clear: bool
+    ) -> Result<u32, &'static str> {
+        let linkno = routing_table.0[destination as usize][0] - 1;
+        let reply = aux_transact(io, linkno,
+            &drtioaux::Packet::SubkernelExceptionSuppressedRequest { destination: destination, clear: clear });
+        match reply {
+            Ok(drtioaux::Packet::SubkernelExceptionSuppressedReply { count }) => Ok(count),
+            Ok(_) => Err("received unexpected aux packet during suppressed exception count request"),
+            Err(_) => Err("aux error on suppressed exception count request")
+        }
+    }
+
+    pub fn subkernel_unsuppress_exceptions(io: &Io,
+        routing_table: &drtio_routing::RoutingTable, destination: u8
+    ) -> Result<(), &'static str> {
+        let linkno = routing_table.0[destination as usize][0] - 1;
+        let reply = aux_transact(io, linkno,
+            &drtioaux::Packet::SubkernelExceptionUnsuppressRequest { destination: destination });
+        match reply {
+            Ok(drtioaux::Packet::SubkernelExceptionSuppressReply { succeeded: true }) => Ok(()),
+            Ok(_) => Err("received unexpected aux packet during exception unsuppression request"),
+            Err(_) => Err("aux error on exception unsuppression request")
+        }
+    }
+
+    pub fn subkernel_set_exception_replay(io: &Io,
+        routing_table: &drtio_routing::RoutingTable, destination: u8, enable: bool
+    ) -> Result<(), &'static str> {
+        let linkno = routing_table.0[destination as usize][0] - 1;
+        let reply = aux_transact(io, linkno,
+            &drtioaux::Packet::SubkernelExceptionReplayRequest { destination: destination, enable: enable });
+        match reply {
+            Ok(drtioaux::Packet::SubkernelExceptionReplayReply { succeeded: true }) => Ok(()),
+            Ok(drtioaux::Packet::SubkernelExceptionReplayReply { succeeded: false }) =>
+                Err("satellite rejected exception replay request"),
+            Ok(_) => Err("received unexpected aux packet during exception replay request"),
+            Err(_) => Err("aux error on exception replay request")
+        }
+    }
+
+    pub fn subkernel_set_comm_timeouts(io: &Io,
+        routing_table: &drtio_routing::RoutingTable, destination: u8, slot_ms: u32, await_poll_ms: u32
+    ) -> Result<(), &'static str> {
+        let linkno = routing_table.0[destination as usize][0] - 1;
+        let reply = aux_transact(io, linkno,
+            &drtioaux::Packet::SubkernelCommTimeoutsRequest {
+                destination: destination, slot_ms: slot_ms, await_poll_ms: await_poll_ms });
+        match reply {
+            Ok(drtioaux::Packet::SubkernelCommTimeoutsReply { succeeded: true }) => Ok(()),
+            Ok(drtioaux::Packet::SubkernelCommTimeoutsReply { succeeded: false }) =>
+                Err("satellite rejected communication timeouts"),
+            Ok(_) => Err("received unexpected aux packet during communication timeouts change"),
+            Err(_) => Err("aux error on communication timeouts change")
+        }
+    }
+
+    pub fn satellite_set_log_rate_limit(io: &Io,
+        routing_table: &drtio_routing::RoutingTable, destination: u8, rate: u32
+    ) -> Result<(), &'static str> {
+        let linkno = routing_table.0[destination as usize][0] - 1;
+        let reply = aux_transact(io, linkno,
+            &drtioaux::Packet::SatelliteLogRateLimitRequest { destination: destination, rate: rate });
+        match reply {
+            Ok(drtioaux::Packet::SatelliteLogBasicReply { succeeded: true }) => Ok(()),
+            Ok(drtioaux::Packet::SatelliteLogBasicReply { succeeded: false }) =>
+                Err("satellite rejected log rate limit change"),
+            Ok(_) => Err("received unexpected aux packet during log rate limit change"),
+            Err(_) => Err("aux error on log rate limit change")
+        }
+    }
+
 }
 
 #[cfg(not(has_drtio))]
 pub mod drtio {
     use super::*;
 
-    pub fn startup(_io: &Io, _aux_mutex: &Mutex,
+    pub fn startup(_io: &Io,
         _routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
         _up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
         _ddma_mutex: &Mutex, _subkernel_mutex: &Mutex) {}
-    pub fn reset(_io: &Io, _aux_mutex: &Mutex) {}
+    pub fn reset(_io: &Io) {}
 }
 
 static mut SEEN_ASYNC_ERRORS: u8 = 0;
 
+pub unsafe fn add_async_errors(errors: u8) {
+    SEEN_ASYNC_ERRORS |= errors;
+}
+
 pub unsafe fn get_async_errors() -> u8 {
     let errors = SEEN_ASYNC_ERRORS;
     SEEN_ASYNC_ERRORS = 0;
@@ -639,21 +1503,21 @@ fn read_device_map() -> DeviceMap {
     device_map
 }
 
-pub fn startup(io: &Io, aux_mutex: &Mutex,
+pub fn startup(io: &Io,
         routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
         up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
         ddma_mutex: &Mutex, subkernel_mutex: &Mutex) {
     set_device_map(read_device_map());
-    drtio::startup(io, aux_mutex, routing_table, up_destinations, ddma_mutex, subkernel_mutex);
+    drtio::startup(io, routing_table, up_destinations, ddma_mutex, subkernel_mutex);
     unsafe {
         csr::rtio_core::reset_phy_write(1);
     }
     io.spawn(4096, async_error_thread);
 }
 
-pub fn reset(io: &Io, aux_mutex: &Mutex) {
+pub fn reset(io: &Io) {
     unsafe {
         csr::rtio_core::reset_write(1);
     }
-    drtio::reset(io, aux_mutex)
+    drtio::reset(io)
 }
@@ -271,6 +271,22 @@ impl<'a> Io<'a> {
         Ok(value.unwrap())
     }
 
+    /// Suspends until `event` is notified or, if given, `deadline_ms` passes.
+    /// Returns whether it was notified.
+    pub fn wait(&self, event: &Event, deadline_ms: Option<u64>) -> Result<bool, Error> {
+        let notified = event.0.get();
+        let mut f = || event.0.get() != notified;
+        let f = unsafe { mem::transmute::<&mut dyn FnMut() -> bool, *mut dyn FnMut() -> bool>(&mut f) };
+        match self.suspend(WaitRequest {
+            timeout: deadline_ms,
+            event:   Some(f)
+        }) {
+            Ok(()) => Ok(true),
+            Err(Error::TimedOut) => Ok(false),
+            Err(err) => Err(err)
+        }
+    }
+
     pub fn join(&self, handle: ThreadHandle) -> Result<(), Error> {
         self.until(move || handle.terminated())
     }
@@ -288,6 +304,20 @@ impl<'a> Io<'a> {
     }
 }
 
+/// Something that threads wait for, notified by the thread that sees it happen, so that
+/// waiters only compare a count rather than check the condition at every scheduler pass.
+pub struct Event(Cell<u32>);
+
+impl Event {
+    pub const fn new() -> Event {
+        Event(Cell::new(0))
+    }
+
+    pub fn notify(&self) {
+        self.0.set(self.0.get().wrapping_add(1))
+    }
+}
+
 #[derive(Clone)]
 pub struct Mutex(Urc<Cell<bool>>);
 
@@ -1,13 +1,13 @@
 use core::{mem, str, cell::{Cell, RefCell}, fmt::Write as FmtWrite};
-use alloc::{vec::Vec, string::{String, ToString}};
+use alloc::{vec::Vec, string::{String, ToString}, collections::VecDeque};
 use byteorder::{ByteOrder, NativeEndian};
 use cslice::CSlice;
 
 use io::{Read, Write, Error as IoError};
 #[cfg(has_drtio)]
 use io::{Cursor, ProtoRead};
-use board_misoc::{ident, cache, config};
-use {mailbox, rpc_queue, kernel};
+use board_misoc::{ident, cache, clock, config};
+use {mailbox, mailbox_queue, kern_log_ring, rpc_queue, kernel, auth};
 use urc::Urc;
 use sched::{ThreadHandle, Io, Mutex, TcpListener, TcpStream, Error as SchedError};
 use rtio_clocking;
@@ -16,6 +16,9 @@ use rtio_dma::Manager as DmaManager;
 use rtio_dma::remote_dma;
 #[cfg(has_drtio)]
 use kernel::{subkernel, subkernel::Error as SubkernelError};
+#[cfg(has_drtio)]
+use proto_artiq::drtioaux_proto::{RPC_REPLY_VALUE, RPC_REPLY_EXCEPTION};
+use proto_artiq::drtioaux_proto::{EXCEPTION_SEVERITY_FATAL, exception_severity};
 use rtio_mgt::get_async_errors;
 use cache::Cache;
 use kern_hwreq;
@@ -25,6 +28,11 @@ use rpc_proto as rpc;
 use session_proto as host;
 use kernel_proto as kern;
 
+// how often queued kernel messages are processed when the mailbox is empty
+const KERN_QUEUE_DRAIN_INTERVAL_MS: u64 = 10;
+// experiments a host may leave to be run once it disconnects
+const EXPERIMENT_QUEUE_MAX_ENTRIES: usize = 16;
+
 #[derive(Fail, Debug)]
 pub enum Error<T> {
     #[fail(display = "cannot load kernel: {}", _0)]
@@ -33,8 +41,16 @@ pub enum Error<T> {
     KernelNotFound,
     #[fail(display = "invalid kernel CPU pointer: {:#08x}", _0)]
     InvalidPointer(usize),
+    #[fail(display = "corrupted kernel CPU message at {:#08x}: {}", _0, _1)]
+    CorruptMessage(usize, &'static str),
     #[fail(display = "RTIO clock failure")]
     ClockFailure,
+    #[fail(display = "session watchdog expired")]
+    WatchdogExpired,
+    #[fail(display = "host did not authenticate")]
+    Unauthorized,
+    #[fail(display = "no heartbeat from host")]
+    HostSilent,
     #[fail(display = "protocol error: {}", _0)]
     Protocol(#[cause] host::Error<T>),
     #[fail(display = "subkernel io error")]
@@ -100,12 +116,24 @@ macro_rules! propagate_subkernel_exception {
     }
 }
 
+// Kernel queued by a host, see host::Request::QueueExperiment
+#[derive(Debug)]
+struct QueuedExperiment {
+    kernel: Vec<u8>,
+    subkernels: Vec<host::SubkernelUpload>,
+    // the watchdog of the session that queued it
+    watchdog_ms: u32
+}
+
 // Persistent state
 #[derive(Debug)]
 struct Congress {
     cache: Cache,
     dma_manager: DmaManager,
-    finished_cleanly: Cell<bool>
+    finished_cleanly: Cell<bool>,
+    experiments: VecDeque<QueuedExperiment>,
+    // subkernel session of the host connected, 0 until it needs one, see attach_subkernel_session
+    subkernel_session: u32
 }
 
 impl Congress {
@@ -113,7 +141,9 @@ impl Congress {
         Congress {
             cache: Cache::new(),
             dma_manager: DmaManager::new(),
-            finished_cleanly: Cell::new(true)
+            finished_cleanly: Cell::new(true),
+            experiments: VecDeque::new(),
+            subkernel_session: 0
         }
     }
 }
@@ -131,7 +161,16 @@ enum KernelState {
 struct Session<'a> {
     congress: &'a mut Congress,
     kernel_state: KernelState,
-    log_buffer: String
+    log_buffer: String,
+    next_queue_drain: u64,
+    // set by the host, see host::Request::SetWatchdog
+    watchdog_ms: u32,
+    // when the kernel running was started plus watchdog_ms
+    watchdog_deadline: Option<u64>,
+    // set by the host, see host::Request::Heartbeat
+    heartbeat_ms: u32,
+    // when the last request of the host came
+    heard_ms: u64
 }
 
 impl<'a> Session<'a> {
@@ -139,10 +178,25 @@ impl<'a> Session<'a> {
         Session {
             congress: congress,
             kernel_state: KernelState::Absent,
-            log_buffer: String::new()
+            log_buffer: String::new(),
+            next_queue_drain: 0,
+            watchdog_ms: 0,
+            watchdog_deadline: None,
+            heartbeat_ms: 0,
+            heard_ms: clock::get_ms()
         }
     }
 
+    // the host is not expected to say anything while its kernel runs on its own
+    fn host_silent(&self) -> bool {
+        self.heartbeat_ms != 0 && self.kernel_state != KernelState::Running &&
+            clock::get_ms() > self.heard_ms + self.heartbeat_ms as u64
+    }
+
+    fn watchdog_expired(&self) -> bool {
+        self.running() && self.watchdog_deadline.map_or(false, |deadline| clock::get_ms() > deadline)
+    }
+
     fn running(&self) -> bool {
         match self.kernel_state {
             KernelState::Absent  | KernelState::Loaded  => false,
@@ -172,8 +226,13 @@ fn host_read<R>(reader: &mut R) -> Result<host::Request, Error<R::ReadError>>
     let request = host::Request::read_from(reader)?;
     match &request {
         &host::Request::LoadKernel(_) => debug!("comm<-host LoadLibrary(...)"),
-        &host::Request::UploadSubkernel { id, destination, kernel: _} => debug!(
+        &host::Request::Authenticate { .. } => debug!("comm<-host Authenticate(...)"),
+        &host::Request::UploadSubkernel { id, destination, .. } => debug!(
             "comm<-host UploadSubkernel(id: {}, destination: {}, ...)", id, destination),
+        &host::Request::UploadSubkernels(ref subkernels) => debug!(
+            "comm<-host UploadSubkernels({} subkernels, ...)", subkernels.len()),
+        &host::Request::StageSubkernels(ref subkernels) => debug!(
+            "comm<-host StageSubkernels({} subkernels, ...)", subkernels.len()),
         _ => debug!("comm<-host {:?}", request)
     }
     Ok(request)
@@ -188,7 +247,7 @@ fn host_write<W>(writer: &mut W, reply: host::Reply) -> Result<(), IoError<W::Wr
 
 pub fn kern_send(io: &Io, request: &kern::Message) -> Result<(), Error<SchedError>> {
     match request {
-        &kern::LoadRequest(_) => debug!("comm->kern LoadRequest(...)"),
+        &kern::LoadRequest { version, .. } => debug!("comm->kern LoadRequest {{ version: {}, ... }}", version),
         &kern::DmaRetrieveReply { trace, duration, uses_ddma } => {
             if trace.map(|data| data.len() > 100).unwrap_or(false) {
                 debug!("comm->kern DmaRetrieveReply {{ trace: ..., duration: {:?}, uses_ddma: {} }}", duration, uses_ddma)
@@ -205,11 +264,11 @@ pub fn kern_send(io: &Io, request: &kern::Message) -> Result<(), Error<SchedErro
 fn kern_recv_notrace<R, F>(io: &Io, f: F) -> Result<R, Error<SchedError>>
         where F: FnOnce(&kern::Message) -> Result<R, Error<SchedError>> {
     io.until(|| mailbox::receive() != 0)?;
-    if !kernel::validate(mailbox::receive()) {
-        return Err(Error::InvalidPointer(mailbox::receive()))
-    }
+    let envelope = mailbox::receive();
+    let message = unsafe { kern::open_envelope(envelope, kernel::validate) }
+        .map_err(|reason| Error::CorruptMessage(envelope, reason))?;
 
-    f(unsafe { &*(mailbox::receive() as *const kern::Message) })
+    f(unsafe { &*(message as *const kern::Message) })
 }
 
 fn kern_recv_dotrace(reply: &kern::Message) {
@@ -249,14 +308,19 @@ unsafe fn kern_load(io: &Io, session: &mut Session, library: &[u8])
 
     kernel::start();
 
-    kern_send(io, &kern::LoadRequest(&library))?;
+    kern_send(io, &kern::LoadRequest { version: kern::KERNEL_PROTO_VERSION, library: &library, resident: false })?;
     kern_recv(io, |reply| {
         match reply {
-            kern::LoadReply(Ok(())) => {
+            kern::LoadReply { version, .. } if *version != kern::KERNEL_PROTO_VERSION => {
+                kernel::stop();
+                Err(Error::Load(format!("kernel CPU support uses protocol version {}, firmware expects {}",
+                                        version, kern::KERNEL_PROTO_VERSION)))
+            }
+            kern::LoadReply { result: Ok(_), .. } => {
                 session.kernel_state = KernelState::Loaded;
                 Ok(())
             }
-            kern::LoadReply(Err(error)) => {
+            kern::LoadReply { result: Err(error), .. } => {
                 kernel::stop();
                 Err(Error::Load(format!("{}", error)))
             }
@@ -272,14 +336,84 @@ fn kern_run(session: &mut Session) -> Result<(), Error<SchedError>> {
     }
 
     session.kernel_state = KernelState::Running;
+    session.watchdog_deadline = match session.watchdog_ms {
+        0 => None,
+        timeout_ms => Some(clock::get_ms() + timeout_ms as u64)
+    };
     // TODO: make this a separate request
     kern_acknowledge()
 }
 
-fn process_host_message(io: &Io, _aux_mutex: &Mutex, _ddma_mutex: &Mutex, _subkernel_mutex: &Mutex,
+// Opens the subkernel session of the host, or reattaches it to `reattach`, left by its
+// previous connection. Returns whether the host has the session it asked for.
+#[cfg(has_drtio)]
+fn attach_subkernel_session(io: &Io, subkernel_mutex: &Mutex,
+                            routing_table: &drtio_routing::RoutingTable, session: &mut Session,
+                            reattach: u32) -> bool {
+    if session.congress.subkernel_session != 0 {
+        return session.congress.subkernel_session == reattach
+    }
+    if subkernel::reattach_session(io, subkernel_mutex, reattach) {
+        session.congress.subkernel_session = reattach;
+        true
+    } else {
+        session.congress.subkernel_session = subkernel::open_session(io, subkernel_mutex, routing_table);
+        false
+    }
+}
+
+// Subkernels may be uploaded while a kernel runs, for the next one, as long as that
+// does not replace any the kernel running may still start or await.
+#[cfg(has_drtio)]
+fn check_replaceable(io: &Io, subkernel_mutex: &Mutex, session: &Session, ids: &[u32])
+                     -> Result<(), SubkernelError> {
+    if session.running() {
+        subkernel::check_replaceable(io, subkernel_mutex, ids)
+    } else {
+        Ok(())
+    }
+}
+
+// Returns the IDs of the subkernels added.
+#[cfg(has_drtio)]
+fn add_subkernels(io: &Io, subkernel_mutex: &Mutex, session: &Session,
+                  uploads: Vec<host::SubkernelUpload>) -> Result<Vec<u32>, SubkernelError> {
+    let ids: Vec<u32> = uploads.iter().map(|upload| upload.id).collect();
+    check_replaceable(io, subkernel_mutex, session, &ids)?;
+    for upload in uploads {
+        subkernel::add_subkernel(io, subkernel_mutex, upload.id, upload.destination,
+            upload.kernel, upload.symbols, upload.persistent)?;
+    }
+    Ok(ids)
+}
+
+// Stops the subkernels of the host session rather than leave them for it to reattach.
+#[cfg(has_drtio)]
+fn close_subkernel_session(io: &Io, subkernel_mutex: &Mutex,
+                           routing_table: &drtio_routing::RoutingTable, session: &mut Session) {
+    let tag = mem::replace(&mut session.congress.subkernel_session, 0);
+    if tag != 0 {
+        subkernel::close_session(io, subkernel_mutex, routing_table, tag);
+    }
+}
+
+fn process_host_message(io: &Io, _ddma_mutex: &Mutex, _subkernel_mutex: &Mutex,
                         _routing_table: &drtio_routing::RoutingTable, stream: &mut TcpStream,
                         session: &mut Session) -> Result<(), Error<SchedError>> {
-    match host_read(stream)? {
+    let request = host_read(stream)?;
+    session.heard_ms = clock::get_ms();
+    match request {
+        // sent by hosts holding a token when the core device requires none, those required
+        // are taken before the session starts, see `authenticate`
+        host::Request::Authenticate { token } => {
+            if !auth::check(&token) {
+                warn!("wrong authentication token from {}", stream.remote_endpoint());
+                host_write(stream, host::Reply::Unauthorized)?;
+                return Err(Error::Unauthorized)
+            }
+            host_write(stream, host::Reply::Authenticated)?
+        }
+
         host::Request::SystemInfo => {
             host_write(stream, host::Reply::SystemInfo {
                 ident: ident::read(&mut [0; 64]),
@@ -289,6 +423,8 @@ fn process_host_message(io: &Io, _aux_mutex: &Mutex, _ddma_mutex: &Mutex, _subke
         }
 
         host::Request::LoadKernel(kernel) => {
+            #[cfg(has_drtio)]
+            attach_subkernel_session(io, _subkernel_mutex, _routing_table, session, 0);
             match unsafe { kern_load(io, session, &kernel) } {
                 Ok(()) => host_write(stream, host::Reply::LoadCompleted)?,
                 Err(error) => {
@@ -305,6 +441,12 @@ fn process_host_message(io: &Io, _aux_mutex: &Mutex, _ddma_mutex: &Mutex, _subke
                 Err(_) => host_write(stream, host::Reply::KernelStartupFailed)?
             },
 
+        host::Request::SetWatchdog { timeout_ms } =>
+            session.watchdog_ms = timeout_ms,
+
+        host::Request::Heartbeat { timeout_ms } =>
+            session.heartbeat_ms = timeout_ms,
+
         host::Request::RpcReply { tag } => {
             if session.kernel_state != KernelState::RpcWait {
                 unexpected!("unsolicited RPC reply")
@@ -368,11 +510,35 @@ fn process_host_message(io: &Io, _aux_mutex: &Mutex, _ddma_mutex: &Mutex, _subke
             session.kernel_state = KernelState::Running
         }
 
-        host::Request::UploadSubkernel { id: _id, destination: _dest, kernel: _kernel } => {
+        host::Request::UploadSubkernel { id: _id, destination: _dest, kernel: _kernel, symbols: _symbols,
+                                         persistent: _persistent } => {
+            #[cfg(has_drtio)]
+            {
+                attach_subkernel_session(io, _subkernel_mutex, _routing_table, session, 0);
+                let uploaded = check_replaceable(io, _subkernel_mutex, session, &[_id]).and_then(|()| {
+                    subkernel::add_subkernel(io, _subkernel_mutex, _id, _dest, _kernel, _symbols, _persistent)?;
+                    subkernel::upload(io, _subkernel_mutex, _routing_table, _id)
+                });
+                match uploaded {
+                    Ok(_) => host_write(stream, host::Reply::LoadCompleted)?,
+                    Err(error) => {
+                        let mut description = String::new();
+                        write!(&mut description, "{}", error).unwrap();
+                        host_write(stream, host::Reply::LoadFailed(&description))?
+                    }
+                }
+            }
+            #[cfg(not(has_drtio))]
+            host_write(stream, host::Reply::LoadFailed("No DRTIO on this system, subkernels are not supported"))?
+        }
+
+        host::Request::UploadSubkernels(_subkernels) => {
             #[cfg(has_drtio)]
             {
-                subkernel::add_subkernel(io, _subkernel_mutex, _id, _dest, _kernel);
-                match subkernel::upload(io, _aux_mutex, _subkernel_mutex, _routing_table, _id) {
+                attach_subkernel_session(io, _subkernel_mutex, _routing_table, session, 0);
+                let uploaded = add_subkernels(io, _subkernel_mutex, session, _subkernels).and_then(|ids|
+                    subkernel::upload_many(io, _subkernel_mutex, _routing_table, &ids));
+                match uploaded {
                     Ok(_) => host_write(stream, host::Reply::LoadCompleted)?,
                     Err(error) => {
                         let mut description = String::new();
@@ -384,19 +550,135 @@ fn process_host_message(io: &Io, _aux_mutex: &Mutex, _ddma_mutex: &Mutex, _subke
             #[cfg(not(has_drtio))]
             host_write(stream, host::Reply::LoadFailed("No DRTIO on this system, subkernels are not supported"))?
         }
+
+        host::Request::StageSubkernels(_subkernels) => {
+            #[cfg(has_drtio)]
+            {
+                attach_subkernel_session(io, _subkernel_mutex, _routing_table, session, 0);
+                match add_subkernels(io, _subkernel_mutex, session, _subkernels) {
+                    Ok(ids) => {
+                        subkernel::stage(io, _subkernel_mutex, &ids);
+                        host_write(stream, host::Reply::LoadCompleted)?
+                    }
+                    Err(error) => {
+                        let mut description = String::new();
+                        write!(&mut description, "{}", error).unwrap();
+                        host_write(stream, host::Reply::LoadFailed(&description))?
+                    }
+                }
+            }
+            #[cfg(not(has_drtio))]
+            host_write(stream, host::Reply::LoadFailed("No DRTIO on this system, subkernels are not supported"))?
+        }
+
+        host::Request::QueueExperiment { kernel, subkernels } => {
+            if !cfg!(has_drtio) && !subkernels.is_empty() {
+                host_write(stream, host::Reply::LoadFailed("No DRTIO on this system, subkernels are not supported"))?
+            } else if session.congress.experiments.len() >= EXPERIMENT_QUEUE_MAX_ENTRIES {
+                host_write(stream, host::Reply::ExperimentQueueFull)?
+            } else {
+                let watchdog_ms = session.watchdog_ms;
+                session.congress.experiments.push_back(QueuedExperiment {
+                    kernel: kernel,
+                    subkernels: subkernels,
+                    watchdog_ms: watchdog_ms
+                });
+                let pending = session.congress.experiments.len() as u32;
+                host_write(stream, host::Reply::ExperimentQueue { pending: pending })?
+            }
+        }
+
+        host::Request::ClearExperiments => {
+            session.congress.experiments.clear();
+            host_write(stream, host::Reply::ExperimentQueue { pending: 0 })?
+        }
+
+        host::Request::Reattach { session: _tag } => {
+            #[cfg(has_drtio)]
+            {
+                let reattached = attach_subkernel_session(io, _subkernel_mutex,
+                    _routing_table, session, _tag);
+                host_write(stream, host::Reply::SessionAttached {
+                    session: session.congress.subkernel_session,
+                    reattached: reattached
+                })?
+            }
+            #[cfg(not(has_drtio))]
+            host_write(stream, host::Reply::SessionAttached { session: 0, reattached: false })?
+        }
     }
 
     Ok(())
 }
 
-fn process_kern_message(io: &Io, aux_mutex: &Mutex,
+fn process_queued_kern_messages(_io: &Io, _routing_table: &drtio_routing::RoutingTable,
+                                _ddma_mutex: &Mutex, session: &mut Session) {
+    // logs and messages queued before the one in the mailbox are always taken first,
+    // queued messages are not left waiting for the next drain
+    let now = clock::get_ms();
+    if mailbox::receive() == 0 && mailbox_queue::receive() == 0 && now < session.next_queue_drain {
+        return
+    }
+    session.next_queue_drain = now + KERN_QUEUE_DRAIN_INTERVAL_MS;
+    let mut text = Vec::new();
+    kern_log_ring::drain(|part| text.extend_from_slice(part));
+    if !text.is_empty() {
+        session.log_buffer += &String::from_utf8_lossy(&text);
+        session.flush_log_buffer();
+    }
+    loop {
+        let message = mailbox_queue::receive();
+        if message == 0 {
+            break
+        }
+        if !mailbox_queue::contains(message) {
+            warn!("invalid queued message pointer {:#08x} from kernel CPU", message);
+        } else {
+            match unsafe { &*(message as *const kern::Message) } {
+                &kern::DmaStartRemoteRequest { id: _id, timestamp: _timestamp } => {
+                    #[cfg(has_drtio)]
+                    remote_dma::playback(_io, _ddma_mutex, _routing_table, _id as u32, _timestamp as u64);
+                }
+                other => warn!("unexpected queued message {:?} from kernel CPU", other)
+            }
+        }
+        mailbox_queue::acknowledge();
+    }
+}
+
+fn process_batched_kern_message(session: &mut Session, message: &kern::Message)
+                               -> Result<Option<kern::Message<'static>>, Error<SchedError>> {
+    match message {
+        &kern::LogSlice(arg) => {
+            session.log_buffer += arg;
+            session.flush_log_buffer();
+            Ok(None)
+        }
+        &kern::RpcFlush => Ok(None),
+        &kern::CacheGetRequest { key } => {
+            let value = session.congress.cache.get(key);
+            // see CacheGetRequest in process_kern_message
+            Ok(Some(kern::CacheGetReply { value: unsafe { mem::transmute(value) } }))
+        }
+        &kern::CachePutRequest { key, value } => {
+            let succeeded = session.congress.cache.put(key, value).is_ok();
+            Ok(Some(kern::CachePutReply { succeeded: succeeded }))
+        }
+        other => unexpected!("unexpected message {:?} in kernel CPU batch", other)
+    }
+}
+
+fn process_kern_message(io: &Io,
                         routing_table: &drtio_routing::RoutingTable,
                         up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
                         ddma_mutex: &Mutex, _subkernel_mutex: &Mutex, mut stream: Option<&mut TcpStream>,
                         session: &mut Session) -> Result<bool, Error<SchedError>> {
+    if session.running() {
+        process_queued_kern_messages(io, routing_table, ddma_mutex, session);
+    }
     kern_recv_notrace(io, |request| {
         match (request, session.kernel_state) {
-            (&kern::LoadReply(_), KernelState::Loaded) |
+            (&kern::LoadReply { .. }, KernelState::Loaded) |
             (&kern::RpcRecvRequest(_), KernelState::RpcWait) => {
                 // We're standing by; ignore the message.
                 return Ok(false)
@@ -410,7 +692,7 @@ fn process_kern_message(io: &Io, aux_mutex: &Mutex,
 
         kern_recv_dotrace(request);
 
-        if kern_hwreq::process_kern_hwreq(io, aux_mutex, routing_table, up_destinations, request)? {
+        if kern_hwreq::process_kern_hwreq(io, routing_table, up_destinations, request)? {
             return Ok(false)
         }
 
@@ -434,7 +716,7 @@ fn process_kern_message(io: &Io, aux_mutex: &Mutex,
                 if let Some(_id) = session.congress.dma_manager.record_start(name) {
                     // replace the record
                     #[cfg(has_drtio)]
-                    remote_dma::erase(io, aux_mutex, ddma_mutex, routing_table, _id);
+                    remote_dma::erase(io, ddma_mutex, routing_table, _id);
                 }
                 kern_acknowledge()
             }
@@ -446,7 +728,7 @@ fn process_kern_message(io: &Io, aux_mutex: &Mutex,
                 let _id = session.congress.dma_manager.record_stop(duration, enable_ddma, io, ddma_mutex);
                 #[cfg(has_drtio)]
                 if enable_ddma {
-                    remote_dma::upload_traces(io, aux_mutex, ddma_mutex, routing_table, _id);
+                    remote_dma::upload_traces(io, ddma_mutex, routing_table, _id);
                 }
                 cache::flush_l2_cache();
                 kern_acknowledge()
@@ -454,7 +736,7 @@ fn process_kern_message(io: &Io, aux_mutex: &Mutex,
             &kern::DmaEraseRequest { name } => {
                 #[cfg(has_drtio)]
                 if let Some(id) = session.congress.dma_manager.get_id(name) {
-                    remote_dma::erase(io, aux_mutex, ddma_mutex, routing_table, *id);
+                    remote_dma::erase(io, ddma_mutex, routing_table, *id);
                 }
                 session.congress.dma_manager.erase(name);
                 kern_acknowledge()
@@ -463,7 +745,7 @@ fn process_kern_message(io: &Io, aux_mutex: &Mutex,
                 session.congress.dma_manager.with_trace(name, |trace, duration| {
                     #[cfg(has_drtio)]
                     let uses_ddma = match trace {
-                        Some(trace) => remote_dma::has_remote_traces(io, aux_mutex, trace.as_ptr() as u32),
+                        Some(trace) => remote_dma::has_remote_traces(io, trace.as_ptr() as u32),
                         None => false
                     };
                     #[cfg(not(has_drtio))]
@@ -476,8 +758,9 @@ fn process_kern_message(io: &Io, aux_mutex: &Mutex,
                 })
             }
             &kern::DmaStartRemoteRequest { id: _id, timestamp: _timestamp } => {
+                // sent through the mailbox when the message queue was full
                 #[cfg(has_drtio)]
-                remote_dma::playback(io, aux_mutex, ddma_mutex, routing_table, _id as u32, _timestamp as u64);
+                remote_dma::playback(io, ddma_mutex, routing_table, _id as u32, _timestamp as u64);
                 kern_acknowledge()
             }
             &kern::DmaAwaitRemoteRequest { id: _id } => {
@@ -531,6 +814,16 @@ fn process_kern_message(io: &Io, aux_mutex: &Mutex,
                 kern_send(io, &kern::CachePutReply { succeeded: succeeded })
             }
 
+            &kern::Batch(messages) => {
+                let mut replies = Vec::with_capacity(messages.len());
+                for message in messages {
+                    if let Some(reply) = process_batched_kern_message(session, message)? {
+                        replies.push(reply);
+                    }
+                }
+                kern_send(io, &kern::BatchReply(&replies))
+            }
+
             &kern::RunFinished => {
                 unsafe { kernel::stop() }
                 session.kernel_state = KernelState::Absent;
@@ -547,7 +840,8 @@ fn process_kern_message(io: &Io, aux_mutex: &Mutex,
             &kern::RunException {
                 exceptions,
                 stack_pointers,
-                backtrace
+                backtrace,
+                ..
             } => {
                 unsafe { kernel::stop() }
                 session.kernel_state = KernelState::Absent;
@@ -566,7 +860,10 @@ fn process_kern_message(io: &Io, aux_mutex: &Mutex,
                             exceptions: exceptions,
                             stack_pointers: stack_pointers,
                             backtrace: backtrace,
-                            async_errors: unsafe { get_async_errors() }
+                            async_errors: unsafe { get_async_errors() },
+                            kernel_log: "",
+                            severity: exceptions.first().and_then(|exception| exception.as_ref())
+                                .map_or(EXCEPTION_SEVERITY_FATAL, |exception| exception_severity(exception.id))
                         }).map_err(|e| e.into())
                     }
                 }
@@ -574,7 +871,7 @@ fn process_kern_message(io: &Io, aux_mutex: &Mutex,
             #[cfg(has_drtio)]
             &kern::SubkernelLoadRunRequest { id, run } => {
                 let succeeded = match subkernel::load(
-                    io, aux_mutex, _subkernel_mutex, routing_table, id, run) {
+                    io, _subkernel_mutex, routing_table, id, run) {
                         Ok(()) => true,
                         Err(e) => { error!("Error loading subkernel: {}", e); false }
                     };
@@ -582,8 +879,19 @@ fn process_kern_message(io: &Io, aux_mutex: &Mutex,
             }
             #[cfg(has_drtio)]
             &kern::SubkernelAwaitFinishRequest{ id, timeout } => {
-                let res = subkernel::await_finish(io, aux_mutex, _subkernel_mutex, routing_table,
-                    id, timeout);
+                let res = loop {
+                    // the timeout starts over after serving an RPC from a subkernel
+                    match subkernel::await_finish(io, _subkernel_mutex, routing_table, id, timeout,
+                            session.watchdog_deadline) {
+                        Err(SubkernelError::RpcPending) =>
+                            serve_forwarded_rpcs(io, routing_table, _subkernel_mutex, &mut stream)?,
+                        res => break res
+                    }
+                };
+                if let Err(SubkernelError::WatchdogExpired) = res {
+                    subkernel::abort(io, _subkernel_mutex, routing_table, id);
+                    return Err(Error::WatchdogExpired)
+                }
                 let status = match res {
                     Ok(ref res) => {
                             if res.comm_lost {
@@ -604,18 +912,36 @@ fn process_kern_message(io: &Io, aux_mutex: &Mutex,
             }
             #[cfg(has_drtio)]
             &kern::SubkernelMsgSend { id, count, tag, data } => {
-                subkernel::message_send(io, aux_mutex, _subkernel_mutex, routing_table, id, count, tag, data)?;
+                subkernel::message_send(io, _subkernel_mutex, routing_table, id, count, tag, data)?;
                 kern_acknowledge()
             }
             #[cfg(has_drtio)]
             &kern::SubkernelMsgRecvRequest { id, timeout } => {
-                let message_received = subkernel::message_await(io, _subkernel_mutex, id, timeout);
+                let message_received = loop {
+                    match subkernel::message_await(io, _subkernel_mutex, id, timeout, session.watchdog_deadline) {
+                        Err(SubkernelError::RpcPending) =>
+                            serve_forwarded_rpcs(io, routing_table, _subkernel_mutex, &mut stream)?,
+                        res => break res
+                    }
+                };
+                if let Err(SubkernelError::WatchdogExpired) = message_received {
+                    subkernel::abort(io, _subkernel_mutex, routing_table, id);
+                    return Err(Error::WatchdogExpired)
+                }
                 let (status, count) = match message_received {
-                    Ok(ref message) => (kern::SubkernelStatus::NoError, message.tag_count),
+                    Ok(ref message) => match rpc::check_tagged(&message.data, message.tag_count as usize) {
+                        Ok(()) => (kern::SubkernelStatus::NoError, message.tag_count),
+                        Err(tag_error) => {
+                            // dropped whole, the kernel decides whether to carry on
+                            warn!("dropping malformed message from subkernel #{}: {}", id, tag_error);
+                            (kern::SubkernelStatus::DecodeError { offset: tag_error.offset as u32 }, 0)
+                        }
+                    },
                     Err(SubkernelError::Timeout) => (kern::SubkernelStatus::Timeout, 0),
                     Err(SubkernelError::IncorrectState) => (kern::SubkernelStatus::IncorrectState, 0),
+                    Err(SubkernelError::ReceiveAborted) => (kern::SubkernelStatus::ReceiveAborted, 0),
                     Err(SubkernelError::SubkernelFinished) => {
-                        let res = subkernel::retrieve_finish_status(io, aux_mutex, _subkernel_mutex,
+                        let res = subkernel::retrieve_finish_status(io, _subkernel_mutex,
                             routing_table, id)?;
                         if res.comm_lost {
                             (kern::SubkernelStatus::CommLost, 0)
@@ -628,13 +954,14 @@ fn process_kern_message(io: &Io, aux_mutex: &Mutex,
                     }
                     Err(_) => (kern::SubkernelStatus::OtherError, 0)
                 };
-                kern_send(io, &kern::SubkernelMsgRecvReply { status: status, count: count })?;
-                if let Ok(message) = message_received {
+                let decodable = match status { kern::SubkernelStatus::NoError => true, _ => false };
+                let master_us = match message_received { Ok(ref message) => message.master_us, Err(_) => 0 };
+                kern_send(io, &kern::SubkernelMsgRecvReply { status: status, count: count, master_us: master_us })?;
+                if let (true, Ok(message)) = (decodable, message_received) {
                     // receive code almost identical to RPC recv, except we are not reading from a stream
                     let mut reader = Cursor::new(message.data);
-                    let mut tag: [u8; 1] = [message.tag];
-                    let mut i = 0;
-                    loop {
+                    for _ in 0..message.tag_count {
+                        let start = reader.position();
                         // kernel has to consume all arguments in the whole message
                         let slot = kern_recv(io, |reply| {
                             match reply {
@@ -643,7 +970,7 @@ fn process_kern_message(io: &Io, aux_mutex: &Mutex,
                                     "expected root value slot from kernel CPU, not {:?}", other)
                             }
                         })?;
-                        let res = rpc::recv_return(&mut reader, &tag, slot, &|size| -> Result<_, Error<SchedError>> {
+                        let res = rpc::recv_tagged(&mut reader, slot, &|size| -> Result<_, Error<SchedError>> {
                             if size == 0 {
                                 return Ok(0 as *mut ())
                             }
@@ -658,34 +985,49 @@ fn process_kern_message(io: &Io, aux_mutex: &Mutex,
                         });
                         match res {
                             Ok(_) => kern_send(io, &kern::RpcRecvReply(Ok(0)))?,
-                            Err(_) => unexpected!("expected valid subkernel message data")
+                            Err(_) => match rpc::tagged_error(reader.get_ref(), start) {
+                                Some(tag_error) => unexpected!("malformed message from subkernel #{}: {}", id, tag_error),
+                                None => unexpected!("expected valid subkernel message data")
+                            }
                         };
-                        i += 1;
-                        if i < message.tag_count {
-                            // update the tag for next read
-                            tag[0] = reader.read_u8()?;
-                        } else {
-                            // should be done by then
-                            break;
-                        }
                     }
                     Ok(())
                 } else {
-                    // if timed out, no data has been received, exception should be raised by kernel
+                    // if timed out or malformed, no data is passed, exception should be raised by kernel
                     Ok(())
                 }
             },
 
+            &kern::SubkernelReplayExceptionRequest => {
+                // exceptions are only stored on satellites
+                kern_send(io, &kern::SubkernelReplayExceptionReply(None))
+            }
+
             request => unexpected!("unexpected request {:?} from kernel CPU", request)
         }.and(Ok(false))
     })
 }
 
-fn process_kern_queued_rpc(stream: &mut TcpStream,
+fn process_kern_queued_rpc(io: &Io, routing_table: &drtio_routing::RoutingTable,
+                           subkernel_mutex: &Mutex, stream: &mut TcpStream,
                            _session: &mut Session) -> Result<(), Error<SchedError>> {
     rpc_queue::dequeue(|slice| {
+        let header = NativeEndian::read_u32(slice);
+        if header & rpc_queue::SUBKERNEL_MESSAGE != 0 {
+            let length = (header & !rpc_queue::SUBKERNEL_MESSAGE) as usize;
+            let id = NativeEndian::read_u32(&slice[4..]);
+            debug!("comm<-kern (async subkernel message to #{})", id);
+            #[cfg(has_drtio)]
+            return subkernel::message_send_serialized(io, subkernel_mutex, routing_table,
+                id, &slice[8..][3..length]).map_err(|e| e.into());
+            #[cfg(not(has_drtio))]
+            {
+                let _ = (io, routing_table, subkernel_mutex, length);
+                unexpected!("subkernel message from kernel without DRTIO support")
+            }
+        }
         debug!("comm<-kern (async RPC)");
-        let length = NativeEndian::read_u32(slice) as usize;
+        let length = header as usize;
         host_write(stream, host::Reply::RpcRequest { async: true })?;
         debug!("{:?}", &slice[4..][..length]);
         stream.write_all(&slice[4..][..length])?;
@@ -693,33 +1035,114 @@ fn process_kern_queued_rpc(stream: &mut TcpStream,
     })
 }
 
-fn host_kernel_worker(io: &Io, aux_mutex: &Mutex,
+// Passes RPCs from subkernels on to the host, and their replies back. The host serves
+// them like those of the kernel, so this is only done while no RPC of its own is pending.
+#[cfg(has_drtio)]
+fn serve_forwarded_rpcs(io: &Io, routing_table: &drtio_routing::RoutingTable,
+                        subkernel_mutex: &Mutex, stream: &mut Option<&mut TcpStream>
+                        ) -> Result<(), Error<SchedError>> {
+    while let Some(forwarded) = subkernel::rpc_take(io, subkernel_mutex)? {
+        let stream: &mut TcpStream = match *stream {
+            Some(ref mut stream) => &mut **stream,
+            None => {
+                warn!("RPC from subkernel #{} cannot be served by flash kernel", forwarded.id);
+                if !forwarded.async {
+                    let mut reply = vec![RPC_REPLY_EXCEPTION];
+                    reply.extend(&[0; 4]);
+                    subkernel::rpc_reply_send(io, subkernel_mutex, routing_table,
+                        forwarded.id, &reply)?;
+                }
+                continue
+            }
+        };
+        debug!("comm<-subkernel #{} (RPC)", forwarded.id);
+        host_write(stream, host::Reply::RpcRequest { async: forwarded.async })?;
+        stream.write_all(&forwarded.data)?;
+        if forwarded.async {
+            continue
+        }
+        // the host replies before handling any other request
+        let mut reply = Vec::new();
+        match host_read(stream)? {
+            host::Request::RpcReply { tag } => {
+                reply.push(RPC_REPLY_VALUE);
+                let mut length = [0; 4];
+                NativeEndian::write_u32(&mut length, tag.len() as u32);
+                reply.extend(&length);
+                reply.extend(&tag);
+                rpc::copy_return(stream, &tag, &mut reply)?;
+            }
+            host::Request::RpcException { id, .. } => {
+                reply.push(RPC_REPLY_EXCEPTION);
+                let mut id_bytes = [0; 4];
+                NativeEndian::write_u32(&mut id_bytes, id);
+                reply.extend(&id_bytes);
+            }
+            other => unexpected!("expected RPC reply for subkernel #{}, not {:?}", forwarded.id, other)
+        }
+        subkernel::rpc_reply_send(io, subkernel_mutex, routing_table, forwarded.id, &reply)?;
+    }
+    Ok(())
+}
+
+fn host_kernel_worker(io: &Io,
                       routing_table: &drtio_routing::RoutingTable,
                       up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
                       ddma_mutex: &Mutex, subkernel_mutex: &Mutex,
                       stream: &mut TcpStream,
                       congress: &mut Congress) -> Result<(), Error<SchedError>> {
     let mut session = Session::new(congress);
-    #[cfg(has_drtio)]
-    subkernel::clear_subkernels(&io, &subkernel_mutex);
 
     loop {
         if stream.can_recv() {
-            process_host_message(io, aux_mutex, ddma_mutex, subkernel_mutex,
+            process_host_message(io, ddma_mutex, subkernel_mutex,
                 routing_table, stream, &mut session)?
         } else if !stream.may_recv() {
             return Ok(())
         }
 
         while !rpc_queue::empty() {
-            process_kern_queued_rpc(stream, &mut session)?
+            process_kern_queued_rpc(io, routing_table, subkernel_mutex, stream, &mut session)?
         }
 
         if mailbox::receive() != 0 {
-            process_kern_message(io, aux_mutex,
-                routing_table, up_destinations,
-                ddma_mutex, subkernel_mutex,
-                Some(stream), &mut session)?;
+            match process_kern_message(io,
+                    routing_table, up_destinations,
+                    ddma_mutex, subkernel_mutex,
+                    Some(stream), &mut session) {
+                Err(Error::WatchdogExpired) => {
+                    #[cfg(has_drtio)]
+                    close_subkernel_session(io, subkernel_mutex, routing_table, &mut session);
+                    host_write(stream, host::Reply::WatchdogExpired)?;
+                    return Err(Error::WatchdogExpired)
+                }
+                result => { result?; }
+            }
+        }
+
+        if session.watchdog_expired() {
+            #[cfg(has_drtio)]
+            close_subkernel_session(io, subkernel_mutex, routing_table, &mut session);
+            host_write(stream, host::Reply::WatchdogExpired)?;
+            return Err(Error::WatchdogExpired)
+        }
+
+        if session.host_silent() {
+            // a hung host would otherwise keep its subkernels, uploads and messages
+            // on the satellites for as long as its connection stays open
+            warn!("no heartbeat from {} for {} ms, ending its session",
+                  stream.remote_endpoint(), session.heartbeat_ms);
+            #[cfg(has_drtio)]
+            close_subkernel_session(io, subkernel_mutex, routing_table, &mut session);
+            host_write(stream, host::Reply::SessionExpired)?;
+            return Err(Error::HostSilent)
+        }
+
+        #[cfg(has_drtio)]
+        {
+            if session.kernel_state == KernelState::Running && subkernel::rpc_pending() {
+                serve_forwarded_rpcs(io, routing_table, subkernel_mutex, &mut Some(&mut *stream))?;
+            }
         }
 
         if session.kernel_state == KernelState::Running {
@@ -733,7 +1156,7 @@ fn host_kernel_worker(io: &Io, aux_mutex: &Mutex,
     }
 }
 
-fn flash_kernel_worker(io: &Io, aux_mutex: &Mutex,
+fn flash_kernel_worker(io: &Io,
                        routing_table: &drtio_routing::RoutingTable,
                        up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
                        ddma_mutex: &Mutex, subkernel_mutex: &Mutex, congress: &mut Congress,
@@ -750,7 +1173,44 @@ fn flash_kernel_worker(io: &Io, aux_mutex: &Mutex,
             _ => Err(Error::KernelNotFound)
         }
     })?;
-    kern_run(&mut session)?;
+    hostless_kernel_run(io, routing_table, up_destinations, ddma_mutex, subkernel_mutex, &mut session)
+}
+
+fn experiment_worker(io: &Io,
+                     routing_table: &drtio_routing::RoutingTable,
+                     up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
+                     ddma_mutex: &Mutex, subkernel_mutex: &Mutex,
+                     congress: &mut Congress) -> Result<(), Error<SchedError>> {
+    let experiment = match congress.experiments.pop_front() {
+        Some(experiment) => experiment,
+        None => return Ok(())
+    };
+    info!("running queued experiment, {} more pending", congress.experiments.len());
+
+    #[cfg(has_drtio)]
+    {
+        let mut ids = Vec::new();
+        for upload in experiment.subkernels {
+            ids.push(upload.id);
+            subkernel::add_subkernel(io, subkernel_mutex, upload.id, upload.destination,
+                upload.kernel, upload.symbols, upload.persistent)?;
+        }
+        subkernel::upload_many(io, subkernel_mutex, routing_table, &ids)?;
+    }
+
+    let mut session = Session::new(congress);
+    session.watchdog_ms = experiment.watchdog_ms;
+    unsafe { kern_load(io, &mut session, &experiment.kernel)? }
+    hostless_kernel_run(io, routing_table, up_destinations, ddma_mutex, subkernel_mutex, &mut session)
+}
+
+// Runs a loaded kernel to its end with no host to serve its RPCs or report its exceptions to.
+fn hostless_kernel_run(io: &Io,
+                       routing_table: &drtio_routing::RoutingTable,
+                       up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
+                       ddma_mutex: &Mutex, subkernel_mutex: &Mutex,
+                       session: &mut Session) -> Result<(), Error<SchedError>> {
+    kern_run(session)?;
 
     loop {
         if !rpc_queue::empty() {
@@ -758,11 +1218,15 @@ fn flash_kernel_worker(io: &Io, aux_mutex: &Mutex,
         }
 
         if mailbox::receive() != 0 {
-            if process_kern_message(io, aux_mutex, routing_table, up_destinations, ddma_mutex, subkernel_mutex, None, &mut session)? {
+            if process_kern_message(io, routing_table, up_destinations, ddma_mutex, subkernel_mutex, None, session)? {
                 return Ok(())
             }
         }
 
+        if session.watchdog_expired() {
+            return Err(Error::WatchdogExpired)
+        }
+
         if !rtio_clocking::crg::check() {
             return Err(Error::ClockFailure)
         }
@@ -786,7 +1250,30 @@ fn respawn<F>(io: &Io, handle: &mut Option<ThreadHandle>, f: F)
     *handle = Some(io.spawn(16384, f))
 }
 
-pub fn thread(io: Io, aux_mutex: &Mutex,
+// Takes the token of a new connection when one is required, before the connection
+// replaces the running session, so that hosts without it cannot interrupt a kernel.
+fn authenticate(stream: &mut TcpStream) -> bool {
+    if !auth::required() {
+        return true
+    }
+    let authenticated = match host::read_auth_token(stream, auth::TOKEN_MAX_SIZE) {
+        Ok(Some(token)) => auth::check(&token),
+        Ok(None) => false,
+        Err(_) => {
+            warn!("cannot read authentication from {}", stream.remote_endpoint());
+            return false
+        }
+    };
+    let reply = if authenticated {
+        host::Reply::Authenticated
+    } else {
+        warn!("unauthenticated connection from {}", stream.remote_endpoint());
+        host::Reply::Unauthorized
+    };
+    host_write(stream, reply).is_ok() && authenticated
+}
+
+pub fn thread(io: Io,
         routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
         up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
         ddma_mutex: &Mutex, subkernel_mutex: &Mutex) {
@@ -796,12 +1283,14 @@ pub fn thread(io: Io, aux_mutex: &Mutex,
 
     let congress = Urc::new(RefCell::new(Congress::new()));
 
-    let mut kernel_thread = None;
+    let mut kernel_thread: Option<ThreadHandle> = None;
+    // whether kernel_thread runs a queued experiment, which no connection interrupts
+    let mut experiment_thread = false;
     {
         let routing_table = routing_table.borrow();
         let mut congress = congress.borrow_mut();
         info!("running startup kernel");
-        match flash_kernel_worker(&io, &aux_mutex, &routing_table, &up_destinations, 
+        match flash_kernel_worker(&io, &routing_table, &up_destinations, 
                 ddma_mutex, subkernel_mutex, &mut congress, "startup_kernel") {
             Ok(()) =>
                 info!("startup kernel finished"),
@@ -815,7 +1304,9 @@ pub fn thread(io: Io, aux_mutex: &Mutex,
     }
 
     loop {
-        if listener.can_accept() {
+        let experiment_running = experiment_thread &&
+            kernel_thread.as_ref().map_or(false, |h| !h.terminated());
+        if listener.can_accept() && !experiment_running {
             let mut stream = listener.accept().expect("session: cannot accept");
             stream.set_timeout(Some(2250));
             stream.set_keep_alive(Some(500));
@@ -836,20 +1327,24 @@ pub fn thread(io: Io, aux_mutex: &Mutex,
                     continue
                 }
             }
+            if !authenticate(&mut stream) {
+                stream.close().expect("session: cannot close");
+                continue
+            }
             info!("new connection from {}", stream.remote_endpoint());
 
-            let aux_mutex = aux_mutex.clone();
             let routing_table = routing_table.clone();
             let up_destinations = up_destinations.clone();
             let congress = congress.clone();
             let ddma_mutex = ddma_mutex.clone();
             let subkernel_mutex = subkernel_mutex.clone();
             let stream = stream.into_handle();
+            experiment_thread = false;
             respawn(&io, &mut kernel_thread, move |io| {
                 let routing_table = routing_table.borrow();
                 let mut congress = congress.borrow_mut();
                 let mut stream = TcpStream::from_handle(&io, stream);
-                match host_kernel_worker(&io, &aux_mutex, &routing_table, &up_destinations, 
+                match host_kernel_worker(&io, &routing_table, &up_destinations, 
                         &ddma_mutex, &subkernel_mutex, &mut stream, &mut *congress) {
                     Ok(()) => (),
                     Err(Error::Protocol(host::Error::Io(IoError::UnexpectedEnd))) =>
@@ -857,21 +1352,56 @@ pub fn thread(io: Io, aux_mutex: &Mutex,
                     Err(Error::Protocol(host::Error::Io(
                             IoError::Other(SchedError::Interrupted)))) =>
                         info!("kernel interrupted"),
+                    Err(Error::Unauthorized) =>
+                        info!("connection closed, host not authenticated"),
                     Err(err) => {
                         congress.finished_cleanly.set(false);
                         error!("session aborted: {}", err);
                     }
                 }
                 stream.close().expect("session: close socket");
+                // the host may be back to take its subkernels, see host::Request::Reattach
                 #[cfg(has_drtio)]
-                subkernel::clear_subkernels(&io, &subkernel_mutex);
+                {
+                    let tag = mem::replace(&mut congress.subkernel_session, 0);
+                    if tag != 0 {
+                        subkernel::detach_session(&io, &subkernel_mutex, &routing_table, tag);
+                    }
+                }
             });
         }
 
+        if kernel_thread.as_ref().map_or(true, |h| h.terminated()) &&
+                !congress.borrow().experiments.is_empty() {
+            let routing_table = routing_table.clone();
+            let up_destinations = up_destinations.clone();
+            let congress = congress.clone();
+            let ddma_mutex = ddma_mutex.clone();
+            let subkernel_mutex = subkernel_mutex.clone();
+            experiment_thread = true;
+            respawn(&io, &mut kernel_thread, move |io| {
+                let routing_table = routing_table.borrow();
+                let mut congress = congress.borrow_mut();
+                #[cfg(has_drtio)]
+                let session_tag = subkernel::open_session(&io, &subkernel_mutex, &routing_table);
+                match experiment_worker(&io, &routing_table, &up_destinations,
+                        &ddma_mutex, &subkernel_mutex, &mut *congress) {
+                    Ok(()) =>
+                        info!("queued experiment finished"),
+                    Err(err) => {
+                        congress.finished_cleanly.set(false);
+                        error!("queued experiment aborted: {}", err);
+                    }
+                }
+                #[cfg(has_drtio)]
+                subkernel::close_session(&io, &subkernel_mutex, &routing_table, session_tag);
+            })
+        }
+
         if kernel_thread.as_ref().map_or(true, |h| h.terminated()) {
             info!("no connection, starting idle kernel");
+            experiment_thread = false;
 
-            let aux_mutex = aux_mutex.clone();
             let routing_table = routing_table.clone();
             let up_destinations = up_destinations.clone();
             let congress = congress.clone();
@@ -880,7 +1410,7 @@ pub fn thread(io: Io, aux_mutex: &Mutex,
             respawn(&io, &mut kernel_thread, move |io| {
                 let routing_table = routing_table.borrow();
                 let mut congress = congress.borrow_mut();
-                match flash_kernel_worker(&io, &aux_mutex, &routing_table, &up_destinations, 
+                match flash_kernel_worker(&io, &routing_table, &up_destinations, 
                     &ddma_mutex, &subkernel_mutex, &mut *congress, "idle_kernel") {
                     Ok(()) =>
                         info!("idle kernel finished, standing by"),
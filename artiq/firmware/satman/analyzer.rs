@@ -1,6 +1,7 @@
 use core::cmp::min;
 use board_misoc::{csr, cache};
 use proto_artiq::drtioaux_proto::SAT_PAYLOAD_MAX_SIZE;
+use sat_payload_size;
 
 const BUFFER_SIZE: usize = 512 * 1024;
 
@@ -38,7 +39,10 @@ pub struct Analyzer {
     // necessary for keeping track of sent data
     data_len: usize,
     sent_bytes: usize,
-    data_pointer: usize
+    data_pointer: usize,
+    // only record while subkernels run, keeping the data of the last run
+    capture: bool,
+    capturing: bool
 }
 
 pub struct Header {
@@ -65,12 +69,41 @@ impl Analyzer {
         Analyzer {
             data_len: 0,
             sent_bytes: 0,
-            data_pointer: 0
+            data_pointer: 0,
+            capture: false,
+            capturing: false
+        }
+    }
+
+    pub fn set_capture(&mut self, enable: bool) {
+        self.capture = enable;
+        self.capturing = false;
+        if enable {
+            // nothing is recorded until a subkernel runs
+            disarm();
+        } else {
+            arm();
+        }
+    }
+
+    pub fn subkernel_starting(&mut self) {
+        if self.capture {
+            arm();
+            self.capturing = true;
+        }
+    }
+
+    // ends the capture once the subkernel is no longer running
+    pub fn service(&mut self, kernel_running: bool) {
+        if self.capturing && !kernel_running {
+            disarm();
+            self.capturing = false;
         }
     }
 
     pub fn get_header(&mut self) -> Header {
         disarm();
+        self.capturing = false;
 
         let overflow = unsafe { csr::rtio_analyzer::message_encoder_overflow_read() != 0 };
         let total_byte_count = unsafe { csr::rtio_analyzer::dma_byte_count_read() };
@@ -89,7 +122,7 @@ impl Analyzer {
     pub fn get_data(&mut self, data_slice: &mut [u8; SAT_PAYLOAD_MAX_SIZE]) -> AnalyzerSliceMeta {
         let data = unsafe { &BUFFER.data[..] };
         let i = (self.data_pointer + self.sent_bytes) % BUFFER_SIZE;
-        let len = min(SAT_PAYLOAD_MAX_SIZE, self.data_len - self.sent_bytes);
+        let len = min(sat_payload_size(), self.data_len - self.sent_bytes);
         let last = self.sent_bytes + len == self.data_len;
 
         if i + len >= BUFFER_SIZE {
@@ -100,7 +133,8 @@ impl Analyzer {
         }
         self.sent_bytes += len;
 
-        if last {
+        // captured data stays until the next subkernel run, so it can be read again
+        if last && !self.capture {
             arm();
         }
         
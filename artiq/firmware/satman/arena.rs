@@ -0,0 +1,68 @@
+use alloc::{vec, vec::Vec};
+use core::cmp::{max, min};
+
+// Kernel libraries are stored in large chunks rather than allocated one by one, so that
+// a satellite cycling through many differently-sized kernels does not fragment its heap.
+// Space in a chunk is handed out in order and reclaimed once nothing in the chunk is used.
+
+const CHUNK_SIZE: usize = 256 * 1024;
+
+struct Chunk {
+    data: Vec<u8>,
+    used: usize,
+    live: usize
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Handle {
+    chunk: usize,
+    offset: usize,
+    length: usize
+}
+
+pub struct Arena {
+    chunks: Vec<Chunk>
+}
+
+impl Arena {
+    pub fn new() -> Arena {
+        Arena { chunks: Vec::new() }
+    }
+
+    pub fn store(&mut self, data: &[u8]) -> Handle {
+        let index = match self.chunks.iter().position(|chunk| chunk.data.len() - chunk.used >= data.len()) {
+            Some(index) => index,
+            None => {
+                let chunk = Chunk { data: vec![0; max(CHUNK_SIZE, data.len())], used: 0, live: 0 };
+                // slots of released chunks are filled first
+                match self.chunks.iter().position(|chunk| chunk.data.is_empty()) {
+                    Some(index) => { self.chunks[index] = chunk; index }
+                    None => { self.chunks.push(chunk); self.chunks.len() - 1 }
+                }
+            }
+        };
+        let chunk = &mut self.chunks[index];
+        let offset = chunk.used;
+        chunk.data[offset..offset + data.len()].copy_from_slice(data);
+        // keep libraries word-aligned
+        chunk.used = min((offset + data.len() + 3) & !3, chunk.data.len());
+        chunk.live += 1;
+        Handle { chunk: index, offset: offset, length: data.len() }
+    }
+
+    pub fn get(&self, handle: Handle) -> &[u8] {
+        &self.chunks[handle.chunk].data[handle.offset..handle.offset + handle.length]
+    }
+
+    pub fn free(&mut self, handle: Handle) {
+        let chunk = &mut self.chunks[handle.chunk];
+        chunk.live -= 1;
+        if chunk.live == 0 {
+            chunk.used = 0;
+            // oversized chunks only hold one library, the heap gets them back
+            if chunk.data.len() > CHUNK_SIZE {
+                chunk.data = Vec::new();
+            }
+        }
+    }
+}
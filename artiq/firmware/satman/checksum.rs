@@ -0,0 +1,31 @@
+#[cfg(has_crc)]
+use board_misoc::csr;
+#[cfg(not(has_crc))]
+use proto_artiq::delta;
+
+// CRC-32 (IEEE) of payloads, computed by the gateware CRC unit when there is one,
+// otherwise in software as done by the master.
+
+#[cfg(has_crc)]
+pub fn crc32(data: &[u8]) -> u32 {
+    unsafe {
+        csr::crc::reset_write(1);
+        // whole words first, the unit takes the remaining bytes one at a time
+        for word in data.chunks(4) {
+            if word.len() == 4 {
+                csr::crc::data32_write(((word[0] as u32) << 24) | ((word[1] as u32) << 16) |
+                                       ((word[2] as u32) << 8) | word[3] as u32);
+            } else {
+                for &byte in word {
+                    csr::crc::data8_write(byte);
+                }
+            }
+        }
+        csr::crc::value_read()
+    }
+}
+
+#[cfg(not(has_crc))]
+pub fn crc32(data: &[u8]) -> u32 {
+    delta::checksum(data)
+}
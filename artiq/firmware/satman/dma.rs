@@ -1,13 +1,21 @@
+use core::mem;
 use board_misoc::{csr, cache::flush_l2_cache};
-use alloc::{vec::Vec, collections::btree_map::BTreeMap};
+use alloc::{vec::Vec, string::String, collections::btree_map::BTreeMap};
 use ::{cricon_select, RtioMaster};
 
 const ALIGNMENT: usize = 64;
 
+// Traces recorded by subkernels share the IDs of those uploaded by master, so that
+// master can play back or erase either. Master uses the address of its copy of the
+// trace, which never has this bit set.
+pub const LOCAL_ID_FLAG: u32 = 0x8000_0000;
+
 #[derive(Debug, PartialEq)]
 enum ManagerState {
     Idle,
-    Playback
+    Playback,
+    // started by the subkernel, which takes the status
+    KernelPlayback
 }
 
 pub struct RtioStatus {
@@ -34,7 +42,12 @@ struct Entry {
 pub struct Manager {
     entries: BTreeMap<u32, Entry>,
     state: ManagerState,
-    currentid: u32
+    currentid: u32,
+    // name -> ID, duration of the traces recorded by subkernels
+    names: BTreeMap<String, (u32, u64)>,
+    recording_name: String,
+    recording_trace: Vec<u8>,
+    next_local_id: u32
 }
 
 impl Manager {
@@ -48,6 +61,10 @@ impl Manager {
             entries: BTreeMap::new(),
             currentid: 0,
             state: ManagerState::Idle,
+            names: BTreeMap::new(),
+            recording_name: String::new(),
+            recording_trace: Vec::new(),
+            next_local_id: 0
         }
     }
 
@@ -99,14 +116,79 @@ impl Manager {
     }
 
     pub fn erase(&mut self, id: u32) -> Result<(), Error> {
+        if id & LOCAL_ID_FLAG != 0 {
+            let name = self.names.iter()
+                .find(|&(_, &(local_id, _))| local_id == id)
+                .map(|(name, _)| name.clone());
+            if let Some(name) = name {
+                self.names.remove(&name);
+            }
+        }
         match self.entries.remove(&id) {
             Some(_) => Ok(()),
             None => Err(Error::IdNotFound)
         }
     }
 
+    pub fn record_start(&mut self, name: &str) {
+        self.recording_name = String::from(name);
+        self.recording_trace = Vec::new();
+        // replacing a trace
+        if let Some(&(id, _)) = self.names.get(name) {
+            let _ = self.erase(id);
+        }
+    }
+
+    pub fn record_append(&mut self, data: &[u8]) {
+        self.recording_trace.extend_from_slice(data)
+    }
+
+    pub fn record_stop(&mut self, duration: u64) -> u32 {
+        let id = LOCAL_ID_FLAG | self.next_local_id;
+        self.next_local_id = (self.next_local_id + 1) & !LOCAL_ID_FLAG;
+        let trace = mem::replace(&mut self.recording_trace, Vec::new());
+        // a trace added whole cannot fail
+        let _ = self.add(id, true, &trace, trace.len());
+        let name = mem::replace(&mut self.recording_name, String::new());
+        self.names.insert(name, (id, duration));
+        id
+    }
+
+    pub fn erase_named(&mut self, name: &str) -> Result<(), Error> {
+        match self.names.get(name) {
+            Some(&(id, _)) => self.erase(id),
+            None => Err(Error::IdNotFound)
+        }
+    }
+
+    /// Returns the aligned trace recorded under `name` and its duration.
+    pub fn retrieve(&self, name: &str) -> Option<(&[u8], u64)> {
+        let &(id, duration) = self.names.get(name)?;
+        let entry = self.entries.get(&id)?;
+        Some((&entry.trace[entry.padding_len..], duration))
+    }
+
+    // the kernel knows its traces by their address, as on master
+    fn local_id_at(&self, address: u32) -> Option<u32> {
+        self.entries.iter()
+            .find(|&(&id, entry)| id & LOCAL_ID_FLAG != 0 && entry.complete &&
+                  entry.trace[entry.padding_len..].as_ptr() as u32 == address)
+            .map(|(&id, _)| id)
+    }
+
+    pub fn kernel_playback(&mut self, address: u32, timestamp: u64) -> Result<(), Error> {
+        let id = self.local_id_at(address).ok_or(Error::IdNotFound)?;
+        self.playback(id, timestamp)?;
+        self.state = ManagerState::KernelPlayback;
+        Ok(())
+    }
+
+    pub fn kernel_playback_pending(&self) -> bool {
+        self.state == ManagerState::KernelPlayback
+    }
+
     pub fn playback(&mut self, id: u32, timestamp: u64) -> Result<(), Error> {
-        if self.state != ManagerState::Idle {
+        if self.running() {
             return Err(Error::PlaybackInProgress);
         }
 
@@ -139,13 +221,25 @@ impl Manager {
             // nothing to report
             return None;
         }
+        self.take_status(RtioMaster::Drtio)
+    }
+
+    pub fn get_kernel_status(&mut self) -> Option<RtioStatus> {
+        if self.state != ManagerState::KernelPlayback {
+            return None;
+        }
+        // RTIO goes back to the subkernel waiting for the playback
+        self.take_status(RtioMaster::Kernel)
+    }
+
+    fn take_status(&mut self, master: RtioMaster) -> Option<RtioStatus> {
         let dma_enable = unsafe { csr::rtio_dma::enable_read() };
         if dma_enable != 0 {
             return None;
         } else {
             self.state = ManagerState::Idle;
             unsafe { 
-                cricon_select(RtioMaster::Drtio);
+                cricon_select(master);
                 let error = csr::rtio_dma::error_read();
                 let channel = csr::rtio_dma::error_channel_read();
                 let timestamp = csr::rtio_dma::error_timestamp_read();
@@ -162,7 +256,12 @@ impl Manager {
     }
 
     pub fn running(&self) -> bool {
-        self.state == ManagerState::Playback
+        match self.state {
+            ManagerState::Idle => false,
+            ManagerState::Playback => true,
+            // the subkernel may have been stopped before taking the status
+            ManagerState::KernelPlayback => unsafe { csr::rtio_dma::enable_read() != 0 }
+        }
     }
 
 }
\ No newline at end of file
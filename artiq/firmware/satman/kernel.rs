@@ -1,41 +1,206 @@
-use core::{mem, option::NoneError, cmp::min};
-use alloc::{string::String, format, vec::Vec, collections::{btree_map::BTreeMap, vec_deque::VecDeque}};
-use cslice::AsCSlice;
+use core::{mem, slice, option::NoneError, cmp::min};
+use alloc::{boxed::Box, string::String, format, vec::Vec, collections::{btree_map::BTreeMap, vec_deque::VecDeque}};
+use cslice::{CSlice, AsCSlice};
 
-use board_artiq::{mailbox, spi};
+use board_artiq::{mailbox, mailbox_queue, kern_log_ring, rpc_queue, spi};
 use board_misoc::{csr, clock, i2c};
 use proto_artiq::{kernel_proto as kern, session_proto::Reply::KernelException as HostKernelException, rpc_proto as rpc};
+use proto_artiq::{compression, delta, fragment::{Fragmenter, Reassembler}};
+use proto_artiq::drtioaux_proto::{LogRecord, LOG_RECORD_HEADER_SIZE, LOG_TARGET_ALL, LOG_TARGET_KERNEL};
+use proto_artiq::drtioaux_proto::{LOG_CONTROL_PAUSE, LOG_CONTROL_RESUME, LOG_CONTROL_CLEAR};
+use proto_artiq::drtioaux_proto::{EXCEPTION_SEVERITY_FATAL, EXCEPTION_SEVERITY_RECOVERABLE, exception_severity};
+use proto_artiq::drtioaux_proto::{MAILBOX_STATS_COUNT, MESSAGE_STATS_COUNT, BENCHMARK_RESULT_COUNT, RPC_REPLY_VALUE};
+use proto_artiq::drtioaux_proto::{PEER_PAYLOAD_SIZE, SUBKERNEL_MESSAGE_CREDITS, SUBKERNEL_MESSAGE_STAMP_SIZE};
 use eh::eh_artiq;
-use io::{Cursor, ProtoRead};
+use io::{Cursor, Write, ProtoRead, ProtoWrite};
 use kernel::eh_artiq::StackPointerBacktrace;
 
 use ::{cricon_select, RtioMaster};
 use cache::Cache;
+use checksum;
+use pool;
+use arena::{self, Arena};
+use dma::Manager as DmaManager;
 use SAT_PAYLOAD_MAX_SIZE;
 use MASTER_PAYLOAD_MAX_SIZE;
+use {sat_payload_size, master_payload_size};
+
+// RTIO async error flags, as reported to the host
+pub const ASYNC_ERROR_COLLISION: u8 = 1 << 0;
+pub const ASYNC_ERROR_BUSY: u8 = 1 << 1;
+pub const ASYNC_ERROR_SEQUENCE_ERROR: u8 = 1 << 2;
+
+// number of recent kernel exceptions kept for retrieval by master
+const EXCEPTION_RING_SIZE: usize = 8;
+// number of exception suppression rules installed by master
+const SUPPRESSION_RULES_MAX: usize = 8;
+// number of recent kernel log lines kept for retrieval by master
+const LOG_RING_LINES: usize = 128;
+// number of kernel log lines waiting to be forwarded to master
+const LOG_FORWARD_LINES: usize = 64;
+// number of recent kernel log lines attached to exception reports
+const LOG_EXCEPTION_LINES: usize = 16;
+// partial kernel log lines are flushed once they reach this length
+const LOG_PARTIAL_LINE_MAX: usize = 256;
+const LOG_CONTINUATION_MARKER: &'static str = " \\";
+// the kernel CPU is considered hung if it does not take a message within that time
+const KERN_SEND_TIMEOUT_MS: u64 = 1000;
+// how long a subkernel waits for the playback of one of its DMA traces, as on master
+const DMA_AWAIT_TIMEOUT_MS: u64 = 10000;
+// acknowledgment of the load request only comes once the kernel is relocated
+const KERN_LOAD_TIMEOUT_MS: u64 = 10000;
+// how often queued kernel messages are processed when the mailbox is empty
+const KERN_QUEUE_DRAIN_INTERVAL_MS: u64 = 10;
+// how often a running kernel is checked for a panic that did not reach the mailbox
+const PANIC_CHECK_INTERVAL_MS: u64 = 100;
+// how long a kernel asked to stop may take to reach a safe point before its CPU is reset
+const KERN_STOP_GRACE_MS: u64 = 100;
+// an incoming message with no new slice for that long is considered abandoned by its sender
+const MESSAGE_STALL_TIMEOUT_MS: u64 = 2000;
+// likewise for a library upload, which the master then starts over
+const UPLOAD_STALL_TIMEOUT_MS: u64 = 2000;
+// asynchronous subkernel messages taken from the kernel CPU ahead of being sent;
+// past that, the kernel waits for room in the queue
+const ASYNC_MESSAGE_BACKLOG_MAX: usize = 16;
+// bounds of the polling interval while waiting on the kernel CPU, doubled after each empty poll
+const KERN_RECV_BACKOFF_MIN_US: u64 = 1;
+const KERN_RECV_BACKOFF_MAX_US: u64 = 100;
+// kernel messages handled in one main loop pass, while the kernel keeps running, and the
+// time spent on them; past either, DRTIO aux packets get their turn
+const KERN_BATCH_MAX_MESSAGES: u32 = 16;
+const KERN_BATCH_BUDGET_US: u64 = 200;
+// defaults of the timeouts that can be changed by the master, see CommTimeouts
+const KERN_MSG_SLOT_TIMEOUT_MS: u32 = 100;
+const KERN_MSG_AWAIT_POLL_MS: u32 = 0;
 
 mod kernel_cpu {
     use super::*;
-    use core::ptr;
+    use core::{ptr, slice};
 
-    use proto_artiq::kernel_proto::{KERNELCPU_EXEC_ADDRESS, KERNELCPU_LAST_ADDRESS, KSUPPORT_HEADER_SIZE};
+    use proto_artiq::kernel_proto::{KERNELCPU_EXEC_ADDRESS, KERNELCPU_PAYLOAD_ADDRESS, KERNELCPU_LAST_ADDRESS,
+                                    KSUPPORT_HEADER_SIZE};
+    use proto_artiq::kernel_proto::{KERNELCPU_PANIC_ADDRESS, KERNELCPU_PANIC_MAGIC, PanicRecord};
 
-    pub unsafe fn start() {
-        if csr::kernel_cpu::reset_read() == 0 {
-            panic!("attempted to start kernel CPU when it is already running")
-        }
-
-        stop();
+    // whether the ksupport image in kernel CPU memory is being, or has been, laid out
+    // afresh by the memory DMA engine since the kernel CPU last ran
+    #[cfg(has_mem_dma)]
+    static mut IMAGE_COPY_STARTED: bool = false;
 
+    unsafe fn ksupport_image() -> (*const u8, usize) {
         extern {
             static _binary____ksupport_ksupport_elf_start: u8;
             static _binary____ksupport_ksupport_elf_end: u8;
         }
         let ksupport_start = &_binary____ksupport_ksupport_elf_start as *const _;
         let ksupport_end   = &_binary____ksupport_ksupport_elf_end as *const _;
-        ptr::copy_nonoverlapping(ksupport_start,
-                                (KERNELCPU_EXEC_ADDRESS - KSUPPORT_HEADER_SIZE) as *mut u8,
-                                ksupport_end as usize - ksupport_start as usize);
+        (ksupport_start, ksupport_end as usize - ksupport_start as usize)
+    }
+
+    // offset in the image of its first writable section, and the checksum of what precedes it
+    static mut IMAGE_TEXT: Option<(usize, u32)> = None;
+
+    unsafe fn read_u16(image: *const u8, offset: usize) -> usize {
+        ptr::read_unaligned(image.add(offset) as *const u16) as usize
+    }
+
+    unsafe fn read_u32(image: *const u8, offset: usize) -> usize {
+        ptr::read_unaligned(image.add(offset) as *const u32) as usize
+    }
+
+    // ksupport is linked as one loadable segment laid out as in the file,
+    // its data sections are found from the section headers
+    unsafe fn writable_offset(image: *const u8, length: usize) -> usize {
+        const SHT_PROGBITS: usize = 1;
+        const SHF_WRITE: usize = 1;
+        let (sh_offset, sh_entsize, sh_count) = (read_u32(image, 0x20), read_u16(image, 0x2e), read_u16(image, 0x30));
+        let mut offset = length;
+        for i in 0..sh_count {
+            let header = sh_offset + i * sh_entsize;
+            if header + 24 > length {
+                // unreadable section headers, the whole image is copied every time
+                return 0
+            }
+            if read_u32(image, header + 4) == SHT_PROGBITS && read_u32(image, header + 8) & SHF_WRITE != 0 {
+                offset = offset.min(read_u32(image, header + 16));
+            }
+        }
+        offset
+    }
+
+    fn text_checksum(text: &[u8]) -> u32 {
+        text.chunks(4).fold(0u32, |sum, word| {
+            word.iter().fold(sum.rotate_left(1), |sum, &byte| sum.wrapping_add(byte as u32))
+        })
+    }
+
+    // Part of the image to copy before the next start: only its data sections
+    // when the rest is still as laid out last time.
+    unsafe fn image_range() -> (usize, usize) {
+        let (image, length) = ksupport_image();
+        let (writable, checksum) = match IMAGE_TEXT {
+            Some(text) => text,
+            None => {
+                let writable = writable_offset(image, length);
+                let text = (writable, text_checksum(slice::from_raw_parts(image, writable)));
+                IMAGE_TEXT = Some(text);
+                text
+            }
+        };
+        // the kernel CPU may have written over it, e.g. through a stray pointer
+        ::board_misoc::cache::flush_cpu_dcache();
+        let laid_out = slice::from_raw_parts((KERNELCPU_EXEC_ADDRESS - KSUPPORT_HEADER_SIZE) as *const u8, writable);
+        if writable > 0 && text_checksum(laid_out) == checksum {
+            (writable, length - writable)
+        } else {
+            (0, length)
+        }
+    }
+
+    // Starts copying ksupport for the next kernel while the kernel CPU is held in reset,
+    // the main loop carries on meanwhile.
+    #[cfg(has_mem_dma)]
+    unsafe fn prepare_image() {
+        if IMAGE_COPY_STARTED {
+            return
+        }
+        let (image, _) = ksupport_image();
+        let (offset, length) = image_range();
+        csr::mem_dma::source_write(image.add(offset) as u32);
+        csr::mem_dma::destination_write((KERNELCPU_EXEC_ADDRESS - KSUPPORT_HEADER_SIZE + offset) as u32);
+        csr::mem_dma::length_write(length as u32);
+        csr::mem_dma::start_write(1);
+        IMAGE_COPY_STARTED = true;
+    }
+
+    #[cfg(has_mem_dma)]
+    unsafe fn copy_image() {
+        prepare_image();
+        while csr::mem_dma::busy_read() != 0 {}
+        // the kernel about to run modifies it
+        IMAGE_COPY_STARTED = false;
+    }
+
+    #[cfg(not(has_mem_dma))]
+    unsafe fn copy_image() {
+        let (image, _) = ksupport_image();
+        let (offset, length) = image_range();
+        ptr::copy_nonoverlapping(image.add(offset),
+                                 (KERNELCPU_EXEC_ADDRESS - KSUPPORT_HEADER_SIZE + offset) as *mut u8, length);
+    }
+
+    pub unsafe fn start() {
+        if csr::kernel_cpu::reset_read() == 0 {
+            panic!("attempted to start kernel CPU when it is already running")
+        }
+
+        stop();
+
+        copy_image();
+        ptr::write_volatile(KERNELCPU_PANIC_ADDRESS as *mut u32, 0);
+        mailbox::clear_stop();
+        mailbox_queue::init();
+        kern_log_ring::init();
+        rpc_queue::init();
 
         csr::kernel_cpu::reset_write(0);
     }
@@ -44,21 +209,82 @@ mod kernel_cpu {
         csr::kernel_cpu::reset_write(1);
         cricon_select(RtioMaster::Drtio);
 
+        super::count_received();
         mailbox::acknowledge();
+        #[cfg(has_mem_dma)]
+        prepare_image();
     }
 
     pub fn validate(ptr: usize) -> bool {
         ptr >= KERNELCPU_EXEC_ADDRESS && ptr <= KERNELCPU_LAST_ADDRESS
     }
+
+    // memory a library takes up once laid out, up to the end of its last loadable segment
+    pub fn image_footprint(library: &[u8]) -> usize {
+        const PT_LOAD: usize = 1;
+        const PHDR_SIZE: usize = 32;
+        if library.len() < 0x34 {
+            return 0
+        }
+        unsafe {
+            let image = library.as_ptr();
+            let (ph_offset, ph_entsize, ph_count) = (read_u32(image, 0x1c), read_u16(image, 0x2a), read_u16(image, 0x2c));
+            let mut end = 0;
+            for i in 0..ph_count {
+                let header = ph_offset + i * ph_entsize;
+                if header + PHDR_SIZE > library.len() {
+                    return 0
+                }
+                if read_u32(image, header) == PT_LOAD {
+                    end = end.max(read_u32(image, header + 8) + read_u32(image, header + 20));
+                }
+            }
+            end
+        }
+    }
+
+    // the kernel stack starts at the top of the kernel CPU memory, see ksupport.ld, and grows
+    // down towards the library; the stack frames of ksupport itself are not accounted for
+    pub fn stack_headroom(footprint: usize) -> usize {
+        (KERNELCPU_LAST_ADDRESS - 15).saturating_sub(KERNELCPU_PAYLOAD_ADDRESS + footprint)
+    }
+
+    pub unsafe fn request_stop() {
+        mailbox::request_stop();
+    }
+
+    pub unsafe fn panic_message() -> Option<String> {
+        ::board_misoc::cache::flush_cpu_dcache();
+        let record = KERNELCPU_PANIC_ADDRESS as *const PanicRecord;
+        if ptr::read_volatile(&(*record).magic) != KERNELCPU_PANIC_MAGIC {
+            return None
+        }
+        let message = ptr::read_volatile(&(*record).message) as usize;
+        let length = ptr::read_volatile(&(*record).length);
+        if !validate(message) || !validate(message + length) {
+            return Some(String::from("panic with unreadable message"))
+        }
+        let message = slice::from_raw_parts(message as *const u8, length);
+        Some(String::from_utf8_lossy(message).into_owned())
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum KernelState {
     Absent,
+    // waiting for ksupport to relocate the library, started when done if `run` is set
+    Loading { started: u64, run: bool },
     Loaded,
+    // waiting for ksupport to take the benchmark request, see Manager::benchmark_start
+    BenchmarkStarting { started: u64 },
     Running,
-    MsgAwait { max_time: u64 },
-    MsgSending
+    MsgAwait { max_time: u64, next_poll: u64 },
+    MsgStreaming,
+    // the values of a message are passed over several main loop passes, see MessageManager::in_passing
+    MsgPassing,
+    MsgSending,
+    RpcAwait,
+    DmaAwait { max_time: u64 }
 }
 
 #[derive(Debug)]
@@ -66,11 +292,19 @@ pub enum Error {
     Load(String),
     KernelNotFound,
     InvalidPointer(usize),
+    CorruptMessage(usize, &'static str),
     Unexpected(String),
     NoMessage,
     AwaitingMessage,
     SubkernelIoError,
-    KernelException(Sliceable)
+    KernelException(Sliceable<'static>),
+    KernelCpuPanic(String),
+    KernelCpuHang,
+    Stopped,
+    LoadTimeout(String),
+    MalformedMessage(rpc::TagError),
+    // error triggered by an exception raised in the kernel
+    CausedByException(Box<Error>, Sliceable<'static>)
 }
 
 impl From<NoneError> for Error {
@@ -85,24 +319,94 @@ impl From<io::Error<!>> for Error {
     }
 }
 
+// stable error codes, reported in param[0] of the SubkernelError raised for runtime failures;
+// mirrored in artiq.coredevice.exceptions.SubkernelError
+#[derive(Debug, Clone, Copy)]
+pub enum ErrorCode {
+    Load = 1,
+    KernelNotFound = 2,
+    InvalidPointer = 3,
+    Unexpected = 4,
+    NoMessage = 5,
+    AwaitingMessage = 6,
+    IoError = 7,
+    KernelException = 8,
+    KernelCpuPanic = 9,
+    KernelCpuHang = 10,
+    Stopped = 11,
+    LoadTimeout = 12,
+    RpcException = 13,
+    MalformedMessage = 14,
+    ReceiveAborted = 15
+}
+
+impl ErrorCode {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ErrorCode::Load => "load_failed",
+            ErrorCode::KernelNotFound => "kernel_not_found",
+            ErrorCode::InvalidPointer => "invalid_pointer",
+            ErrorCode::Unexpected => "unexpected",
+            ErrorCode::NoMessage => "no_message",
+            ErrorCode::AwaitingMessage => "awaiting_message",
+            ErrorCode::IoError => "io_error",
+            ErrorCode::KernelException => "kernel_exception",
+            ErrorCode::KernelCpuPanic => "kernel_cpu_panic",
+            ErrorCode::KernelCpuHang => "kernel_cpu_hang",
+            ErrorCode::Stopped => "stopped",
+            ErrorCode::LoadTimeout => "load_timeout",
+            ErrorCode::RpcException => "rpc_exception",
+            ErrorCode::MalformedMessage => "malformed_message",
+            ErrorCode::ReceiveAborted => "receive_aborted"
+        }
+    }
+}
+
+impl Error {
+    pub fn code(&self) -> ErrorCode {
+        match *self {
+            Error::Load(_) => ErrorCode::Load,
+            Error::KernelNotFound => ErrorCode::KernelNotFound,
+            Error::InvalidPointer(_) |
+            Error::CorruptMessage(..) => ErrorCode::InvalidPointer,
+            Error::Unexpected(_) => ErrorCode::Unexpected,
+            Error::NoMessage => ErrorCode::NoMessage,
+            Error::AwaitingMessage => ErrorCode::AwaitingMessage,
+            Error::SubkernelIoError => ErrorCode::IoError,
+            Error::KernelException(_) => ErrorCode::KernelException,
+            Error::KernelCpuPanic(_) => ErrorCode::KernelCpuPanic,
+            Error::KernelCpuHang => ErrorCode::KernelCpuHang,
+            Error::Stopped => ErrorCode::Stopped,
+            Error::LoadTimeout(_) => ErrorCode::LoadTimeout,
+            Error::MalformedMessage(_) => ErrorCode::MalformedMessage,
+            Error::CausedByException(ref cause, _) => cause.code()
+        }
+    }
+}
+
 macro_rules! unexpected {
     ($($arg:tt)*) => (return Err(Error::Unexpected(format!($($arg)*))));
 }
 
-/* represents data that has to be sent to Master */
+/* represents data that has to be sent to Master, owned or borrowed from where it is kept */
 #[derive(Debug)]
-pub struct Sliceable {
-    it: usize,
-    data: Vec<u8>
+pub struct Sliceable<'a> {
+    fragments: Fragmenter<'a>
 }
 
 /* represents interkernel messages */
 struct Message {
     count: u8,
-    tag: u8,
+    // arguments, each with its tags inline
     data: Vec<u8>
 }
 
+/* interkernel message of which not all fragments arrived yet */
+struct PartialMessage {
+    count: u8,
+    fragments: Reassembler
+}
+
 #[derive(PartialEq)]
 enum OutMessageState {
     NoMessage,
@@ -114,37 +418,486 @@ enum OutMessageState {
 
 /* for dealing with incoming and outgoing interkernel messages */
 struct MessageManager {
-    out_message: Option<Sliceable>,
+    out_message: Option<Sliceable<'static>>,
     out_state: OutMessageState,
+    // serialized messages waiting for out_message
+    out_queue: VecDeque<OutgoingMessage>,
+    out_async: bool,
+    out_rpc: bool,
+    out_peer: Option<u8>,
+    // when the first slice of out_message was handed to the link
+    out_started_us: u64,
+    // messages the master will still take, see drtioaux_proto::SUBKERNEL_MESSAGE_CREDITS
+    out_credits: u8,
     in_queue: VecDeque<Message>,
-    in_buffer: Option<Message>,
+    in_buffer: Option<PartialMessage>,
+    // injected by the host, may arrive interleaved with a message from the main kernel
+    in_injected: Option<PartialMessage>,
+    in_stream: Option<InStream>,
+    in_passing: Option<PassingMessage>,
+    // when the last slice of an incomplete message arrived
+    in_updated_ms: u64,
+    in_rpc_reply: Vec<u8>,
+    rpc_reply: Option<Vec<u8>>,
+}
+
+struct OutgoingMessage {
+    data: Vec<u8>,
+    // nobody waits for it to be acknowledged
+    async: bool,
+    // an RPC for the master to pass on to the host, rather than a subkernel message
+    rpc: bool,
+    // destination of the subkernel on another satellite it is sent to
+    peer: Option<u8>,
+    // satellite clock when the kernel sent it, passed on to the master
+    sent_us: u64
+}
+
+// Serializes a subkernel message straight into its buffer, leaving out the first bytes of
+// the service number written by rpc::send_args; the one left is then replaced by the count.
+struct MessageWriter {
+    skip: usize,
+    data: Vec<u8>
+}
+
+impl Write for MessageWriter {
+    type WriteError = !;
+    type FlushError = !;
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::WriteError> {
+        let skipped = min(self.skip, buf.len());
+        self.skip -= skipped;
+        self.data.extend_from_slice(&buf[skipped..]);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::FlushError> {
+        Ok(())
+    }
+}
+
+struct PassingMessage {
+    message: Message,
+    // where the next value starts in the message data, and how many are left
+    position: usize,
+    remaining: u8
+}
+
+// list or array copied straight from aux packets into the kernel allocation
+struct InStream {
+    // where the next element data goes
+    dest: *mut u8,
+    remaining: usize
+}
+
+/* log record waiting to be forwarded to master */
+struct PendingRecord {
+    id: u32,
+    level: u8,
+    target: u8,
+    timestamp: u64,
+    message: String
+}
+
+/* recent kernel log output, kept across runs until retrieved by master,
+   and records not yet forwarded to master */
+struct LogRing {
+    lines: VecDeque<(u32, String)>,
+    lines_dropped: u32,
+    out: Option<Sliceable<'static>>,
+    forward: VecDeque<PendingRecord>,
+    forward_dropped: u32,
+    // where kernel output goes besides the ring
+    to_uart: bool,
+    to_master: bool,
+    paused: bool
+}
+
+// satellite's own warnings and errors, picked up by the kernel manager
+// since the logger has no access to it
+static mut SATELLITE_RECORDS: Vec<PendingRecord> = Vec::new();
+static mut SATELLITE_RECORDS_DROPPED: u32 = 0;
+
+// see set_wait_hook
+static mut KERN_WAIT_HOOK: Option<fn()> = None;
+// time the kernel work of one main loop pass may take, 0 if unbounded, see Manager::set_work_budget
+static mut WORK_BUDGET_US: u64 = 0;
+static mut PASS_STARTED_US: u64 = 0;
+// master clock and local clock when it was received (us), see set_master_time
+static mut MASTER_TIME: Option<(u64, u64)> = None;
+
+/// Keeps the time of the master clock sent by the master, for subkernels.
+/// Returns the satellite clock it corresponds to.
+pub fn set_master_time(master_us: u64) -> u64 {
+    let now = clock::get_us();
+    unsafe { MASTER_TIME = Some((master_us, now)) }
+    now
+}
+
+pub fn clear_master_time() {
+    unsafe { MASTER_TIME = None }
+}
+
+// see message_class
+const MESSAGE_CLASS_COUNT: usize = 7;
+
+struct MailboxStats {
+    received: [u32; MESSAGE_CLASS_COUNT],
+    acknowledged: u32,
+    sent: u32,
+    send_wait_total_us: u64,
+    send_wait_max_us: u64,
+    early_acknowledged: u32,
+    early_ack_saved_us: u64
+}
+
+static mut MAILBOX_STATS: MailboxStats = MailboxStats {
+    received: [0; MESSAGE_CLASS_COUNT],
+    acknowledged: 0,
+    sent: 0,
+    send_wait_total_us: 0,
+    send_wait_max_us: 0,
+    early_acknowledged: 0,
+    early_ack_saved_us: 0
+};
+
+fn message_class(message: &kern::Message) -> usize {
+    match message {
+        &kern::Log(_) | &kern::LogSlice(_) => 0,
+        &kern::CacheGetRequest { .. } | &kern::CachePutRequest { .. } => 1,
+        &kern::RpcSend { .. } | &kern::RpcRecvRequest(_) | &kern::RpcFlush => 2,
+        &kern::DmaRecordStart(_) | &kern::DmaRecordAppend(_) | &kern::DmaRecordStop { .. } |
+        &kern::DmaEraseRequest { .. } | &kern::DmaRetrieveRequest { .. } |
+        &kern::DmaStartRemoteRequest { .. } | &kern::DmaAwaitRemoteRequest { .. } => 3,
+        &kern::SubkernelLoadRunRequest { .. } | &kern::SubkernelAwaitFinishRequest { .. } |
+        &kern::SubkernelMsgSend { .. } | &kern::SubkernelMsgRecvRequest { .. } |
+        &kern::SubkernelReplayExceptionRequest => 4,
+        &kern::RtioInitRequest | &kern::RtioDestinationStatusRequest { .. } |
+        &kern::I2cStartRequest { .. } | &kern::I2cRestartRequest { .. } | &kern::I2cStopRequest { .. } |
+        &kern::I2cWriteRequest { .. } | &kern::I2cReadRequest { .. } | &kern::I2cSwitchSelectRequest { .. } |
+        &kern::SpiSetConfigRequest { .. } | &kern::SpiWriteRequest { .. } | &kern::SpiReadRequest { .. } |
+        &kern::HwreqErrorInjectRequest { .. } |
+        &kern::MoninjProbeRequest { .. } | &kern::MoninjInjectionStatusRequest { .. } |
+        &kern::MasterTimeRequest => 5,
+        _ => 6
+    }
+}
+
+// Copy of a kernel CPU message that needs no reply, taken so that the kernel CPU
+// can be acknowledged before the message is processed.
+enum BufferedMessage {
+    Log(String),
+    UnformattableLog,
+    // log while output is paused, not worth formatting
+    PausedLog,
+    Flush
+}
+
+fn buffer_kern_message(message: &kern::Message, log_paused: bool) -> Option<BufferedMessage> {
+    match message {
+        &kern::Log(_) | &kern::LogSlice(_) if log_paused => Some(BufferedMessage::PausedLog),
+        &kern::Log(args) => {
+            use core::fmt::Write;
+            let mut text = String::new();
+            match text.write_fmt(args) {
+                Ok(()) => Some(BufferedMessage::Log(text)),
+                Err(_) => Some(BufferedMessage::UnformattableLog)
+            }
+        }
+        &kern::LogSlice(arg) => Some(BufferedMessage::Log(String::from(arg))),
+        &kern::RpcFlush => Some(BufferedMessage::Flush),
+        _ => None
+    }
+}
+
+// message in the mailbox last looked at by kern_recv, counted once it is taken:
+// acknowledged, replied to or dropped with the kernel CPU; a message may be looked
+// at several times before that, e.g. RunFinished while RPCs are still queued
+static mut PEEKED_MESSAGE: Option<*const ()> = None;
+
+fn count_received() {
+    let message = match unsafe { PEEKED_MESSAGE.take() } {
+        Some(message) => unsafe { &*(message as *const kern::Message) },
+        None => return
+    };
+    let stats = unsafe { &mut MAILBOX_STATS };
+    match message {
+        &kern::Batch(messages) => for message in messages {
+            stats.received[message_class(message)] += 1;
+        },
+        message => stats.received[message_class(message)] += 1
+    }
+}
+
+/// Returns the mailbox counters in the order given by drtioaux_proto::MAILBOX_STATS_COUNT.
+pub fn mailbox_stats(clear: bool) -> [u32; MAILBOX_STATS_COUNT] {
+    let stats = unsafe { &mut MAILBOX_STATS };
+    let mut counters = [0; MAILBOX_STATS_COUNT];
+    counters[..MESSAGE_CLASS_COUNT].copy_from_slice(&stats.received);
+    counters[MESSAGE_CLASS_COUNT] = stats.acknowledged;
+    counters[MESSAGE_CLASS_COUNT + 1] = stats.sent;
+    counters[MESSAGE_CLASS_COUNT + 2] = (stats.send_wait_total_us / 1000) as u32;
+    counters[MESSAGE_CLASS_COUNT + 3] = stats.send_wait_max_us as u32;
+    counters[MESSAGE_CLASS_COUNT + 4] = stats.early_acknowledged;
+    counters[MESSAGE_CLASS_COUNT + 5] = stats.early_ack_saved_us.min(u32::max_value() as u64) as u32;
+    if clear {
+        *stats = MailboxStats {
+            received: [0; MESSAGE_CLASS_COUNT],
+            acknowledged: 0,
+            sent: 0,
+            send_wait_total_us: 0,
+            send_wait_max_us: 0,
+            early_acknowledged: 0,
+            early_ack_saved_us: 0
+        };
+    }
+    counters
+}
+
+struct MessageStats {
+    messages_sent: u32,
+    messages_received: u32,
+    rpcs_sent: u32,
+    rpc_replies_received: u32,
+    bytes_serialized: u64,
+    bytes_received: u64,
+    slices_sent: u32,
+    slices_received: u32,
+    service_total_us: u64,
+    service_max_us: u64,
+    link_total_us: u64,
+    link_max_us: u64
+}
+
+const MESSAGE_STATS_ZERO: MessageStats = MessageStats {
+    messages_sent: 0,
+    messages_received: 0,
+    rpcs_sent: 0,
+    rpc_replies_received: 0,
+    bytes_serialized: 0,
+    bytes_received: 0,
+    slices_sent: 0,
+    slices_received: 0,
+    service_total_us: 0,
+    service_max_us: 0,
+    link_total_us: 0,
+    link_max_us: 0
+};
+
+static mut MESSAGE_STATS: MessageStats = MESSAGE_STATS_ZERO;
+
+fn count_serialized(length: usize, rpc: bool) {
+    let stats = unsafe { &mut MESSAGE_STATS };
+    if rpc {
+        stats.rpcs_sent += 1;
+    } else {
+        stats.messages_sent += 1;
+    }
+    stats.bytes_serialized += length as u64;
+}
+
+fn count_slice_received(last: bool, length: usize, rpc: bool) {
+    let stats = unsafe { &mut MESSAGE_STATS };
+    stats.slices_received += 1;
+    stats.bytes_received += length as u64;
+    if last && rpc {
+        stats.rpc_replies_received += 1;
+    } else if last {
+        stats.messages_received += 1;
+    }
+}
+
+// kernel CPU message processed after it was acknowledged, from the acknowledgment on
+fn count_early_ack(acknowledged_us: u64) {
+    let stats = unsafe { &mut MAILBOX_STATS };
+    stats.early_acknowledged += 1;
+    stats.early_ack_saved_us = stats.early_ack_saved_us.saturating_add(clock::get_us() - acknowledged_us);
+}
+
+// time spent (de)serializing a message between the kernel and its aux slices
+fn count_service_time(started_us: u64) {
+    let stats = unsafe { &mut MESSAGE_STATS };
+    let time = clock::get_us() - started_us;
+    stats.service_total_us += time;
+    stats.service_max_us = stats.service_max_us.max(time);
+}
+
+fn count_link_time(started_us: u64) {
+    let stats = unsafe { &mut MESSAGE_STATS };
+    let time = clock::get_us() - started_us;
+    stats.link_total_us += time;
+    stats.link_max_us = stats.link_max_us.max(time);
+}
+
+/// Returns the message counters in the order given by drtioaux_proto::MESSAGE_STATS_COUNT.
+pub fn message_stats(clear: bool) -> [u32; MESSAGE_STATS_COUNT] {
+    fn clamp(value: u64) -> u32 {
+        value.min(u32::max_value() as u64) as u32
+    }
+    let stats = unsafe { &mut MESSAGE_STATS };
+    let pool_stats = pool::stats(clear);
+    let counters = [
+        stats.messages_sent,
+        stats.messages_received,
+        stats.rpcs_sent,
+        stats.rpc_replies_received,
+        clamp(stats.bytes_serialized),
+        clamp(stats.bytes_received),
+        stats.slices_sent,
+        stats.slices_received,
+        clamp(stats.service_total_us / 1000),
+        clamp(stats.service_max_us),
+        clamp(stats.link_total_us / 1000),
+        clamp(stats.link_max_us),
+        pool_stats[0],
+        pool_stats[1],
+        pool_stats[2]
+    ];
+    if clear {
+        *stats = MESSAGE_STATS_ZERO;
+    }
+    counters
+}
+
+/// Sets a function called between polls while waiting on the kernel CPU,
+/// for work that cannot wait that long and needs no kernel manager (e.g.
+/// reporting link errors). Aux packets are left to the main loop: most need
+/// the manager, which is busy waiting, and replies must keep their order.
+pub fn set_wait_hook(hook: fn()) {
+    unsafe { KERN_WAIT_HOOK = Some(hook) }
+}
+
+fn bounded() -> bool {
+    unsafe { WORK_BUDGET_US != 0 }
+}
+
+// whether the current main loop pass spent its work budget, what is left waits for the next one
+fn over_budget() -> bool {
+    unsafe { WORK_BUDGET_US != 0 && clock::get_us() - PASS_STARTED_US >= WORK_BUDGET_US }
+}
+
+pub fn forward_log_record(record: &::log::Record) {
+    // kernel output is forwarded by the kernel manager itself
+    if record.level() > ::log::Level::Warn || record.target() == "kernel" {
+        return
+    }
+    let records = unsafe { &mut SATELLITE_RECORDS };
+    if records.len() < LOG_FORWARD_LINES {
+        records.push(PendingRecord {
+            id: 0,
+            level: record.level() as u8,
+            target: ::log_target_id(record.target()),
+            timestamp: clock::get_ms(),
+            message: format!("{}", record.args())
+        });
+    } else {
+        unsafe { SATELLITE_RECORDS_DROPPED += 1 }
+    }
+}
+
+/* serialized exception, prefixed with the time it was raised, its severity and whether it is compressed */
+struct StoredException {
+    id: u32,
+    data: Vec<u8>,
+    // position of the next slice to hand over to master
+    it: usize
+}
+
+/* exceptions matching a rule are counted and reported without details */
+struct SuppressionRule {
+    exception_id: Option<u32>,
+    prefix: Vec<u8>
+}
+
+impl SuppressionRule {
+    fn matches(&self, exception_id: u32, message: Option<&[u8]>) -> bool {
+        // messages stored on the host can only match an empty prefix
+        self.exception_id.map_or(true, |id| id == exception_id) &&
+            (self.prefix.is_empty() || message.map_or(false, |message| message.starts_with(&self.prefix)))
+    }
 }
 
 // Per-run state
 struct Session {
     kernel_state: KernelState,
     log_buffer: String,
-    last_exception: Option<Sliceable>,
-    messages: MessageManager
+    messages: MessageManager,
+    injected_errors: u32,
+    async_errors: u8,
+    log_window_start: u64,
+    log_window_count: u32,
+    next_queue_drain: u64,
+    next_panic_check: u64,
+    // set once a stop is requested, the kernel CPU is reset if it has not stopped by then
+    stop_deadline: Option<u64>
+}
+
+/* benchmark run by ksupport in place of a kernel, see kern::BenchmarkRequest */
+struct Benchmark {
+    iterations: u32,
+    message_size: u32,
+    phase_started_us: u64,
+    phases_us: Vec<u64>
 }
 
 #[derive(Debug)]
 struct KernelLibrary {
-    library: Vec<u8>,
-    complete: bool
+    library: arena::Handle,
+    // function addresses and names, used to annotate backtraces
+    symbols: Vec<u8>,
+    symbols_complete: bool
+}
+
+// Timeouts of the exchanges with the kernel CPU, tunable for slow kernels or large messages.
+#[derive(Debug, Clone, Copy)]
+pub struct CommTimeouts {
+    // how long the kernel CPU may take to provide a slot for each value of a received message
+    pub slot_ms: u32,
+    // interval between checks for an incoming message while the kernel awaits one,
+    // 0 checks on every pass of the main loop
+    pub await_poll_ms: u32
 }
 
 pub struct Manager {
+    // complete libraries, stored in the arena
     kernels: BTreeMap<u32, KernelLibrary>,
+    arena: Arena,
+    // library being uploaded, the buffer is kept across uploads
+    upload_id: Option<u32>,
+    upload: Reassembler,
     current_id: u32,
     session: Session,
     cache: Cache,
-    last_finished: Option<SubkernelFinished>
+    log_ring: LogRing,
+    log_rate_limit: u32,
+    exceptions: VecDeque<StoredException>,
+    suppression_rules: Vec<SuppressionRule>,
+    suppressed_count: u32,
+    // ids of firmware-raised exceptions, as negotiated with ksupport on load
+    exception_ids: Vec<(&'static str, u32)>,
+    // debug mode, kernels may ask for the last stored exception to be raised again
+    exception_replay: bool,
+    // holds the replayed exception, its strings are referenced by the kernel
+    replay_buffer: Vec<u8>,
+    last_finished: Option<SubkernelFinished>,
+    comm_timeouts: CommTimeouts,
+    // kernel whose library is still laid out in the kernel CPU memory from its last load
+    resident_id: Option<u32>,
+    // delta upload being received, applied to the library it was made against once complete
+    delta: Reassembler,
+    // read by the kernel CPU until it takes it, see KernelState::Loading and BenchmarkStarting
+    load_request: Option<Box<kern::Message<'static>>>,
+    benchmark: Option<Benchmark>,
+    benchmark_results: Option<[u32; BENCHMARK_RESULT_COUNT]>,
+    // destinations of the subkernels on other satellites, as told by the master;
+    // messages sent to them are relayed there rather than handed to the main kernel
+    peers: BTreeMap<u32, u8>
 }
 
 pub struct SubkernelFinished {
     pub id: u32,
-    pub with_exception: bool
+    pub with_exception: bool,
+    pub async_errors: u8
 }
 
 pub struct SliceMeta {
@@ -153,35 +906,82 @@ pub struct SliceMeta {
 }
 
 macro_rules! get_slice_fn {
-    ( $name:tt, $size:expr ) => {
-        pub fn $name(&mut self, data_slice: &mut [u8; $size]) -> SliceMeta {
-            if self.data.len() == 0 {
-                return SliceMeta { len: 0, last: true };
-            }
-            let len = min($size, self.data.len() - self.it);
-            let last = self.it + len == self.data.len();
-    
-            data_slice[..len].clone_from_slice(&self.data[self.it..self.it+len]);
-            self.it += len;
-    
+    ( $name:tt, $capacity:expr, $size:expr ) => {
+        pub fn $name(&mut self, data_slice: &mut [u8; $capacity]) -> SliceMeta {
+            let fragment = self.fragments.next(data_slice, $size);
             SliceMeta {
-                len: len as u16,
-                last: last
+                len: fragment.len as u16,
+                last: fragment.last
             }
         }
     };
 }
 
-impl Sliceable {
-    pub fn new(data: Vec<u8>) -> Sliceable {
+impl Sliceable<'static> {
+    pub fn new(data: Vec<u8>) -> Sliceable<'static> {
+        Sliceable {
+            fragments: Fragmenter::new(data)
+        }
+    }
+}
+
+impl<'a> Sliceable<'a> {
+    // slices data kept elsewhere from the given position, without copying it
+    pub fn borrowed(data: &'a [u8], position: usize) -> Sliceable<'a> {
         Sliceable {
-            it: 0,
-            data: data
+            fragments: Fragmenter::borrowed(data, position)
         }
     }
 
-    get_slice_fn!(get_slice_sat, SAT_PAYLOAD_MAX_SIZE);
-    get_slice_fn!(get_slice_master, MASTER_PAYLOAD_MAX_SIZE);
+    pub fn data(&self) -> &[u8] {
+        self.fragments.data()
+    }
+
+    // how much of the data was sliced so far
+    pub fn position(&self) -> usize {
+        self.fragments.position()
+    }
+
+    pub fn into_vec(self) -> Vec<u8> {
+        self.fragments.into_vec()
+    }
+
+    get_slice_fn!(get_slice_sat, SAT_PAYLOAD_MAX_SIZE, sat_payload_size());
+    get_slice_fn!(get_slice_master, MASTER_PAYLOAD_MAX_SIZE, master_payload_size());
+    get_slice_fn!(get_slice_peer, MASTER_PAYLOAD_MAX_SIZE, PEER_PAYLOAD_SIZE);
+    get_slice_fn!(get_slice_run_done, SAT_PAYLOAD_MAX_SIZE, master_payload_size() - /*status, async errors*/2);
+}
+
+// returns the message once its last slice arrived
+fn add_message_slice(buffer: &mut Option<PartialMessage>, last: bool, data: &[u8]) -> Option<Message> {
+    let now = clock::get_ms();
+    let pushed = match buffer.as_mut() {
+        Some(message) => message.fragments.push(now, last, data),
+        None if data.is_empty() => return None,
+        None => {
+            let mut fragments = Reassembler::with_buffer(pool::take(data.len() - 1));
+            let pushed = fragments.push(now, last, &data[1..]);
+            *buffer = Some(PartialMessage {
+                count: data[0],
+                fragments: fragments
+            });
+            pushed
+        }
+    };
+    match pushed {
+        Ok(true) => buffer.take().map(|mut message| Message {
+            count: message.count,
+            data: message.fragments.take()
+        }),
+        Ok(false) => None,
+        Err(e) => {
+            error!("dropping subkernel message: {}", e);
+            if let Some(mut message) = buffer.take() {
+                pool::give(message.fragments.take());
+            }
+            None
+        }
+    }
 }
 
 impl MessageManager {
@@ -189,34 +989,180 @@ impl MessageManager {
         MessageManager {
             out_message: None,
             out_state: OutMessageState::NoMessage,
+            out_queue: VecDeque::new(),
+            out_async: false,
+            out_rpc: false,
+            out_peer: None,
+            out_started_us: 0,
+            out_credits: SUBKERNEL_MESSAGE_CREDITS,
             in_queue: VecDeque::new(),
-            in_buffer: None
+            in_buffer: None,
+            in_injected: None,
+            in_stream: None,
+            in_passing: None,
+            in_updated_ms: 0,
+            in_rpc_reply: Vec::new(),
+            rpc_reply: None
+        }
+    }
+
+    // as new, keeping the queues and buffers allocated, message data goes back to the pool
+    fn reset(&mut self) {
+        if let Some(message) = self.out_message.take() {
+            pool::give(message.into_vec());
+        }
+        self.out_state = OutMessageState::NoMessage;
+        for message in self.out_queue.drain(..) {
+            pool::give(message.data);
+        }
+        self.out_async = false;
+        self.out_rpc = false;
+        self.out_peer = None;
+        self.out_started_us = 0;
+        self.out_credits = SUBKERNEL_MESSAGE_CREDITS;
+        for message in self.in_queue.drain(..) {
+            pool::give(message.data);
+        }
+        self.discard_partial_incoming();
+        if let Some(passing) = self.in_passing.take() {
+            pool::give(passing.message.data);
+        }
+        self.in_updated_ms = 0;
+        self.in_rpc_reply.clear();
+        if let Some(reply) = self.rpc_reply.take() {
+            pool::give(reply);
         }
     }
 
     pub fn handle_incoming(&mut self, last: bool, length: usize, data: &[u8; MASTER_PAYLOAD_MAX_SIZE]) {
         // called when receiving a message from master
-        match self.in_buffer.as_mut() {
-            Some(message) => message.data.extend(&data[..length]),
-            None => {
-                self.in_buffer = Some(Message {
-                    count: data[0],
-                    tag: data[1],
-                    data: data[2..length].to_vec()
-                });
+        self.in_updated_ms = clock::get_ms();
+        if let Some(message) = add_message_slice(&mut self.in_buffer, last, &data[..length]) {
+            self.push_incoming(message);
+        }
+    }
+
+    pub fn handle_injected(&mut self, last: bool, data: &[u8]) {
+        self.in_updated_ms = clock::get_ms();
+        if let Some(message) = add_message_slice(&mut self.in_injected, last, data) {
+            self.push_incoming(message);
+        }
+    }
+
+    fn push_incoming(&mut self, mut message: Message) {
+        if message.count & compression::HEADER_COMPRESSED != 0 {
+            match compression::decompress(&message.data) {
+                Ok(data) => {
+                    message.count &= !compression::HEADER_COMPRESSED;
+                    message.data = data;
+                }
+                Err(e) => {
+                    error!("dropping subkernel message: {}", e);
+                    return
+                }
             }
+        }
+        self.in_queue.push_back(message);
+    }
+
+    fn next_outgoing(&mut self) {
+        if self.out_state != OutMessageState::NoMessage {
+            return
+        }
+        let to_master = match self.out_queue.front() {
+            Some(message) => !message.rpc && message.peer.is_none(),
+            None => return
         };
+        if to_master {
+            // the sender stalls, with its messages queued here, until the master takes some
+            if self.out_credits == 0 {
+                return
+            }
+            self.out_credits -= 1;
+        }
+        if let Some(mut message) = self.out_queue.pop_front() {
+            if to_master {
+                // so that the master can order it with messages of other satellites
+                let sent_us = message.sent_us;
+                message.data.splice(1..1, (0..SUBKERNEL_MESSAGE_STAMP_SIZE).rev()
+                    .map(|i| (sent_us >> (8 * i)) as u8));
+            }
+            self.out_message = Some(Sliceable::new(message.data));
+            self.out_async = message.async;
+            self.out_rpc = message.rpc;
+            self.out_peer = message.peer;
+            self.out_state = OutMessageState::MessageReady;
+        }
+    }
+
+    pub fn has_outgoing(&self) -> bool {
+        !self.out_queue.is_empty() || match self.out_state {
+            OutMessageState::MessageReady | OutMessageState::MessageBeingSent |
+                OutMessageState::MessageSent => true,
+            OutMessageState::NoMessage | OutMessageState::MessageAcknowledged => false
+        }
+    }
+
+    pub fn async_backlog(&self) -> usize {
+        self.out_queue.iter().filter(|message| message.async).count()
+    }
+
+    pub fn accept_async(&mut self, data: Vec<u8>, peer: Option<u8>) {
+        count_serialized(data.len(), false);
+        let data = compression::compress_payload(data);
+        self.out_queue.push_back(OutgoingMessage {
+            data: data, async: true, rpc: false, peer: peer, sent_us: clock::get_us()
+        });
+    }
+
+    pub fn accept_rpc(&mut self, data: Vec<u8>) {
+        count_serialized(data.len(), true);
+        let data = compression::compress_payload(data);
+        // a synchronous RPC waits for its reply rather than the acknowledgment
+        self.out_queue.push_back(OutgoingMessage {
+            data: data, async: true, rpc: true, peer: None, sent_us: clock::get_us()
+        });
+        self.next_outgoing();
+    }
+
+    pub fn grant_credits(&mut self, credits: u8) {
+        self.out_credits = self.out_credits.saturating_add(credits);
+    }
+
+    pub fn is_outgoing_rpc(&self) -> bool {
+        self.out_rpc
+    }
+
+    pub fn outgoing_peer(&self) -> Option<u8> {
+        self.out_peer
+    }
+
+    pub fn handle_rpc_reply(&mut self, last: bool, data: &[u8]) {
+        self.in_rpc_reply.extend(data);
         if last {
-            // when done, remove from working queue
-            self.in_queue.push_back(self.in_buffer.take().unwrap());
+            let reply = mem::replace(&mut self.in_rpc_reply, pool::take(0));
+            match compression::decompress_payload(reply) {
+                Ok(reply) => self.rpc_reply = Some(reply),
+                // an empty reply raises an exception in the kernel rather than leaving it waiting
+                Err(e) => {
+                    error!("invalid RPC reply: {}", e);
+                    self.rpc_reply = Some(Vec::new())
+                }
+            }
         }
     }
 
+    pub fn take_rpc_reply(&mut self) -> Option<Vec<u8>> {
+        self.rpc_reply.take()
+    }
+
     pub fn is_outgoing_ready(&mut self) -> bool {
         // called by main loop, to see if there's anything to send, will send it afterwards
+        self.next_outgoing();
         match self.out_state {
             OutMessageState::MessageReady => {
                 self.out_state = OutMessageState::MessageBeingSent;
+                self.out_started_us = clock::get_us();
                 true
             },
             _ => false
@@ -237,10 +1183,16 @@ impl MessageManager {
         if self.out_state != OutMessageState::MessageBeingSent {
             return None;
         }
-        let meta = self.out_message.as_mut()?.get_slice_master(data_slice);
+        let meta = match self.out_peer {
+            Some(_) => self.out_message.as_mut()?.get_slice_peer(data_slice),
+            None => self.out_message.as_mut()?.get_slice_master(data_slice)
+        };
+        unsafe { MESSAGE_STATS.slices_sent += 1 }
         if meta.last {
             // clear the message slot
-            self.out_message = None;
+            if let Some(message) = self.out_message.take() {
+                pool::give(message.into_vec());
+            }
             // notify kernel with a flag that message is sent
             self.out_state = OutMessageState::MessageSent;
         }
@@ -251,7 +1203,14 @@ impl MessageManager {
         // returns whether or not there's more to be sent
         match self.out_state {
             OutMessageState::MessageBeingSent => true,
+            OutMessageState::MessageSent if self.out_async => {
+                count_link_time(self.out_started_us);
+                // nobody waits for it, go on with the next one
+                self.out_state = OutMessageState::NoMessage;
+                false
+            },
             OutMessageState::MessageSent => {
+                count_link_time(self.out_started_us);
                 self.out_state = OutMessageState::MessageAcknowledged;
                 false
             },
@@ -262,88 +1221,444 @@ impl MessageManager {
         }
     }
 
-    pub fn accept_outgoing(&mut self, count: u8, tag: &[u8], data: *const *const ()) -> Result<(), Error>  {
-        let mut writer = Cursor::new(Vec::new());
+    pub fn accept_outgoing(&mut self, count: u8, tag: &[u8], data: *const *const (),
+            peer: Option<u8>) -> Result<(), Error>  {
+        let started = clock::get_us();
+        let mut writer = MessageWriter { skip: 3, data: pool::take(0) };
         rpc::send_args(&mut writer, 0, tag, data)?;
-        // skip service tag, but write the count
-        let mut data = writer.into_inner().split_off(3);
+        let mut data = writer.data;
         data[0] = count;
-        self.out_message = Some(Sliceable::new(data));
-        self.out_state = OutMessageState::MessageReady;
+        count_serialized(data.len(), false);
+        let data = compression::compress_payload(data);
+        count_service_time(started);
+        // after any asynchronous message sent before it
+        self.out_queue.push_back(OutgoingMessage {
+            data: data, async: false, rpc: false, peer: peer, sent_us: started
+        });
+        self.next_outgoing();
         Ok(())
     }
 
     pub fn get_incoming(&mut self) -> Option<Message> {
         self.in_queue.pop_front()
     }
-}
 
-impl Session {
-    pub fn new() -> Session {
-        Session {
-            kernel_state: KernelState::Absent,
-            log_buffer: String::new(),
-            last_exception: None,
-            messages: MessageManager::new()
-        }
+    fn has_partial_incoming(&self) -> bool {
+        self.in_buffer.is_some() || self.in_injected.is_some() || self.in_stream.is_some()
     }
 
-    fn running(&self) -> bool {
-        match self.kernel_state {
-            KernelState::Absent  | KernelState::Loaded  => false,
-            KernelState::Running | KernelState::MsgAwait { .. } |
-                KernelState::MsgSending => true
+    // drops any message still being received, returns whether there was one
+    fn discard_partial_incoming(&mut self) -> bool {
+        let discarded = self.has_partial_incoming();
+        for mut message in self.in_buffer.take().into_iter().chain(self.in_injected.take()) {
+            pool::give(message.fragments.take());
         }
+        // stream data is already in the kernel allocation, nothing to give back
+        self.in_stream = None;
+        discarded
     }
 
-    fn flush_log_buffer(&mut self) {
-        if &self.log_buffer[self.log_buffer.len() - 1..] == "\n" {
-            for line in self.log_buffer.lines() {
-                info!(target: "kernel", "{}", line);
+    // whether a new message may be given to the kernel as it arrives, without overtaking another
+    fn can_stream(&self) -> bool {
+        self.in_buffer.is_none() && self.in_queue.is_empty() && self.in_stream.is_none()
+    }
+
+    // copies element data of the current stream into the kernel, returns whether all of it arrived
+    fn stream_slice(&mut self, last: bool, data: &[u8]) -> Result<bool, Error> {
+        self.in_updated_ms = clock::get_ms();
+        let stream = self.in_stream.as_mut().unwrap();
+        // anything past the element data (i.e. the return tag) is dropped
+        let length = min(data.len(), stream.remaining);
+        unsafe {
+            core::ptr::copy_nonoverlapping(data.as_ptr(), stream.dest, length);
+            stream.dest = stream.dest.add(length);
+        }
+        stream.remaining -= length;
+        let remaining = stream.remaining;
+        if last {
+            self.in_stream = None;
+            if remaining > 0 {
+                unexpected!("subkernel message ended {} bytes short", remaining)
             }
-            self.log_buffer.clear()
         }
+        Ok(remaining == 0)
     }
 }
 
-impl Manager {
-    pub fn new() -> Manager {
-        Manager {
-            kernels: BTreeMap::new(),
-            current_id: 0,
-            session: Session::new(),
-            cache: Cache::new(),
-            last_finished: None,
+impl LogRing {
+    pub fn new() -> LogRing {
+        LogRing {
+            lines: VecDeque::new(),
+            lines_dropped: 0,
+            out: None,
+            forward: VecDeque::new(),
+            forward_dropped: 0,
+            to_uart: true,
+            to_master: true,
+            paused: false
         }
     }
 
-    pub fn add(&mut self, id: u32, last: bool, data: &[u8], data_len: usize) -> Result<(), Error> {
-        let kernel = match self.kernels.get_mut(&id) {
-            Some(kernel) => {
-                if kernel.complete {
-                    // replace entry
-                    self.kernels.remove(&id);
-                    self.kernels.insert(id, KernelLibrary {
-                        library: Vec::new(),
-                        complete: false });
-                    self.kernels.get_mut(&id)?
+    pub fn clear(&mut self) {
+        self.lines.clear();
+        self.lines_dropped = 0;
+        self.out = None;
+        self.forward.clear();
+        self.forward_dropped = 0;
+    }
+
+    pub fn set_outputs(&mut self, to_uart: bool, to_master: bool) {
+        self.to_uart = to_uart;
+        self.to_master = to_master;
+    }
+
+    pub fn push(&mut self, id: u32, timestamp: u64, line: &str) {
+        if self.lines.len() >= LOG_RING_LINES {
+            self.lines.pop_front();
+            self.lines_dropped += 1;
+        }
+        self.lines.push_back((id, format!("[SUBKERNEL#{}] [{:6}.{:03}s] {}",
+                                          id, timestamp / 1000, timestamp % 1000, line)));
+        if !self.to_master {
+            return
+        }
+        self.push_record(PendingRecord {
+            id: id,
+            level: ::log::Level::Info as u8,
+            target: LOG_TARGET_KERNEL,
+            timestamp: timestamp,
+            message: String::from(line)
+        });
+    }
+
+    pub fn drop_message(&mut self) {
+        self.forward_dropped += 1;
+    }
+
+    fn push_record(&mut self, record: PendingRecord) {
+        // when full, newer records are dropped, and a marker takes
+        // their place once there is room again
+        if self.forward.len() >= LOG_FORWARD_LINES {
+            self.forward_dropped += 1;
+            return
+        }
+        self.push_dropped_marker(record.id, record.timestamp);
+        self.forward.push_back(record);
+    }
+
+    fn push_dropped_marker(&mut self, id: u32, timestamp: u64) {
+        if self.forward_dropped > 0 {
+            self.forward.push_back(PendingRecord {
+                id: id,
+                level: ::log::Level::Warn as u8,
+                target: LOG_TARGET_ALL,
+                timestamp: timestamp,
+                message: format!("{} messages dropped", self.forward_dropped)
+            });
+            self.forward_dropped = 0;
+        }
+    }
+
+    fn collect_satellite_records(&mut self, id: u32) {
+        let records = unsafe { &mut SATELLITE_RECORDS };
+        for mut record in records.drain(..) {
+            record.id = id;
+            self.push_record(record);
+        }
+        self.forward_dropped += unsafe { mem::replace(&mut SATELLITE_RECORDS_DROPPED, 0) };
+    }
+
+    pub fn forward_is_ready(&mut self, id: u32) -> bool {
+        self.collect_satellite_records(id);
+        if self.forward.is_empty() {
+            self.push_dropped_marker(id, clock::get_ms());
+        }
+        !self.forward.is_empty()
+    }
+
+    pub fn forward_get_slice(&mut self, data_slice: &mut [u8; MASTER_PAYLOAD_MAX_SIZE]) -> Option<(u32, u16)> {
+        // packs as many whole records of a single kernel as fit in one packet
+        let id = self.forward.front()?.id;
+        let size = master_payload_size();
+        let mut len = 0;
+        while let Some(pending) = self.forward.front() {
+            // messages longer than a packet are cut short
+            let message_len = min(pending.message.len(), size - LOG_RECORD_HEADER_SIZE);
+            let record = LogRecord {
+                level: pending.level,
+                target: pending.target,
+                timestamp: pending.timestamp,
+                message: &pending.message.as_bytes()[..message_len]
+            };
+            if pending.id != id || len + record.size() > size {
+                break;
+            }
+            record.write_to(&mut Cursor::new(&mut data_slice[len..])).unwrap();
+            len += record.size();
+            self.forward.pop_front();
+        }
+        Some((id, len as u16))
+    }
+
+    pub fn recent(&self, id: u32) -> String {
+        let mut lines: Vec<&str> = self.lines.iter().rev()
+            .filter(|&&(line_id, _)| line_id == id)
+            .map(|&(_, ref line)| line.as_str())
+            .take(LOG_EXCEPTION_LINES)
+            .collect();
+        lines.reverse();
+        lines.join("\n")
+    }
+
+    pub fn get_slice(&mut self, filter: Option<u32>, data_slice: &mut [u8; SAT_PAYLOAD_MAX_SIZE]) -> SliceMeta {
+        if self.out.is_none() {
+            // lines are handed over to master only once,
+            // lines of other kernels stay when filtering by id
+            let mut data = Vec::new();
+            if self.lines_dropped > 0 {
+                data.extend(format!("{} messages dropped\n", self.lines_dropped).as_bytes());
+                self.lines_dropped = 0;
+            }
+            self.lines.retain(|&(id, ref line)| {
+                if filter.map_or(true, |filter_id| filter_id == id) {
+                    data.extend(line.as_bytes());
+                    data.push(b'\n');
+                    false
                 } else {
-                    kernel
+                    true
                 }
+            });
+            self.out = Some(Sliceable::new(data));
+        }
+        let meta = self.out.as_mut().unwrap().get_slice_sat(data_slice);
+        if meta.last {
+            self.out = None;
+        }
+        meta
+    }
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session {
+            kernel_state: KernelState::Absent,
+            log_buffer: String::new(),
+            messages: MessageManager::new(),
+            injected_errors: 0,
+            async_errors: 0,
+            log_window_start: 0,
+            log_window_count: 0,
+            next_queue_drain: 0,
+            next_panic_check: 0,
+            stop_deadline: None
+        }
+    }
+
+    fn reset(&mut self) {
+        self.kernel_state = KernelState::Absent;
+        self.log_buffer.clear();
+        self.messages.reset();
+        self.injected_errors = 0;
+        self.async_errors = 0;
+        self.log_window_start = 0;
+        self.log_window_count = 0;
+        self.next_queue_drain = 0;
+        self.next_panic_check = 0;
+        self.stop_deadline = None;
+    }
+
+    fn running(&self) -> bool {
+        match self.kernel_state {
+            KernelState::Absent  | KernelState::Loading { .. } | KernelState::Loaded  => false,
+            KernelState::BenchmarkStarting { .. } |
+                KernelState::Running | KernelState::MsgAwait { .. } |
+                KernelState::MsgStreaming | KernelState::MsgPassing | KernelState::MsgSending |
+                KernelState::RpcAwait | KernelState::DmaAwait { .. } => true
+        }
+    }
+
+    fn log_allowed(&mut self, rate_limit: u32) -> bool {
+        // at most rate_limit messages per second, 0 for no limit
+        if rate_limit == 0 {
+            return true
+        }
+        let now = clock::get_ms();
+        if now >= self.log_window_start + 1000 {
+            self.log_window_start = now;
+            self.log_window_count = 0;
+        }
+        self.log_window_count += 1;
+        self.log_window_count <= rate_limit
+    }
+
+    fn flush_log_buffer(&mut self, id: u32, log_ring: &mut LogRing) {
+        // complete lines are flushed, a partial line only once it gets too long
+        let partial_start = match self.log_buffer.rfind('\n') {
+            Some(index) => index + 1,
+            None => 0
+        };
+        let flush_len = if self.log_buffer.len() - partial_start >= LOG_PARTIAL_LINE_MAX {
+            self.log_buffer.len()
+        } else {
+            partial_start
+        };
+        if flush_len == 0 {
+            return
+        }
+
+        // retrieved lines carry the satellite clock, so that logs
+        // from several destinations can be put side by side
+        let timestamp = clock::get_ms();
+        let mut lines = self.log_buffer[..flush_len].lines().peekable();
+        while let Some(line) = lines.next() {
+            let line = if lines.peek().is_none() && flush_len != partial_start {
+                // the rest of the line follows in the next flush
+                format!("{}{}", line, LOG_CONTINUATION_MARKER)
+            } else {
+                String::from(line)
+            };
+            if log_ring.to_uart {
+                info!(target: "kernel", "{}", line);
+            }
+            log_ring.push(id, timestamp, &line);
+        }
+        self.log_buffer.drain(..flush_len);
+    }
+}
+
+impl Manager {
+    pub fn new() -> Manager {
+        pool::init();
+        Manager {
+            kernels: BTreeMap::new(),
+            arena: Arena::new(),
+            upload_id: None,
+            upload: Reassembler::new(),
+            current_id: 0,
+            session: Session::new(),
+            cache: Cache::new(),
+            log_ring: LogRing::new(),
+            log_rate_limit: 0,
+            exceptions: VecDeque::new(),
+            suppression_rules: Vec::new(),
+            suppressed_count: 0,
+            exception_ids: kern::FIRMWARE_EXCEPTIONS.to_vec(),
+            exception_replay: false,
+            replay_buffer: Vec::new(),
+            last_finished: None,
+            comm_timeouts: CommTimeouts {
+                slot_ms: KERN_MSG_SLOT_TIMEOUT_MS,
+                await_poll_ms: KERN_MSG_AWAIT_POLL_MS
             },
-            None => {
-                self.kernels.insert(id, KernelLibrary {
-                    library: Vec::new(),
-                    complete: false });
-                self.kernels.get_mut(&id)?
-            },
+            resident_id: None,
+            delta: Reassembler::new(),
+            load_request: None,
+            benchmark: None,
+            benchmark_results: None,
+            peers: BTreeMap::new()
+        }
+    }
+
+    pub fn add(&mut self, id: u32, last: bool, data: &[u8], data_len: usize) -> Result<(), Error> {
+        // a full upload follows a delta that was cut short or rejected
+        self.delta.clear();
+        if self.resident_id == Some(id) {
+            // the library laid out in memory is about to be replaced
+            self.resident_id = None;
+        }
+        let now = clock::get_ms();
+        if self.upload_id != Some(id) || self.upload.stalled(now, UPLOAD_STALL_TIMEOUT_MS) {
+            // a new upload replaces the library, and drops any other one left unfinished
+            self.upload_id = Some(id);
+            self.upload.clear();
+            self.remove_library(id);
+        }
+        let complete = match self.upload.push(now, last, &data[0..data_len]) {
+            Ok(complete) => complete,
+            Err(e) => {
+                self.upload_id = None;
+                return Err(Error::Load(format!("cannot upload kernel: {}", e)))
+            }
         };
-        kernel.library.extend(&data[0..data_len]);
 
-        kernel.complete = last;
+        if complete {
+            self.upload_id = None;
+            let library = self.arena.store(self.upload.payload());
+            self.upload.clear();
+            self.kernels.insert(id, KernelLibrary {
+                library: library,
+                symbols: Vec::new(),
+                symbols_complete: false });
+        }
+        Ok(())
+    }
+
+    fn remove_library(&mut self, id: u32) {
+        if let KernelState::Loading { .. } = self.session.kernel_state {
+            if self.current_id == id {
+                // the kernel CPU may still be reading the library
+                self.stop();
+            }
+        }
+        if let Some(kernel) = self.kernels.remove(&id) {
+            self.arena.free(kernel.library);
+        }
+    }
+
+    pub fn add_delta(&mut self, id: u32, last: bool, data: &[u8], data_len: usize) -> Result<(), Error> {
+        let now = clock::get_ms();
+        if self.delta.stalled(now, UPLOAD_STALL_TIMEOUT_MS) {
+            self.delta.clear();
+        }
+        let complete = self.delta.push(now, last, &data[0..data_len])
+            .map_err(|e| Error::Load(format!("cannot apply delta upload: {}", e)))?;
+        if !complete {
+            return Ok(())
+        }
+        let delta = self.delta.take();
+        let library = match self.kernels.get(&id) {
+            Some(kernel) => delta::apply_with(self.arena.get(kernel.library), &delta, checksum::crc32),
+            None => Err("no library to apply the delta to")
+        }.map_err(|e| Error::Load(format!("cannot apply delta upload: {}", e)))?;
+        if self.resident_id == Some(id) {
+            self.resident_id = None;
+        }
+        self.remove_library(id);
+        // symbols refer to the previous library and are uploaded again
+        let library = self.arena.store(&library);
+        self.kernels.insert(id, KernelLibrary {
+            library: library,
+            symbols: Vec::new(),
+            symbols_complete: false });
         Ok(())
     }
 
+    pub fn add_symbols(&mut self, id: u32, last: bool, data: &[u8], data_len: usize) -> Result<(), Error> {
+        // symbols are uploaded after the kernel they belong to
+        let kernel = self.kernels.get_mut(&id)?;
+        if kernel.symbols_complete {
+            kernel.symbols.clear();
+        }
+        kernel.symbols.extend(&data[0..data_len]);
+
+        kernel.symbols_complete = last;
+        Ok(())
+    }
+
+    fn kernel_symbols(&self) -> &[u8] {
+        match self.kernels.get(&self.current_id) {
+            Some(kernel) if kernel.symbols_complete => &kernel.symbols,
+            _ => &[]
+        }
+    }
+
+    pub fn add_async_errors(&mut self, errors: u8) {
+        // RTIO errors seen while a kernel runs are reported along with it
+        if self.is_running() {
+            self.session.async_errors |= errors;
+        }
+    }
+
     pub fn is_running(&self) -> bool {
         self.session.running()
     }
@@ -357,167 +1672,1067 @@ impl Manager {
 
     pub fn stop(&mut self) {
         unsafe { kernel_cpu::stop() }
+        self.load_request = None;
         self.session.kernel_state = KernelState::Absent;
         unsafe { self.cache.unborrow() }
     }
 
-    pub fn run(&mut self, id: u32) -> Result<(), Error> {
-        info!("starting subkernel #{}", id);
-        if self.session.kernel_state != KernelState::Loaded
-            || self.current_id != id {
-            self.load(id)?;
+    pub fn request_stop(&mut self, id: u32) -> bool {
+        if let KernelState::Loading { run: true, .. } = self.session.kernel_state {
+            if self.current_id == id {
+                // nothing ran yet, the load is abandoned and the kernel reported as stopped
+                info!("stopping subkernel #{} before it started", id);
+                self.stop();
+                self.runtime_exception(Error::Stopped);
+                self.last_finished = Some(SubkernelFinished {
+                    id: id, with_exception: true, async_errors: 0 });
+                return true
+            }
+        }
+        if !self.is_running() || self.current_id != id {
+            return false
+        }
+        info!("stopping subkernel #{}", id);
+        unsafe { kernel_cpu::request_stop() }
+        // a kernel waiting for a message stops from within its receive loop
+        match self.session.kernel_state {
+            KernelState::MsgAwait { .. } | KernelState::MsgStreaming => {
+                self.session.messages.in_stream = None;
+                self.session.kernel_state = KernelState::Running;
+            }
+            KernelState::MsgPassing => {
+                if let Some(passing) = self.session.messages.in_passing.take() {
+                    pool::give(passing.message.data);
+                }
+                self.session.kernel_state = KernelState::Running;
+            }
+            KernelState::RpcAwait => {
+                self.session.messages.in_rpc_reply.clear();
+                self.session.kernel_state = KernelState::Running;
+            }
+            KernelState::DmaAwait { .. } => self.session.kernel_state = KernelState::Running,
+            _ => ()
+        }
+        self.session.stop_deadline = Some(clock::get_ms() + KERN_STOP_GRACE_MS);
+        true
+    }
+
+    pub fn run(&mut self, id: u32) -> Result<(), Error> {
+        info!("starting subkernel #{}", id);
+        self.load(id)?;
+        match self.session.kernel_state {
+            KernelState::Loading { started, .. } => {
+                self.session.kernel_state = KernelState::Loading { started: started, run: true };
+                Ok(())
+            }
+            _ => self.start_loaded()
+        }
+    }
+
+    fn start_loaded(&mut self) -> Result<(), Error> {
+        self.session.kernel_state = KernelState::Running;
+        cricon_select(RtioMaster::Kernel);
+    
+        kern_acknowledge()
+    }
+
+    pub fn message_handle_incoming(&mut self, last: bool, length: usize, slice: &[u8; MASTER_PAYLOAD_MAX_SIZE]) {
+        if !self.is_running() {
+            return;
+        }
+        count_slice_received(last, length, false);
+        match self.stream_incoming(last, &slice[..length]) {
+            Ok(true) => (),
+            Ok(false) => self.session.messages.handle_incoming(last, length, slice),
+            Err(e) => self.external_message_failed(e)
+        }
+    }
+
+    // Message from the host rather than the main kernel, only taken by the subkernel it is meant for.
+    // It is queued like any other and received at the next subkernel_recv.
+    pub fn inject_message(&mut self, id: u32, last: bool, data: &[u8]) -> bool {
+        if !self.is_running() || self.current_id != id {
+            self.session.messages.in_injected = None;
+            return false
+        }
+        count_slice_received(last, data.len(), false);
+        self.session.messages.handle_injected(last, data);
+        true
+    }
+
+    // A single list or array of booleans or numbers, or bytes or bytearray, for an awaiting kernel
+    // is copied from the aux packets straight into its kernel allocation, rather than being buffered whole.
+    // Returns false if the slice is to be buffered instead.
+    fn stream_incoming(&mut self, last: bool, slice: &[u8]) -> Result<bool, Error> {
+        if self.session.messages.in_stream.is_some() {
+            if self.session.messages.stream_slice(last, slice)? {
+                self.finish_stream()?;
+            }
+            return Ok(true)
+        }
+        match self.session.kernel_state {
+            KernelState::MsgAwait { .. } if self.session.messages.can_stream() && slice[0] == 1 => (),
+            _ => return Ok(false)
+        }
+        let (header_length, data_length) = match rpc::stream_header_length(&slice[1..]) {
+            Some((header_length, data_length)) if data_length > 0 => (header_length, data_length),
+            _ => return Ok(false)
+        };
+
+        kern_send(&kern::SubkernelMsgRecvReply { status: kern::SubkernelStatus::NoError, count: 1, master_us: 0 })?;
+        self.session.kernel_state = KernelState::MsgStreaming;
+        let dest = pass_stream_header_to_kernel(&slice[1..1 + header_length], &self.log_ring, self.current_id,
+            self.kernel_symbols(), self.session.async_errors, self.comm_timeouts.slot_ms as u64)?;
+        self.session.messages.in_stream = Some(InStream { dest: dest, remaining: data_length });
+        if self.session.messages.stream_slice(last, &slice[1 + header_length..])? {
+            self.finish_stream()?;
+        }
+        Ok(true)
+    }
+
+    fn finish_stream(&mut self) -> Result<(), Error> {
+        // the rest of the message may still arrive, but the kernel has everything it needs
+        if self.session.kernel_state == KernelState::MsgStreaming {
+            kern_send(&kern::RpcRecvReply(Ok(0)))?;
+            self.session.kernel_state = KernelState::Running;
+        }
+        Ok(())
+    }
+    
+    // asynchronous messages may still be on their way after the kernel finished
+    pub fn message_get_slice(&mut self, slice: &mut [u8; MASTER_PAYLOAD_MAX_SIZE]) -> Option<SliceMeta> {
+        self.session.messages.get_outgoing_slice(slice)
+    }
+
+    pub fn message_ack_slice(&mut self) -> bool {
+        self.session.messages.ack_slice()
+    }
+
+    pub fn message_source_id(&self) -> u32 {
+        self.current_id
+    }
+
+    pub fn message_is_rpc(&self) -> bool {
+        self.session.messages.is_outgoing_rpc()
+    }
+
+    pub fn message_peer(&self) -> Option<u8> {
+        self.session.messages.outgoing_peer()
+    }
+
+    pub fn message_grant_credits(&mut self, id: u32, credits: u8) {
+        // credits granted to a previous run are of no use to this one
+        if self.is_running() && self.current_id == id {
+            self.session.messages.grant_credits(credits);
+        }
+    }
+
+    pub fn add_peer(&mut self, id: u32, destination: u8) {
+        self.peers.insert(id, destination);
+    }
+
+    // messages of a subkernel to its own id go back to the main kernel that runs it
+    fn peer_of(&self, id: u32) -> Option<u8> {
+        if id == self.current_id {
+            return None
+        }
+        self.peers.get(&id).cloned()
+    }
+
+    pub fn rpc_reply_handle_incoming(&mut self, last: bool, data: &[u8]) {
+        if self.session.kernel_state != KernelState::RpcAwait {
+            warn!("dropping RPC reply, subkernel is not waiting for one");
+            return
+        }
+        count_slice_received(last, data.len(), true);
+        self.session.messages.handle_rpc_reply(last, data);
+    }
+
+    pub fn message_is_ready(&mut self) -> bool {
+        self.session.messages.is_outgoing_ready()
+    }
+
+    pub fn get_last_finished(&mut self) -> Option<SubkernelFinished> {
+        // the master gets every message sent by the kernel before learning that it finished
+        if self.session.messages.has_outgoing() {
+            return None
+        }
+        self.last_finished.take()
+    }
+
+    pub fn benchmark_start(&mut self, iterations: u32, message_size: u32) -> bool {
+        if self.is_running() || iterations == 0 {
+            return false
+        }
+        self.session.reset();
+        self.stop();
+        // the benchmark writes over the payload area
+        self.resident_id = None;
+        self.benchmark_results = None;
+        let request = Box::new(kern::BenchmarkRequest { iterations: iterations, message_size: message_size });
+        unsafe {
+            kernel_cpu::start();
+            // taken once ksupport is up, checked for by process_kern_requests
+            mailbox::send(&*request as *const _ as usize);
+        }
+        self.load_request = Some(request);
+        self.session.kernel_state = KernelState::BenchmarkStarting { started: clock::get_ms() };
+        self.benchmark = Some(Benchmark {
+            iterations: iterations,
+            message_size: message_size,
+            phase_started_us: 0,
+            phases_us: Vec::new()
+        });
+        true
+    }
+
+    fn process_benchmark_start(&mut self, started: u64) {
+        if mailbox::acknowledged() {
+            self.load_request = None;
+            self.session.kernel_state = KernelState::Running;
+            if let Some(benchmark) = self.benchmark.as_mut() {
+                benchmark.phase_started_us = clock::get_us();
+            }
+        } else if clock::get_ms() > started + KERN_LOAD_TIMEOUT_MS {
+            // the master gives up waiting for the results on its own
+            error!("kernel CPU did not take the benchmark request within {} ms", KERN_LOAD_TIMEOUT_MS);
+            self.benchmark = None;
+            self.stop();
+        }
+    }
+
+    pub fn benchmark_results(&mut self) -> Option<[u32; BENCHMARK_RESULT_COUNT]> {
+        self.benchmark_results.take()
+    }
+
+    fn benchmark_phase_done(&mut self) {
+        let finished = match self.benchmark.as_mut() {
+            Some(benchmark) => {
+                let now = clock::get_us();
+                benchmark.phases_us.push(now - benchmark.phase_started_us);
+                benchmark.phase_started_us = now;
+                benchmark.phases_us.len() == 3
+            }
+            None => return
+        };
+        if !finished {
+            return
+        }
+        self.stop();
+        let benchmark = self.benchmark.take().unwrap();
+        let per_iteration = |time_us: u64| (time_us * 1000 / benchmark.iterations as u64).min(u32::max_value() as u64) as u32;
+        let message_time_us = benchmark.phases_us[2].max(1);
+        let results = [
+            per_iteration(benchmark.phases_us[0]),
+            per_iteration(benchmark.phases_us[1]),
+            per_iteration(benchmark.phases_us[2]),
+            (benchmark.message_size as u64 * benchmark.iterations as u64 * 1000 / message_time_us)
+                .min(u32::max_value() as u64) as u32
+        ];
+        info!("benchmark finished: mailbox round trip {} ns, cache put and get {} ns, message loopback {} ns ({} kB/s)",
+            results[0], results[1], results[2], results[3]);
+        self.benchmark_results = Some(results);
+    }
+
+    // during a benchmark, messages from the kernel come back to it rather than going to master
+    fn loop_back_messages(&mut self) {
+        let mut slice: [u8; MASTER_PAYLOAD_MAX_SIZE] = [0; MASTER_PAYLOAD_MAX_SIZE];
+        while self.session.messages.is_outgoing_ready() {
+            let mut first = true;
+            while let Some(meta) = self.session.messages.get_outgoing_slice(&mut slice) {
+                let mut length = meta.len as usize;
+                if first {
+                    // drop the stamp meant for the master, after the tag count
+                    slice[1..length].rotate_left(SUBKERNEL_MESSAGE_STAMP_SIZE);
+                    length -= SUBKERNEL_MESSAGE_STAMP_SIZE;
+                    first = false;
+                }
+                self.message_handle_incoming(meta.last, length, &slice);
+                if !self.session.messages.ack_slice() {
+                    break
+                }
+            }
+        }
+    }
+
+    /// Starts laying out kernel `id` on the kernel CPU. The load is completed by
+    /// `process_kern_requests`, so that aux packets are still answered meanwhile.
+    pub fn load(&mut self, id: u32) -> Result<(), Error> {
+        if self.current_id == id {
+            match self.session.kernel_state {
+                KernelState::Loaded | KernelState::Loading { .. } => return Ok(()),
+                _ => ()
+            }
+        }
+        let library = match self.kernels.get(&id) {
+            Some(kernel) => self.arena.get(kernel.library),
+            None => return Err(Error::KernelNotFound)
+        };
+        // the kernel CPU reads the request, and the library through it, until it replies;
+        // the library stays in the arena until replaced, which stops the load first
+        let library = unsafe { slice::from_raw_parts(library.as_ptr(), library.len()) };
+        self.current_id = id;
+        self.session.reset();
+        self.stop();
+
+        // loading the same kernel again only restores its writable segments,
+        // anything else lays the whole library out again
+        let resident = self.resident_id == Some(id);
+        self.resident_id = None;
+        let request = Box::new(kern::LoadRequest {
+            version: kern::KERNEL_PROTO_VERSION,
+            library: library,
+            resident: resident
+        });
+        unsafe {
+            kernel_cpu::start();
+            // ksupport takes the request only once the kernel is relocated, and replies right away
+            mailbox::send(&*request as *const _ as usize);
+        }
+        self.load_request = Some(request);
+        self.session.kernel_state = KernelState::Loading { started: clock::get_ms(), run: false };
+        Ok(())
+    }
+
+    fn process_load(&mut self, started: u64, run: bool) {
+        let id = self.current_id;
+        let result = if mailbox::receive() != 0 {
+            self.finish_load()
+        } else if clock::get_ms() > started + KERN_LOAD_TIMEOUT_MS {
+            let waiting_for = if mailbox::acknowledged() { "load reply" } else { "kernel CPU to take the load request" };
+            let panic = unsafe { kernel_cpu::panic_message() };
+            let length = self.kernels.get(&id).map_or(0, |kernel| self.arena.get(kernel.library).len());
+            self.stop();
+            let mut diagnostics = format!("subkernel {} ({} bytes): waited {} ms for {}",
+                id, length, clock::get_ms() - started, waiting_for);
+            if let Some(message) = panic {
+                diagnostics += &format!(", kernel CPU panicked: {}", message);
+            }
+            Err(Error::LoadTimeout(diagnostics))
+        } else {
+            return
+        };
+        self.load_request = None;
+        match result.and_then(|()| if run { self.start_loaded() } else { Ok(()) }) {
+            Ok(()) => (),
+            Err(e) => {
+                error!("failed to load subkernel {}: {:?}", id, e);
+                self.stop();
+                if run {
+                    // the master is waiting for this kernel to finish
+                    self.runtime_exception(e);
+                    self.last_finished = Some(SubkernelFinished {
+                        id: id, with_exception: true, async_errors: 0 })
+                }
+            }
+        }
+    }
+
+    fn finish_load(&mut self) -> Result<(), Error> {
+        let id = self.current_id;
+        kern_recv(|reply| {
+            match reply {
+                kern::LoadReply { version, .. } if *version != kern::KERNEL_PROTO_VERSION => {
+                    Err(Error::Load(format!("kernel CPU support uses protocol version {}, firmware expects {}",
+                                            version, kern::KERNEL_PROTO_VERSION)))
+                }
+                kern::LoadReply { result: Ok(exception_table), .. } => {
+                    self.exception_ids = negotiate_exception_ids(exception_table);
+                    self.session.kernel_state = KernelState::Loaded;
+                    self.resident_id = Some(id);
+                    Ok(())
+                }
+                kern::LoadReply { result: Err(error), .. } => {
+                    Err(Error::Load(format!("{}", error)))
+                }
+                other => {
+                    unexpected!("unexpected kernel CPU reply to load request: {:?}", other)
+                }
+            }
+        })
+    }
+
+    pub fn add_suppression_rule(&mut self, exception_id: Option<u32>, prefix: &[u8]) -> bool {
+        if self.suppression_rules.len() >= SUPPRESSION_RULES_MAX {
+            return false
+        }
+        self.suppression_rules.push(SuppressionRule {
+            exception_id: exception_id,
+            prefix: prefix.to_vec()
+        });
+        true
+    }
+
+    pub fn set_exception_replay(&mut self, enable: bool) {
+        self.exception_replay = enable;
+    }
+
+    /// Size of the library of the current kernel, how much of the kernel CPU memory it
+    /// takes up once laid out and the space left from there for the kernel stack, in bytes.
+    pub fn memory_usage(&self) -> (usize, usize, usize) {
+        let library = match self.kernels.get(&self.current_id) {
+            Some(kernel) => self.arena.get(kernel.library),
+            None => &[]
+        };
+        let footprint = kernel_cpu::image_footprint(library);
+        (library.len(), footprint, kernel_cpu::stack_headroom(footprint))
+    }
+
+    /// Caps the time spent on kernel work in each main loop pass, so that aux packets are
+    /// answered within about that much: messages are passed to the kernel a few values at a
+    /// time and queued kernel messages over several passes. 0 lifts the cap.
+    pub fn set_work_budget(&mut self, budget_us: u32) {
+        unsafe { WORK_BUDGET_US = budget_us as u64 }
+    }
+
+    pub fn set_comm_timeouts(&mut self, timeouts: CommTimeouts) -> bool {
+        if timeouts.slot_ms == 0 {
+            return false
+        }
+        self.comm_timeouts = timeouts;
+        true
+    }
+
+    fn prepare_replay(&mut self) -> Result<bool, Error> {
+        // unpacks the last stored exception into the replay buffer
+        if !self.exception_replay {
+            return Ok(false)
+        }
+        let data = match self.exceptions.back() {
+            Some(exception) if exception.data.len() >= 10 => &exception.data,
+            _ => return Ok(false)
+        };
+        self.replay_buffer = if data[9] != 0 {
+            compression::decompress(&data[10..]).map_err(|e| Error::Unexpected(String::from(e)))?
+        } else {
+            data[10..].to_vec()
+        };
+        Ok(true)
+    }
+
+    pub fn suppressed_count(&mut self, clear: bool) -> u32 {
+        let count = self.suppressed_count;
+        if clear {
+            self.suppressed_count = 0;
+        }
+        count
+    }
+
+    pub fn remove_suppression_rules(&mut self) {
+        self.suppression_rules.clear();
+    }
+
+    fn suppress_exception(&mut self, exception: Sliceable<'static>) -> Sliceable<'static> {
+        // first exception: id, then message (or host string key)
+        let (exception_id, message) = {
+            let mut reader = Cursor::new(exception.data());
+            reader.set_position(9);
+            let exception_id = match reader.read_u32() {
+                Ok(exception_id) => exception_id,
+                Err(_) => return exception
+            };
+            let message = match reader.read_u32() {
+                Ok(length) if length != u32::max_value() => {
+                    let start = reader.position();
+                    exception.data().get(start..start + length as usize)
+                },
+                _ => None
+            };
+            (exception_id, message)
+        };
+        if !self.suppression_rules.iter().any(|rule| rule.matches(exception_id, message)) {
+            return exception
+        }
+        self.suppressed_count += 1;
+
+        // same exception type, so the kernel on master sees the usual failure
+        let mut writer = Cursor::new(Vec::new());
+        match (HostKernelException {
+            exceptions: &[Some(eh_artiq::Exception {
+                id:       exception_id,
+                message:  format!("suppressed on satellite ({} so far)", self.suppressed_count).as_c_slice(),
+                param:    [0, 0, 0],
+                file:     file!().as_c_slice(),
+                line:     line!(),
+                column:   column!(),
+                function: format!("subkernel id {}", self.current_id).as_c_slice(),
+            })],
+            stack_pointers: &[StackPointerBacktrace {
+                stack_pointer: 0,
+                initial_backtrace_size: 0,
+                current_backtrace_size: 0
+            }],
+            backtrace: &[],
+            async_errors: self.session.async_errors,
+            kernel_log: "",
+            severity: exception_severity(exception_id)
+        }).write_to(&mut writer) {
+            Ok(_) => Sliceable::new(writer.into_inner()),
+            Err(_) => exception
+        }
+    }
+
+    fn store_exception(&mut self, exception: Sliceable<'static>, severity: u8) {
+        let exception = self.suppress_exception(exception);
+        if self.exceptions.len() >= EXCEPTION_RING_SIZE {
+            self.exceptions.pop_front();
+        }
+        let mut header = Vec::with_capacity(10);
+        header.extend(&clock::get_ms().to_be_bytes());
+        header.push(severity);
+        // only worth it for exceptions that take several slices to retrieve
+        // compression is left out when the main loop has a work budget, it may take longer
+        let compressed = if exception.data().len() > sat_payload_size() && !bounded() {
+            Some(compression::compress(exception.data()))
+                .filter(|compressed| compressed.len() < exception.data().len())
+        } else {
+            None
+        };
+        let mut data = match compressed {
+            Some(compressed) => {
+                header.push(1);
+                compressed
+            }
+            None => {
+                header.push(0);
+                exception.into_vec()
+            }
+        };
+        // the header is inserted in place rather than into a copy of the exception
+        data.splice(0..0, header);
+        self.exceptions.push_back(StoredException {
+            id: self.current_id,
+            data: data,
+            it: 0
+        });
+    }
+
+    pub fn exception_get_slice(&mut self, id: u32, data_slice: &mut [u8; SAT_PAYLOAD_MAX_SIZE]) -> SliceMeta {
+        self.exception_slice_with(id, data_slice, |data, slice| data.get_slice_sat(slice))
+    }
+
+    // start of the exception, sent along with the finish notification
+    pub fn exception_get_head(&mut self, id: u32, data_slice: &mut [u8; SAT_PAYLOAD_MAX_SIZE]) -> SliceMeta {
+        self.exception_slice_with(id, data_slice, |data, slice| data.get_slice_run_done(slice))
+    }
+
+    fn exception_slice_with<F>(&mut self, id: u32, data_slice: &mut [u8; SAT_PAYLOAD_MAX_SIZE], get_slice: F) -> SliceMeta
+            where F: FnOnce(&mut Sliceable, &mut [u8; SAT_PAYLOAD_MAX_SIZE]) -> SliceMeta {
+        // latest exception of the given kernel, it can be retrieved again later
+        match self.exceptions.iter_mut().rev().find(|exception| exception.id == id) {
+            Some(exception) => {
+                let (meta, it) = {
+                    let mut data = Sliceable::borrowed(&exception.data, exception.it);
+                    let meta = get_slice(&mut data, data_slice);
+                    (meta, data.position())
+                };
+                exception.it = if meta.last { 0 } else { it };
+                meta
+            },
+            None => SliceMeta { len: 0, last: true }
+        }
+    }
+
+    pub fn log_get_slice(&mut self, filter: Option<u32>, data_slice: &mut [u8; SAT_PAYLOAD_MAX_SIZE]) -> SliceMeta {
+        self.log_ring.get_slice(filter, data_slice)
+    }
+
+    pub fn set_log_outputs(&mut self, to_uart: bool, to_master: bool) {
+        self.log_ring.set_outputs(to_uart, to_master);
+    }
+
+    pub fn log_control(&mut self, action: u8) -> bool {
+        match action {
+            LOG_CONTROL_PAUSE => self.log_ring.paused = true,
+            LOG_CONTROL_RESUME => self.log_ring.paused = false,
+            LOG_CONTROL_CLEAR => {
+                self.log_ring.clear();
+                self.session.log_buffer.clear();
+            }
+            _ => return false
+        }
+        true
+    }
+
+    pub fn set_log_rate_limit(&mut self, rate: u32) {
+        self.log_rate_limit = rate;
+    }
+
+    pub fn log_forward_is_ready(&mut self) -> bool {
+        self.log_ring.forward_is_ready(self.current_id)
+    }
+
+    pub fn log_forward_get_slice(&mut self, data_slice: &mut [u8; MASTER_PAYLOAD_MAX_SIZE]) -> Option<(u32, u16)> {
+        self.log_ring.forward_get_slice(data_slice)
+    }
+
+    fn exception_id(&self, name: &str) -> u32 {
+        self.exception_ids.iter()
+            .find(|&&(n, _)| n == name)
+            .map(|&(_, id)| id)
+            .expect("firmware exception missing from FIRMWARE_EXCEPTIONS")
+    }
+
+    fn pass_rpc_reply_to_kernel(&self, reply: &[u8]) -> Result<(), Error> {
+        let (symbols, async_errors) = (self.kernel_symbols(), self.session.async_errors);
+        let slot_timeout = self.comm_timeouts.slot_ms as u64;
+        let slot = kern_recv_slot(&self.log_ring, self.current_id, symbols, async_errors, slot_timeout,
+            "RPC return value")?;
+        if reply.get(0) != Some(&RPC_REPLY_VALUE) {
+            // the exception raised on the host cannot be reconstructed here, only its ID is kept
+            let host_id = reply.get(1..).and_then(|id| Cursor::new(id).read_u32().ok()).unwrap_or(0);
+            return kern_send(&kern::RpcRecvReply(Err(eh_artiq::Exception {
+                id:       self.exception_id("SubkernelError"),
+                message:  "rpc_exception: RPC from subkernel id {1} raised exception {2} on the host (code {0})"
+                              .as_c_slice(),
+                param:    [ErrorCode::RpcException as i64, self.current_id as i64, host_id as i64],
+                file:     file!().as_c_slice(),
+                line:     line!(),
+                column:   column!(),
+                function: "".as_c_slice(),
+            })))
+        }
+        let mut reader = Cursor::new(&reply[1..]);
+        let tag = reader.read_bytes()?;
+        let res = rpc::recv_return(&mut reader, &tag, slot, &|size| -> Result<_, Error> {
+            if size == 0 {
+                return Ok(0 as *mut ())
+            }
+            kern_send(&kern::RpcRecvReply(Ok(size)))?;
+            kern_recv_slot(&self.log_ring, self.current_id, symbols, async_errors, slot_timeout, "nested value")
+        });
+        match res {
+            Ok(_) => kern_send(&kern::RpcRecvReply(Ok(0))),
+            Err(Error::KernelException(exception)) => Err(Error::CausedByException(
+                Box::new(Error::Unexpected(String::from("kernel raised an exception while receiving RPC reply"))),
+                exception)),
+            Err(_) => unexpected!("expected valid RPC reply data")
+        }
+    }
+
+    fn runtime_exception(&mut self, error: Error) {
+        // a kernel that failed this way may have written over its own code
+        self.resident_id = None;
+        self.benchmark = None;
+        let (cause, kernel_exception) = match error {
+            Error::CausedByException(cause, exception) => (*cause, Some(exception)),
+            cause => (cause, None)
+        };
+        let severity = match (&cause, &kernel_exception) {
+            (_, &Some(ref exception)) => kernel_exception_severity(exception.data()),
+            (&Error::SubkernelIoError, _) => EXCEPTION_SEVERITY_RECOVERABLE,
+            _ => EXCEPTION_SEVERITY_FATAL
+        };
+        let code = cause.code();
+        // details (e.g. load error strings) only go to the log, the message stays machine-readable
+        // up to the code; only the position of a decoding error is spelled out after it
+        let detail = match cause {
+            Error::InvalidPointer(ptr) |
+            Error::CorruptMessage(ptr, _) => ptr as i64,
+            Error::MalformedMessage(ref tag_error) => tag_error.offset as i64,
+            _ => 0
+        };
+        let message = match cause {
+            Error::MalformedMessage(ref tag_error) =>
+                format!("{}: in subkernel id {{1}} (code {{0}}): {}", code.name(), tag_error),
+            _ => format!("{}: in subkernel id {{1}} (code {{0}})", code.name())
+        };
+        let raw_exception: Vec<u8> = Vec::new();
+        let mut writer = Cursor::new(raw_exception);
+        match (HostKernelException {
+            exceptions: &[Some(eh_artiq::Exception {
+                id:       self.exception_id("SubkernelError"),
+                message:  message.as_c_slice(),
+                param:    [code as i64, self.current_id as i64, detail],
+                file:     file!().as_c_slice(),
+                line:     line!(),
+                column:   column!(),
+                function: format!("subkernel id {}", self.current_id).as_c_slice(),
+            })],
+            stack_pointers: &[StackPointerBacktrace {
+                stack_pointer: 0,
+                initial_backtrace_size: 0,
+                current_backtrace_size: 0
+            }],
+            backtrace: &[],
+            async_errors: self.session.async_errors,
+            kernel_log: &self.log_ring.recent(self.current_id),
+            severity: severity
+        }).write_to(&mut writer) {
+            Ok(_) => {
+                let mut exception = writer.into_inner();
+                if let Some(kernel_exception) = kernel_exception {
+                    // keep the original traceback, the runtime error is reported as raised during its handling
+                    match chain_exception(kernel_exception.data(), &exception) {
+                        Ok(chained) => exception = chained,
+                        Err(_) => error!("Error chaining exception data")
+                    }
+                }
+                self.store_exception(Sliceable::new(exception), severity)
+            },
+            Err(_) => error!("Error writing exception data")
+        }
+    }
+
+    pub fn kern_message_posted(&self) -> bool {
+        self.is_running() && mailbox::posted()
+    }
+
+    pub fn process_kern_requests(&mut self, rank: u8, dma_manager: &mut DmaManager) {
+        if let KernelState::Loading { started, run } = self.session.kernel_state {
+            self.process_load(started, run);
+            return;
+        }
+        if let KernelState::BenchmarkStarting { started } = self.session.kernel_state {
+            self.process_benchmark_start(started);
+            return;
+        }
+        if !self.is_running() {
+            return;
+        }
+        unsafe { PASS_STARTED_US = clock::get_us() }
+        // cleared before reading, so that a message posted meanwhile sets it again
+        mailbox::clear_posted();
+        if self.benchmark.is_some() {
+            self.loop_back_messages();
+        }
+
+        match self.process_external_messages(dma_manager) {
+            Ok(()) => (),
+            Err(Error::AwaitingMessage) => return, // kernel still waiting, do not process kernel messages
+            Err(e) => self.external_message_failed(e)
+        }
+
+        let started = clock::get_us();
+        let mut processed = 0;
+        let result = loop {
+            if !self.process_queued_kern_messages(dma_manager) {
+                break Ok(None)
+            }
+            let result = self.process_kern_message(rank, dma_manager);
+            match result {
+                Ok(None) => processed += 1,
+                // the kernel CPU may still be preparing its next message
+                Err(Error::NoMessage) if processed > 0 => (),
+                _ => break result
+            }
+            if self.session.kernel_state != KernelState::Running ||
+                    processed >= KERN_BATCH_MAX_MESSAGES ||
+                    clock::get_us() - started >= KERN_BATCH_BUDGET_US || over_budget() {
+                break Ok(None)
+            }
+        };
+        let result = match result {
+            Err(Error::NoMessage) => self.check_kernel_panic()
+                .and_then(|()| self.check_stop_deadline()).and(Ok(None)),
+            other => other
+        };
+        match result {
+            Ok(Some(with_exception)) => {
+                self.last_finished = Some(SubkernelFinished {
+                    id: self.current_id, with_exception: with_exception, async_errors: self.session.async_errors })
+            },
+            Ok(None) | Err(Error::NoMessage) => (),
+            Err(e) => { 
+                error!("Error while running kernel: {:?}", e); 
+                self.stop(); 
+                self.runtime_exception(e);
+                self.last_finished = Some(SubkernelFinished {
+                    id: self.current_id, with_exception: true, async_errors: self.session.async_errors })
+            }
+        }
+    }
+
+    // returns whether all queued messages were taken, which must be so before the one in the mailbox
+    fn process_queued_kern_messages(&mut self, dma_manager: &mut DmaManager) -> bool {
+        // logs and messages queued before the one in the mailbox are always taken first,
+        // queued messages are not left waiting for the next drain
+        let now = clock::get_ms();
+        if mailbox::receive() == 0 && mailbox_queue::receive() == 0 && now < self.session.next_queue_drain {
+            return true
+        }
+        self.session.next_queue_drain = now + KERN_QUEUE_DRAIN_INTERVAL_MS;
+        let mut text = Vec::new();
+        kern_log_ring::drain(|part| text.extend_from_slice(part));
+        if !text.is_empty() {
+            self.append_kernel_log(&String::from_utf8_lossy(&text));
+        }
+        loop {
+            let message = mailbox_queue::receive();
+            if message == 0 {
+                break
+            }
+            if !mailbox_queue::contains(message) {
+                warn!("invalid queued message pointer {:#08x} from kernel CPU", message);
+            } else {
+                match unsafe { &*(message as *const kern::Message) } {
+                    &kern::DmaStartRemoteRequest { id, timestamp } =>
+                        self.start_kernel_playback(dma_manager, id, timestamp),
+                    other => warn!("unexpected queued message {:?} from kernel CPU", other)
+                }
+            }
+            mailbox_queue::acknowledge();
+            if over_budget() {
+                // taken up again at the next pass
+                self.session.next_queue_drain = now;
+                return false
+            }
+        }
+        while !rpc_queue::empty() && self.session.messages.async_backlog() < ASYNC_MESSAGE_BACKLOG_MAX {
+            let message = rpc_queue::dequeue(|slice| -> Result<(Vec<u8>, bool, u32), ()> {
+                let mut reader = Cursor::new(&slice[..]);
+                let header = reader.read_u32().map_err(|_| ())?;
+                if header & rpc_queue::SUBKERNEL_MESSAGE == 0 {
+                    // a background RPC, forwarded to the host through the master
+                    if 4 + header as usize > slice.len() {
+                        return Err(())
+                    }
+                    let mut data = pool::take(1 + header as usize);
+                    data.push(1);
+                    data.extend(&slice[4..][..header as usize]);
+                    return Ok((data, true, 0))
+                }
+                let length = (header & !rpc_queue::SUBKERNEL_MESSAGE) as usize;
+                let id = reader.read_u32().map_err(|_| ())?;
+                if length < 3 || 8 + length > slice.len() {
+                    return Err(())
+                }
+                // skip the service number
+                let mut data = pool::take(length - 3);
+                data.extend(&slice[8..][3..length]);
+                Ok((data, false, id))
+            });
+            match message {
+                Ok((data, false, id)) => {
+                    let peer = self.peer_of(id);
+                    self.session.messages.accept_async(data, peer)
+                },
+                Ok((data, true, _)) => self.session.messages.accept_rpc(data),
+                Err(()) => warn!("dropping malformed background message from subkernel")
+            }
+            if over_budget() {
+                // taken up again at the next pass
+                self.session.next_queue_drain = now;
+                break
+            }
+        }
+        true
+    }
+
+    fn start_kernel_playback(&self, dma_manager: &mut DmaManager, id: i32, timestamp: i64) {
+        // the DMA core belongs to satman, which plays the trace back for the kernel
+        if dma_manager.kernel_playback(id as u32, timestamp as u64).is_err() {
+            warn!("subkernel {} could not play back DMA trace at {:#010x}", self.current_id, id);
         }
-        self.session.kernel_state = KernelState::Running;
-        cricon_select(RtioMaster::Kernel);
-    
-        kern_acknowledge()
     }
 
-    pub fn message_handle_incoming(&mut self, last: bool, length: usize, slice: &[u8; MASTER_PAYLOAD_MAX_SIZE]) {
-        if !self.is_running() {
-            return;
+    fn append_kernel_log(&mut self, arg: &str) {
+        if self.log_ring.paused {
+            return
         }
-        self.session.messages.handle_incoming(last, length, slice);
-    }
-    
-    pub fn message_get_slice(&mut self, slice: &mut [u8; MASTER_PAYLOAD_MAX_SIZE]) -> Option<SliceMeta> {
-        if !self.is_running() {
-            return None;
+        if !self.session.log_allowed(self.log_rate_limit) {
+            self.log_ring.drop_message();
+            return
         }
-        self.session.messages.get_outgoing_slice(slice)
+        self.session.log_buffer += arg;
+        self.session.flush_log_buffer(self.current_id, &mut self.log_ring);
     }
 
-    pub fn message_ack_slice(&mut self) -> bool {
-        if !self.is_running() {
-            warn!("received unsolicited SubkernelMessageAck");
-            return false;
+    fn process_buffered_kern_message(&mut self, message: BufferedMessage) {
+        match message {
+            BufferedMessage::Log(text) => self.append_kernel_log(&text),
+            BufferedMessage::UnformattableLog => {
+                warn!("cannot append to session log buffer");
+                self.log_ring.drop_message();
+            }
+            BufferedMessage::PausedLog => (),
+            // we do not have to do anything about this request,
+            // it is sent by the kernel firmware regardless of RPC being used
+            BufferedMessage::Flush => ()
         }
-        self.session.messages.ack_slice()
-    }
-
-    pub fn message_is_ready(&mut self) -> bool {
-        self.session.messages.is_outgoing_ready()
     }
 
-    pub fn get_last_finished(&mut self) -> Option<SubkernelFinished> {
-        self.last_finished.take()
+    fn process_batched_kern_message(&mut self, message: &kern::Message) -> Result<Option<kern::Message<'static>>, Error> {
+        match message {
+            &kern::LogSlice(arg) => {
+                self.append_kernel_log(arg);
+                Ok(None)
+            }
+            &kern::RpcFlush => Ok(None),
+            &kern::CacheGetRequest { key } => {
+                let value = self.cache.get(key);
+                Ok(Some(kern::CacheGetReply { value: unsafe { mem::transmute(value) } }))
+            }
+            &kern::CachePutRequest { key, value } => {
+                let succeeded = self.cache.put(key, value).is_ok();
+                Ok(Some(kern::CachePutReply { succeeded: succeeded }))
+            }
+            other => unexpected!("unexpected message {:?} in kernel CPU batch", other)
+        }
     }
 
-    pub fn load(&mut self, id: u32) -> Result<(), Error> {
-        if self.current_id == id && self.session.kernel_state == KernelState::Loaded {
+    fn check_kernel_panic(&mut self) -> Result<(), Error> {
+        let now = clock::get_ms();
+        if now < self.session.next_panic_check {
             return Ok(())
         }
-        if !self.kernels.get(&id)?.complete {
-            return Err(Error::KernelNotFound)
-        }
-        self.current_id = id;
-        self.session = Session::new();
-        self.stop();
-        
-        unsafe { 
-            kernel_cpu::start();
-
-            kern_send(&kern::LoadRequest(&self.kernels.get(&id)?.library)).unwrap();
-            kern_recv(|reply| {
-                match reply {
-                    kern::LoadReply(Ok(())) => {
-                        self.session.kernel_state = KernelState::Loaded;
-                        Ok(())
-                    }
-                    kern::LoadReply(Err(error)) => {
-                        kernel_cpu::stop();
-                        Err(Error::Load(format!("{}", error)))
-                    }
-                    other => {
-                        unexpected!("unexpected kernel CPU reply to load request: {:?}", other)
-                    }
-                }
-            })
+        self.session.next_panic_check = now + PANIC_CHECK_INTERVAL_MS;
+        match unsafe { kernel_cpu::panic_message() } {
+            Some(message) => Err(Error::KernelCpuPanic(message)),
+            None => Ok(())
         }
     }
 
-    pub fn exception_get_slice(&mut self, data_slice: &mut [u8; SAT_PAYLOAD_MAX_SIZE]) -> SliceMeta {
-        match self.session.last_exception.as_mut() {
-            Some(exception) => exception.get_slice_sat(data_slice),
-            None => SliceMeta { len: 0, last: true }
+    fn check_stop_deadline(&self) -> Result<(), Error> {
+        match self.session.stop_deadline {
+            Some(deadline) if clock::get_ms() > deadline => {
+                warn!("subkernel #{} did not reach a safe point, resetting the kernel CPU", self.current_id);
+                Err(Error::Stopped)
+            }
+            _ => Ok(())
         }
     }
 
-    fn runtime_exception(&mut self, cause: Error) {
-        let raw_exception: Vec<u8> = Vec::new();
-        let mut writer = Cursor::new(raw_exception);
-        match (HostKernelException {
-            exceptions: &[Some(eh_artiq::Exception {
-                id:       11,  // SubkernelError, defined in ksupport
-                message:  format!("in subkernel id {}: {:?}", self.current_id, cause).as_c_slice(),
-                param:    [0, 0, 0],
-                file:     file!().as_c_slice(),
-                line:     line!(),
-                column:   column!(),
-                function: format!("subkernel id {}", self.current_id).as_c_slice(),
-            })],
-            stack_pointers: &[StackPointerBacktrace {
-                stack_pointer: 0,
-                initial_backtrace_size: 0,
-                current_backtrace_size: 0
-            }],
-            backtrace: &[],
-            async_errors: 0
-        }).write_to(&mut writer) {
-            Ok(_) => self.session.last_exception = Some(Sliceable::new(writer.into_inner())),
-            Err(_) => error!("Error writing exception data")
+    fn pass_message(&mut self, mut passing: PassingMessage) -> Result<(), Error> {
+        self.session.kernel_state = KernelState::Running;
+        let started = clock::get_us();
+        let result = pass_message_to_kernel(&passing.message, &mut passing.position, &mut passing.remaining,
+            &self.log_ring, self.current_id, self.kernel_symbols(), self.session.async_errors,
+            self.comm_timeouts.slot_ms as u64);
+        count_service_time(started);
+        match result {
+            Ok(false) => {
+                // the kernel keeps waiting for the rest, kernel messages are not processed meanwhile
+                self.session.messages.in_passing = Some(passing);
+                self.session.kernel_state = KernelState::MsgPassing;
+                Err(Error::AwaitingMessage)
+            }
+            result => {
+                pool::give(passing.message.data);
+                result.map(|_| ())
+            }
         }
     }
 
-    pub fn process_kern_requests(&mut self, rank: u8) {
-        if !self.is_running() {
-            return;
-        }
-
-        match self.process_external_messages() {
-            Ok(()) => (),
-            Err(Error::AwaitingMessage) => return, // kernel still waiting, do not process kernel messages
-            Err(Error::KernelException(exception)) => {
+    fn external_message_failed(&mut self, error: Error) {
+        match error {
+            Error::KernelException(exception) => {
                 unsafe { kernel_cpu::stop() }
                 self.session.kernel_state = KernelState::Absent;
                 unsafe { self.cache.unborrow() }
-                self.session.last_exception = Some(exception);
-                self.last_finished = Some(SubkernelFinished { id: self.current_id, with_exception: true })
+                let severity = kernel_exception_severity(exception.data());
+                self.store_exception(exception, severity);
             },
-            Err(e) => { 
+            e => {
                 error!("Error while running processing external messages: {:?}", e);
                 self.stop();
                 self.runtime_exception(e);
-                self.last_finished = Some(SubkernelFinished { id: self.current_id, with_exception: true })
-             }
+            }
         }
+        self.last_finished = Some(SubkernelFinished {
+            id: self.current_id, with_exception: true, async_errors: self.session.async_errors })
+    }
 
-        match self.process_kern_message(rank) {
-            Ok(Some(with_exception)) => {
-                self.last_finished = Some(SubkernelFinished { id: self.current_id, with_exception: with_exception })
-            },
-            Ok(None) | Err(Error::NoMessage) => (),
-            Err(e) => { 
-                error!("Error while running kernel: {:?}", e); 
-                self.stop(); 
-                self.runtime_exception(e);
-                self.last_finished = Some(SubkernelFinished { id: self.current_id, with_exception: true })
+    /// Drops the messages still being received, e.g. when their sender went away,
+    /// and lets a kernel waiting for one know. Returns whether anything was dropped.
+    pub fn cancel_incoming(&mut self) -> bool {
+        if !self.is_running() || !self.session.messages.discard_partial_incoming() {
+            return false
+        }
+        if let Err(e) = self.abort_reception() {
+            self.external_message_failed(e)
+        }
+        true
+    }
+
+    fn abort_reception(&mut self) -> Result<(), Error> {
+        match self.session.kernel_state {
+            KernelState::MsgAwait { .. } => {
+                kern_send(&kern::SubkernelMsgRecvReply {
+                    status: kern::SubkernelStatus::ReceiveAborted, count: 0, master_us: 0
+                })?;
+            }
+            KernelState::MsgStreaming => {
+                // the kernel is already receiving the value, it can only be interrupted with an exception
+                kern_send(&kern::RpcRecvReply(Err(eh_artiq::Exception {
+                    id:       self.exception_id("SubkernelError"),
+                    message:  "receive_aborted: message for subkernel id {1} stopped arriving (code {0})"
+                                  .as_c_slice(),
+                    param:    [ErrorCode::ReceiveAborted as i64, self.current_id as i64, 0],
+                    file:     file!().as_c_slice(),
+                    line:     line!(),
+                    column:   column!(),
+                    function: "".as_c_slice(),
+                })))?;
             }
+            _ => return Ok(())
         }
+        self.session.kernel_state = KernelState::Running;
+        Ok(())
     }
 
-    fn process_external_messages(&mut self) -> Result<(), Error> {
+    fn process_external_messages(&mut self, dma_manager: &mut DmaManager) -> Result<(), Error> {
+        if self.session.messages.has_partial_incoming() &&
+                clock::get_ms() > self.session.messages.in_updated_ms + MESSAGE_STALL_TIMEOUT_MS {
+            warn!("dropping incomplete message for subkernel {}, no data for {} ms",
+                  self.current_id, MESSAGE_STALL_TIMEOUT_MS);
+            self.session.messages.discard_partial_incoming();
+            return self.abort_reception()
+        }
         match self.session.kernel_state {
-            KernelState::MsgAwait { max_time } => {
-                if clock::get_ms() > max_time {
-                    kern_send(&kern::SubkernelMsgRecvReply { status: kern::SubkernelStatus::Timeout, count: 0 })?;
+            KernelState::MsgAwait { max_time, next_poll } => {
+                let now = clock::get_ms();
+                if now > max_time {
+                    kern_send(&kern::SubkernelMsgRecvReply {
+                        status: kern::SubkernelStatus::Timeout, count: 0, master_us: 0
+                    })?;
                     self.session.kernel_state = KernelState::Running;
                     return Ok(())
                 }
+                if now < next_poll {
+                    return Err(Error::AwaitingMessage)
+                }
+                self.session.kernel_state = KernelState::MsgAwait {
+                    max_time: max_time,
+                    next_poll: now + self.comm_timeouts.await_poll_ms as u64
+                };
                 if let Some(message) = self.session.messages.get_incoming() {
-                    kern_send(&kern::SubkernelMsgRecvReply { status: kern::SubkernelStatus::NoError, count: message.count })?;
+                    if let Err(tag_error) = rpc::check_tagged(&message.data, message.count as usize) {
+                        // dropped whole, the kernel decides whether to carry on
+                        warn!("dropping malformed message for subkernel {}: {}", self.current_id, tag_error);
+                        pool::give(message.data);
+                        kern_send(&kern::SubkernelMsgRecvReply {
+                            status: kern::SubkernelStatus::DecodeError { offset: tag_error.offset as u32 },
+                            count: 0,
+                            master_us: 0
+                        })?;
+                        self.session.kernel_state = KernelState::Running;
+                        return Ok(())
+                    }
+                    // messages from the master are not stamped, only those sent to it are
+                    kern_send(&kern::SubkernelMsgRecvReply {
+                        status: kern::SubkernelStatus::NoError, count: message.count, master_us: 0
+                    })?;
+                    let count = message.count;
+                    self.pass_message(PassingMessage { message: message, position: 0, remaining: count })
+                } else {
+                    Err(Error::AwaitingMessage)
+                }
+            },
+            KernelState::MsgPassing => {
+                let passing = self.session.messages.in_passing.take().unwrap();
+                self.pass_message(passing)
+            },
+            // the rest of the list or array is written as it arrives, see stream_incoming
+            KernelState::MsgStreaming => Err(Error::AwaitingMessage),
+            KernelState::RpcAwait => {
+                if let Some(reply) = self.session.messages.take_rpc_reply() {
                     self.session.kernel_state = KernelState::Running;
-                    pass_message_to_kernel(&message)
+                    let started = clock::get_us();
+                    let result = self.pass_rpc_reply_to_kernel(&reply);
+                    count_service_time(started);
+                    pool::give(reply);
+                    result
                 } else {
                     Err(Error::AwaitingMessage)
                 }
@@ -530,16 +2745,31 @@ impl Manager {
                     Err(Error::AwaitingMessage)
                 }
             },
+            KernelState::DmaAwait { max_time } => {
+                let reply = match dma_manager.get_kernel_status() {
+                    Some(status) => kern::DmaAwaitRemoteReply {
+                        timeout: false,
+                        error: status.error,
+                        channel: status.channel,
+                        timestamp: status.timestamp
+                    },
+                    None if clock::get_ms() > max_time =>
+                        kern::DmaAwaitRemoteReply { timeout: true, error: 0, channel: 0, timestamp: 0 },
+                    None => return Err(Error::AwaitingMessage)
+                };
+                self.session.kernel_state = KernelState::Running;
+                kern_send(&reply)
+            },
             _ => Ok(())
         }
     }
 
-    fn process_kern_message(&mut self, rank: u8) -> Result<Option<bool>, Error> {
+    fn process_kern_message(&mut self, rank: u8, dma_manager: &mut DmaManager) -> Result<Option<bool>, Error> {
         // returns Ok(with_exception) on finish
         // None if the kernel is still running
         kern_recv(|request| {
             match (request, self.session.kernel_state) {
-                (&kern::LoadReply(_), KernelState::Loaded) => {
+                (&kern::LoadReply { .. }, KernelState::Loaded) => {
                     // We're standing by; ignore the message.
                     return Ok(None)
                 }
@@ -550,32 +2780,21 @@ impl Manager {
                 },
             }
 
-            if process_kern_hwreq(request, rank)? {
+            if let Some(buffered) = buffer_kern_message(request, self.log_ring.paused) {
+                // nothing refers to the mailbox past this point, so the kernel CPU
+                // may go on preparing its next message while we process this one
+                kern_acknowledge()?;
+                let acknowledged = clock::get_us();
+                self.process_buffered_kern_message(buffered);
+                count_early_ack(acknowledged);
                 return Ok(None)
             }
 
-            match request {
-                &kern::Log(args) => {
-                    use core::fmt::Write;
-                    self.session.log_buffer
-                        .write_fmt(args)
-                        .unwrap_or_else(|_| warn!("cannot append to session log buffer"));
-                    self.session.flush_log_buffer();
-                    kern_acknowledge()
-                }
-
-                &kern::LogSlice(arg) => {
-                    self.session.log_buffer += arg;
-                    self.session.flush_log_buffer();
-                    kern_acknowledge()
-                }
-
-                &kern::RpcFlush => {
-                    // we do not have to do anything about this request,
-                    // it is sent by the kernel firmware regardless of RPC being used
-                    kern_acknowledge()
-                }
+            if process_kern_hwreq(request, rank, &mut self.session.injected_errors)? {
+                return Ok(None)
+            }
 
+            match request {
                 &kern::CacheGetRequest { key } => {
                     let value = self.cache.get(key);
                     kern_send(&kern::CacheGetReply {
@@ -588,6 +2807,29 @@ impl Manager {
                     kern_send(&kern::CachePutReply { succeeded: succeeded })
                 }
 
+                &kern::BenchmarkPing => kern_send(&kern::BenchmarkPong),
+
+                &kern::BenchmarkPhaseDone => {
+                    kern_acknowledge()?;
+                    self.benchmark_phase_done();
+                    Ok(())
+                }
+
+                &kern::Batch(messages) => {
+                    let mut replies = Vec::with_capacity(messages.len());
+                    for message in messages {
+                        if let Some(reply) = self.process_batched_kern_message(message)? {
+                            replies.push(reply);
+                        }
+                    }
+                    kern_send(&kern::BatchReply(&replies))
+                }
+
+                &kern::RunFinished if !rpc_queue::empty() => {
+                    // left in the mailbox until the remaining asynchronous messages are taken
+                    Ok(())
+                }
+
                 &kern::RunFinished => {
                     unsafe { kernel_cpu::stop() }
                     self.session.kernel_state = KernelState::Absent;
@@ -595,28 +2837,119 @@ impl Manager {
 
                     return Ok(Some(false))
                 }
-                &kern::RunException { exceptions, stack_pointers, backtrace } => {
+                &kern::RunAborted => {
+                    let message = unsafe { kernel_cpu::panic_message() }
+                        .unwrap_or(String::from("kernel CPU aborted"));
+                    return Err(Error::KernelCpuPanic(message))
+                }
+                &kern::RunStopped => {
+                    return Err(Error::Stopped)
+                }
+                &kern::RunException { exceptions, stack_pointers, backtrace, registers, stack_snapshot, rtio_counter } => {
                     unsafe { kernel_cpu::stop() }
                     self.session.kernel_state = KernelState::Absent;
                     unsafe { self.cache.unborrow() }    
-                    let exception = slice_kernel_exception(&exceptions, &stack_pointers, &backtrace)?;
-                    self.session.last_exception = Some(exception);
+                    let exception = slice_kernel_exception(&exceptions, &stack_pointers, &backtrace,
+                        &self.log_ring.recent(self.current_id), self.kernel_symbols(), registers, stack_snapshot,
+                        self.session.async_errors, self.current_id, rtio_counter)?;
+                    let severity = kernel_exception_severity(exception.data());
+                    self.store_exception(exception, severity);
                     return Ok(Some(true))
                 }
 
-                &kern::SubkernelMsgSend { id: _, count, tag, data } => {
-                    self.session.messages.accept_outgoing(count, tag, data)?;
+                &kern::RpcSend { async, service, tag, data } => {
+                    let started = clock::get_us();
+                    let mut writer = Cursor::new(vec![async as u8]);
+                    rpc::send_args(&mut writer, service, tag, data)?;
+                    // the arguments are copied out, the kernel CPU may go on while they are queued
+                    kern_acknowledge()?;
+                    let acknowledged = clock::get_us();
+                    self.session.messages.accept_rpc(writer.into_inner());
+                    count_early_ack(acknowledged);
+                    count_service_time(started);
+                    if !async {
+                        // the kernel asks for the return value next, held off until it arrives
+                        self.session.kernel_state = KernelState::RpcAwait;
+                    }
+                    Ok(())
+                }
+
+                &kern::SubkernelMsgSend { id, count, tag, data } => {
+                    let peer = self.peer_of(id);
+                    self.session.messages.accept_outgoing(count, tag, data, peer)?;
                     // acknowledge after the message is sent
                     self.session.kernel_state = KernelState::MsgSending;
                     Ok(())
                 }
 
+                &kern::SubkernelReplayExceptionRequest => {
+                    if self.prepare_replay()? {
+                        let exception = read_first_exception(&self.replay_buffer)?;
+                        info!("replaying exception to subkernel {}: {:?}", self.current_id, exception);
+                        kern_send(&kern::SubkernelReplayExceptionReply(Some(exception)))
+                    } else {
+                        kern_send(&kern::SubkernelReplayExceptionReply(None))
+                    }
+                }
+
                 &kern::SubkernelMsgRecvRequest { id: _, timeout } => {
-                    let max_time = clock::get_ms() + timeout as u64;
-                    self.session.kernel_state = KernelState::MsgAwait { max_time: max_time };
+                    let now = clock::get_ms();
+                    self.session.kernel_state = KernelState::MsgAwait {
+                        max_time: now + timeout as u64,
+                        next_poll: now
+                    };
                     Ok(())
                 },
 
+                &kern::DmaRecordStart(name) => {
+                    dma_manager.record_start(name);
+                    kern_acknowledge()
+                }
+                &kern::DmaRecordAppend(data) => {
+                    dma_manager.record_append(data);
+                    kern_acknowledge()
+                }
+                &kern::DmaRecordStop { duration, enable_ddma } => {
+                    if enable_ddma {
+                        warn!("subkernel {} recorded a DMA trace for other destinations, it is played back from here",
+                              self.current_id);
+                    }
+                    let id = dma_manager.record_stop(duration);
+                    debug!("subkernel {} recorded DMA trace {:#010x}", self.current_id, id);
+                    kern_acknowledge()
+                }
+                &kern::DmaEraseRequest { name } => {
+                    let _ = dma_manager.erase_named(name);
+                    kern_acknowledge()
+                }
+                &kern::DmaRetrieveRequest { name } => {
+                    let (trace, duration) = match dma_manager.retrieve(name) {
+                        Some((trace, duration)) => (Some(trace), duration),
+                        None => (None, 0)
+                    };
+                    kern_send(&kern::DmaRetrieveReply {
+                        trace:    trace,
+                        duration: duration,
+                        uses_ddma: false,
+                    })
+                }
+                &kern::DmaStartRemoteRequest { id, timestamp } => {
+                    // sent through the mailbox when the message queue was full
+                    self.start_kernel_playback(dma_manager, id, timestamp);
+                    kern_acknowledge()
+                }
+                &kern::DmaAwaitRemoteRequest { id: _ } => {
+                    if dma_manager.kernel_playback_pending() {
+                        self.session.kernel_state = KernelState::DmaAwait {
+                            max_time: clock::get_ms() + DMA_AWAIT_TIMEOUT_MS
+                        };
+                        Ok(())
+                    } else {
+                        // playback did not start
+                        kern_send(&kern::DmaAwaitRemoteReply { timeout: true, error: 0, channel: 0, timestamp: 0 })
+                    }
+                }
+
                 request => unexpected!("unexpected request {:?} from kernel CPU", request)
             }.and(Ok(None))
         })
@@ -637,10 +2970,12 @@ fn kern_recv<R, F>(f: F) -> Result<R, Error>
     if mailbox::receive() == 0 {
         return Err(Error::NoMessage);
     };
-    if !kernel_cpu::validate(mailbox::receive()) {
-        return Err(Error::InvalidPointer(mailbox::receive()))
-    }
-    f(unsafe { &*(mailbox::receive() as *const kern::Message) })
+    let envelope = mailbox::receive();
+    let message = unsafe { kern::open_envelope(envelope, kernel_cpu::validate) }
+        .map_err(|reason| Error::CorruptMessage(envelope, reason))?;
+    let message = unsafe { &*(message as *const kern::Message) };
+    unsafe { PEEKED_MESSAGE = Some(message as *const _ as *const ()) }
+    f(message)
 }
 
 fn kern_recv_w_timeout<R, F>(timeout: u64, f: F) -> Result<R, Error>
@@ -650,36 +2985,170 @@ fn kern_recv_w_timeout<R, F>(timeout: u64, f: F) -> Result<R, Error>
     // we cannot wait indefinitely to keep the satellite responsive
     // so a timeout is used instead
     let max_time = clock::get_ms() + timeout;
+    let mut backoff = KERN_RECV_BACKOFF_MIN_US;
     while clock::get_ms() < max_time {
         match kern_recv(f) {
-            Err(Error::NoMessage) => continue,
+            Err(Error::NoMessage) => (),
             anything_else => return anything_else
         }
+        if let Some(hook) = unsafe { KERN_WAIT_HOOK } {
+            hook();
+        }
+        clock::spin_us(backoff);
+        backoff = (backoff * 2).min(KERN_RECV_BACKOFF_MAX_US);
     }
     Err(Error::NoMessage)
 }
 
 fn kern_acknowledge() -> Result<(), Error> {
+    count_received();
     mailbox::acknowledge();
+    unsafe { MAILBOX_STATS.acknowledged += 1 }
     Ok(())
 }
 
-fn kern_send(request: &kern::Message) -> Result<(), Error> {
+fn kern_send_w_timeout(request: &kern::Message, timeout: u64) -> Result<(), Error> {
+    // a wedged kernel CPU must not freeze the whole satellite
+    count_received();
     unsafe { mailbox::send(request as *const _ as usize) }
-    while !mailbox::acknowledged() {}
+    let start = clock::get_us();
+    let max_time = clock::get_ms() + timeout;
+    while !mailbox::acknowledged() {
+        if clock::get_ms() > max_time {
+            return Err(Error::KernelCpuHang)
+        }
+        if let Some(hook) = unsafe { KERN_WAIT_HOOK } {
+            hook();
+        }
+    }
+    let stats = unsafe { &mut MAILBOX_STATS };
+    let wait = clock::get_us() - start;
+    stats.sent += 1;
+    stats.send_wait_total_us += wait;
+    stats.send_wait_max_us = stats.send_wait_max_us.max(wait);
     Ok(())
 }
 
+fn kern_send(request: &kern::Message) -> Result<(), Error> {
+    kern_send_w_timeout(request, KERN_SEND_TIMEOUT_MS)
+}
+
+fn symbolize_backtrace(symbols: &[u8], backtrace: &[(usize, usize)]) -> String {
+    // table entries: address, name length and name, sorted by address
+    let mut table = Vec::new();
+    let mut reader = Cursor::new(symbols);
+    while let (Ok(address), Ok(length)) = (reader.read_u32(), reader.read_u32()) {
+        let start = reader.position();
+        let end = start + length as usize;
+        if end > symbols.len() {
+            break;
+        }
+        table.push((address as usize, String::from_utf8_lossy(&symbols[start..end])));
+        reader.set_position(end);
+    }
+
+    let mut annotated = String::new();
+    for &(address, _) in backtrace {
+        // return addresses point just after the call
+        let symbol = match table.binary_search_by_key(&address.saturating_sub(1), |&(start, _)| start) {
+            Ok(index) => Some(&table[index]),
+            Err(0) => None,
+            Err(index) => Some(&table[index - 1])
+        };
+        match symbol {
+            Some(&(start, ref name)) =>
+                annotated += &format!("  0x{:08x} {}+0x{:x}\n", address, name, address - start),
+            None => annotated += &format!("  0x{:08x} ??\n", address)
+        }
+    }
+    annotated
+}
+
+fn negotiate_exception_ids(table: &[(&str, u32)]) -> Vec<(&'static str, u32)> {
+    // ids reported by ksupport take precedence, so firmware and kernel never disagree
+    kern::FIRMWARE_EXCEPTIONS.iter().map(|&(name, default_id)| {
+        let id = match table.iter().find(|&&(n, _)| n == name) {
+            Some(&(_, id)) => id,
+            None => {
+                warn!("ksupport does not know exception {}, using id {}", name, default_id);
+                default_id
+            }
+        };
+        if let Some(&(other, _)) = table.iter().find(|&&(n, other_id)| other_id == id && n != name) {
+            warn!("exception id {} of {} collides with {} in ksupport", id, name, other);
+        }
+        (name, id)
+    }).collect()
+}
+
+fn kernel_exception_severity(data: &[u8]) -> u8 {
+    // id of the first exception, after the sync bytes, reply type and exception count
+    let mut reader = Cursor::new(data);
+    reader.set_position(9);
+    reader.read_u32().map_or(EXCEPTION_SEVERITY_FATAL, exception_severity)
+}
+
+fn format_frame_snapshot(registers: &[usize], stack_snapshot: &[usize]) -> String {
+    let mut snapshot = String::from("Registers:");
+    for (index, register) in registers.iter().enumerate() {
+        if index % 4 == 0 {
+            snapshot += "\n ";
+        }
+        snapshot += &format!(" x{:<2} 0x{:08x}", index, register);
+    }
+    // stack snapshot starts at the stack pointer of the frame (x2)
+    let sp = registers.get(2).cloned().unwrap_or(0);
+    snapshot += &format!("\nStack at 0x{:08x}:", sp);
+    for (index, word) in stack_snapshot.iter().enumerate() {
+        if index % 4 == 0 {
+            snapshot += &format!("\n  0x{:08x}:", sp + index * 4);
+        }
+        snapshot += &format!(" 0x{:08x}", word);
+    }
+    snapshot
+}
+
 fn slice_kernel_exception(exceptions: &[Option<eh_artiq::Exception>],
     stack_pointers: &[eh_artiq::StackPointerBacktrace],
-    backtrace: &[(usize, usize)]
-) -> Result<Sliceable, Error> {
-    error!("exception in kernel");
+    backtrace: &[(usize, usize)],
+    kernel_log: &str,
+    symbols: &[u8],
+    registers: &[usize],
+    stack_snapshot: &[usize],
+    async_errors: u8,
+    id: u32,
+    rtio_counter: i64
+) -> Result<Sliceable<'static>, Error> {
+    error!("exception in subkernel {} at RTIO counter {}", id, rtio_counter);
     for exception in exceptions {
         error!("{:?}", exception.unwrap());
     }
     error!("stack pointers: {:?}", stack_pointers);
     error!("backtrace: {:?}", backtrace);
+    // lets post-mortems line up failures of different satellites on the experiment timeline
+    let header = format!("Subkernel {} failed at RTIO counter {} mu", id, rtio_counter);
+    let mut kernel_log = if kernel_log.is_empty() {
+        header
+    } else {
+        format!("{}\n{}", header, kernel_log)
+    };
+    if !symbols.is_empty() && !backtrace.is_empty() {
+        let annotated = symbolize_backtrace(symbols, backtrace);
+        error!("symbolized backtrace:\n{}", annotated.trim_end());
+        if !kernel_log.is_empty() {
+            kernel_log += "\n";
+        }
+        kernel_log += "Backtrace:\n";
+        kernel_log += annotated.trim_end();
+    }
+    if registers.iter().any(|&register| register != 0) {
+        let snapshot = format_frame_snapshot(registers, stack_snapshot);
+        error!("{}", snapshot);
+        if !kernel_log.is_empty() {
+            kernel_log += "\n";
+        }
+        kernel_log += &snapshot;
+    }
     // master will only pass the exception data back to the host:
     let raw_exception: Vec<u8> = Vec::new();
     let mut writer = Cursor::new(raw_exception);
@@ -687,7 +3156,10 @@ fn slice_kernel_exception(exceptions: &[Option<eh_artiq::Exception>],
         exceptions: exceptions,
         stack_pointers: stack_pointers,
         backtrace: backtrace,
-        async_errors: 0
+        async_errors: async_errors,
+        kernel_log: &kernel_log,
+        severity: exceptions.first().and_then(|exception| exception.as_ref())
+            .map_or(EXCEPTION_SEVERITY_FATAL, |exception| exception_severity(exception.id))
     }).write_to(&mut writer) {
         // save last exception data to be received by master
         Ok(_) => Ok(Sliceable::new(writer.into_inner())),
@@ -695,62 +3167,208 @@ fn slice_kernel_exception(exceptions: &[Option<eh_artiq::Exception>],
     }
 }
 
-fn pass_message_to_kernel(message: &Message) -> Result<(), Error> {
-    let mut reader = Cursor::new(&message.data);
-    let mut tag: [u8; 1] = [message.tag];
-    let count = message.count;
-    let mut i = 0;
-    loop {
-        let slot = kern_recv_w_timeout(100, |reply| {
-            match reply {
-                &kern::RpcRecvRequest(slot) => Ok(slot),
-                &kern::RunException { exceptions, stack_pointers, backtrace } => {
-                    let exception = slice_kernel_exception(&exceptions, &stack_pointers, &backtrace)?;
-                    Err(Error::KernelException(exception))
-                },
-                other => unexpected!(
-                    "expected root value slot from kernel CPU, not {:?}", other)
-            }
-        })?;
+fn exception_bounds(data: &[u8]) -> Result<(u32, usize, usize), Error> {
+    // returns the exception count and the range of serialized exceptions,
+    // skipping the sync bytes and the reply type
+    let mut reader = Cursor::new(data);
+    reader.set_position(5);
+    let count = reader.read_u32()?;
+    let start = reader.position();
+    for _ in 0..count {
+        reader.read_u32()?;
+        // message, params, file, line, column, function
+        skip_exception_string(&mut reader)?;
+        for _ in 0..3 {
+            reader.read_u64()?;
+        }
+        skip_exception_string(&mut reader)?;
+        reader.read_u32()?;
+        reader.read_u32()?;
+        skip_exception_string(&mut reader)?;
+    }
+    Ok((count, start, reader.position()))
+}
 
-        let res = rpc::recv_return(&mut reader, &tag, slot, &|size| -> Result<_, Error> {
+fn read_exception_string<'a>(reader: &mut Cursor<&'a [u8]>) -> Result<CSlice<'a, u8>, Error> {
+    let length = reader.read_u32()?;
+    if length == u32::max_value() {
+        // host string, the key takes the place of the pointer, as in the kernel
+        let key = reader.read_u32()?;
+        return Ok(unsafe { CSlice::new(key as *const u8, usize::MAX) })
+    }
+    let start = reader.position();
+    let end = start + length as usize;
+    let data: &'a [u8] = *reader.get_ref();
+    if end > data.len() {
+        return Err(Error::SubkernelIoError);
+    }
+    reader.set_position(end);
+    Ok(unsafe { CSlice::new(data[start..end].as_ptr(), length as usize) })
+}
+
+fn read_first_exception<'a>(data: &'a [u8]) -> Result<eh_artiq::Exception<'a>, Error> {
+    let (count, start, _) = exception_bounds(data)?;
+    if count == 0 {
+        return Err(Error::Unexpected(String::from("stored exception report is empty")))
+    }
+    let mut reader = Cursor::new(data);
+    reader.set_position(start);
+    let id = reader.read_u32()?;
+    let message = read_exception_string(&mut reader)?;
+    let mut param = [0; 3];
+    for p in param.iter_mut() {
+        *p = reader.read_u64()? as i64;
+    }
+    let file = read_exception_string(&mut reader)?;
+    let line = reader.read_u32()?;
+    let column = reader.read_u32()?;
+    let function = read_exception_string(&mut reader)?;
+    Ok(eh_artiq::Exception {
+        id:       id,
+        message:  message,
+        param:    param,
+        file:     file,
+        line:     line,
+        column:   column,
+        function: function
+    })
+}
+
+fn skip_exception_string(reader: &mut Cursor<&[u8]>) -> Result<(), Error> {
+    let length = reader.read_u32()?;
+    if length == u32::max_value() {
+        // host string, only the key follows
+        reader.read_u32()?;
+    } else {
+        let end = reader.position() + length as usize;
+        if end > reader.get_ref().len() {
+            return Err(Error::SubkernelIoError);
+        }
+        reader.set_position(end);
+    }
+    Ok(())
+}
+
+fn chain_exception(cause: &[u8], exception: &[u8]) -> Result<Vec<u8>, Error> {
+    // appends the exceptions of a serialized report to the ones of another,
+    // so that the host shows them as raised during handling of the cause
+    let (cause_count, cause_start, cause_end) = exception_bounds(cause)?;
+    let (count, start, end) = exception_bounds(exception)?;
+    // stack pointer entries (3 words each) follow the exceptions, then the backtrace length
+    let cause_sp_end = cause_end + cause_count as usize * 12;
+    if cause.len() < cause_sp_end + 4 {
+        return Err(Error::SubkernelIoError);
+    }
+    let backtrace_size = Cursor::new(&cause[cause_sp_end..]).read_u32()?;
+
+    let mut writer = Cursor::new(Vec::with_capacity(cause.len() + end - start + 12 * count as usize));
+    writer.write_all(&cause[..cause_start - 4])?;
+    writer.write_u32(cause_count + count)?;
+    writer.write_all(&cause[cause_start..cause_end])?;
+    writer.write_all(&exception[start..end])?;
+    writer.write_all(&cause[cause_end..cause_sp_end])?;
+    for _ in 0..count {
+        // no backtrace of their own, raised after the kernel one
+        writer.write_u32(0)?;
+        writer.write_u32(backtrace_size)?;
+        writer.write_u32(backtrace_size)?;
+    }
+    writer.write_all(&cause[cause_sp_end..])?;
+    Ok(writer.into_inner())
+}
+
+fn kern_recv_slot(log_ring: &LogRing, id: u32, symbols: &[u8], async_errors: u8, slot_timeout: u64,
+        what: &'static str) -> Result<*mut (), Error> {
+    kern_recv_w_timeout(slot_timeout, |reply| {
+        match reply {
+            &kern::RpcRecvRequest(slot) => Ok(slot),
+            &kern::RunException { exceptions, stack_pointers, backtrace, registers, stack_snapshot, rtio_counter } => {
+                let exception = slice_kernel_exception(&exceptions, &stack_pointers, &backtrace,
+                    &log_ring.recent(id), symbols, registers, stack_snapshot, async_errors, id, rtio_counter)?;
+                Err(Error::KernelException(exception))
+            },
+            other => unexpected!(
+                "expected {} slot from kernel CPU, not {:?}", what, other)
+        }
+    })
+}
+
+// Passes the values of the message from `position` on, returns whether all of them were
+// passed or the work budget of this main loop pass ran out first, after at least one.
+fn pass_message_to_kernel(message: &Message, position: &mut usize, remaining: &mut u8, log_ring: &LogRing,
+        id: u32, symbols: &[u8], async_errors: u8, slot_timeout: u64) -> Result<bool, Error> {
+    let mut reader = Cursor::new(&message.data);
+    reader.set_position(*position);
+    while *remaining > 0 {
+        let start = reader.position();
+        let slot = kern_recv_slot(log_ring, id, symbols, async_errors, slot_timeout, "root value")?;
+        let res = rpc::recv_tagged(&mut reader, slot, &|size| -> Result<_, Error> {
             if size == 0 {
                 return Ok(0 as *mut ())
             }
             kern_send(&kern::RpcRecvReply(Ok(size)))?;
-            Ok(kern_recv_w_timeout(100, |reply| {
-                match reply {
-                    &kern::RpcRecvRequest(slot) => Ok(slot),
-                    &kern::RunException { 
-                        exceptions,
-                        stack_pointers,
-                        backtrace 
-                    }=> {
-                        let exception = slice_kernel_exception(&exceptions, &stack_pointers, &backtrace)?;
-                        Err(Error::KernelException(exception))
-                    },
-                    other => unexpected!(
-                        "expected nested value slot from kernel CPU, not {:?}", other)
-                }
-            })?)
+            kern_recv_slot(log_ring, id, symbols, async_errors, slot_timeout, "nested value")
         });
         match res {
             Ok(_) => kern_send(&kern::RpcRecvReply(Ok(0)))?,
-            Err(_) => unexpected!("expected valid subkernel message data")
+            Err(Error::KernelException(exception)) => return Err(Error::CausedByException(
+                Box::new(Error::Unexpected(String::from("kernel raised an exception while receiving subkernel message"))),
+                exception)),
+            Err(_) => match rpc::tagged_error(&message.data, start) {
+                Some(tag_error) => return Err(Error::MalformedMessage(tag_error)),
+                None => unexpected!("expected valid subkernel message data")
+            }
         };
-        i += 1;
-        if i < count {
-            // update the tag for next read
-            tag[0] = reader.read_u8()?;
-        } else {
-            // should be done by then
-            break;
+        *remaining -= 1;
+        *position = reader.position();
+        if *remaining > 0 && over_budget() {
+            return Ok(false)
         }
     }
-    Ok(())
+    Ok(true)
 }
 
-fn process_kern_hwreq(request: &kern::Message, rank: u8) -> Result<bool, Error> {
+// Sets up the value being streamed into the kernel, returning where its contents go.
+fn pass_stream_header_to_kernel(header: &[u8], log_ring: &LogRing, id: u32, symbols: &[u8],
+        async_errors: u8, slot_timeout: u64) -> Result<*mut u8, Error> {
+    let slot = kern_recv_slot(log_ring, id, symbols, async_errors, slot_timeout, "root value")?;
+    let res = rpc::recv_stream_header(header, slot, &|size| -> Result<_, Error> {
+        if size == 0 {
+            return Ok(0 as *mut ())
+        }
+        kern_send(&kern::RpcRecvReply(Ok(size)))?;
+        kern_recv_slot(log_ring, id, symbols, async_errors, slot_timeout, "nested value")
+    });
+    match res {
+        Ok(elements) => Ok(elements),
+        Err(Error::KernelException(exception)) => Err(Error::CausedByException(
+            Box::new(Error::Unexpected(String::from("kernel raised an exception while receiving subkernel message"))),
+            exception)),
+        Err(e) => Err(e)
+    }
+}
+
+fn inject_error(injected_errors: &mut u32) -> bool {
+    // consumes one injected failure, if any are left
+    if *injected_errors > 0 {
+        *injected_errors -= 1;
+        true
+    } else {
+        false
+    }
+}
+
+// moninj only reaches the channels of this satellite, given either with its destination or 0
+fn local_moninj_channel(channel: u32) -> Option<u16> {
+    let destination = (channel >> 16) as u8;
+    if cfg!(has_rtio_moninj) && (destination == 0 || Some(destination) == unsafe { ::DESTINATION }) {
+        Some(channel as u16)
+    } else {
+        None
+    }
+}
+
+fn process_kern_hwreq(request: &kern::Message, rank: u8, injected_errors: &mut u32) -> Result<bool, Error> {
     match request {
         &kern::RtioInitRequest => {
             unsafe {
@@ -769,18 +3387,22 @@ fn process_kern_hwreq(request: &kern::Message, rank: u8) -> Result<bool, Error>
         }
 
         &kern::I2cStartRequest { busno } => {
-            let succeeded = i2c::start(busno as u8).is_ok();
+            let succeeded = !inject_error(injected_errors) && i2c::start(busno as u8).is_ok();
             kern_send(&kern::I2cBasicReply { succeeded: succeeded })
         }
         &kern::I2cRestartRequest { busno } => {
-            let succeeded = i2c::restart(busno as u8).is_ok();
+            let succeeded = !inject_error(injected_errors) && i2c::restart(busno as u8).is_ok();
             kern_send(&kern::I2cBasicReply { succeeded: succeeded })
         }
         &kern::I2cStopRequest { busno } => {
-            let succeeded = i2c::stop(busno as u8).is_ok();
+            let succeeded = !inject_error(injected_errors) && i2c::stop(busno as u8).is_ok();
             kern_send(&kern::I2cBasicReply { succeeded: succeeded })
         }
         &kern::I2cWriteRequest { busno, data } => {
+            if inject_error(injected_errors) {
+                // pretend the device did not acknowledge
+                return kern_send(&kern::I2cWriteReply { succeeded: true, ack: false }).and(Ok(true))
+            }
             match i2c::write(busno as u8, data) {
                 Ok(ack) => kern_send(
                     &kern::I2cWriteReply { succeeded: true, ack: ack }),
@@ -789,6 +3411,9 @@ fn process_kern_hwreq(request: &kern::Message, rank: u8) -> Result<bool, Error>
             }
         }
         &kern::I2cReadRequest { busno, ack } => {
+            if inject_error(injected_errors) {
+                return kern_send(&kern::I2cReadReply { succeeded: false, data: 0xff }).and(Ok(true))
+            }
             match i2c::read(busno as u8, ack) {
                 Ok(data) => kern_send(
                     &kern::I2cReadReply { succeeded: true, data: data }),
@@ -797,19 +3422,24 @@ fn process_kern_hwreq(request: &kern::Message, rank: u8) -> Result<bool, Error>
             }
         }
         &kern::I2cSwitchSelectRequest { busno, address, mask } => {
-            let succeeded = i2c::switch_select(busno as u8, address, mask).is_ok();
+            let succeeded = !inject_error(injected_errors) &&
+                i2c::switch_select(busno as u8, address, mask).is_ok();
             kern_send(&kern::I2cBasicReply { succeeded: succeeded })
         }
 
         &kern::SpiSetConfigRequest { busno, flags, length, div, cs } => {
-            let succeeded = spi::set_config(busno as u8, flags, length, div, cs).is_ok();
+            let succeeded = !inject_error(injected_errors) &&
+                spi::set_config(busno as u8, flags, length, div, cs).is_ok();
             kern_send(&kern::SpiBasicReply { succeeded: succeeded })
         },
         &kern::SpiWriteRequest { busno, data } => {
-            let succeeded = spi::write(busno as u8, data).is_ok();
+            let succeeded = !inject_error(injected_errors) && spi::write(busno as u8, data).is_ok();
             kern_send(&kern::SpiBasicReply { succeeded: succeeded })
         }
         &kern::SpiReadRequest { busno } => {
+            if inject_error(injected_errors) {
+                return kern_send(&kern::SpiReadReply { succeeded: false, data: 0 }).and(Ok(true))
+            }
             match spi::read(busno as u8) {
                 Ok(data) => kern_send(
                     &kern::SpiReadReply { succeeded: true, data: data }),
@@ -818,6 +3448,74 @@ fn process_kern_hwreq(request: &kern::Message, rank: u8) -> Result<bool, Error>
             }
         }
 
+        &kern::HwreqErrorInjectRequest { count } => {
+            #[cfg(feature = "hwreq_error_injection")]
+            let succeeded = {
+                warn!("failing the next {} I2C/SPI request(s) on kernel request", count);
+                *injected_errors = count;
+                true
+            };
+            #[cfg(not(feature = "hwreq_error_injection"))]
+            let succeeded = {
+                warn!("kernel requested I2C/SPI error injection ({}), but it is not enabled", count);
+                false
+            };
+            kern_send(&kern::HwreqErrorInjectReply { succeeded: succeeded })
+        }
+
+        &kern::MasterTimeRequest => {
+            let reply = match unsafe { MASTER_TIME } {
+                Some((master_us, received_us)) => kern::MasterTimeReply {
+                    valid: true,
+                    master_us: master_us + (clock::get_us() - received_us)
+                },
+                None => kern::MasterTimeReply { valid: false, master_us: 0 }
+            };
+            kern_send(&reply)
+        }
+
+        &kern::MoninjProbeRequest { channel, probe } => {
+            match local_moninj_channel(channel) {
+                Some(_channel) => {
+                    let value;
+                    #[cfg(has_rtio_moninj)]
+                    unsafe {
+                        csr::rtio_moninj::mon_chan_sel_write(_channel as _);
+                        csr::rtio_moninj::mon_probe_sel_write(probe);
+                        csr::rtio_moninj::mon_value_update_write(1);
+                        value = csr::rtio_moninj::mon_value_read() as u64;
+                    }
+                    #[cfg(not(has_rtio_moninj))]
+                    {
+                        let _ = probe;
+                        value = 0;
+                    }
+                    kern_send(&kern::MoninjProbeReply { succeeded: true, value: value })
+                }
+                None => kern_send(&kern::MoninjProbeReply { succeeded: false, value: 0 })
+            }
+        }
+        &kern::MoninjInjectionStatusRequest { channel, overrd } => {
+            match local_moninj_channel(channel) {
+                Some(_channel) => {
+                    let value;
+                    #[cfg(has_rtio_moninj)]
+                    unsafe {
+                        csr::rtio_moninj::inj_chan_sel_write(_channel as _);
+                        csr::rtio_moninj::inj_override_sel_write(overrd);
+                        value = csr::rtio_moninj::inj_value_read();
+                    }
+                    #[cfg(not(has_rtio_moninj))]
+                    {
+                        let _ = overrd;
+                        value = 0;
+                    }
+                    kern_send(&kern::MoninjInjectionStatusReply { succeeded: true, value: value })
+                }
+                None => kern_send(&kern::MoninjInjectionStatusReply { succeeded: false, value: 0 })
+            }
+        }
+
         _ => return Ok(false)
     }.and(Ok(true))
-}
\ No newline at end of file
+}
@@ -0,0 +1,67 @@
+use board_misoc::clock;
+use proto_artiq::drtioaux_proto::LOOP_STATS_COUNT;
+
+// Time taken by passes of the main loop and by its kernel and aux parts, to check that
+// the satellite stays responsive to DRTIO as the firmware grows.
+
+#[derive(Clone, Copy)]
+pub enum Section {
+    Kernel = 0,
+    Aux = 1,
+    Iteration = 2
+}
+
+const SECTION_COUNT: usize = 3;
+// bucket n counts the passes that took less than 2^n us, the last one all that took longer
+const BUCKET_COUNT: usize = 24;
+
+#[derive(Clone, Copy)]
+struct Timing {
+    count: u32,
+    max_us: u32,
+    buckets: [u32; BUCKET_COUNT]
+}
+
+const TIMING_ZERO: Timing = Timing { count: 0, max_us: 0, buckets: [0; BUCKET_COUNT] };
+
+static mut TIMINGS: [Timing; SECTION_COUNT] = [TIMING_ZERO; SECTION_COUNT];
+
+pub fn record(section: Section, started_us: u64) {
+    let elapsed = (clock::get_us() - started_us).min(u32::max_value() as u64) as u32;
+    let bucket = ((32 - elapsed.leading_zeros()) as usize).min(BUCKET_COUNT - 1);
+    let timing = unsafe { &mut TIMINGS[section as usize] };
+    timing.count = timing.count.saturating_add(1);
+    timing.max_us = timing.max_us.max(elapsed);
+    timing.buckets[bucket] = timing.buckets[bucket].saturating_add(1);
+}
+
+// upper bound of the bucket the given fraction (per mille) of the passes falls within
+fn percentile_us(timing: &Timing, per_mille: u64) -> u32 {
+    let target = (timing.count as u64 * per_mille + 999) / 1000;
+    let mut seen = 0;
+    for (n, &count) in timing.buckets.iter().enumerate() {
+        seen += count as u64;
+        if seen >= target && seen > 0 {
+            return ((1u64 << n) - 1).min(timing.max_us as u64) as u32
+        }
+    }
+    timing.max_us
+}
+
+/// Returns, for the kernel processing, the aux packet handling and whole main loop
+/// passes, in that order: the number of passes, the median, 99th percentile and
+/// longest time (us).
+pub fn stats(clear: bool) -> [u32; LOOP_STATS_COUNT] {
+    let timings = unsafe { &mut TIMINGS };
+    let mut counters = [0; LOOP_STATS_COUNT];
+    for (timing, counters) in timings.iter().zip(counters.chunks_mut(4)) {
+        counters[0] = timing.count;
+        counters[1] = percentile_us(timing, 500);
+        counters[2] = percentile_us(timing, 990);
+        counters[3] = timing.max_us;
+    }
+    if clear {
+        *timings = [TIMING_ZERO; SECTION_COUNT];
+    }
+    counters
+}
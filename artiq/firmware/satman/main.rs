@@ -14,18 +14,20 @@ extern crate io;
 extern crate eh;
 
 use core::convert::TryFrom;
+use core::cmp::min;
 use board_misoc::{csr, ident, clock, uart_logger, i2c, pmp};
 #[cfg(has_si5324)]
 use board_artiq::si5324;
 use board_artiq::{spi, drtioaux, drtio_routing};
 #[cfg(soc_platform = "efc")]
 use board_artiq::ad9117;
-use proto_artiq::drtioaux_proto::{SAT_PAYLOAD_MAX_SIZE, MASTER_PAYLOAD_MAX_SIZE};
+use proto_artiq::drtioaux_proto::{SAT_PAYLOAD_MAX_SIZE, MASTER_PAYLOAD_MAX_SIZE, AUX_PACKET_DEFAULT_SIZE, BENCHMARK_RESULT_COUNT};
+use proto_artiq::drtioaux_proto::{LOG_TARGET_ALL, LOG_TARGET_KERNEL, LOG_TARGET_DRTIO, LOG_TARGET_MANAGER};
 #[cfg(has_drtio_eem)]
 use board_artiq::drtio_eem;
 use riscv::register::{mcause, mepc, mtval};
 use dma::Manager as DmaManager;
-use kernel::Manager as KernelManager;
+use kernel::{Manager as KernelManager, SliceMeta, SubkernelFinished};
 use analyzer::Analyzer;
 
 #[global_allocator]
@@ -36,6 +38,25 @@ mod dma;
 mod analyzer;
 mod kernel;
 mod cache;
+mod pool;
+mod checksum;
+mod arena;
+mod latency;
+
+// size of aux packets agreed on with the master
+static mut AUX_PACKET_SIZE: usize = AUX_PACKET_DEFAULT_SIZE;
+// of this satellite, learnt from the status requests of the master
+static mut DESTINATION: Option<u8> = None;
+// taken from the link ahead of its turn, see poll_priority_aux
+static mut DEFERRED_PACKET: Option<drtioaux::Packet> = None;
+
+pub fn sat_payload_size() -> usize {
+    proto_artiq::drtioaux_proto::sat_payload_size(unsafe { AUX_PACKET_SIZE })
+}
+
+pub fn master_payload_size() -> usize {
+    proto_artiq::drtioaux_proto::master_payload_size(unsafe { AUX_PACKET_SIZE })
+}
 
 fn drtiosat_reset(reset: bool) {
     unsafe {
@@ -102,6 +123,44 @@ macro_rules! forward {
     ($routing_table:expr, $destination:expr, $rank:expr, $repeaters:expr, $packet:expr) => {}
 }
 
+// module prefixes of the kernel output, the DRTIO link and aux handling,
+// and the kernel and DMA managers
+const LOG_TARGETS: [(u8, &'static [&'static str]); 3] = [
+    (LOG_TARGET_KERNEL,  &["kernel"]),
+    (LOG_TARGET_DRTIO,   &["satman", "satman::repeater", "board_artiq::drtioaux"]),
+    (LOG_TARGET_MANAGER, &["satman::kernel", "satman::dma", "satman::cache"]),
+];
+
+pub fn log_target_id(target: &str) -> u8 {
+    let mut matched = (LOG_TARGET_ALL, 0);
+    for &(id, prefixes) in LOG_TARGETS.iter() {
+        for prefix in prefixes.iter() {
+            if uart_logger::target_matches(target, prefix) && prefix.len() > matched.1 {
+                matched = (id, prefix.len());
+            }
+        }
+    }
+    matched.0
+}
+
+fn set_log_filter(target: u8, level: u8) -> bool {
+    let level = match level {
+        0 => log::LevelFilter::Off,
+        1 => log::LevelFilter::Error,
+        2 => log::LevelFilter::Warn,
+        3 => log::LevelFilter::Info,
+        4 => log::LevelFilter::Debug,
+        5 => log::LevelFilter::Trace,
+        _ => return false
+    };
+    let prefixes: &[&'static str] = match LOG_TARGETS.iter().find(|&&(id, _)| id == target) {
+        Some(&(_, prefixes)) => prefixes,
+        None if target == LOG_TARGET_ALL => &[""],
+        None => return false
+    };
+    prefixes.iter().all(|&prefix| uart_logger::ConsoleLogger::set_target_level(prefix, level))
+}
+
 fn process_aux_packet(dmamgr: &mut DmaManager, analyzer: &mut Analyzer, kernelmgr: &mut KernelManager,
         _repeaters: &mut [repeater::Repeater], _routing_table: &mut drtio_routing::RoutingTable, _rank: &mut u8,
         packet: drtioaux::Packet) -> Result<(), drtioaux::Error<!>> {
@@ -110,6 +169,26 @@ fn process_aux_packet(dmamgr: &mut DmaManager, analyzer: &mut Analyzer, kernelmg
     match packet {
         drtioaux::Packet::EchoRequest =>
             drtioaux::send(0, &drtioaux::Packet::EchoReply),
+        drtioaux::Packet::TimestampedPingRequest { destination: _destination, timestamp } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            drtioaux::send(0, &drtioaux::Packet::TimestampedPingReply { timestamp: timestamp })
+        }
+        drtioaux::Packet::MasterTimeUpdate { destination: _destination, master_us } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            let satellite_us = kernel::set_master_time(master_us);
+            drtioaux::send(0, &drtioaux::Packet::MasterTimeAck { satellite_us: satellite_us })
+        }
+        drtioaux::Packet::SubkernelMessageCredit { destination: _destination, id, credits } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            kernelmgr.message_grant_credits(id, credits);
+            drtioaux::send(0, &drtioaux::Packet::SubkernelMessageCreditAck)
+        }
+        drtioaux::Packet::SubkernelKeepaliveRequest { destination: _destination, id } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            drtioaux::send(0, &drtioaux::Packet::SubkernelKeepaliveReply {
+                running: kernelmgr.get_current_id() == Some(id)
+            })
+        }
         drtioaux::Packet::ResetRequest => {
             info!("resetting RTIO");
             drtiosat_reset(true);
@@ -130,23 +209,25 @@ fn process_aux_packet(dmamgr: &mut DmaManager, analyzer: &mut Analyzer, kernelmg
             let hop = 0;
 
             if hop == 0 {
+                unsafe { DESTINATION = Some(destination); }
                 // async messages
                 if let Some(status) = dmamgr.get_status() {
                     info!("playback done, error: {}, channel: {}, timestamp: {}", status.error, status.channel, status.timestamp);
                     drtioaux::send(0, &drtioaux::Packet::DmaPlaybackStatus { 
                         destination: destination, id: status.id, error: status.error, channel: status.channel, timestamp: status.timestamp })?;
-                } else if let Some(subkernel_finished) = kernelmgr.get_last_finished() {
-                    info!("subkernel {} finished, with exception: {}", subkernel_finished.id, subkernel_finished.with_exception);
-                    drtioaux::send(0, &drtioaux::Packet::SubkernelFinished {
-                        id: subkernel_finished.id, with_exception: subkernel_finished.with_exception
+                } else if kernelmgr.log_forward_is_ready() {
+                    // kernel output goes out before the kernel is reported finished
+                    let mut data_slice: [u8; MASTER_PAYLOAD_MAX_SIZE] = [0; MASTER_PAYLOAD_MAX_SIZE];
+                    let (id, length) = kernelmgr.log_forward_get_slice(&mut data_slice).unwrap();
+                    drtioaux::send(0, &drtioaux::Packet::SubkernelLogForward {
+                        destination: destination, id: id, length: length, data: data_slice
                     })?;
+                } else if let Some(subkernel_finished) = kernelmgr.get_last_finished() {
+                    drtioaux::send(0, &run_done_packet(kernelmgr, destination, subkernel_finished))?;
                 } else if kernelmgr.message_is_ready() {
                     let mut data_slice: [u8; MASTER_PAYLOAD_MAX_SIZE] = [0; MASTER_PAYLOAD_MAX_SIZE];
                     let meta = kernelmgr.message_get_slice(&mut data_slice).unwrap();
-                    drtioaux::send(0, &drtioaux::Packet::SubkernelMessage {
-                        destination: destination, id: kernelmgr.get_current_id().unwrap(),
-                        last: meta.last, length: meta.len as u16, data: data_slice
-                    })?;
+                    drtioaux::send(0, &outgoing_message_packet(kernelmgr, destination, meta, data_slice))?;
                 } else {
                     let errors;
                     unsafe {
@@ -158,6 +239,7 @@ fn process_aux_packet(dmamgr: &mut DmaManager, analyzer: &mut Analyzer, kernelmg
                             channel = csr::drtiosat::sequence_error_channel_read();
                             csr::drtiosat::rtio_error_write(1);
                         }
+                        kernelmgr.add_async_errors(kernel::ASYNC_ERROR_SEQUENCE_ERROR);
                         drtioaux::send(0,
                             &drtioaux::Packet::DestinationSequenceErrorReply { channel })?;
                     } else if errors & 2 != 0 {
@@ -166,6 +248,7 @@ fn process_aux_packet(dmamgr: &mut DmaManager, analyzer: &mut Analyzer, kernelmg
                             channel = csr::drtiosat::collision_channel_read();
                             csr::drtiosat::rtio_error_write(2);
                         }
+                        kernelmgr.add_async_errors(kernel::ASYNC_ERROR_COLLISION);
                         drtioaux::send(0,
                             &drtioaux::Packet::DestinationCollisionReply { channel })?;
                     } else if errors & 4 != 0 {
@@ -174,6 +257,7 @@ fn process_aux_packet(dmamgr: &mut DmaManager, analyzer: &mut Analyzer, kernelmg
                             channel = csr::drtiosat::busy_channel_read();
                             csr::drtiosat::rtio_error_write(4);
                         }
+                        kernelmgr.add_async_errors(kernel::ASYNC_ERROR_BUSY);
                         drtioaux::send(0,
                             &drtioaux::Packet::DestinationBusyReply { channel })?;
                     }
@@ -370,6 +454,13 @@ fn process_aux_packet(dmamgr: &mut DmaManager, analyzer: &mut Analyzer, kernelmg
             })
         }
 
+        drtioaux::Packet::AnalyzerCaptureRequest { destination: _destination, enable } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            info!("analyzer capture of subkernel runs {}", if enable { "enabled" } else { "disabled" });
+            analyzer.set_capture(enable);
+            drtioaux::send(0, &drtioaux::Packet::AnalyzerCaptureReply { succeeded: true })
+        }
+
         drtioaux::Packet::DmaAddTraceRequest { destination: _destination, id, last, length, trace } => {
             forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
             let succeeded = dmamgr.add(id, last, &trace, length as usize).is_ok();
@@ -396,25 +487,55 @@ fn process_aux_packet(dmamgr: &mut DmaManager, analyzer: &mut Analyzer, kernelmg
             drtioaux::send(0,
                 &drtioaux::Packet::SubkernelAddDataReply { succeeded: succeeded })
         }
+        drtioaux::Packet::SubkernelAddDeltaRequest { destination: _destination, id, last, length, data } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            let succeeded = match kernelmgr.add_delta(id, last, &data, length as usize) {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!("failed to add subkernel {}: {:?}", id, e);
+                    false
+                }
+            };
+            drtioaux::send(0,
+                &drtioaux::Packet::SubkernelAddDataReply { succeeded: succeeded })
+        }
+        drtioaux::Packet::SubkernelAddSymbolsRequest { destination: _destination, id, last, length, data } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            let succeeded = kernelmgr.add_symbols(id, last, &data, length as usize).is_ok();
+            drtioaux::send(0,
+                &drtioaux::Packet::SubkernelAddDataReply { succeeded: succeeded })
+        }
         drtioaux::Packet::SubkernelLoadRunRequest { destination: _destination, id, run } => {
             forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
-            let mut succeeded = kernelmgr.load(id).is_ok();
+            let mut succeeded = match kernelmgr.load(id) {
+                Ok(()) => true,
+                Err(e) => {
+                    error!("failed to load subkernel {}: {:?}", id, e);
+                    false
+                }
+            };
             // allow preloading a kernel with delayed run
             if run {
                 if dmamgr.running() {
                     // cannot run kernel while DDMA is running
                     succeeded = false;
                 } else {
+                    analyzer.subkernel_starting();
                     succeeded |= kernelmgr.run(id).is_ok();
                 }
             }
             drtioaux::send(0,
                 &drtioaux::Packet::SubkernelLoadRunReply { succeeded: succeeded })
         }
-        drtioaux::Packet::SubkernelExceptionRequest { destination: _destination } => {
+        drtioaux::Packet::SubkernelStopRequest { destination: _destination, id } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            let succeeded = kernelmgr.request_stop(id);
+            drtioaux::send(0, &drtioaux::Packet::SubkernelStopReply { succeeded: succeeded })
+        }
+        drtioaux::Packet::SubkernelExceptionRequest { destination: _destination, id } => {
             forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
             let mut data_slice: [u8; SAT_PAYLOAD_MAX_SIZE] = [0; SAT_PAYLOAD_MAX_SIZE];
-            let meta = kernelmgr.exception_get_slice(&mut data_slice);
+            let meta = kernelmgr.exception_get_slice(id, &mut data_slice);
             drtioaux::send(0, &drtioaux::Packet::SubkernelException {
                 last: meta.last,
                 length: meta.len,
@@ -433,31 +554,275 @@ fn process_aux_packet(dmamgr: &mut DmaManager, analyzer: &mut Analyzer, kernelmg
             if kernelmgr.message_ack_slice() {
                 let mut data_slice: [u8; MASTER_PAYLOAD_MAX_SIZE] = [0; MASTER_PAYLOAD_MAX_SIZE];
                 if let Some(meta) = kernelmgr.message_get_slice(&mut data_slice) {
-                    drtioaux::send(0, &drtioaux::Packet::SubkernelMessage {
-                        destination: *_rank, id: kernelmgr.get_current_id().unwrap(),
-                        last: meta.last, length: meta.len as u16, data: data_slice
-                    })?
+                    drtioaux::send(0, &outgoing_message_packet(kernelmgr, *_rank, meta, data_slice))?
                 } else {
                     error!("Error receiving message slice");
                 }
             }
             Ok(())
         }
+        drtioaux::Packet::SubkernelPeerMessage { destination, source, id: _id, last, length, data } => {
+            forward!(_routing_table, destination, *_rank, _repeaters, &packet);
+            kernelmgr.message_handle_incoming(last, length as usize, &data);
+            drtioaux::send(0, &drtioaux::Packet::SubkernelMessageAck {
+                destination: source
+            })
+        }
+        drtioaux::Packet::SubkernelPeerAddRequest { destination: _destination, id, peer } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            kernelmgr.add_peer(id, peer);
+            drtioaux::send(0, &drtioaux::Packet::SubkernelPeerAddReply { succeeded: true })
+        }
+        drtioaux::Packet::SubkernelInjectRequest { destination: _destination, id, last, length, data } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            let succeeded = kernelmgr.inject_message(id, last, &data[..length as usize]);
+            drtioaux::send(0, &drtioaux::Packet::SubkernelInjectReply { succeeded: succeeded })
+        }
+        drtioaux::Packet::SubkernelRpcReply { destination, id: _id, last, length, data } => {
+            forward!(_routing_table, destination, *_rank, _repeaters, &packet);
+            kernelmgr.rpc_reply_handle_incoming(last, &data[..length as usize]);
+            drtioaux::send(0, &drtioaux::Packet::SubkernelMessageAck {
+                destination: destination
+            })
+        }
+        drtioaux::Packet::SubkernelLogRequest { destination: _destination, filter, id } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            let mut data_slice: [u8; SAT_PAYLOAD_MAX_SIZE] = [0; SAT_PAYLOAD_MAX_SIZE];
+            let meta = kernelmgr.log_get_slice(if filter { Some(id) } else { None }, &mut data_slice);
+            drtioaux::send(0, &drtioaux::Packet::SubkernelLog {
+                last: meta.last,
+                length: meta.len,
+                data: data_slice,
+            })
+        }
+
+        drtioaux::Packet::SatelliteLogFilterRequest { destination: _destination, target, level } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            let succeeded = set_log_filter(target, level);
+            if succeeded {
+                info!("log level of target {} set to {}", target, level);
+            } else {
+                warn!("cannot set log level of target {} to {}", target, level);
+            }
+            drtioaux::send(0, &drtioaux::Packet::SatelliteLogBasicReply { succeeded: succeeded })
+        }
+        drtioaux::Packet::SatelliteLogOutputRequest { destination: _destination, uart, forward } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            info!("kernel log output to UART: {}, to master: {}", uart, forward);
+            kernelmgr.set_log_outputs(uart, forward);
+            drtioaux::send(0, &drtioaux::Packet::SatelliteLogBasicReply { succeeded: true })
+        }
+        drtioaux::Packet::SatelliteLogControlRequest { destination: _destination, action } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            let succeeded = kernelmgr.log_control(action);
+            if !succeeded {
+                warn!("unknown kernel log control action {}", action);
+            }
+            drtioaux::send(0, &drtioaux::Packet::SatelliteLogBasicReply { succeeded: succeeded })
+        }
+        drtioaux::Packet::SubkernelExceptionSuppressRequest { destination: _destination, match_id, exception_id, length, prefix } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            let exception_id = if match_id { Some(exception_id) } else { None };
+            let succeeded = kernelmgr.add_suppression_rule(exception_id, &prefix[..length as usize]);
+            if !succeeded {
+                warn!("too many exception suppression rules");
+            }
+            drtioaux::send(0, &drtioaux::Packet::SubkernelExceptionSuppressReply { succeeded: succeeded })
+        }
+        drtioaux::Packet::SubkernelExceptionReplayRequest { destination: _destination, enable } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            info!("subkernel exception replay {}", if enable { "enabled" } else { "disabled" });
+            kernelmgr.set_exception_replay(enable);
+            drtioaux::send(0, &drtioaux::Packet::SubkernelExceptionReplayReply { succeeded: true })
+        }
+        drtioaux::Packet::SubkernelCommTimeoutsRequest { destination: _destination, slot_ms, await_poll_ms } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            let timeouts = kernel::CommTimeouts { slot_ms: slot_ms, await_poll_ms: await_poll_ms };
+            let succeeded = kernelmgr.set_comm_timeouts(timeouts);
+            if succeeded {
+                info!("subkernel communication timeouts set to {:?}", timeouts);
+            } else {
+                warn!("rejected subkernel communication timeouts {:?}", timeouts);
+            }
+            drtioaux::send(0, &drtioaux::Packet::SubkernelCommTimeoutsReply { succeeded: succeeded })
+        }
+        drtioaux::Packet::SubkernelExceptionSuppressedRequest { destination: _destination, clear } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            let count = kernelmgr.suppressed_count(clear);
+            drtioaux::send(0, &drtioaux::Packet::SubkernelExceptionSuppressedReply { count: count })
+        }
+        drtioaux::Packet::SubkernelExceptionUnsuppressRequest { destination: _destination } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            info!("removing exception suppression rules");
+            kernelmgr.remove_suppression_rules();
+            drtioaux::send(0, &drtioaux::Packet::SubkernelExceptionSuppressReply { succeeded: true })
+        }
+        drtioaux::Packet::SubkernelMailboxStatsRequest { destination: _destination, clear } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            drtioaux::send(0, &drtioaux::Packet::SubkernelMailboxStatsReply {
+                counters: kernel::mailbox_stats(clear)
+            })
+        }
+        drtioaux::Packet::SubkernelMessageStatsRequest { destination: _destination, clear } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            drtioaux::send(0, &drtioaux::Packet::SubkernelMessageStatsReply {
+                counters: kernel::message_stats(clear)
+            })
+        }
+        drtioaux::Packet::SubkernelMessageCancelRequest { destination: _destination } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            let cancelled = kernelmgr.cancel_incoming();
+            if cancelled {
+                info!("incomplete subkernel message cancelled");
+            }
+            drtioaux::send(0, &drtioaux::Packet::SubkernelMessageCancelReply { cancelled: cancelled })
+        }
+        drtioaux::Packet::PacketSizeRequest { destination: _destination, size } => {
+            // lowered to what the uplink, and the downlink on the way if any, can carry
+            let size = min(size as usize, drtioaux::packet_capacity(0));
+            #[cfg(has_drtio_routing)]
+            {
+                let hop = _routing_table.0[_destination as usize][*_rank as usize];
+                if hop != 0 {
+                    let repno = (hop - 1) as usize;
+                    if repno >= _repeaters.len() {
+                        return Err(drtioaux::Error::RoutingError);
+                    }
+                    let size = min(size, drtioaux::packet_capacity(hop));
+                    return _repeaters[repno].aux_forward(&drtioaux::Packet::PacketSizeRequest {
+                        destination: _destination, size: size as u16 });
+                }
+            }
+            unsafe { AUX_PACKET_SIZE = size; }
+            info!("aux packets of up to {} bytes agreed on with master", size);
+            drtioaux::send(0, &drtioaux::Packet::PacketSizeReply { size: size as u16 })
+        }
+        drtioaux::Packet::SatelliteWorkBudgetRequest { destination: _destination, budget_us } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            if budget_us == 0 {
+                info!("main loop work budget lifted");
+            } else {
+                info!("main loop work budget set to {} us", budget_us);
+            }
+            kernelmgr.set_work_budget(budget_us);
+            drtioaux::send(0, &drtioaux::Packet::SatelliteWorkBudgetReply { succeeded: true })
+        }
+        drtioaux::Packet::SatelliteLinkErrorsRequest { destination: _destination, clear } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            // the uplink, the satellite end of the link the master counts for this destination
+            drtioaux::send(0, &drtioaux::Packet::SatelliteLinkErrorsReply {
+                counters: drtioaux::link_errors(0, clear)
+            })
+        }
+        drtioaux::Packet::SatelliteMemoryStatsRequest { destination: _destination } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            let heap = unsafe { ALLOC.stats() };
+            let (library, footprint, headroom) = kernelmgr.memory_usage();
+            drtioaux::send(0, &drtioaux::Packet::SatelliteMemoryStatsReply {
+                counters: [heap.used as u32, heap.free as u32, heap.largest_free as u32,
+                           library as u32, footprint as u32, headroom as u32]
+            })
+        }
+        drtioaux::Packet::SatelliteLoopStatsRequest { destination: _destination, clear } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            drtioaux::send(0, &drtioaux::Packet::SatelliteLoopStatsReply {
+                counters: latency::stats(clear)
+            })
+        }
+        drtioaux::Packet::SubkernelBenchmarkRequest { destination: _destination, iterations, message_size } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            let succeeded = kernelmgr.benchmark_start(iterations, message_size);
+            drtioaux::send(0, &drtioaux::Packet::SubkernelBenchmarkReply { succeeded: succeeded })
+        }
+        drtioaux::Packet::SubkernelBenchmarkResultRequest { destination: _destination } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            let results = kernelmgr.benchmark_results();
+            drtioaux::send(0, &drtioaux::Packet::SubkernelBenchmarkResultReply {
+                ready: results.is_some(),
+                results: results.unwrap_or([0; BENCHMARK_RESULT_COUNT])
+            })
+        }
+        drtioaux::Packet::SatelliteLogRateLimitRequest { destination: _destination, rate } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            info!("kernel log rate limit set to {} messages per second", rate);
+            kernelmgr.set_log_rate_limit(rate);
+            drtioaux::send(0, &drtioaux::Packet::SatelliteLogBasicReply { succeeded: true })
+        }
 
         _ => {
+            drtioaux::count_unsolicited(0);
             warn!("received unexpected aux packet");
             Ok(())
         }
     }
 }
 
+fn run_done_packet(kernelmgr: &mut KernelManager, source: u8, finished: SubkernelFinished) -> drtioaux::Packet {
+    info!("subkernel {} finished, with exception: {}", finished.id, finished.with_exception);
+    let mut data_slice: [u8; SAT_PAYLOAD_MAX_SIZE] = [0; SAT_PAYLOAD_MAX_SIZE];
+    // master gets the start of the exception right away
+    let meta = if finished.with_exception {
+        kernelmgr.exception_get_head(finished.id, &mut data_slice)
+    } else {
+        SliceMeta { len: 0, last: true }
+    };
+    drtioaux::Packet::SubkernelRunDone {
+        destination: source, id: finished.id, with_exception: finished.with_exception,
+        async_errors: finished.async_errors, last: meta.last, length: meta.len, data: data_slice
+    }
+}
+
+// the master learns that a subkernel finished as soon as it does, rather than at its next status request
+fn push_run_done(kernelmgr: &mut KernelManager) {
+    let destination = match unsafe { DESTINATION } {
+        Some(destination) => destination,
+        None => return
+    };
+    // kernel output goes out with the status requests, before the kernel is reported finished
+    if kernelmgr.log_forward_is_ready() {
+        return
+    }
+    if let Some(finished) = kernelmgr.get_last_finished() {
+        if let Err(e) = drtioaux::send(0, &run_done_packet(kernelmgr, destination, finished)) {
+            error!("aux packet error: {}", e);
+        }
+    }
+}
+
+// slice of the outgoing subkernel message or RPC, sent to the master as the source
+fn outgoing_message_packet(kernelmgr: &KernelManager, source: u8, meta: SliceMeta,
+                           data: [u8; MASTER_PAYLOAD_MAX_SIZE]) -> drtioaux::Packet {
+    let id = kernelmgr.message_source_id();
+    if let Some(peer) = kernelmgr.message_peer() {
+        // the master passes it on to the satellite of the other subkernel
+        drtioaux::Packet::SubkernelPeerMessage {
+            destination: peer, source: source, id: id, last: meta.last, length: meta.len as u16, data: data
+        }
+    } else if kernelmgr.message_is_rpc() {
+        drtioaux::Packet::SubkernelRpcRequest {
+            destination: source, id: id, last: meta.last, length: meta.len as u16, data: data
+        }
+    } else {
+        drtioaux::Packet::SubkernelMessage {
+            destination: source, id: id, last: meta.last, length: meta.len as u16, data: data
+        }
+    }
+}
+
 fn process_aux_packets(dma_manager: &mut DmaManager, analyzer: &mut Analyzer,
         kernelmgr: &mut KernelManager, repeaters: &mut [repeater::Repeater],
         routing_table: &mut drtio_routing::RoutingTable, rank: &mut u8) {
+    let received = match unsafe { DEFERRED_PACKET.take() } {
+        Some(packet) => Ok(Some(packet)),
+        None => drtioaux::recv(0)
+    };
     let result =
-        drtioaux::recv(0).and_then(|packet| {
+        received.and_then(|packet| {
             if let Some(packet) = packet {
-                process_aux_packet(dma_manager, analyzer, kernelmgr, repeaters, routing_table, rank, packet)
+                let started = clock::get_us();
+                let result = process_aux_packet(dma_manager, analyzer, kernelmgr, repeaters, routing_table, rank, packet);
+                latency::record(latency::Section::Aux, started);
+                result
             } else {
                 Ok(())
             }
@@ -468,6 +833,32 @@ fn process_aux_packets(dma_manager: &mut DmaManager, analyzer: &mut Analyzer,
     }
 }
 
+// priority requests (see Packet::is_priority) are handled as soon as they arrive, rather than
+// after the repeaters and the kernel are serviced; anything else waits for its turn
+fn poll_priority_aux(dma_manager: &mut DmaManager, analyzer: &mut Analyzer,
+        kernelmgr: &mut KernelManager, repeaters: &mut [repeater::Repeater],
+        routing_table: &mut drtio_routing::RoutingTable, rank: &mut u8) {
+    if unsafe { DEFERRED_PACKET.is_some() } || !drtioaux::has_packet(0) {
+        return
+    }
+    match drtioaux::recv(0) {
+        Ok(Some(packet)) => {
+            if !packet.is_priority() {
+                unsafe { DEFERRED_PACKET = Some(packet) };
+                return
+            }
+            let started = clock::get_us();
+            if let Err(e) = process_aux_packet(dma_manager, analyzer, kernelmgr, repeaters, routing_table,
+                                               rank, packet) {
+                warn!("aux packet error ({})", e);
+            }
+            latency::record(latency::Section::Aux, started);
+        }
+        Ok(None) => (),
+        Err(e) => warn!("aux packet error ({})", e)
+    }
+}
+
 fn drtiosat_process_errors() {
     let errors = unsafe { csr::drtiosat::protocol_error_read() };
     if errors & 1 != 0 {
@@ -595,6 +986,7 @@ pub extern fn main() -> i32 {
 
     clock::init();
     uart_logger::ConsoleLogger::register();
+    uart_logger::ConsoleLogger::set_record_hook(kernel::forward_log_record);
 
     info!("ARTIQ satellite manager starting...");
     info!("software ident {}", csr::CONFIG_IDENTIFIER_STR);
@@ -672,6 +1064,8 @@ pub extern fn main() -> i32 {
 
     let mut hardware_tick_ts = 0;
 
+    kernel::set_wait_hook(drtiosat_process_errors);
+
     #[cfg(soc_platform = "efc")]
     ad9117::init().expect("AD9117 initialization failed");
     
@@ -704,6 +1098,11 @@ pub extern fn main() -> i32 {
         let mut dma_manager = DmaManager::new();
         let mut analyzer = Analyzer::new();
         let mut kernelmgr = KernelManager::new();
+        unsafe {
+            AUX_PACKET_SIZE = AUX_PACKET_DEFAULT_SIZE;
+            DESTINATION = None;
+        }
+        kernel::clear_master_time();
 
         cricon_select(RtioMaster::Drtio);
         drtioaux::reset(0);
@@ -711,11 +1110,18 @@ pub extern fn main() -> i32 {
         drtiosat_reset_phy(false);
 
         while drtiosat_link_rx_up() {
+            let iteration_started = clock::get_us();
             drtiosat_process_errors();
             process_aux_packets(&mut dma_manager, &mut analyzer, 
                 &mut kernelmgr, &mut repeaters, 
                 &mut routing_table, &mut rank);
             for rep in repeaters.iter_mut() {
+                // servicing repeaters may take a while, do not keep the kernel waiting
+                if kernelmgr.kern_message_posted() {
+                    let started = clock::get_us();
+                    kernelmgr.process_kern_requests(rank, &mut dma_manager);
+                    latency::record(latency::Section::Kernel, started);
+                }
                 rep.service(&routing_table, rank);
             }
             #[cfg(all(soc_platform = "kasli", hw_rev = "v2.0"))]
@@ -737,7 +1143,19 @@ pub extern fn main() -> i32 {
                     error!("aux packet error: {}", e);
                 }
             }
-            kernelmgr.process_kern_requests(rank);
+            // a stop request that came in meanwhile is not held up by the kernel pass
+            poll_priority_aux(&mut dma_manager, &mut analyzer, &mut kernelmgr, &mut repeaters,
+                &mut routing_table, &mut rank);
+            // idle passes would only hide the time taken by kernels
+            let kernel_running = kernelmgr.is_running();
+            let started = clock::get_us();
+            kernelmgr.process_kern_requests(rank, &mut dma_manager);
+            if kernel_running {
+                latency::record(latency::Section::Kernel, started);
+            }
+            analyzer.service(kernelmgr.is_running());
+            push_run_done(&mut kernelmgr);
+            latency::record(latency::Section::Iteration, iteration_started);
         }
 
         drtiosat_reset_phy(true);
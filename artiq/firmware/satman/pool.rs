@@ -0,0 +1,79 @@
+use alloc::vec::Vec;
+
+// Buffers for subkernel messages are taken from here and given back once the message
+// is sent or passed to the kernel, so that long runs do not fragment the heap.
+
+// capacities of the pooled buffers, longer messages are allocated as usual
+const SIZE_CLASSES: [usize; 3] = [512, 4096, 32768];
+const BUFFERS_PER_CLASS: usize = 4;
+
+struct Pool {
+    free: [Vec<Vec<u8>>; 3],
+    reused: u32,
+    exhausted: u32,
+    oversized: u32
+}
+
+static mut POOL: Pool = Pool {
+    free: [Vec::new(), Vec::new(), Vec::new()],
+    reused: 0,
+    exhausted: 0,
+    oversized: 0
+};
+
+pub fn init() {
+    let pool = unsafe { &mut POOL };
+    for (free, &size) in pool.free.iter_mut().zip(SIZE_CLASSES.iter()) {
+        while free.len() < BUFFERS_PER_CLASS {
+            free.push(Vec::with_capacity(size));
+        }
+    }
+}
+
+/// Returns an empty buffer that can hold at least `length` bytes.
+pub fn take(length: usize) -> Vec<u8> {
+    let pool = unsafe { &mut POOL };
+    match SIZE_CLASSES.iter().position(|&size| size >= length) {
+        Some(class) => match pool.free[class].pop() {
+            Some(buffer) => {
+                pool.reused += 1;
+                buffer
+            }
+            None => {
+                pool.exhausted += 1;
+                Vec::with_capacity(SIZE_CLASSES[class])
+            }
+        },
+        None => {
+            pool.oversized += 1;
+            Vec::with_capacity(length)
+        }
+    }
+}
+
+pub fn give(mut buffer: Vec<u8>) {
+    let pool = unsafe { &mut POOL };
+    // buffers that grew well past the largest class go back to the heap
+    if buffer.capacity() > 2 * SIZE_CLASSES[SIZE_CLASSES.len() - 1] {
+        return
+    }
+    if let Some(class) = SIZE_CLASSES.iter().rposition(|&size| size <= buffer.capacity()) {
+        if pool.free[class].len() < BUFFERS_PER_CLASS {
+            buffer.clear();
+            pool.free[class].push(buffer);
+        }
+    }
+}
+
+/// Returns how many buffers were reused, allocated because their class ran out,
+/// and allocated because they were too large for the pool.
+pub fn stats(clear: bool) -> [u32; 3] {
+    let pool = unsafe { &mut POOL };
+    let counters = [pool.reused, pool.exhausted, pool.oversized];
+    if clear {
+        pool.reused = 0;
+        pool.exhausted = 0;
+        pool.oversized = 0;
+    }
+    counters
+}
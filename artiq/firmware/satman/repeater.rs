@@ -10,6 +10,14 @@ fn rep_link_rx_up(repno: u8) -> bool {
     }
 }
 
+// packets satellites send of their own accord are passed on towards the master
+#[cfg(has_drtio_routing)]
+fn push_upstream(packet: &drtioaux::Packet) {
+    if let Err(e) = drtioaux::send(0, packet) {
+        error!("aux packet error pushing packet upstream ({})", e);
+    }
+}
+
 #[cfg(has_drtio_routing)]
 #[derive(Clone, Copy, PartialEq)]
 enum RepeaterState {
@@ -96,6 +104,7 @@ impl Repeater {
                                 error!("[REP#{}] ping failed", self.repno);
                                 self.state = RepeaterState::Failed;
                             } else {
+                                drtioaux::count_retry(self.auxno);
                                 self.state = RepeaterState::SendPing { ping_count: ping_count };
                             }
                         }
@@ -123,7 +132,11 @@ impl Repeater {
 
     fn process_unsolicited_aux(&self) {
         match drtioaux::recv(self.auxno) {
-            Ok(Some(packet)) => warn!("[REP#{}] unsolicited aux packet: {:?}", self.repno, packet),
+            Ok(Some(packet @ drtioaux::Packet::SubkernelRunDone { .. })) => push_upstream(&packet),
+            Ok(Some(packet)) => {
+                drtioaux::count_unsolicited(self.auxno);
+                warn!("[REP#{}] unsolicited aux packet: {:?}", self.repno, packet)
+            }
             Ok(None) => (),
             Err(_) => warn!("[REP#{}] aux packet error", self.repno)
         }
@@ -169,9 +182,12 @@ impl Repeater {
                 return Err(drtioaux::Error::LinkDown);
             }
             if clock::get_ms() > max_time {
+                drtioaux::count_timeout(self.auxno);
                 return Err(drtioaux::Error::TimedOut);
             }
             match drtioaux::recv(self.auxno) {
+                // pushed by a satellite further down, not the reply
+                Ok(Some(packet @ drtioaux::Packet::SubkernelRunDone { .. })) => push_upstream(&packet),
                 Ok(Some(packet)) => return Ok(packet),
                 Ok(None) => (),
                 Err(e) => return Err(e)
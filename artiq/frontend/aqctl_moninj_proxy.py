@@ -10,7 +10,7 @@ from sipyco.asyncio_tools import AsyncioServer, SignalHandler
 from sipyco.pc_rpc import Server
 from sipyco import common_args
 
-from artiq.coredevice.comm_moninj import CommMonInj
+from artiq.coredevice.comm_moninj import CommMonInj, SubkernelState
 
 
 logger = logging.getLogger(__name__)
@@ -19,12 +19,16 @@ logger = logging.getLogger(__name__)
 class EventType(Enum):
     PROBE = 0
     INJECTION = 1
+    SUBKERNELS = 2
 
 
 class MonitorMux:
     def __init__(self):
         self.listeners = dict()
         self.comm_moninj = None
+        # last states reported, replayed to listeners subscribing later
+        self.subkernels = dict()
+        self.destinations = dict()
 
     def _monitor(self, listener, event):
         try:
@@ -38,12 +42,20 @@ class MonitorMux:
             elif event[0] == EventType.INJECTION:
                 logger.debug("starting monitoring channel %d injection %d", event[1], event[2])
                 self.comm_moninj.monitor_injection(True, event[1], event[2])
+            elif event[0] == EventType.SUBKERNELS:
+                logger.debug("starting monitoring subkernels")
+                self.comm_moninj.monitor_subkernels(True)
             else:
                 raise ValueError
         if listener in listeners:
             logger.warning("listener trying to subscribe twice to %s", event)
         else:
             listeners.append(listener)
+            if event[0] == EventType.SUBKERNELS:
+                for id, (destination, state) in self.subkernels.items():
+                    listener.subkernel_cb(id, destination, state)
+                for destination, up in self.destinations.items():
+                    listener.destination_cb(destination, up)
 
     def _unmonitor(self, listener, event):
         try:
@@ -63,6 +75,8 @@ class MonitorMux:
             elif event[0] == EventType.INJECTION:
                 logger.debug("stopped monitoring channel %d injection %d", event[1], event[2])
                 self.comm_moninj.monitor_injection(False, event[1], event[2])
+            elif event[0] == EventType.SUBKERNELS:
+                self._stop_subkernels()
             else:
                 raise ValueError
 
@@ -78,6 +92,18 @@ class MonitorMux:
         else:
             self._unmonitor(listener, (EventType.INJECTION, channel, overrd))
 
+    def monitor_subkernels(self, listener, enable):
+        if enable:
+            self._monitor(listener, (EventType.SUBKERNELS,))
+        else:
+            self._unmonitor(listener, (EventType.SUBKERNELS,))
+
+    def _stop_subkernels(self):
+        logger.debug("stopped monitoring subkernels")
+        self.comm_moninj.monitor_subkernels(False)
+        self.subkernels.clear()
+        self.destinations.clear()
+
     def _event_cb(self, event, value):
         try:
             listeners = self.listeners[event]
@@ -99,6 +125,19 @@ class MonitorMux:
     def injection_status_cb(self, channel, override, value):
         self._event_cb((EventType.INJECTION, channel, override), value)
 
+    def subkernel_cb(self, id, destination, state):
+        if state == SubkernelState.removed:
+            self.subkernels.pop(id, None)
+        else:
+            self.subkernels[id] = (destination, state)
+        for listener in self.listeners.get((EventType.SUBKERNELS,), []):
+            listener.subkernel_cb(id, destination, state)
+
+    def destination_cb(self, destination, up):
+        self.destinations[destination] = up
+        for listener in self.listeners.get((EventType.SUBKERNELS,), []):
+            listener.destination_cb(destination, up)
+
     def remove_listener(self, listener):
         for event, listeners in list(self.listeners.items()):
             try:
@@ -113,11 +152,15 @@ class MonitorMux:
                 elif event[0] == EventType.INJECTION:
                     logger.debug("stopped monitoring channel %d injection %d", event[1], event[2])
                     self.comm_moninj.monitor_injection(False, event[1], event[2])
+                elif event[0] == EventType.SUBKERNELS:
+                    self._stop_subkernels()
                 else:
                     raise ValueError
 
     def disconnect_cb(self):
         self.listeners.clear()
+        self.subkernels.clear()
+        self.destinations.clear()
 
 
 class ProxyConnection:
@@ -148,6 +191,10 @@ class ProxyConnection:
                     packet = await self.reader.readexactly(6)
                     enable, channel, overrd = struct.unpack("<blb", packet)
                     self.monitor_mux.monitor_injection(self, enable, channel, overrd)
+                elif ty == b"\x04":   # MonitorSubkernels
+                    packet = await self.reader.readexactly(1)
+                    enable, = struct.unpack("<b", packet)
+                    self.monitor_mux.monitor_subkernels(self, enable)
                 else:
                     raise ValueError
         finally:
@@ -161,6 +208,14 @@ class ProxyConnection:
         packet = struct.pack("<blbb", 1, channel, override, value)
         self.writer.write(packet)
 
+    def subkernel_cb(self, id, destination, state):
+        packet = struct.pack("<bLBB", 2, id, destination, state.value)
+        self.writer.write(packet)
+
+    def destination_cb(self, destination, up):
+        packet = struct.pack("<bB?", 3, destination, up)
+        self.writer.write(packet)
+
 
 class ProxyServer(AsyncioServer):
     def __init__(self, monitor_mux):
@@ -208,7 +263,9 @@ def main():
             monitor_mux = MonitorMux()
             comm_moninj = CommMonInj(monitor_mux.monitor_cb,
                                      monitor_mux.injection_status_cb,
-                                     monitor_mux.disconnect_cb)
+                                     monitor_mux.disconnect_cb,
+                                     monitor_mux.subkernel_cb,
+                                     monitor_mux.destination_cb)
             monitor_mux.comm_moninj = comm_moninj
             loop.run_until_complete(comm_moninj.connect(args.core_addr))
             try:
@@ -3,7 +3,7 @@
 import argparse
 import struct
 
-from sipyco import common_args
+from sipyco import common_args, pyon
 
 from artiq import __version__ as artiq_version
 from artiq.master.databases import DeviceDB
@@ -24,6 +24,9 @@ def get_argparser():
     parser.add_argument("-D", "--device", default=None,
                         help="use specified core device address instead of "
                              "reading device database")
+    parser.add_argument("--auth-token", default=None,
+                        help="authentication token of the core device, instead "
+                             "of the one in the device database")
 
     tools = parser.add_subparsers(dest="tool")
     tools.required = True
@@ -48,6 +51,163 @@ def get_argparser():
     p_set_uart_level.add_argument("level", metavar="LEVEL", type=str,
                                   help="log level (one of: OFF ERROR WARN INFO DEBUG TRACE)")
 
+    p_satellite = subparsers.add_parser("satellite",
+                                        help="read recent kernel log lines kept by a satellite")
+    p_satellite.add_argument("destination", metavar="DESTINATION", type=int,
+                             help="DRTIO destination of the satellite")
+    p_satellite.add_argument("-k", "--kernel-id", default=None, type=int,
+                             help="only read lines printed by the given subkernel")
+
+    p_set_satellite_level = subparsers.add_parser("set_satellite_level",
+                                                  help="set minimum level for messages to be logged "
+                                                       "by a satellite")
+    p_set_satellite_level.add_argument("destination", metavar="DESTINATION", type=int,
+                                       help="DRTIO destination of the satellite")
+    p_set_satellite_level.add_argument("target", metavar="TARGET", type=str,
+                                       help="log target (one of: ALL KERNEL DRTIO MANAGER)")
+    p_set_satellite_level.add_argument("level", metavar="LEVEL", type=str,
+                                       help="log level (one of: OFF ERROR WARN INFO DEBUG TRACE)")
+
+    p_set_satellite_rate_limit = subparsers.add_parser("set_satellite_rate_limit",
+                                                       help="limit the number of kernel log messages "
+                                                            "per second kept by a satellite")
+    p_set_satellite_rate_limit.add_argument("destination", metavar="DESTINATION", type=int,
+                                            help="DRTIO destination of the satellite")
+    p_set_satellite_rate_limit.add_argument("rate", metavar="RATE", type=int,
+                                            help="messages per second (0 for no limit)")
+
+    p_set_satellite_outputs = subparsers.add_parser("set_satellite_outputs",
+                                                    help="choose where a satellite sends kernel "
+                                                         "log output")
+    p_set_satellite_outputs.add_argument("destination", metavar="DESTINATION", type=int,
+                                         help="DRTIO destination of the satellite")
+    p_set_satellite_outputs.add_argument("outputs", metavar="OUTPUTS", type=str,
+                                         choices=["uart", "master", "both"],
+                                         help="kernel log outputs (one of: uart master both)")
+
+    p_satellite_control = subparsers.add_parser("satellite_control",
+                                                help="pause, resume or clear kernel logging "
+                                                     "on a satellite")
+    p_satellite_control.add_argument("destination", metavar="DESTINATION", type=int,
+                                     help="DRTIO destination of the satellite")
+    p_satellite_control.add_argument("control", metavar="ACTION", type=str,
+                                     choices=["pause", "resume", "clear"],
+                                     help="action (one of: pause resume clear)")
+
+    # subkernels
+    t_subkernel = tools.add_parser("subkernel",
+                                   help="control subkernel exception reporting on satellites")
+
+    subparsers = t_subkernel.add_subparsers(dest="action")
+    subparsers.required = True
+
+    p_suppress = subparsers.add_parser("suppress",
+                                       help="only count matching subkernel exceptions, "
+                                            "reporting them without details")
+    p_suppress.add_argument("destination", metavar="DESTINATION", type=int,
+                            help="DRTIO destination of the satellite")
+    p_suppress.add_argument("-i", "--exception-id", default=None, type=int,
+                            help="only suppress exceptions with the given id")
+    p_suppress.add_argument("-p", "--prefix", default="", type=str,
+                            help="only suppress exceptions whose message starts "
+                                 "with the given prefix")
+
+    p_suppressed = subparsers.add_parser("suppressed",
+                                         help="show the number of suppressed exceptions")
+    p_suppressed.add_argument("destination", metavar="DESTINATION", type=int,
+                              help="DRTIO destination of the satellite")
+    p_suppressed.add_argument("-c", "--clear", default=False, action="store_true",
+                              help="reset the count")
+
+    p_unsuppress = subparsers.add_parser("unsuppress",
+                                         help="remove all exception suppression rules, "
+                                              "the count is kept")
+    p_unsuppress.add_argument("destination", metavar="DESTINATION", type=int,
+                              help="DRTIO destination of the satellite")
+
+    p_mailbox = subparsers.add_parser("mailbox",
+                                      help="show kernel CPU mailbox traffic statistics")
+    p_mailbox.add_argument("destination", metavar="DESTINATION", type=int,
+                           help="DRTIO destination of the satellite")
+    p_mailbox.add_argument("-c", "--clear", default=False, action="store_true",
+                           help="reset the statistics after reading them")
+
+    p_messages = subparsers.add_parser("messages",
+                                       help="show subkernel message and RPC "
+                                            "throughput statistics")
+    p_messages.add_argument("destination", metavar="DESTINATION", type=int,
+                            help="DRTIO destination of the satellite")
+    p_messages.add_argument("-c", "--clear", default=False, action="store_true",
+                            help="reset the statistics after reading them")
+
+    p_replay = subparsers.add_parser("replay",
+                                     help="let subkernels re-raise the last stored "
+                                          "exception, for debugging")
+    p_replay.add_argument("destination", metavar="DESTINATION", type=int,
+                          help="DRTIO destination of the satellite")
+    p_replay.add_argument("state", metavar="STATE", type=str,
+                          choices=["on", "off"],
+                          help="enable or disable exception replay (one of: on off)")
+
+    p_timeouts = subparsers.add_parser("timeouts",
+                                       help="tune how long a satellite waits on subkernels "
+                                            "receiving messages")
+    p_timeouts.add_argument("destination", metavar="DESTINATION", type=int,
+                            help="DRTIO destination of the satellite")
+    p_timeouts.add_argument("-s", "--slot", default=100, type=int,
+                            help="time given to the kernel for each value of a "
+                                 "received message, in ms (default: %(default)s)")
+    p_timeouts.add_argument("-p", "--poll", default=0, type=int,
+                            help="interval between checks for incoming messages, "
+                                 "in ms (default: %(default)s, every main loop pass)")
+
+    p_inject = subparsers.add_parser("inject",
+                                     help="pass a message to a running subkernel, "
+                                          "received with subkernel_recv")
+    p_inject.add_argument("destination", metavar="DESTINATION", type=int,
+                          help="DRTIO destination of the satellite")
+    p_inject.add_argument("subkernel_id", metavar="ID", type=int,
+                          help="id of the running subkernel")
+    p_inject.add_argument("values", metavar="VALUE", nargs="*", type=pyon.decode,
+                          help="values of the message, in PYON (e.g. 1.5 or "
+                               "\"[1, 2]\")")
+
+    p_cancel = subparsers.add_parser("cancel",
+                                     help="drop subkernel messages that stopped "
+                                          "arriving part way")
+    p_cancel.add_argument("destination", metavar="DESTINATION", type=int,
+                          help="DRTIO destination of the satellite")
+
+    p_budget = subparsers.add_parser("budget",
+                                     help="bound the subkernel work done by a satellite "
+                                          "between DRTIO aux requests")
+    p_budget.add_argument("destination", metavar="DESTINATION", type=int,
+                          help="DRTIO destination of the satellite")
+    p_budget.add_argument("budget", metavar="US", type=int,
+                          help="time budget of each main loop pass, in us "
+                               "(0 for no bound)")
+
+    p_analyzer = subparsers.add_parser("analyzer",
+                                       help="capture RTIO analyzer data of a satellite "
+                                            "only while subkernels run")
+    p_analyzer.add_argument("destination", metavar="DESTINATION", type=int,
+                            help="DRTIO destination of the satellite")
+    p_analyzer.add_argument("state", metavar="STATE", type=str,
+                            choices=["on", "off"],
+                            help="enable or disable the capture (one of: on off)")
+
+    p_benchmark = subparsers.add_parser("benchmark",
+                                        help="time kernel to firmware round trips on a "
+                                             "satellite, replacing its loaded subkernel")
+    p_benchmark.add_argument("destination", metavar="DESTINATION", type=int,
+                             help="DRTIO destination of the satellite")
+    p_benchmark.add_argument("-n", "--iterations", default=1000, type=int,
+                             help="round trips timed in each phase "
+                                  "(default: %(default)s)")
+    p_benchmark.add_argument("-s", "--size", default=1024, type=int,
+                             help="size of the looped back messages, in bytes "
+                                  "(default: %(default)s)")
+
     # configuration
     t_config = tools.add_parser("config",
                                 help="read and change core device configuration")
@@ -81,6 +241,10 @@ def get_argparser():
 
     subparsers.add_parser("erase", help="fully erase core device config")
 
+    subparsers.add_parser("reload_routing",
+                          help="apply the DRTIO routing table in the config "
+                               "without rebooting")
+
     # booting
     t_boot = tools.add_parser("reboot",
                               help="reboot the running system")
@@ -95,6 +259,42 @@ def get_argparser():
     p_allocator = subparsers.add_parser("allocator",
                                         help="show heap layout")
 
+    p_link_errors = subparsers.add_parser("link_errors",
+                                          help="show DRTIO aux error counters of the "
+                                               "link towards a satellite")
+    p_link_errors.add_argument("destination", metavar="DESTINATION", type=int,
+                               help="DRTIO destination of the satellite")
+    p_link_errors.add_argument("-c", "--clear", default=False, action="store_true",
+                               help="reset the counters after reading them")
+
+    p_latency = subparsers.add_parser("aux_latency",
+                                      help="time DRTIO aux round trips to a satellite")
+    p_latency.add_argument("destination", metavar="DESTINATION", type=int,
+                           help="DRTIO destination of the satellite")
+    p_latency.add_argument("-n", "--count", default=100, type=int,
+                           help="round trips to time, 0 to show the last "
+                                "measurement (default: %(default)s)")
+
+    p_loop = subparsers.add_parser("satellite_loop",
+                                   help="show how long a satellite takes to go "
+                                        "through its main loop")
+    p_loop.add_argument("destination", metavar="DESTINATION", type=int,
+                        help="DRTIO destination of the satellite")
+    p_loop.add_argument("-c", "--clear", default=False, action="store_true",
+                        help="reset the statistics after reading them")
+
+    p_memory = subparsers.add_parser("satellite_memory",
+                                     help="show how much heap and kernel CPU "
+                                          "memory a satellite uses")
+    p_memory.add_argument("destination", metavar="DESTINATION", type=int,
+                          help="DRTIO destination of the satellite")
+
+    p_subkernels = subparsers.add_parser("subkernels",
+                                         help="show what the subkernels of the "
+                                              "current or last session did")
+    p_subkernels.add_argument("-c", "--clear", default=False, action="store_true",
+                              help="reset the statistics after reading them")
+
     return parser
 
 
@@ -102,23 +302,76 @@ def main():
     args = get_argparser().parse_args()
     common_args.init_logger_from_args(args)
 
+    auth_token = args.auth_token
     if args.device is None:
         ddb = DeviceDB(args.device_db)
-        core_addr = ddb.get("core", resolve_alias=True)["arguments"]["host"]
+        core_arguments = ddb.get("core", resolve_alias=True)["arguments"]
+        core_addr = core_arguments["host"]
+        if auth_token is None:
+            auth_token = core_arguments.get("auth_token")
     else:
         core_addr = args.device
-    mgmt = CommMgmt(core_addr)
+    mgmt = CommMgmt(core_addr, auth_token=auth_token)
 
     if args.tool == "log":
         if args.action == "set_level":
             mgmt.set_log_level(args.level)
         if args.action == "set_uart_level":
             mgmt.set_uart_log_level(args.level)
+        if args.action == "set_satellite_level":
+            mgmt.set_satellite_log_level(args.destination, args.target, args.level)
+        if args.action == "set_satellite_rate_limit":
+            mgmt.set_satellite_log_rate_limit(args.destination, args.rate)
+        if args.action == "satellite_control":
+            if args.control == "pause":
+                mgmt.pause_satellite_log(args.destination)
+            if args.control == "resume":
+                mgmt.resume_satellite_log(args.destination)
+            if args.control == "clear":
+                mgmt.clear_satellite_log(args.destination)
+        if args.action == "set_satellite_outputs":
+            mgmt.set_satellite_log_outputs(args.destination,
+                                           args.outputs in ("uart", "both"),
+                                           args.outputs in ("master", "both"))
         if args.action == "clear":
             mgmt.clear_log()
+        if args.action == "satellite":
+            print(mgmt.get_satellite_log(args.destination, args.kernel_id), end="")
         if args.action == None:
             print(mgmt.get_log(), end="")
 
+    if args.tool == "subkernel":
+        if args.action == "suppress":
+            mgmt.suppress_satellite_exception(args.destination, args.exception_id, args.prefix)
+        if args.action == "suppressed":
+            print(mgmt.get_satellite_suppressed_count(args.destination, args.clear))
+        if args.action == "unsuppress":
+            mgmt.unsuppress_satellite_exceptions(args.destination)
+        if args.action == "mailbox":
+            stats = mgmt.get_satellite_mailbox_stats(args.destination, args.clear)
+            for name, value in stats.items():
+                print("{}: {}".format(name, value))
+        if args.action == "messages":
+            stats = mgmt.get_satellite_message_stats(args.destination, args.clear)
+            for name, value in stats.items():
+                print("{}: {}".format(name, value))
+        if args.action == "replay":
+            mgmt.set_satellite_exception_replay(args.destination, args.state == "on")
+        if args.action == "timeouts":
+            mgmt.set_satellite_comm_timeouts(args.destination, args.slot, args.poll)
+        if args.action == "inject":
+            mgmt.inject_subkernel_message(args.destination, args.subkernel_id, *args.values)
+        if args.action == "cancel":
+            mgmt.cancel_subkernel_messages(args.destination)
+        if args.action == "budget":
+            mgmt.set_satellite_work_budget(args.destination, args.budget)
+        if args.action == "analyzer":
+            mgmt.set_analyzer_capture(args.destination, args.state == "on")
+        if args.action == "benchmark":
+            results = mgmt.run_satellite_benchmark(args.destination, args.iterations, args.size)
+            for name, value in results.items():
+                print("{}: {}".format(name, value))
+
     if args.tool == "config":
         if args.action == "read":
             value = mgmt.config_read(args.key)
@@ -137,6 +390,8 @@ def main():
                 mgmt.config_remove(key)
         if args.action == "erase":
             mgmt.config_erase()
+        if args.action == "reload_routing":
+            mgmt.reload_routing_table()
 
     if args.tool == "reboot":
         mgmt.reboot()
@@ -144,6 +399,27 @@ def main():
     if args.tool == "debug":
         if args.action == "allocator":
             mgmt.debug_allocator()
+        if args.action == "link_errors":
+            errors = mgmt.get_link_errors(args.destination, args.clear)
+            for end, counters in errors.items():
+                for name, value in counters.items():
+                    print("{} {}: {}".format(end, name, value))
+        if args.action == "aux_latency":
+            latency = mgmt.measure_aux_latency(args.destination, args.count)
+            for name, value in latency.items():
+                print("{}: {}".format(name, value))
+        if args.action == "satellite_loop":
+            stats = mgmt.get_satellite_loop_stats(args.destination, args.clear)
+            for name, value in stats.items():
+                print("{}: {}".format(name, value))
+        if args.action == "satellite_memory":
+            stats = mgmt.get_satellite_memory_stats(args.destination)
+            for name, value in stats.items():
+                print("{}: {}".format(name, value))
+        if args.action == "subkernels":
+            stats = mgmt.get_subkernel_stats(args.clear)
+            for name, value in stats.items():
+                print("{}: {}".format(name, value))
 
 
 if __name__ == "__main__":
@@ -1,15 +1,20 @@
 from migen import *
 from misoc.interconnect import wishbone
+from misoc.interconnect.csr import *
 
 
 class Mailbox(Module):
     def __init__(self, size=1, adr_width=30):
         self.i1 = wishbone.Interface(data_width=32, adr_width=adr_width)
         self.i2 = wishbone.Interface(data_width=32, adr_width=adr_width)
+        # pulses when the kernel CPU posts a message (nonzero write to the first word)
+        self.posted = Signal()
 
         # # #
 
         values = Array([Signal(32) for _ in range(size)])
+        self.sync += self.posted.eq(self.i2.cyc & self.i2.stb & ~self.i2.ack & self.i2.we &
+                                    (self.i2.adr[:bits_for(size-1)] == 0) & (self.i2.dat_w != 0))
         for i in self.i1, self.i2:
             self.sync += [
                 i.dat_r.eq(values[i.adr[:bits_for(size-1)]]),
@@ -19,3 +24,19 @@ class Mailbox(Module):
                     If(i.we, values[i.adr[:bits_for(size-1)]].eq(i.dat_w))
                 )
             ]
+
+
+class MailboxNotifier(Module, AutoCSR):
+    """Lets the comms CPU check whether the kernel CPU posted a message since
+    the last time it cleared the flag, without reading the mailbox itself."""
+    def __init__(self, posted):
+        self.pending = CSR()
+
+        # # #
+
+        pending = Signal()
+        self.sync += [
+            If(self.pending.re & self.pending.r, pending.eq(0)),
+            If(posted, pending.eq(1))
+        ]
+        self.comb += self.pending.w.eq(pending)
@@ -3,7 +3,7 @@ from misoc.cores import timer
 from misoc.interconnect import wishbone
 
 from artiq.gateware.amp.kernel_cpu import KernelCPU
-from artiq.gateware.amp.mailbox import Mailbox
+from artiq.gateware.amp.mailbox import Mailbox, MailboxNotifier
 
 
 class AMPSoC:
@@ -19,7 +19,8 @@ class AMPSoC:
         self.add_cpulevel_sdram_if(self.kernel_cpu.wb_sdram)
         self.csr_devices.append("kernel_cpu")
 
-        mailbox_size = 3
+        # message, RPC queue send and receive, kernel stop request
+        mailbox_size = 4
         self.csr_separation = self.kernel_cpu.cpu_dw//8
 
         self.submodules.mailbox = Mailbox(mailbox_size, adr_width=32-log2_int(self.csr_separation))
@@ -31,6 +32,9 @@ class AMPSoC:
                                self.mem_map["mailbox"] | 0x80000000,
                                self.csr_separation*mailbox_size)
 
+        self.submodules.mailbox_notifier = MailboxNotifier(self.mailbox.posted)
+        self.csr_devices.append("mailbox_notifier")
+
     def register_kernel_cpu_csrdevice(self, name, csrs=None):
         if csrs is None:
             csrs = getattr(self, name).get_csrs()
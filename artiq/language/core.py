@@ -89,6 +89,10 @@ def subkernel(arg=None, destination=0, flags={}):
     actually start running. To help with that, subkernels can be preloaded, with
     ``subkernel_preload(subkernel)`` function. A call to a preloaded subkernel 
     will take less time, but only one subkernel can be preloaded at a time.
+
+    With ``flags={"persistent"}``, the subkernel stays on its satellite after
+    the experiment, through the idle kernel; the next experiment skips its
+    upload if it did not change.
     """
     if isinstance(arg, str):
         def inner_decorator(function):
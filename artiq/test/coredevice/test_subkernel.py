@@ -0,0 +1,156 @@
+import unittest
+import numpy
+
+from artiq.experiment import *
+from artiq.test.hardware_testbench import ExperimentCase
+
+
+# Values passed to subkernels are decoded by the satellite firmware, values they
+# return by the master firmware, both from the tags sent along with them.
+class _NestedValues(EnvExperiment):
+    def build(self):
+        self.setattr_device("core")
+
+    @subkernel(destination=1)
+    def echo_nested(self, value: TTuple([TInt32, TTuple([TFloat, TStr])])) -> TTuple([TInt32, TTuple([TFloat, TStr])]):
+        return value
+
+    @subkernel(destination=1)
+    def echo_pairs(self, pairs: TList(TTuple([TInt32, TInt64]))) -> TList(TTuple([TInt32, TInt64])):
+        return pairs
+
+    @subkernel(destination=1)
+    def sum_pairs(self, pairs: TList(TTuple([TInt32, TInt64]))) -> TInt64:
+        total = int64(0)
+        for (a, b) in pairs:
+            total += int64(a) + b
+        return total
+
+    @subkernel(destination=1)
+    def scale(self, value: TInt32, factor: TTuple([TInt32, TInt32])) -> TInt32:
+        return value * factor[0] + factor[1]
+
+    def report(self, value):
+        self.result = value
+
+    @kernel
+    def nested(self):
+        self.echo_nested((1, (2.5, "three")))
+        self.report(subkernel_await(self.echo_nested))
+
+    @kernel
+    def pairs(self):
+        self.echo_pairs([(1, int64(2)), (3, int64(1) << 40), (-5, int64(-6))])
+        self.report(subkernel_await(self.echo_pairs))
+
+    @kernel
+    def pairs_sum(self):
+        self.sum_pairs([(1, int64(2)), (3, int64(4))])
+        self.report(subkernel_await(self.sum_pairs))
+
+    @kernel
+    def empty_pairs_sum(self):
+        self.sum_pairs([])
+        self.report(subkernel_await(self.sum_pairs))
+
+    @kernel
+    def keyword(self):
+        # sent with a keyword tag, in the position of the parameter
+        self.scale(3, factor=(2, 5))
+        self.report(subkernel_await(self.scale))
+
+
+class _ArrayValues(EnvExperiment):
+    def build(self):
+        self.setattr_device("core")
+
+    @subkernel(destination=1)
+    def echo_image(self, image: TArray(TFloat, 2)) -> TArray(TFloat, 2):
+        return image
+
+    @subkernel(destination=1)
+    def sum_frames(self, frames: TArray(TInt32, 3)) -> TInt32:
+        total = 0
+        for i in range(frames.shape[0]):
+            for j in range(frames.shape[1]):
+                for k in range(frames.shape[2]):
+                    total += frames[i, j, k]
+        return total
+
+    @subkernel(destination=1)
+    def shape_of(self, frames: TArray(TInt32, 3)) -> TTuple([TInt32, TInt32, TInt32]):
+        return (frames.shape[0], frames.shape[1], frames.shape[2])
+
+    def report(self, value):
+        self.result = value
+
+    @kernel
+    def image(self, image):
+        self.echo_image(image)
+        self.report(subkernel_await(self.echo_image))
+
+    @kernel
+    def frames_sum(self, frames):
+        self.sum_frames(frames)
+        self.report(subkernel_await(self.sum_frames))
+
+    @kernel
+    def frames_shape(self, frames):
+        self.shape_of(frames)
+        self.report(subkernel_await(self.shape_of))
+
+
+class SubkernelTest(ExperimentCase):
+    def setUp(self):
+        super().setUp()
+        if 1 not in self.device_db.get_device_db().get("satellite_cpu_targets", {}):
+            self.tearDown()
+            raise unittest.SkipTest("no satellite at destination 1")
+
+    def test_nested_tuple(self):
+        exp = self.create(_NestedValues)
+        exp.nested()
+        self.assertEqual(exp.result, (1, (2.5, "three")))
+
+    def test_list_of_tuples(self):
+        exp = self.create(_NestedValues)
+        exp.pairs()
+        self.assertEqual(exp.result, [(1, 2), (3, 1 << 40), (-5, -6)])
+        exp.pairs_sum()
+        self.assertEqual(exp.result, 10)
+        exp.empty_pairs_sum()
+        self.assertEqual(exp.result, 0)
+
+    def test_keyword(self):
+        exp = self.create(_NestedValues)
+        exp.keyword()
+        self.assertEqual(exp.result, 11)
+
+    def test_array(self):
+        exp = self.create(_ArrayValues)
+        image = numpy.array([[1.0, 2.0, 3.0], [4.0, 5.0, 6.0]])
+        exp.image(image)
+        numpy.testing.assert_equal(exp.result, image)
+
+    def test_array_rank(self):
+        exp = self.create(_ArrayValues)
+        frames = numpy.arange(2 * 3 * 4, dtype=numpy.int32).reshape((2, 3, 4))
+        exp.frames_sum(frames)
+        self.assertEqual(exp.result, numpy.sum(frames))
+        exp.frames_shape(frames)
+        self.assertEqual(exp.result, (2, 3, 4))
+
+    def test_empty_array(self):
+        exp = self.create(_ArrayValues)
+        frames = numpy.zeros((2, 0, 4), dtype=numpy.int32)
+        exp.frames_shape(frames)
+        self.assertEqual(exp.result, (2, 0, 4))
+        exp.frames_sum(frames)
+        self.assertEqual(exp.result, 0)
+
+    def test_large_array(self):
+        # spans many aux packets, streamed into the kernel allocation
+        exp = self.create(_ArrayValues)
+        image = numpy.linspace(0.0, 1.0, 32 * 64).reshape((32, 64))
+        exp.image(image)
+        numpy.testing.assert_equal(exp.result, image)